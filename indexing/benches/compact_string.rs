@@ -0,0 +1,89 @@
+//! Compares building token values as `String` against `CompactString`.
+//!
+//! Run with `cargo bench --bench compact_string`. Reports heap allocations and the wall time spent
+//! converting the words of a synthetic corpus into owned token values.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use indexing::CompactString;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 20;
+
+fn corpus() -> String {
+    let words = [
+        "the",
+        "index",
+        "is",
+        "queried",
+        "with",
+        "a",
+        "term",
+        "and",
+        "returns",
+        "files",
+        "tokeniser",
+        "normaliser",
+        "2021-06-01T12:00:00Z",
+        "INFO",
+        "request_id=4f1c9a",
+        "an_unusually_long_identifier_name_that_spills",
+    ];
+
+    (0..50_000)
+        .map(|i| words[i % words.len()])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn measure<T>(name: &str, input: &str, convert: fn(&str) -> T) {
+    let mut elapsed = Duration::default();
+    let mut allocations = 0;
+
+    for _ in 0..ROUNDS {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        let values = input.split_whitespace().map(convert).collect::<Vec<_>>();
+        black_box(&values);
+
+        elapsed += start.elapsed();
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+
+    println!(
+        "{:>14}: {:>10?} per round, {:>8} allocations per round",
+        name,
+        elapsed / ROUNDS as u32,
+        allocations / ROUNDS
+    );
+}
+
+fn main() {
+    let input = corpus();
+
+    measure("String", &input, |s| s.to_owned());
+    measure("CompactString", &input, |s| CompactString::from(s));
+}
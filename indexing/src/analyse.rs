@@ -0,0 +1,130 @@
+//! This module defines an [`Analyser`] that turns text into index terms.
+//!
+//! An analyser pairs a [`TokeniserFactory`] with a sequence of [`TokenNormaliser`]s. Every
+//! [`crate::Indexer`] has a default analyser, and different analysers can be used for the files under
+//! specific paths, see [`crate::Indexer::set_root_analyser`].
+
+use crate::{
+    normalise::{NormaliserOverrides, TokenNormaliser},
+    tokenise::{Token, Tokeniser, TokeniserFactory},
+};
+
+/// Tokeniser along with the normalisers applied to its tokens.
+pub struct Analyser {
+    tokeniser_factory: Box<dyn TokeniserFactory>,
+    normalisers: Vec<Box<dyn TokenNormaliser>>,
+    position_gaps: bool,
+    version: u32,
+}
+
+impl Analyser {
+    /// Create a new [`Analyser`] with the provided [`TokeniserFactory`] and no normalisers.
+    pub fn new<F>(tokeniser_factory: F) -> Self
+    where
+        F: 'static + TokeniserFactory,
+    {
+        Self {
+            tokeniser_factory: Box::new(tokeniser_factory),
+            normalisers: Vec::new(),
+            position_gaps: true,
+            version: 0,
+        }
+    }
+
+    /// Add a [`TokenNormaliser`] to be applied after the already added ones.
+    pub fn with_normaliser<T>(mut self, normaliser: T) -> Self
+    where
+        T: 'static + TokenNormaliser,
+    {
+        self.normalisers.push(Box::new(normaliser));
+        self
+    }
+
+    /// Set whether the tokens dropped by the normalisers leave a gap in the positions of the kept
+    /// ones, which is the default.
+    ///
+    /// With gaps, the phrase `"to be or not to be"` with `to`, `or` and `not` dropped only matches
+    /// texts with three dropped tokens between the two `be`, without them it matches `"be be"`.
+    pub fn with_position_gaps(mut self, enabled: bool) -> Self {
+        self.position_gaps = enabled;
+        self
+    }
+
+    /// Set the version of the analysis pipeline, 0 by default.
+    ///
+    /// The version is recorded along with every file indexed with the analyser (see
+    /// [`crate::DocumentMetadata::analyser_version`]), and should be bumped whenever the tokeniser
+    /// or the normalisers change the terms they produce.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Version of the analysis pipeline, see [`Analyser::with_version`].
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Create a fresh tokeniser.
+    pub(crate) fn tokeniser(&self) -> Box<dyn Tokeniser> {
+        self.tokeniser_factory.create()
+    }
+
+    /// Normalise the given token by applying sequentially all the normalisers.
+    pub(crate) fn normalise(&self, token: Token) -> Option<Token> {
+        self.normalisers
+            .iter()
+            .try_fold(token, |token, norm| norm.normalise(token))
+    }
+
+    /// Normalise the given token like [`Analyser::normalise`] does.
+    ///
+    /// If the token gets dropped, it is returned as an error in the state it was passed to the
+    /// normaliser that dropped it.
+    pub(crate) fn normalise_or_dropped(&self, token: Token) -> Result<Token, Token> {
+        self.normalise_or_dropped_with(token, &NormaliserOverrides::default())
+    }
+
+    /// Normalise the given token like [`Analyser::normalise_or_dropped`] does, with the normalisers
+    /// skipped or substituted by the overrides.
+    pub(crate) fn normalise_or_dropped_with(
+        &self,
+        token: Token,
+        overrides: &NormaliserOverrides,
+    ) -> Result<Token, Token> {
+        self.normalisers.iter().try_fold(token, |token, norm| {
+            match overrides.resolve(norm.as_ref()) {
+                Some(norm) => norm.normalise(token.clone()).ok_or(token),
+                None => Ok(token),
+            }
+        })
+    }
+
+    /// Carry the position increments of the dropped tokens over to the next kept one.
+    ///
+    /// `gap` accumulates the increments of the tokens dropped since the last kept one.
+    pub(crate) fn position(
+        &self,
+        gap: &mut u32,
+        token: Result<Token, Token>,
+    ) -> Result<Token, Token> {
+        match token {
+            Ok(mut token) => {
+                token.position_increment += std::mem::take(gap);
+                Ok(token)
+            }
+            Err(dropped) => {
+                self.skip_position(gap, dropped.position_increment);
+                Err(dropped)
+            }
+        }
+    }
+
+    /// Carry the position increment of a dropped token over like [`Analyser::position`] does, when
+    /// the token itself is gone.
+    pub(crate) fn skip_position(&self, gap: &mut u32, increment: u32) {
+        if self.position_gaps {
+            *gap += increment;
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! This module implements reading the files stored inside of archives.
+//!
+//! Files inside of an archive are indexed under a path composed of the archive path and the path of
+//! the entry within the archive, separated by `!`: `/data/archive.zip!/inner/path.txt`. Nested
+//! archives extend the path further: `/data/outer.tar!/inner.zip!/file.txt`.
+//!
+//! The sizes in the headers of the entries are controlled by the archive, so they aren't trusted:
+//! the entries are read up to the [`ArchiveLimits`], the ones going over are skipped.
+
+use std::{
+    ffi::OsString,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+use crate::Result;
+
+/// Limits of the data read from an archive, see [`crate::Indexer::with_archive_limits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ArchiveLimits {
+    /// Maximum size of a single entry once extracted.
+    pub max_entry_size: u64,
+    /// Maximum size of all the entries of an archive once extracted.
+    pub max_total_size: u64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        Self {
+            max_entry_size: 64 << 20,
+            max_total_size: 1 << 30,
+        }
+    }
+}
+
+/// Supported archive formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+impl ArchiveKind {
+    /// Detect the archive format by the path's extension.
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+
+        if extension.eq_ignore_ascii_case("zip") {
+            Some(ArchiveKind::Zip)
+        } else if extension.eq_ignore_ascii_case("tar") {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Read all the file entries of the archive within the limits, returning their paths and
+    /// contents.
+    pub fn entries(self, archive: &[u8], limits: ArchiveLimits) -> Result<Vec<(String, Vec<u8>)>> {
+        match self {
+            ArchiveKind::Zip => zip_entries(archive, limits),
+            ArchiveKind::Tar => tar_entries(archive, limits),
+        }
+    }
+}
+
+/// Path of the given entry inside of an archive.
+pub(crate) fn entry_path(archive: &Path, entry: &str) -> PathBuf {
+    entries_root(archive).join(entry.trim_start_matches('/'))
+}
+
+/// Path of the outermost archive the given entry is located in, if it's an archive entry.
+///
+/// Real files and directories can have names ending in `!` too, so a path component only denotes an
+/// archive if it has the extension of one once stripped, and `is_archive` accepts it.
+pub(crate) fn archive_of<F>(entry: &Path, is_archive: F) -> Option<PathBuf>
+where
+    F: Fn(&Path) -> bool,
+{
+    entry
+        .ancestors()
+        .filter_map(|ancestor| ancestor.to_str()?.strip_suffix('!'))
+        .map(Path::new)
+        .filter(|archive| ArchiveKind::of(archive).is_some() && is_archive(archive))
+        .last()
+        .map(Path::to_path_buf)
+}
+
+/// Path all the entries of the archive are located under.
+pub(crate) fn entries_root(archive: &Path) -> PathBuf {
+    let mut root = OsString::from(archive.as_os_str());
+    root.push("!");
+    PathBuf::from(root)
+}
+
+fn zip_entries(archive: &[u8], limits: ArchiveLimits) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive))?;
+    let mut entries = Vec::new();
+    let mut total = 0;
+
+    for i in 0..zip.len() {
+        let entry = zip.by_index(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+
+        let name = entry.name().to_owned();
+        if let Some(contents) = read_entry(&name, entry, limits, &mut total)? {
+            entries.push((name, contents));
+        }
+    }
+
+    Ok(entries)
+}
+
+fn tar_entries(archive: &[u8], limits: ArchiveLimits) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut tar = tar::Archive::new(archive);
+    let mut entries = Vec::new();
+    let mut total = 0;
+
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if let Some(contents) = read_entry(&name, entry, limits, &mut total)? {
+            entries.push((name, contents));
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Read the contents of an entry, unless it goes over the limits given the `total` size of the
+/// entries read so far, in which case it's logged and skipped.
+fn read_entry<R: Read>(
+    name: &str,
+    entry: R,
+    limits: ArchiveLimits,
+    total: &mut u64,
+) -> Result<Option<Vec<u8>>> {
+    let limit = limits
+        .max_entry_size
+        .min(limits.max_total_size.saturating_sub(*total));
+    let mut contents = Vec::new();
+    entry
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut contents)?;
+
+    if contents.len() as u64 > limit {
+        warn!(
+            entry = name,
+            max_entry_size = limits.max_entry_size,
+            max_total_size = limits.max_total_size,
+            "skipped an archive entry going over the size limits"
+        );
+        return Ok(None);
+    }
+    *total += contents.len() as u64;
+    Ok(Some(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, Write},
+        path::Path,
+    };
+
+    use super::{archive_of, entry_path, ArchiveKind, ArchiveLimits};
+
+    #[test]
+    fn entry_paths_are_nested_under_archive() {
+        assert_eq!(
+            entry_path(Path::new("/data/a.zip"), "dir/file.txt"),
+            Path::new("/data/a.zip!/dir/file.txt")
+        );
+        assert!(!entry_path(Path::new("/data/a.zip"), "f").starts_with("/data/a.zip"));
+    }
+
+    #[test]
+    fn only_archive_paths_are_archives_of_entries() {
+        let entry = Path::new("/data/outer.tar!/inner.zip!/file.txt");
+        assert_eq!(
+            archive_of(entry, |_| true).as_deref(),
+            Some(Path::new("/data/outer.tar"))
+        );
+        assert_eq!(
+            archive_of(entry, |archive| archive.ends_with("inner.zip")).as_deref(),
+            Some(Path::new("/data/outer.tar!/inner.zip"))
+        );
+        assert_eq!(archive_of(Path::new("/home/notes!/a.txt"), |_| true), None);
+        assert_eq!(archive_of(Path::new("/data/a.zip!/f"), |_| false), None);
+    }
+
+    #[test]
+    fn zip_entries_are_read() {
+        let mut buf = Cursor::new(Vec::new());
+        let mut zip = zip::ZipWriter::new(&mut buf);
+        zip.add_directory("dir/", Default::default()).unwrap();
+        zip.start_file("dir/file.txt", Default::default()).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.finish().unwrap();
+        drop(zip);
+
+        let entries = ArchiveKind::Zip
+            .entries(buf.get_ref(), ArchiveLimits::default())
+            .unwrap();
+
+        assert_eq!(entries, [("dir/file.txt".to_owned(), b"hello".to_vec())]);
+    }
+
+    #[test]
+    fn entries_over_the_limits_are_skipped() {
+        let mut buf = Cursor::new(Vec::new());
+        let mut zip = zip::ZipWriter::new(&mut buf);
+        for (name, size) in [
+            ("a.txt", 10),
+            ("big.txt", 100),
+            ("b.txt", 10),
+            ("c.txt", 10),
+        ] {
+            zip.start_file(name, Default::default()).unwrap();
+            zip.write_all(&vec![b'x'; size]).unwrap();
+        }
+        zip.finish().unwrap();
+        drop(zip);
+
+        let limits = ArchiveLimits {
+            max_entry_size: 50,
+            max_total_size: 25,
+        };
+        let names = |kind: ArchiveKind, archive: &[u8]| {
+            kind.entries(archive, limits)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(ArchiveKind::Zip, buf.get_ref()), ["a.txt", "b.txt"]);
+
+        let mut tar = tar::Builder::new(Vec::new());
+        for (name, size) in [("big.txt", 100), ("a.txt", 20), ("b.txt", 10)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(size as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, name, vec![b'x'; size].as_slice())
+                .unwrap();
+        }
+        assert_eq!(
+            names(ArchiveKind::Tar, &tar.into_inner().unwrap()),
+            ["a.txt"]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! This module implements an opt-in audit trail of the [`crate::LiveIndexer`] activity.
+//!
+//! When enabled (see [`crate::LiveIndexerOptions::with_audit_log`]), every file system event received
+//! from the watcher and every indexing action taken is recorded as an [`AuditEntry`] along with its
+//! outcome. Only a bounded number of the most recent entries is kept.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::bounded_log::BoundedLog;
+
+/// Origin of an [`AuditEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditSource {
+    /// File system event received from the watcher.
+    Event,
+    /// Action taken by the indexing worker.
+    Action,
+}
+
+/// Single record of the audit trail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    pub source: AuditSource,
+    /// Kind of the event or action, e.g. `write` or `index`.
+    pub kind: &'static str,
+    pub path: Option<PathBuf>,
+    /// Error message if the action failed, `None` for events and successful actions.
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Check whether the entry concerns the given path or a path beneath it.
+    pub fn concerns(&self, path: &Path) -> bool {
+        self.path.as_deref().is_some_and(|p| p.starts_with(path))
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        write!(
+            f,
+            "{}.{:03} {:?} {}",
+            time.as_secs(),
+            time.subsec_millis(),
+            self.source,
+            self.kind
+        )?;
+        if let Some(path) = &self.path {
+            write!(f, " {}", path.display())?;
+        }
+        match &self.error {
+            Some(error) => write!(f, ": {}", error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Bounded log of the most recent [`AuditEntry`] records.
+pub(crate) struct AuditLog {
+    entries: BoundedLog<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: BoundedLog::new(capacity),
+        }
+    }
+
+    /// Record an event received from the watcher, the watcher might report an error instead.
+    pub fn event(&self, kind: &'static str, path: Option<&Path>, error: Option<String>) {
+        self.push(AuditSource::Event, kind, path, error);
+    }
+
+    /// Record an action taken along with its outcome.
+    pub fn action<T, E>(&self, kind: &'static str, path: &Path, result: &Result<T, E>)
+    where
+        E: fmt::Display,
+    {
+        let error = result.as_ref().err().map(ToString::to_string);
+        self.push(AuditSource::Action, kind, Some(path), error);
+    }
+
+    /// All the recorded entries, from the oldest.
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.records()
+    }
+
+    fn push(
+        &self,
+        source: AuditSource,
+        kind: &'static str,
+        path: Option<&Path>,
+        error: Option<String>,
+    ) {
+        self.entries.push(AuditEntry {
+            time: SystemTime::now(),
+            source,
+            kind,
+            path: path.map(Path::to_owned),
+            error,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{AuditLog, AuditSource};
+
+    #[test]
+    fn events_and_actions_are_recorded_in_order_with_their_outcome() {
+        let log = AuditLog::new(10);
+
+        log.event("create", Some(Path::new("/a")), None);
+        log.event("error", None, Some("watcher failed".to_string()));
+        log.action("index", Path::new("/a"), &Ok::<_, String>(()));
+        log.action("index", Path::new("/b"), &Err::<(), _>("failed"));
+
+        let entries = log
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.source, entry.kind, entry.path, entry.error))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            entries,
+            [
+                (AuditSource::Event, "create", Some("/a".into()), None),
+                (
+                    AuditSource::Event,
+                    "error",
+                    None,
+                    Some("watcher failed".to_string())
+                ),
+                (AuditSource::Action, "index", Some("/a".into()), None),
+                (
+                    AuditSource::Action,
+                    "index",
+                    Some("/b".into()),
+                    Some("failed".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn entries_concern_their_path_and_its_parents() {
+        let log = AuditLog::new(10);
+
+        log.action("index", Path::new("/dir/a"), &Err::<(), _>("failed"));
+        log.event("error", None, Some("watcher failed".to_string()));
+
+        let entries = log.entries();
+
+        assert!(entries[0].concerns(Path::new("/dir/a")));
+        assert!(entries[0].concerns(Path::new("/dir")));
+        assert!(!entries[0].concerns(Path::new("/di")));
+        assert!(!entries[1].concerns(Path::new("/")));
+        assert!(entries[0]
+            .to_string()
+            .ends_with("Action index /dir/a: failed"));
+        assert!(entries[1]
+            .to_string()
+            .ends_with("Event error: watcher failed"));
+    }
+}
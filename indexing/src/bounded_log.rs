@@ -0,0 +1,61 @@
+//! This module implements the bounded logs of the most recent records, e.g. the audit trail of a
+//! [`crate::LiveIndexer`] or the slow queries of an [`crate::Indexer`].
+
+use std::{collections::VecDeque, sync::Mutex};
+
+/// Log keeping up to `capacity` most recent records, the oldest ones are dropped first.
+pub(crate) struct BoundedLog<T> {
+    capacity: usize,
+    records: Mutex<VecDeque<T>>,
+}
+
+impl<T: Clone> BoundedLog<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Add a record, dropping the oldest one if the log is full.
+    pub fn push(&self, record: T) {
+        self.push_with(|| record);
+    }
+
+    /// Add the record built by `record`, which isn't called if the log keeps no records at all.
+    pub fn push_with<F>(&self, record: F)
+    where
+        F: FnOnce() -> T,
+    {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record());
+    }
+
+    /// All the kept records, from the oldest.
+    pub fn records(&self) -> Vec<T> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BoundedLog;
+
+    #[test]
+    fn oldest_records_are_dropped_first() {
+        let log = BoundedLog::new(2);
+        (1..=3).for_each(|record| log.push(record));
+        assert_eq!(log.records(), [2, 3]);
+
+        let log = BoundedLog::new(0);
+        log.push_with(|| -> u8 { unreachable!() });
+        assert!(log.records().is_empty());
+    }
+}
@@ -0,0 +1,416 @@
+//! This module implements opt-in size budgets of an index, see [`crate::Indexer::with_budget`].
+//!
+//! An [`IndexBudget`] sets thresholds on the number of documents, the number of terms and the
+//! estimated memory used by the index. The usage is checked as files are indexed (at most once per
+//! check interval), and once a threshold is crossed a [`BudgetAlert`] is logged and sent to the
+//! subscribers (see [`crate::Indexer::subscribe_budget_alerts`]), and the [`IndexHealth`] of the index
+//! turns degraded until the usage goes back under the thresholds.
+//!
+//! Optionally, the index can keep working at reduced fidelity while the memory budget is exceeded:
+//! new documents are indexed without the offsets of their terms, and the terms with the most
+//! occurrences can be downgraded to keep only the documents they occur in. Such terms are still
+//! found by queries, but phrases can't be matched against them, see
+//! [`IndexBudget::with_memory_degradation`].
+//!
+//! For capacity planning, the distribution of the number of documents the terms occur in can be
+//! exported as a [`FrequencyHistogram`], see [`crate::Indexer::frequency_histogram`].
+
+use std::{
+    fmt,
+    sync::{mpsc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+/// Thresholds of the size of an index.
+#[derive(Debug, Clone)]
+pub struct IndexBudget {
+    max_documents: Option<u64>,
+    max_terms: Option<u64>,
+    max_memory_bytes: Option<u64>,
+    check_interval: Duration,
+    memory_degradation: bool,
+    hot_term_downgrade: Option<usize>,
+}
+
+impl Default for IndexBudget {
+    fn default() -> Self {
+        Self {
+            max_documents: None,
+            max_terms: None,
+            max_memory_bytes: None,
+            check_interval: Duration::from_secs(1),
+            memory_degradation: false,
+            hot_term_downgrade: None,
+        }
+    }
+}
+
+impl IndexBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Alert once more than the given number of documents are indexed.
+    pub fn with_max_documents(mut self, max_documents: u64) -> Self {
+        self.max_documents = Some(max_documents);
+        self
+    }
+
+    /// Alert once more than the given number of distinct terms are indexed.
+    pub fn with_max_terms(mut self, max_terms: u64) -> Self {
+        self.max_terms = Some(max_terms);
+        self
+    }
+
+    /// Alert once the estimated memory used by the index exceeds the given number of bytes, see
+    /// [`IndexUsage::memory_bytes`].
+    pub fn with_max_memory(mut self, max_memory_bytes: u64) -> Self {
+        self.max_memory_bytes = Some(max_memory_bytes);
+        self
+    }
+
+    /// Index the documents without the offsets of their terms while the memory budget is exceeded.
+    ///
+    /// Such documents are found by the queries for single terms, but not by phrases. Once they are
+    /// modified after the usage goes back under the budget, they are indexed in full again.
+    pub fn with_memory_degradation(mut self) -> Self {
+        self.memory_degradation = true;
+        self
+    }
+
+    /// Drop the offsets of the given number of terms with the most occurrences when the memory
+    /// budget gets exceeded, like the posting cap does (see [`crate::Indexer::with_posting_cap`]).
+    pub fn with_hot_term_downgrade(mut self, count: usize) -> Self {
+        self.hot_term_downgrade = Some(count);
+        self
+    }
+
+    /// Check the usage at most once per the given interval while indexing, 1 second by default.
+    ///
+    /// Computing the usage walks the whole index, so checking it after every file would slow
+    /// indexing down.
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Limits the given usage exceeds.
+    fn exceeded(&self, usage: &IndexUsage) -> Vec<BudgetAlert> {
+        [
+            (Resource::Documents, usage.documents, self.max_documents),
+            (Resource::Terms, usage.terms, self.max_terms),
+            (
+                Resource::MemoryBytes,
+                usage.memory_bytes,
+                self.max_memory_bytes,
+            ),
+        ]
+        .iter()
+        .filter_map(|&(resource, usage, limit)| {
+            limit
+                .filter(|&limit| usage > limit)
+                .map(|limit| BudgetAlert {
+                    resource,
+                    usage,
+                    limit,
+                })
+        })
+        .collect()
+    }
+}
+
+/// Size of an index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexUsage {
+    /// Number of indexed documents.
+    pub documents: u64,
+    /// Number of distinct terms with at least one posting.
+    pub terms: u64,
+    /// Rough estimate of the memory used by the postings and the term vectors.
+    pub memory_bytes: u64,
+}
+
+impl IndexUsage {
+    /// Add the usage of another part of the index to this one.
+    pub(crate) fn merge(&mut self, other: IndexUsage) {
+        self.documents += other.documents;
+        self.terms += other.terms;
+        self.memory_bytes += other.memory_bytes;
+    }
+}
+
+/// Resource limited by an [`IndexBudget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Documents,
+    Terms,
+    MemoryBytes,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Documents => f.write_str("documents"),
+            Resource::Terms => f.write_str("terms"),
+            Resource::MemoryBytes => f.write_str("memory bytes"),
+        }
+    }
+}
+
+/// Notice of a resource exceeding its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetAlert {
+    pub resource: Resource,
+    pub usage: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for BudgetAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} exceed the budget: {} of {}",
+            self.resource, self.usage, self.limit
+        )
+    }
+}
+
+/// Distribution of the document frequencies of the terms: how many terms occur in a single
+/// document, in 2 to 10 documents, in 11 to 100 and so on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrequencyHistogram {
+    /// Buckets from the lowest frequencies, up to the highest one in the index.
+    pub buckets: Vec<FrequencyBucket>,
+}
+
+/// Number of terms occurring in between `min_documents` and `max_documents` documents, inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrequencyBucket {
+    pub min_documents: u64,
+    pub max_documents: u64,
+    pub terms: u64,
+}
+
+impl FrequencyHistogram {
+    /// Build the histogram of the given document frequencies.
+    pub(crate) fn new<I>(frequencies: I) -> Self
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let mut buckets = Vec::<FrequencyBucket>::new();
+
+        for frequency in frequencies.into_iter().filter(|&f| f > 0) {
+            while buckets.last().is_none_or(|b| b.max_documents < frequency) {
+                let (min_documents, max_documents) = match buckets.last() {
+                    None => (1, 1),
+                    Some(last) if last.max_documents == 1 => (2, 10),
+                    Some(last) => (last.max_documents + 1, last.max_documents * 10),
+                };
+                buckets.push(FrequencyBucket {
+                    min_documents,
+                    max_documents,
+                    terms: 0,
+                });
+            }
+
+            let bucket = buckets
+                .iter_mut()
+                .find(|b| (b.min_documents..=b.max_documents).contains(&frequency))
+                .unwrap();
+            bucket.terms += 1;
+        }
+
+        Self { buckets }
+    }
+}
+
+impl fmt::Display for FrequencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for bucket in &self.buckets {
+            if bucket.min_documents == bucket.max_documents {
+                write!(f, "{}", bucket.min_documents)?;
+            } else {
+                write!(f, "{}-{}", bucket.min_documents, bucket.max_documents)?;
+            }
+            writeln!(f, " documents: {} terms", bucket.terms)?;
+        }
+        Ok(())
+    }
+}
+
+/// Status of an index with respect to its budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexHealth {
+    /// The usage is within the budget, or no budget is set.
+    Healthy,
+    /// Some of the resources exceed their limits.
+    Degraded(Vec<BudgetAlert>),
+}
+
+impl IndexHealth {
+    pub fn is_healthy(&self) -> bool {
+        *self == IndexHealth::Healthy
+    }
+}
+
+/// Budget of an index along with the outcome of the last check.
+pub(crate) struct BudgetMonitor {
+    budget: IndexBudget,
+    health: RwLock<IndexHealth>,
+    last_check: Mutex<Option<Instant>>,
+    subscribers: Mutex<Vec<mpsc::Sender<BudgetAlert>>>,
+}
+
+impl BudgetMonitor {
+    pub fn new(budget: IndexBudget) -> Self {
+        Self {
+            budget,
+            health: RwLock::new(IndexHealth::Healthy),
+            last_check: Mutex::new(None),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Status as of the last check.
+    pub fn health(&self) -> IndexHealth {
+        self.health.read().unwrap().clone()
+    }
+
+    /// Check whether the documents should be indexed without the offsets of their terms.
+    pub fn is_memory_degraded(&self) -> bool {
+        self.budget.memory_degradation && memory_exceeded(&self.health.read().unwrap())
+    }
+
+    /// Number of the hottest terms to downgrade once the memory budget gets exceeded.
+    pub fn hot_term_downgrade(&self) -> Option<usize> {
+        self.budget.hot_term_downgrade
+    }
+
+    pub fn subscribe(&self) -> mpsc::Receiver<BudgetAlert> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Check the usage computed by `usage` if the check interval elapsed since the last check.
+    ///
+    /// Returns the newly raised alerts, see [`BudgetMonitor::check`].
+    pub fn check_due<F>(&self, usage: F) -> Vec<BudgetAlert>
+    where
+        F: FnOnce() -> IndexUsage,
+    {
+        {
+            let mut last_check = self.last_check.lock().unwrap();
+            if last_check.is_some_and(|time| time.elapsed() < self.budget.check_interval) {
+                return Vec::new();
+            }
+            *last_check = Some(Instant::now());
+        }
+
+        self.check(usage())
+    }
+
+    /// Update the status according to the given usage, alerting about the newly exceeded limits.
+    ///
+    /// Returns the newly raised alerts.
+    pub fn check(&self, usage: IndexUsage) -> Vec<BudgetAlert> {
+        let exceeded = self.budget.exceeded(&usage);
+        let health = if exceeded.is_empty() {
+            IndexHealth::Healthy
+        } else {
+            IndexHealth::Degraded(exceeded.clone())
+        };
+
+        let previous = std::mem::replace(&mut *self.health.write().unwrap(), health);
+        let was_exceeded = |resource| match &previous {
+            IndexHealth::Healthy => false,
+            IndexHealth::Degraded(alerts) => alerts.iter().any(|a| a.resource == resource),
+        };
+
+        let raised = exceeded
+            .into_iter()
+            .filter(|alert| !was_exceeded(alert.resource))
+            .collect::<Vec<_>>();
+
+        for alert in &raised {
+            warn!(%alert, "index budget exceeded");
+            self.subscribers
+                .lock()
+                .unwrap()
+                .retain(|subscriber| subscriber.send(*alert).is_ok());
+        }
+        raised
+    }
+}
+
+/// Check whether the memory budget is exceeded according to the given status.
+fn memory_exceeded(health: &IndexHealth) -> bool {
+    match health {
+        IndexHealth::Healthy => false,
+        IndexHealth::Degraded(alerts) => alerts
+            .iter()
+            .any(|alert| alert.resource == Resource::MemoryBytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BudgetMonitor, FrequencyBucket, FrequencyHistogram, IndexBudget, IndexHealth, IndexUsage,
+        Resource,
+    };
+
+    #[test]
+    fn alerts_are_sent_once_per_crossing() {
+        let monitor =
+            BudgetMonitor::new(IndexBudget::new().with_max_documents(2).with_max_terms(10));
+        let alerts = monitor.subscribe();
+        let usage = |documents, terms| IndexUsage {
+            documents,
+            terms,
+            memory_bytes: 0,
+        };
+
+        assert!(monitor.check(usage(2, 10)).is_empty());
+        assert!(monitor.health().is_healthy());
+        assert_eq!(monitor.check(usage(3, 10)).len(), 1);
+        assert!(!monitor.health().is_healthy());
+        monitor.check(usage(4, 11));
+        monitor.check(usage(4, 11));
+
+        let received = alerts.try_iter().map(|a| a.resource).collect::<Vec<_>>();
+        assert_eq!(received, [Resource::Documents, Resource::Terms]);
+
+        monitor.check(usage(1, 1));
+        assert_eq!(monitor.health(), IndexHealth::Healthy);
+        monitor.check(usage(3, 1));
+        assert_eq!(alerts.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn histogram_buckets_grow_tenfold() {
+        let histogram = FrequencyHistogram::new(vec![1, 1, 2, 10, 11, 250, 0]);
+        let bucket = |min_documents, max_documents, terms| FrequencyBucket {
+            min_documents,
+            max_documents,
+            terms,
+        };
+
+        assert_eq!(
+            histogram.buckets,
+            [
+                bucket(1, 1, 2),
+                bucket(2, 10, 2),
+                bucket(11, 100, 1),
+                bucket(101, 1000, 1)
+            ]
+        );
+        assert_eq!(
+            histogram.to_string(),
+            "1 documents: 2 terms\n2-10 documents: 2 terms\n11-100 documents: 1 terms\n\
+             101-1000 documents: 1 terms\n"
+        );
+    }
+}
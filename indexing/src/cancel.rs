@@ -0,0 +1,55 @@
+//! This module defines the tokens used to abort long-running queries, see
+//! [`crate::Indexer::query_wildcard_cancellable`].
+//!
+//! A [`CancellationToken`] is cancelled once [`CancellationToken::cancel`] is called on any of its
+//! clones, e.g. from another thread when the user gives up on a query, or once its deadline passes.
+//! Queries check the token as they scan the index and fail with [`crate::Error::Timeout`] when it's
+//! cancelled.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Cancellation flag shared by all the clones of a token, along with an optional deadline.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    /// Create a token that is only cancelled explicitly.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a token that is cancelled once `timeout` elapses from now, if not before.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    /// Create a token that is cancelled at `deadline`, if not before.
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            cancelled: Arc::default(),
+            deadline: Some(deadline),
+        }
+    }
+
+    /// Cancel the token and all its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether the token was cancelled or its deadline passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
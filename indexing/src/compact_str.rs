@@ -0,0 +1,251 @@
+//! This module defines [`CompactString`], a small-string optimised string type.
+//!
+//! Most of the tokens produced while indexing text are short words, so allocating each of them on the
+//! heap is wasteful. [`CompactString`] stores strings of up to [`INLINE_CAPACITY`] bytes inline and
+//! only falls back to a heap allocation for longer values, while staying the same size as [`String`].
+
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    iter::FromIterator,
+    ops::Deref,
+    str,
+};
+
+/// Maximum length in bytes of a string that is stored inline.
+pub const INLINE_CAPACITY: usize = 22;
+
+/// Immutable string that stores short values inline.
+///
+/// Comparison, ordering and hashing behave exactly like they do for the underlying `str`, so
+/// a `CompactString` can be looked up by `&str` in maps and trees.
+#[derive(Clone)]
+pub struct CompactString(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Inline { len: u8, buf: [u8; INLINE_CAPACITY] },
+    Heap(Box<str>),
+}
+
+impl CompactString {
+    /// Create an empty string.
+    pub const fn new() -> Self {
+        Self(Repr::Inline {
+            len: 0,
+            buf: [0; INLINE_CAPACITY],
+        })
+    }
+
+    /// Extract a string slice of the whole value.
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            // SAFETY: the inline buffer is only ever filled from valid UTF-8 strings and `len`
+            // always points at a character boundary.
+            Repr::Inline { len, buf } => unsafe { str::from_utf8_unchecked(&buf[..*len as usize]) },
+            Repr::Heap(s) => s,
+        }
+    }
+
+    /// Returns `true` if the value is stored inline, without a heap allocation.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline { .. })
+    }
+
+    /// Convert the value to its ASCII lower case equivalent in-place.
+    pub fn make_ascii_lowercase(&mut self) {
+        match &mut self.0 {
+            Repr::Inline { len, buf } => buf[..*len as usize].make_ascii_lowercase(),
+            Repr::Heap(s) => s.make_ascii_lowercase(),
+        }
+    }
+
+    /// Try to build an inline value, returns `None` if `s` doesn't fit.
+    fn inline(s: &str) -> Option<Self> {
+        if s.len() > INLINE_CAPACITY {
+            return None;
+        }
+
+        let mut buf = [0; INLINE_CAPACITY];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+
+        Some(Self(Repr::Inline {
+            len: s.len() as u8,
+            buf,
+        }))
+    }
+}
+
+impl Default for CompactString {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for CompactString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for CompactString {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for CompactString {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for CompactString {
+    fn from(s: &str) -> Self {
+        Self::inline(s).unwrap_or_else(|| Self(Repr::Heap(s.into())))
+    }
+}
+
+impl From<String> for CompactString {
+    fn from(s: String) -> Self {
+        Self::inline(&s).unwrap_or_else(|| Self(Repr::Heap(s.into_boxed_str())))
+    }
+}
+
+impl From<CompactString> for String {
+    fn from(s: CompactString) -> Self {
+        match s.0 {
+            Repr::Heap(s) => s.into_string(),
+            Repr::Inline { .. } => s.as_str().to_owned(),
+        }
+    }
+}
+
+impl FromIterator<char> for CompactString {
+    fn from_iter<I: IntoIterator<Item = char>>(iter: I) -> Self {
+        let mut buf = [0; INLINE_CAPACITY];
+        let mut len = 0;
+        let mut iter = iter.into_iter();
+
+        while let Some(c) = iter.next() {
+            if len + c.len_utf8() > INLINE_CAPACITY {
+                // Spill everything collected so far into a heap allocated string.
+                // SAFETY: `buf[..len]` only contains whole encoded characters.
+                let mut s = unsafe { str::from_utf8_unchecked(&buf[..len]) }.to_owned();
+                s.push(c);
+                s.extend(iter);
+
+                return Self(Repr::Heap(s.into_boxed_str()));
+            }
+
+            len += c.encode_utf8(&mut buf[len..]).len();
+        }
+
+        Self(Repr::Inline {
+            len: len as u8,
+            buf,
+        })
+    }
+}
+
+impl PartialEq for CompactString {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for CompactString {}
+
+impl PartialEq<str> for CompactString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for CompactString {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for CompactString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CompactString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for CompactString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Debug for CompactString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for CompactString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::*;
+
+    #[test]
+    fn compact_string_is_not_larger_than_string() {
+        assert_eq!(mem::size_of::<CompactString>(), mem::size_of::<String>());
+    }
+
+    #[test]
+    fn short_strings_are_inline() {
+        let s = CompactString::from("a".repeat(INLINE_CAPACITY).as_str());
+
+        assert!(s.is_inline());
+        assert_eq!(s, "a".repeat(INLINE_CAPACITY).as_str());
+    }
+
+    #[test]
+    fn long_strings_are_on_heap() {
+        let s = CompactString::from("a".repeat(INLINE_CAPACITY + 1));
+
+        assert!(!s.is_inline());
+        assert_eq!(s.len(), INLINE_CAPACITY + 1);
+    }
+
+    #[test]
+    fn collect_spills_to_heap_on_multibyte_boundary() {
+        let input = "ü".repeat(INLINE_CAPACITY / 2 + 1);
+        let s = input.chars().collect::<CompactString>();
+
+        assert!(!s.is_inline());
+        assert_eq!(s, input.as_str());
+    }
+
+    #[test]
+    fn ordering_matches_str() {
+        let mut values = ["b", "a", "aa", "ab"]
+            .iter()
+            .map(|&s| CompactString::from(s))
+            .collect::<Vec<_>>();
+        values.sort();
+
+        assert_eq!(values, ["a", "aa", "ab", "b"]);
+    }
+}
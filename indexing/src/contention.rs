@@ -0,0 +1,227 @@
+//! This module reports the contention of the locks of the index trees, enabled by the
+//! `lock-contention` feature.
+//!
+//! Index trees serialise their writers with a lock and swap their root under a read-write lock, so
+//! concurrent indexing and querying can wait on each other. Every acquisition of those locks is
+//! counted process-wide, across all the indexes, along with the time spent waiting when the lock was
+//! held by another thread. The aggregates tell whether the single writer design is a bottleneck for
+//! the workload, see [`report`].
+
+use std::{
+    convert::TryFrom,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LockResult, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
+    time::{Duration, Instant},
+};
+
+/// Lock of the index trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lock {
+    /// Serialises the writers of a tree.
+    Writer,
+    /// Taken to snapshot the root of a tree.
+    RootRead,
+    /// Taken to replace the root of a tree once modified.
+    RootWrite,
+}
+
+/// Aggregates of the acquisitions of a lock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    pub acquisitions: u64,
+    /// Number of the acquisitions that had to wait for another thread to release the lock.
+    pub contended: u64,
+    /// Total time spent waiting for the lock.
+    pub total_wait: Duration,
+    /// Longest time spent waiting for the lock at once.
+    pub max_wait: Duration,
+}
+
+impl fmt::Display for LockStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} acquisitions, {} contended, {:?} waiting in total, {:?} at most",
+            self.acquisitions, self.contended, self.total_wait, self.max_wait
+        )
+    }
+}
+
+/// Contention of the locks of the index trees since the start of the process or the last
+/// [`reset`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContentionReport {
+    /// Lock serialising the writers of a tree.
+    pub writer: LockStats,
+    /// Read lock taken to snapshot a tree.
+    pub root_read: LockStats,
+    /// Write lock taken to replace the root of a tree.
+    pub root_write: LockStats,
+}
+
+impl fmt::Display for ContentionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "writer: {}", self.writer)?;
+        writeln!(f, "root read: {}", self.root_read)?;
+        writeln!(f, "root write: {}", self.root_write)
+    }
+}
+
+/// Get the contention of the locks of the index trees so far.
+pub fn report() -> ContentionReport {
+    ContentionReport {
+        writer: WRITER.stats(),
+        root_read: ROOT_READ.stats(),
+        root_write: ROOT_WRITE.stats(),
+    }
+}
+
+/// Start counting the contention from scratch.
+pub fn reset() {
+    for counters in [&WRITER, &ROOT_READ, &ROOT_WRITE] {
+        counters.reset();
+    }
+}
+
+static WRITER: Counters = Counters::new();
+static ROOT_READ: Counters = Counters::new();
+static ROOT_WRITE: Counters = Counters::new();
+
+struct Counters {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_nanos: AtomicU64,
+    max_wait_nanos: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+            max_wait_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn of(lock: Lock) -> &'static Self {
+        match lock {
+            Lock::Writer => &WRITER,
+            Lock::RootRead => &ROOT_READ,
+            Lock::RootWrite => &ROOT_WRITE,
+        }
+    }
+
+    /// Count an acquisition, along with the time waited for it if it was contended.
+    fn record(&self, wait: Option<Duration>) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(wait) = wait {
+            let nanos = u64::try_from(wait.as_nanos()).unwrap_or(u64::MAX);
+            self.contended.fetch_add(1, Ordering::Relaxed);
+            self.wait_nanos.fetch_add(nanos, Ordering::Relaxed);
+            self.max_wait_nanos.fetch_max(nanos, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            total_wait: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+            max_wait: Duration::from_nanos(self.max_wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.acquisitions,
+            &self.contended,
+            &self.wait_nanos,
+            &self.max_wait_nanos,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Acquire a mutex, counting the acquisition.
+///
+/// The lock is tried first, so that uncontended acquisitions aren't timed.
+pub(crate) fn lock<T>(lock: Lock, mutex: &Mutex<T>) -> LockResult<MutexGuard<'_, T>> {
+    acquire(lock, mutex.try_lock(), || mutex.lock())
+}
+
+/// Acquire a read-write lock for reading, counting the acquisition.
+pub(crate) fn read<T>(lock: Lock, rw_lock: &RwLock<T>) -> LockResult<RwLockReadGuard<'_, T>> {
+    acquire(lock, rw_lock.try_read(), || rw_lock.read())
+}
+
+/// Acquire a read-write lock for writing, counting the acquisition.
+pub(crate) fn write<T>(lock: Lock, rw_lock: &RwLock<T>) -> LockResult<RwLockWriteGuard<'_, T>> {
+    acquire(lock, rw_lock.try_write(), || rw_lock.write())
+}
+
+fn acquire<G, F>(lock: Lock, attempt: Result<G, TryLockError<G>>, wait: F) -> LockResult<G>
+where
+    F: FnOnce() -> LockResult<G>,
+{
+    let counters = Counters::of(lock);
+
+    match attempt {
+        Ok(guard) => {
+            counters.record(None);
+            Ok(guard)
+        }
+        Err(TryLockError::Poisoned(e)) => {
+            counters.record(None);
+            Err(e)
+        }
+        Err(TryLockError::WouldBlock) => {
+            let start = Instant::now();
+            let guard = wait();
+            counters.record(Some(start.elapsed()));
+            guard
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use super::{lock, report, Lock};
+
+    #[test]
+    fn contended_acquisitions_are_timed() {
+        let mutex = Arc::new(Mutex::new(()));
+        let before = report().writer;
+
+        drop(lock(Lock::Writer, &mutex).unwrap());
+
+        let (locked, wait_locked) = mpsc::channel();
+        let holder = {
+            let mutex = Arc::clone(&mutex);
+            thread::spawn(move || {
+                let _guard = mutex.lock().unwrap();
+                locked.send(()).unwrap();
+                thread::sleep(Duration::from_millis(20));
+            })
+        };
+        wait_locked.recv().unwrap();
+        drop(lock(Lock::Writer, &mutex).unwrap());
+        holder.join().unwrap();
+
+        // Other tests count their acquisitions concurrently, so only the lower bounds are known.
+        let after = report().writer;
+        assert!(after.acquisitions >= before.acquisitions + 2);
+        assert!(after.contended > before.contended);
+        assert!(after.max_wait >= Duration::from_millis(10));
+    }
+}
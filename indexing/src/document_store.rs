@@ -0,0 +1,110 @@
+//! This module defines a [`DocumentStore`] that retains the contents of the indexed files.
+//!
+//! When a document store is set (see [`crate::Indexer::with_document_store`]), the contents of every
+//! file are kept as they were indexed, so that they can be retrieved (see [`crate::Indexer::document`])
+//! even after the file was modified or removed.
+//!
+//! [`MemoryDocumentStore`] keeps the contents compressed in memory, other storages can be plugged in
+//! by implementing [`DocumentStore`] trait.
+
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{storage::MvccAvl, Result};
+
+/// Storage of the contents of the indexed files.
+///
+/// Files are identified by their index keys, see [`crate::PathCase`].
+pub trait DocumentStore: Send + Sync {
+    /// Store the contents of a file, replacing the previously stored ones.
+    fn put(&self, key: &Path, contents: &[u8]) -> Result<()>;
+
+    /// Retrieve the stored contents of a file.
+    fn get(&self, key: &Path) -> Result<Option<Vec<u8>>>;
+
+    /// Remove the stored contents of a file, if any.
+    fn remove(&self, key: &Path) -> Result<()>;
+}
+
+/// Document store that keeps deflate compressed contents in memory.
+pub struct MemoryDocumentStore {
+    level: Compression,
+    documents: MvccAvl<PathBuf, Arc<[u8]>>,
+}
+
+impl MemoryDocumentStore {
+    pub fn new() -> Self {
+        Self {
+            level: Compression::default(),
+            documents: MvccAvl::new(),
+        }
+    }
+
+    /// Set the compression level, from 0 (no compression) to 9 (best compression).
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        self.level = Compression::new(level.min(9));
+        self
+    }
+}
+
+impl Default for MemoryDocumentStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DocumentStore for MemoryDocumentStore {
+    fn put(&self, key: &Path, contents: &[u8]) -> Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), self.level);
+        encoder.write_all(contents)?;
+
+        self.documents
+            .insert(key.to_owned(), encoder.finish()?.into());
+        Ok(())
+    }
+
+    fn get(&self, key: &Path) -> Result<Option<Vec<u8>>> {
+        let compressed = match self.documents.snapshot().get(key) {
+            Some(compressed) => Arc::clone(&compressed),
+            None => return Ok(None),
+        };
+
+        let mut contents = Vec::new();
+        DeflateDecoder::new(&compressed[..]).read_to_end(&mut contents)?;
+        Ok(Some(contents))
+    }
+
+    fn remove(&self, key: &Path) -> Result<()> {
+        self.documents.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{DocumentStore, MemoryDocumentStore};
+
+    #[test]
+    fn contents_round_trip() {
+        let store = MemoryDocumentStore::new().with_compression_level(9);
+        let contents = "line of text\n".repeat(100);
+
+        store.put(Path::new("/a"), contents.as_bytes()).unwrap();
+
+        assert_eq!(
+            store.get(Path::new("/a")).unwrap().as_deref(),
+            Some(contents.as_bytes())
+        );
+
+        store.remove(Path::new("/a")).unwrap();
+
+        assert!(store.get(Path::new("/a")).unwrap().is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! This module counts the file system events the [`crate::LiveIndexer`] didn't act upon.
+//!
+//! Events are either merged into later ones (e.g. the watcher notices a write and reports it once it
+//! settles), or dropped: the watcher lost track of the changes, reported an error, or the indexing
+//! worker gave up on a file after too many attempts. The counts are kept per path, so that a file
+//! missing from the index can be told apart from a bug, see [`crate::LiveIndexer::dropped_events`].
+//!
+//! Counting only takes a short lock and allocates once per new path, so it's always on. Past
+//! [`MAX_TRACKED_PATHS`] paths, the events of the new ones are only counted in total.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Number of paths the events are counted for individually.
+pub const MAX_TRACKED_PATHS: usize = 10_000;
+
+/// Events of a single path the live indexer didn't act upon.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DroppedEvents {
+    pub path: PathBuf,
+    /// Events merged into a later one for the same path.
+    pub merged: u64,
+    /// Events or indexing actions given up on.
+    pub dropped: u64,
+}
+
+/// Counts of the events the live indexer didn't act upon, see [`crate::LiveIndexer::dropped_events`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DropReport {
+    /// Counts per path, sorted by path.
+    pub paths: Vec<DroppedEvents>,
+    /// Times the watcher lost track of the changes and asked for a rescan, for unknown paths.
+    pub rescans: u64,
+    /// Events of the paths past [`MAX_TRACKED_PATHS`], merged or dropped.
+    pub untracked: u64,
+}
+
+impl DropReport {
+    /// Number of the events dropped for the given path or the paths beneath it.
+    pub fn dropped_under(&self, path: &Path) -> u64 {
+        self.paths
+            .iter()
+            .filter(|events| events.path.starts_with(path))
+            .map(|events| events.dropped)
+            .sum()
+    }
+}
+
+impl fmt::Display for DropReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} rescans, {} untracked events",
+            self.rescans, self.untracked
+        )?;
+        for events in &self.paths {
+            write!(
+                f,
+                "\n{}: {} merged, {} dropped",
+                events.path.display(),
+                events.merged,
+                events.dropped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Kind of an event the live indexer didn't act upon.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum DropKind {
+    Merged,
+    Dropped,
+}
+
+/// Shared counters of the events the live indexer didn't act upon.
+#[derive(Default)]
+pub(crate) struct DropCounter {
+    counts: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    /// Merged and dropped events per path.
+    paths: HashMap<PathBuf, (u64, u64)>,
+    rescans: u64,
+    untracked: u64,
+}
+
+impl DropCounter {
+    pub fn record(&self, path: &Path, drop: DropKind) {
+        let mut counts = self.counts.lock().unwrap();
+        if !counts.paths.contains_key(path) {
+            if counts.paths.len() == MAX_TRACKED_PATHS {
+                counts.untracked += 1;
+                return;
+            }
+            counts.paths.insert(path.to_owned(), (0, 0));
+        }
+
+        let count = counts.paths.get_mut(path).unwrap();
+        match drop {
+            DropKind::Merged => count.0 += 1,
+            DropKind::Dropped => count.1 += 1,
+        }
+    }
+
+    pub fn record_rescan(&self) {
+        self.counts.lock().unwrap().rescans += 1;
+    }
+
+    pub fn report(&self) -> DropReport {
+        let counts = self.counts.lock().unwrap();
+        let mut paths = counts
+            .paths
+            .iter()
+            .map(|(path, &(merged, dropped))| DroppedEvents {
+                path: path.clone(),
+                merged,
+                dropped,
+            })
+            .collect::<Vec<_>>();
+        paths.sort_by(|a, b| a.path.cmp(&b.path));
+
+        DropReport {
+            paths,
+            rescans: counts.rescans,
+            untracked: counts.untracked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{DropCounter, DropKind, MAX_TRACKED_PATHS};
+
+    #[test]
+    fn counts_are_kept_per_path_up_to_the_limit() {
+        let counter = DropCounter::default();
+        counter.record(Path::new("/a/x"), DropKind::Merged);
+        counter.record(Path::new("/a/x"), DropKind::Dropped);
+        counter.record(Path::new("/a/y"), DropKind::Dropped);
+        counter.record_rescan();
+
+        let report = counter.report();
+        assert_eq!(report.paths.len(), 2);
+        assert_eq!((report.paths[0].merged, report.paths[0].dropped), (1, 1));
+        assert_eq!(report.dropped_under(Path::new("/a")), 2);
+        assert_eq!(report.rescans, 1);
+
+        for i in 0..MAX_TRACKED_PATHS {
+            counter.record(&Path::new("/b").join(i.to_string()), DropKind::Dropped);
+        }
+        assert_eq!(counter.report().untracked, 2);
+    }
+}
@@ -0,0 +1,139 @@
+//! This module defines the report of a dry run of the indexing pipeline, see
+//! [`crate::Indexer::dry_run_file`].
+//!
+//! A [`DryRun`] lists every token the tokeniser produced for a file, the term each of them was
+//! normalised to and the index it would be stored in, along with the resulting postings. It tells
+//! why a file doesn't match a query without touching the index.
+
+use std::{collections::BTreeMap, fmt, ops::Range, path::PathBuf};
+
+/// Index a term would be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    /// The main index, searched by [`crate::Indexer::query`].
+    Index,
+    /// The index of the tokens dropped by the normalisers, only searched for quoted terms, see
+    /// [`crate::Indexer::with_stop_word_index`].
+    StopWordIndex,
+    /// None, the token is dropped.
+    Dropped,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target = match self {
+            Target::Index => "index",
+            Target::StopWordIndex => "stop word index",
+            Target::Dropped => "dropped",
+        };
+        f.write_str(target)
+    }
+}
+
+/// Token produced by the tokeniser, along with what the normalisers made of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRunToken {
+    /// Text of the token in the file, invalid UTF-8 replaced.
+    pub text: String,
+    /// Byte range of the token in the file.
+    pub range: Range<u64>,
+    /// Term the token was normalised to, or its value when the normaliser dropped it.
+    pub term: String,
+    pub target: Target,
+}
+
+/// Postings of a term that would be written for the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRunPosting {
+    pub term: String,
+    pub target: Target,
+    /// Byte offsets of the term in the file, sorted.
+    pub offsets: Vec<u64>,
+}
+
+/// Outcome of running a file through the indexing pipeline without storing it, see
+/// [`crate::Indexer::dry_run_file`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRun {
+    /// Path the file would be indexed under.
+    pub path: PathBuf,
+    /// Version of the analyser of the file, see [`crate::analyse::Analyser::with_version`].
+    pub analyser_version: u32,
+    /// Whether the offsets would be left out of the postings, see
+    /// [`crate::budget::IndexBudget::with_memory_degradation`].
+    pub membership_only: bool,
+    /// Every token in the order of the file.
+    pub tokens: Vec<DryRunToken>,
+    /// Postings of the kept terms, sorted by index and term.
+    pub postings: Vec<DryRunPosting>,
+}
+
+impl DryRun {
+    /// Build the report from the tokens of a file, grouping the kept ones into postings.
+    pub(crate) fn new(
+        path: PathBuf,
+        analyser_version: u32,
+        membership_only: bool,
+        tokens: Vec<DryRunToken>,
+    ) -> Self {
+        let mut postings = BTreeMap::<(Target, &str), Vec<u64>>::new();
+        for token in tokens
+            .iter()
+            .filter(|token| token.target != Target::Dropped)
+        {
+            postings
+                .entry((token.target, &token.term))
+                .or_default()
+                .push(token.range.start);
+        }
+        let postings = postings
+            .into_iter()
+            .map(|((target, term), offsets)| DryRunPosting {
+                term: term.to_owned(),
+                target,
+                offsets,
+            })
+            .collect();
+
+        Self {
+            path,
+            analyser_version,
+            membership_only,
+            tokens,
+            postings,
+        }
+    }
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} tokens, {} postings, analyser version {}{}",
+            self.path.display(),
+            self.tokens.len(),
+            self.postings.len(),
+            self.analyser_version,
+            if self.membership_only {
+                ", membership only"
+            } else {
+                ""
+            }
+        )?;
+        for token in &self.tokens {
+            writeln!(
+                f,
+                " @{} {:?} -> {:?} ({})",
+                token.range.start, token.text, token.term, token.target
+            )?;
+        }
+        for posting in &self.postings {
+            writeln!(
+                f,
+                " {} ({}): {:?}",
+                posting.term, posting.target, posting.offsets
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, path::PathBuf};
 
 use thiserror::Error;
 
@@ -18,4 +18,74 @@ pub enum Error {
     /// Walkdir errors.
     #[error(transparent)]
     WalkDir(#[from] walkdir::Error),
+
+    /// Invalid glob patterns.
+    #[error(transparent)]
+    Glob(#[from] globset::Error),
+
+    /// Archive reading errors.
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+
+    /// The file changed while it was being indexed, so its entries were discarded.
+    #[error("file was modified while being indexed: {}", .0.display())]
+    ModifiedDuringIndexing(PathBuf),
+
+    /// No watch group has the given name, see [`crate::LiveIndexer::add_group`].
+    #[error("unknown watch group: {0}")]
+    UnknownWatchGroup(String),
+
+    /// The data being imported is not a snapshot exported by [`crate::Indexer::export_snapshot`].
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(&'static str),
+
+    /// The query doesn't follow the expected syntax, e.g. see [`crate::Indexer::query_near`].
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+
+    /// The path is outside of the roots the live indexer is restricted to, see
+    /// [`crate::LiveIndexer::restrict_to`].
+    #[error("path is outside of the allowed roots: {}", .0.display())]
+    PathNotAllowed(PathBuf),
+
+    /// The query was cancelled or ran past its deadline, see [`crate::cancel::CancellationToken`].
+    #[error("query was cancelled or timed out")]
+    Timeout,
+
+    /// No path format has the given name, see [`crate::path_format::PathFormat`].
+    #[error("unknown path format: {0}")]
+    UnknownPathFormat(String),
+
+    /// The path can't be written to a snapshot on this platform as it isn't valid Unicode, see
+    /// [`crate::Indexer::export_snapshot`].
+    #[error("path can't be exported: {}", .0.display())]
+    UnexportablePath(PathBuf),
+}
+
+impl Error {
+    /// Check whether the error is likely to go away if the operation is retried shortly, e.g. the
+    /// file is temporarily locked by another process.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Io(e) => is_transient_io(e),
+            Error::WalkDir(e) => e.io_error().is_some_and(is_transient_io),
+            _ => false,
+        }
+    }
+
+    /// Check whether the error is caused by contents that aren't valid text, e.g. a binary file.
+    pub fn is_invalid_text(&self) -> bool {
+        matches!(self, Error::Io(e) if e.kind() == io::ErrorKind::InvalidData)
+    }
+}
+
+fn is_transient_io(e: &io::Error) -> bool {
+    // Windows reports files locked by other processes as sharing (32) and lock (33) violations.
+    let locked = cfg!(windows) && matches!(e.raw_os_error(), Some(32) | Some(33));
+
+    locked
+        || matches!(
+            e.kind(),
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
 }
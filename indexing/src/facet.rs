@@ -0,0 +1,128 @@
+//! This module defines a [`Facet`] trait that allows to count query hits per category.
+//!
+//! There are several predefined facets:
+//!  * [`Extension`] - categorises files by their extension
+//!  * [`PathTags`] - categorises files by user-defined tags assigned to path prefixes
+//!
+//! Additionally, arbitrary facets can be defined by implementing [`Facet`] trait.
+//!
+//! See [`crate::Indexer::query_faceted`] for how facets are used with an indexer.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+/// Facet assigns a category value to an indexed file.
+pub trait Facet: Send + Sync {
+    /// Name of the facet.
+    fn name(&self) -> &str;
+
+    /// Category of the given file, files without a category are not counted.
+    fn value(&self, path: &Path) -> Option<String>;
+}
+
+/// File extension facet.
+///
+/// Files without an extension are not counted.
+pub struct Extension;
+
+impl Facet for Extension {
+    fn name(&self) -> &str {
+        "extension"
+    }
+
+    fn value(&self, path: &Path) -> Option<String> {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+    }
+}
+
+/// Path tags facet.
+///
+/// Assigns user-defined tags (e.g. project names) to path prefixes. A file is categorised by the tag
+/// of the longest prefix it is located under.
+pub struct PathTags {
+    name: String,
+    tags: Vec<(PathBuf, String)>,
+}
+
+impl PathTags {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Tag all the files under the given path prefix.
+    pub fn with_tag<P>(mut self, prefix: P, tag: &str) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.tags.push((prefix.into(), tag.to_owned()));
+        self
+    }
+}
+
+impl Facet for PathTags {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn value(&self, path: &Path) -> Option<String> {
+        self.tags
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.components().count())
+            .map(|(_, tag)| tag.clone())
+    }
+}
+
+/// Number of hits per category of a single facet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FacetCounts {
+    /// Name of the facet.
+    pub name: String,
+    /// Number of files per category.
+    pub counts: BTreeMap<String, usize>,
+}
+
+/// Result of a faceted query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FacetedResults {
+    /// Set of files the term was found in.
+    pub paths: HashSet<String>,
+    /// Counts for every requested facet, in the order the facets were requested.
+    pub facets: Vec<FacetCounts>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Extension, Facet, PathTags};
+
+    #[test]
+    fn extension_facet() {
+        assert_eq!(Extension.value(Path::new("/a/b.rs")), Some("rs".to_owned()));
+        assert_eq!(Extension.value(Path::new("/a/Makefile")), None);
+    }
+
+    #[test]
+    fn path_tags_prefer_longest_prefix() {
+        let tags = PathTags::new("project")
+            .with_tag("/src", "all")
+            .with_tag("/src/indexing", "indexing");
+
+        assert_eq!(
+            tags.value(Path::new("/src/indexing/lib.rs")),
+            Some("indexing".to_owned())
+        );
+        assert_eq!(
+            tags.value(Path::new("/src/service/main.rs")),
+            Some("all".to_owned())
+        );
+        assert_eq!(tags.value(Path::new("/docs/readme.md")), None);
+    }
+}
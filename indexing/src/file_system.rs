@@ -0,0 +1,451 @@
+//! This module defines the [`FileSystem`] the indexed files are read from.
+//!
+//! By default files are read from the operating system's file system ([`OsFileSystem`]), other
+//! sources (e.g. virtual file systems) can be plugged in by implementing [`FileSystem`] trait, see
+//! [`crate::Indexer::with_file_system`]. [`MemoryFileSystem`] keeps the files in memory, which makes
+//! for hermetic tests.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use walkdir::WalkDir;
+
+use crate::path;
+
+/// Metadata of an entry of a [`FileSystem`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Whether the entry is a regular file (or a symlink to one), as opposed to a directory.
+    pub is_file: bool,
+    /// Size of the file in bytes.
+    pub len: u64,
+    /// Modification time of the entry, if it's known.
+    pub modified: Option<SystemTime>,
+    /// Identity of the file, if the file system has one, see [`FileId`].
+    pub file_id: Option<FileId>,
+}
+
+/// Identity of a file on the operating system's file system, shared by all the hard links to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId {
+    pub device: u64,
+    pub inode: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(Self {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn of(_metadata: &fs::Metadata) -> Option<Self> {
+        None
+    }
+}
+
+/// Order of the entries listed by [`FileSystem::walk_ordered`].
+///
+/// By default the entries are listed depth-first, in the order the file system returns them, which
+/// may differ from one run to the next. Sorting the entries makes the order deterministic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalkOrder {
+    breadth_first: bool,
+    sorted: bool,
+}
+
+impl WalkOrder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// List all the entries of a directory before descending into its subdirectories.
+    pub fn with_breadth_first(mut self) -> Self {
+        self.breadth_first = true;
+        self
+    }
+
+    /// List the entries of a directory sorted by name.
+    pub fn with_sorted_entries(mut self) -> Self {
+        self.sorted = true;
+        self
+    }
+
+    /// Reorder the entries listed by [`FileSystem::walk`], errors are listed after all the entries.
+    fn apply<'a>(
+        self,
+        entries: Box<dyn Iterator<Item = io::Result<PathBuf>> + 'a>,
+    ) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + 'a> {
+        if self == Self::new() {
+            return entries;
+        }
+
+        let (mut paths, mut errors) = (Vec::new(), Vec::new());
+        for entry in entries {
+            match entry {
+                Ok(path) => paths.push(path),
+                Err(e) => errors.push(Err(e)),
+            }
+        }
+
+        // Paths are compared component by component, so sorting them lists every directory right
+        // before its own entries.
+        let depth = |path: &PathBuf| path.components().count();
+        match (self.breadth_first, self.sorted) {
+            (true, true) => paths.sort_by(|a, b| depth(a).cmp(&depth(b)).then_with(|| a.cmp(b))),
+            (true, false) => paths.sort_by_key(depth),
+            (false, _) => paths.sort(),
+        }
+        Box::new(paths.into_iter().map(Ok).chain(errors))
+    }
+}
+
+/// Source of the indexed files.
+pub trait FileSystem: Send + Sync {
+    /// Get the metadata of the entry at the given path.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Read the whole contents of a file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Read the contents of a file starting from the given offset.
+    fn read_from(&self, path: &Path, offset: u64) -> io::Result<Vec<u8>> {
+        let mut contents = self.read(path)?;
+        contents.drain(..(offset as usize).min(contents.len()));
+        Ok(contents)
+    }
+
+    /// Get the canonical, absolute form of an existing path.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// List the entries under the given path recursively, including the path itself.
+    fn walk(&self, root: &Path) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_>;
+
+    /// List the entries under the given path recursively in the given order, see [`WalkOrder`].
+    ///
+    /// By default the entries listed by [`FileSystem::walk`] are collected and reordered, unless the
+    /// order is the default one.
+    fn walk_ordered(
+        &self,
+        root: &Path,
+        order: WalkOrder,
+    ) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_> {
+        order.apply(self.walk(root))
+    }
+
+    /// Check whether the changes to the files can be observed by the OS file watcher.
+    ///
+    /// Changes to the files of other file systems have to be reported to a [`crate::LiveIndexer`]
+    /// by other means.
+    fn is_watchable(&self) -> bool {
+        false
+    }
+}
+
+impl<F> FileSystem for Arc<F>
+where
+    F: FileSystem + ?Sized,
+{
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        (**self).metadata(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        (**self).read(path)
+    }
+
+    fn read_from(&self, path: &Path, offset: u64) -> io::Result<Vec<u8>> {
+        (**self).read_from(path, offset)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        (**self).canonicalize(path)
+    }
+
+    fn walk(&self, root: &Path) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_> {
+        (**self).walk(root)
+    }
+
+    fn walk_ordered(
+        &self,
+        root: &Path,
+        order: WalkOrder,
+    ) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_> {
+        (**self).walk_ordered(root, order)
+    }
+
+    fn is_watchable(&self) -> bool {
+        (**self).is_watchable()
+    }
+}
+
+/// File system of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Metadata {
+            is_file: metadata.is_file(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            file_id: FileId::of(&metadata),
+        })
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_from(&self, path: &Path, offset: u64) -> io::Result<Vec<u8>> {
+        use io::{Read, Seek, SeekFrom};
+
+        let mut file = fs::File::open(path)?;
+        let mut contents = Vec::new();
+
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn walk(&self, root: &Path) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_> {
+        Box::new(WalkDir::new(root).into_iter().map(|entry| {
+            entry
+                .map(|entry| entry.into_path())
+                .map_err(io::Error::from)
+        }))
+    }
+
+    fn walk_ordered(
+        &self,
+        root: &Path,
+        order: WalkOrder,
+    ) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_> {
+        if order != WalkOrder::new().with_sorted_entries() {
+            return order.apply(self.walk(root));
+        }
+
+        // Sorted depth-first walks don't need to be collected first.
+        Box::new(
+            WalkDir::new(root)
+                .sort_by_file_name()
+                .into_iter()
+                .map(|entry| {
+                    entry
+                        .map(|entry| entry.into_path())
+                        .map_err(io::Error::from)
+                }),
+        )
+    }
+
+    fn is_watchable(&self) -> bool {
+        true
+    }
+}
+
+/// File system that keeps the files in memory.
+///
+/// Paths must be absolute, directories exist implicitly as long as there are files under them. There
+/// are no symlinks, so canonicalisation only resolves `.` and `..` components.
+///
+/// Share it through an [`Arc`] to keep modifying the files once it's given to an indexer.
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    files: RwLock<BTreeMap<PathBuf, MemoryFile>>,
+}
+
+struct MemoryFile {
+    contents: Vec<u8>,
+    modified: SystemTime,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create or replace a file with the given contents.
+    pub fn write(&self, path: &Path, contents: &[u8]) {
+        self.files.write().unwrap().insert(
+            path::lexical_clean(path),
+            MemoryFile {
+                contents: contents.to_owned(),
+                modified: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Remove a file, or all the files under a directory.
+    pub fn remove(&self, path: &Path) {
+        let path = path::lexical_clean(path);
+        self.files
+            .write()
+            .unwrap()
+            .retain(|file, _| !file.starts_with(&path));
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such file or directory: {}", path.display()),
+        )
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = path::lexical_clean(path);
+        let files = self.files.read().unwrap();
+
+        if let Some(file) = files.get(&path) {
+            return Ok(Metadata {
+                is_file: true,
+                len: file.contents.len() as u64,
+                modified: Some(file.modified),
+                file_id: None,
+            });
+        }
+
+        files
+            .range(path.clone()..)
+            .next()
+            .filter(|(file, _)| file.starts_with(&path))
+            .map(|(_, file)| Metadata {
+                is_file: false,
+                len: 0,
+                modified: Some(file.modified),
+                file_id: None,
+            })
+            .ok_or_else(|| Self::not_found(&path))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = path::lexical_clean(path);
+
+        self.files
+            .read()
+            .unwrap()
+            .get(&path)
+            .map(|file| file.contents.clone())
+            .ok_or_else(|| Self::not_found(&path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = path::lexical_clean(path);
+        self.metadata(&path).map(|_| path)
+    }
+
+    fn walk(&self, root: &Path) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_> {
+        let root = path::lexical_clean(root);
+        if let Err(e) = self.metadata(&root) {
+            return Box::new(std::iter::once(Err(e)));
+        }
+
+        let files = self
+            .files
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|file| file.starts_with(&root) && **file != root)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Box::new(std::iter::once(Ok(root)).chain(files.into_iter().map(Ok)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{FileSystem, MemoryFileSystem, WalkOrder};
+
+    #[test]
+    fn memory_file_system_has_implicit_directories() {
+        let fs = MemoryFileSystem::new();
+        fs.write(Path::new("/root/dir/a.txt"), b"a");
+        fs.write(Path::new("/root/dir/nested/b.txt"), b"b");
+        fs.write(Path::new("/root/directory.txt"), b"c");
+
+        assert!(!fs.metadata(Path::new("/root/dir")).unwrap().is_file);
+        assert_eq!(fs.metadata(Path::new("/root/dir/a.txt")).unwrap().len, 1);
+        assert_eq!(
+            fs.canonicalize(Path::new("/root/dir/../dir/./a.txt"))
+                .unwrap(),
+            Path::new("/root/dir/a.txt")
+        );
+        assert_eq!(
+            fs.read_from(Path::new("/root/directory.txt"), 1).unwrap(),
+            b""
+        );
+
+        let walked = fs
+            .walk(Path::new("/root/dir"))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            walked,
+            [
+                Path::new("/root/dir"),
+                Path::new("/root/dir/a.txt"),
+                Path::new("/root/dir/nested/b.txt")
+            ]
+        );
+
+        fs.remove(Path::new("/root/dir"));
+
+        assert!(fs.metadata(Path::new("/root/dir")).is_err());
+        assert!(fs.read(Path::new("/root/directory.txt")).is_ok());
+    }
+
+    #[test]
+    fn walks_can_be_breadth_first() {
+        let fs = MemoryFileSystem::new();
+        fs.write(Path::new("/root/b/nested/c.txt"), b"c");
+        fs.write(Path::new("/root/b/b.txt"), b"b");
+        fs.write(Path::new("/root/z.txt"), b"z");
+        fs.write(Path::new("/root/a.txt"), b"a");
+
+        let walked = |order| {
+            fs.walk_ordered(Path::new("/root"), order)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            walked(WalkOrder::new().with_breadth_first().with_sorted_entries()),
+            [
+                Path::new("/root"),
+                Path::new("/root/a.txt"),
+                Path::new("/root/z.txt"),
+                Path::new("/root/b/b.txt"),
+                Path::new("/root/b/nested/c.txt")
+            ]
+        );
+        assert_eq!(
+            walked(WalkOrder::new().with_sorted_entries()),
+            [
+                Path::new("/root"),
+                Path::new("/root/a.txt"),
+                Path::new("/root/b/b.txt"),
+                Path::new("/root/b/nested/c.txt"),
+                Path::new("/root/z.txt")
+            ]
+        );
+    }
+}
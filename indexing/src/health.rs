@@ -0,0 +1,98 @@
+//! This module defines the report of an index consistency check, see [`crate::Indexer::verify`].
+//!
+//! The index keeps both the postings of every term and the term vector of every file, and these are
+//! updated one after the other. A [`HealthReport`] lists every [`Inconsistency`] found between the
+//! two, which points to the index having drifted.
+
+use std::{fmt, path::PathBuf};
+
+/// Result of an index consistency check.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Number of terms with at least one posting.
+    pub terms: usize,
+    /// Number of term-file postings.
+    pub postings: usize,
+    /// Number of files with a term vector.
+    pub files: usize,
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl HealthReport {
+    /// Check whether no inconsistencies were found.
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+
+    /// Merge the report of another part of the index into this one.
+    pub(crate) fn merge(&mut self, other: HealthReport) {
+        self.terms += other.terms;
+        self.postings += other.postings;
+        self.files += other.files;
+        self.inconsistencies.extend(other.inconsistencies);
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} terms, {} postings, {} files, {} inconsistencies",
+            self.terms,
+            self.postings,
+            self.files,
+            self.inconsistencies.len()
+        )?;
+        self.inconsistencies
+            .iter()
+            .try_for_each(|inconsistency| writeln!(f, " - {}", inconsistency))
+    }
+}
+
+/// Mismatch between the postings of a term and the term vector of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The term has a posting for the file, but the file's term vector doesn't have the term.
+    MissingFromTermVector { term: String, path: PathBuf },
+    /// The file's term vector has the term, but the term has no posting for the file.
+    MissingPosting { term: String, path: PathBuf },
+    /// The number of occurrences of the term in the file differs between the two.
+    CountMismatch {
+        term: String,
+        path: PathBuf,
+        postings: u64,
+        term_vector: u64,
+    },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::MissingFromTermVector { term, path } => write!(
+                f,
+                "{:?} is posted for {}, but missing from its term vector",
+                term,
+                path.display()
+            ),
+            Inconsistency::MissingPosting { term, path } => write!(
+                f,
+                "{:?} is in the term vector of {}, but not posted for it",
+                term,
+                path.display()
+            ),
+            Inconsistency::CountMismatch {
+                term,
+                path,
+                postings,
+                term_vector,
+            } => write!(
+                f,
+                "{:?} occurs {} times in {} by postings, but {} times by its term vector",
+                term,
+                postings,
+                path.display(),
+                term_vector
+            ),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! This module implements an opt-in history of the index mutations, see
+//! [`crate::Indexer::with_mutation_history`].
+//!
+//! Every file indexed, updated or removed is recorded as a [`Mutation`] along with the time it took
+//! and the number of tokens written, so that what just happened to the index can be told without
+//! going through the logs. Only a bounded number of the most recent mutations is kept.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::bounded_log::BoundedLog;
+
+/// Kind of a [`Mutation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutationKind {
+    /// File indexed from scratch, see [`crate::Indexer::index_file`].
+    Index,
+    /// Changed lines of a file indexed again, see [`crate::Indexer::update_file`].
+    Update,
+    /// Data appended to a file indexed, see [`crate::Indexer::with_incremental_appends`].
+    Append,
+    /// File removed, see [`crate::Indexer::clear_from_index`].
+    Remove,
+    /// Directory removed, see [`crate::Indexer::clear_dir_from_index`].
+    RemoveDir,
+}
+
+impl fmt::Display for MutationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MutationKind::Index => "index",
+            MutationKind::Update => "update",
+            MutationKind::Append => "append",
+            MutationKind::Remove => "remove",
+            MutationKind::RemoveDir => "remove_dir",
+        })
+    }
+}
+
+/// Single record of the mutation history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutation {
+    pub time: SystemTime,
+    pub kind: MutationKind,
+    pub path: PathBuf,
+    pub duration: Duration,
+    /// Number of tokens written to the index, none for removals.
+    pub tokens: usize,
+    /// Generation the index reached once the mutation was applied, see
+    /// [`crate::Indexer::generation`].
+    pub generation: u64,
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        write!(
+            f,
+            "{}.{:03} {} {}: {} tokens in {:?} (generation {})",
+            time.as_secs(),
+            time.subsec_millis(),
+            self.kind,
+            self.path.display(),
+            self.tokens,
+            self.duration,
+            self.generation
+        )
+    }
+}
+
+/// Bounded history of the most recent [`Mutation`]s.
+pub(crate) struct MutationHistory {
+    mutations: BoundedLog<Mutation>,
+}
+
+impl MutationHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            mutations: BoundedLog::new(capacity),
+        }
+    }
+
+    /// Record a mutation of the given path that started at `start`.
+    pub fn record(
+        &self,
+        kind: MutationKind,
+        path: &Path,
+        start: Instant,
+        tokens: usize,
+        generation: u64,
+    ) {
+        self.mutations.push_with(|| Mutation {
+            time: SystemTime::now(),
+            kind,
+            path: path.to_owned(),
+            duration: start.elapsed(),
+            tokens,
+            generation,
+        });
+    }
+
+    /// All the recorded mutations, from the oldest.
+    pub fn mutations(&self) -> Vec<Mutation> {
+        self.mutations.records()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        thread,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use super::{MutationHistory, MutationKind};
+
+    #[test]
+    fn mutations_record_their_fields() {
+        let history = MutationHistory::new(2);
+        let before = SystemTime::now();
+        let start = Instant::now();
+        thread::sleep(Duration::from_millis(5));
+
+        history.record(MutationKind::Update, Path::new("/a"), start, 7, 4);
+
+        let mutations = history.mutations();
+        let mutation = &mutations[0];
+
+        assert_eq!(mutation.kind, MutationKind::Update);
+        assert_eq!(mutation.path, Path::new("/a"));
+        assert_eq!(mutation.tokens, 7);
+        assert_eq!(mutation.generation, 4);
+        assert!(mutation.duration >= Duration::from_millis(5));
+        assert!(mutation.time >= before && mutation.time <= SystemTime::now());
+        assert!(mutation.to_string().ends_with(&format!(
+            "update /a: 7 tokens in {:?} (generation 4)",
+            mutation.duration
+        )));
+    }
+}
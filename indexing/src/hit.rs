@@ -0,0 +1,232 @@
+//! This module defines the rich results of a query, see [`crate::Indexer::query_hits`].
+//!
+//! Every [`QueryHit`] lists the offsets the query terms occur at in a file, along with their line
+//! and column, so that editors and other tools can jump straight to the matches. Matches can also
+//! carry an excerpt of the text around them, see [`crate::Indexer::with_snippets`].
+//!
+//! [`SymbolMatch`]es locate the occurrences of an identifier for editors, see
+//! [`crate::Indexer::find_symbol`].
+
+use std::{convert::TryFrom, fmt, ops::Range, path::PathBuf, sync::Arc};
+
+/// File found by a query, along with the matches of the query terms in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryHit {
+    /// Path of the file, shared with the index.
+    pub path: Arc<PathBuf>,
+    /// Matches sorted by offset. Terms whose offsets are not kept (see
+    /// [`crate::budget::IndexBudget::with_hot_term_downgrade`]) have no matches.
+    pub matches: Vec<Match>,
+}
+
+/// Occurrence of a query term in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Normalised term, as found in the index.
+    pub term: String,
+    /// Byte offset of the occurrence in the file.
+    pub offset: u64,
+    /// Line and column of the occurrence, `None` if the file couldn't be read or changed since it
+    /// was indexed.
+    pub position: Option<LineColumn>,
+    /// Text around the occurrence, if snippets are enabled and the position is known.
+    pub snippet: Option<Snippet>,
+}
+
+/// Excerpt of the line of a match, see [`crate::Indexer::with_snippets`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    /// Byte range of the match in the text.
+    pub highlight: Range<usize>,
+    /// Whether the line goes on before the text.
+    pub cut_before: bool,
+    /// Whether the line goes on after the text.
+    pub cut_after: bool,
+}
+
+impl fmt::Display for Snippet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}[{}]{}{}",
+            if self.cut_before { "..." } else { "" },
+            &self.text[..self.highlight.start],
+            &self.text[self.highlight.clone()],
+            &self.text[self.highlight.end..],
+            if self.cut_after { "..." } else { "" }
+        )
+    }
+}
+
+/// Occurrence of an identifier found by [`crate::Indexer::find_symbol`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolMatch {
+    pub path: Arc<PathBuf>,
+    /// Normalised term the identifier was found in.
+    pub term: String,
+    /// Byte offset of the term in the file.
+    pub offset: u64,
+    /// Line and column of the term, `None` if the file couldn't be read or changed since it was
+    /// indexed.
+    pub position: Option<LineColumn>,
+    /// Whether the term is the identifier itself, rather than a compound identifier containing it
+    /// (e.g. `parse_query` for `query`).
+    pub exact: bool,
+}
+
+impl fmt::Display for SymbolMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{}:{}", self.path.display(), position),
+            None => write!(f, "{}@{}", self.path.display(), self.offset),
+        }
+    }
+}
+
+/// Position in a text file, both counted from 1.
+///
+/// Columns are counted in characters rather than in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LineColumn {
+    pub line: u64,
+    pub column: u64,
+}
+
+impl fmt::Display for LineColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl fmt::Display for QueryHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        for m in &self.matches {
+            match m.position {
+                Some(position) => write!(f, "\n  {}: {}", position, m.term)?,
+                None => write!(f, "\n  @{}: {}", m.offset, m.term)?,
+            }
+            if let Some(snippet) = &m.snippet {
+                write!(f, " ({})", snippet)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compute the positions of the given sorted byte offsets in the contents, in a single pass.
+///
+/// Offsets past the end of the contents or within a character have no position.
+pub(crate) fn line_columns(contents: &[u8], offsets: &[u64]) -> Vec<Option<LineColumn>> {
+    let mut positions = Vec::with_capacity(offsets.len());
+    let mut current = LineColumn { line: 1, column: 1 };
+    let mut consumed = 0;
+
+    for &offset in offsets {
+        let offset = match usize::try_from(offset) {
+            Ok(offset) if offset <= contents.len() && offset >= consumed => offset,
+            _ => {
+                positions.push(None);
+                continue;
+            }
+        };
+
+        for &byte in &contents[consumed..offset] {
+            if byte == b'\n' {
+                current = LineColumn {
+                    line: current.line + 1,
+                    column: 1,
+                };
+            } else if byte & 0xc0 != 0x80 {
+                // Continuation bytes don't start a character.
+                current.column += 1;
+            }
+        }
+        consumed = offset;
+
+        let within_character = contents.get(offset).is_some_and(|byte| byte & 0xc0 == 0x80);
+        positions.push(if within_character {
+            None
+        } else {
+            Some(current)
+        });
+    }
+    positions
+}
+
+/// Cut an excerpt of up to `window` bytes on either side of the given range out of its line.
+///
+/// The excerpt is widened to whole characters, and invalid UTF-8 is replaced.
+pub(crate) fn snippet(contents: &[u8], matched: Range<usize>, window: usize) -> Snippet {
+    let line_start = contents[..matched.start]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let line_end = contents[matched.end..]
+        .iter()
+        .position(|&byte| byte == b'\n' || byte == b'\r')
+        .map_or(contents.len(), |newline| matched.end + newline);
+
+    let mut start = matched.start.saturating_sub(window).max(line_start);
+    while start > line_start && contents[start] & 0xc0 == 0x80 {
+        start -= 1;
+    }
+    let mut end = (matched.end + window).min(line_end);
+    while end < line_end && contents[end] & 0xc0 == 0x80 {
+        end += 1;
+    }
+
+    let before = String::from_utf8_lossy(&contents[start..matched.start]);
+    let matched_text = String::from_utf8_lossy(&contents[matched.clone()]);
+    let after = String::from_utf8_lossy(&contents[matched.end..end]);
+    let highlight = before.len()..before.len() + matched_text.len();
+
+    Snippet {
+        text: format!("{}{}{}", before, matched_text, after),
+        highlight,
+        cut_before: start > line_start,
+        cut_after: end < line_end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{line_columns, snippet, LineColumn};
+
+    #[test]
+    fn positions_count_lines_and_characters() {
+        let contents = "ab\nçd e\n\nf".as_bytes();
+        let positions = line_columns(contents, &[0, 3, 6, 7, 10, 11, 4]);
+        let position = |line, column| Some(LineColumn { line, column });
+
+        assert_eq!(
+            positions,
+            [
+                position(1, 1),
+                position(2, 1),
+                position(2, 3),
+                position(2, 4),
+                position(4, 1),
+                position(4, 2),
+                None
+            ]
+        );
+        assert_eq!(line_columns(contents, &[12, 4]), [None, None]);
+    }
+
+    #[test]
+    fn snippets_stay_within_the_line() {
+        let contents = "first line\nthe quick brown fox jumps\nlast".as_bytes();
+        let fox = snippet(contents, 27..30, 6);
+
+        assert_eq!(fox.to_string(), "...brown [fox] jumps");
+        assert_eq!(&fox.text[fox.highlight.clone()], "fox");
+
+        let the = snippet(contents, 11..14, 100);
+        assert_eq!(the.to_string(), "[the] quick brown fox jumps");
+
+        let accented = snippet("aéb".as_bytes(), 3..4, 1);
+        assert_eq!(accented.to_string(), "...é[b]");
+    }
+}
@@ -1,8 +1,63 @@
-use std::{collections::HashSet, fs, io::BufReader, path::Path, time::Instant};
+use std::{
+    cmp::{self, Ordering},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::{Read, Write},
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicU64},
+        mpsc, Arc, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use tracing::{instrument, trace};
+use globset::Glob;
+use tracing::{debug, field, instrument, trace, warn, Span};
 
-use crate::{normalise, storage::AvlStorage, tokenise, Result};
+use crate::{
+    analyse::Analyser,
+    archive::{self, ArchiveKind, ArchiveLimits},
+    budget::{
+        BudgetAlert, BudgetMonitor, FrequencyHistogram, IndexBudget, IndexHealth, IndexUsage,
+        Resource,
+    },
+    cancel::CancellationToken,
+    document_store::DocumentStore,
+    dry_run::{self, DryRun, DryRunToken},
+    facet::{Facet, FacetCounts, FacetedResults},
+    file_system::MemoryFileSystem,
+    file_system::{FileId, FileSystem, Metadata, OsFileSystem, WalkOrder},
+    hash,
+    health::HealthReport,
+    history::{Mutation, MutationHistory, MutationKind},
+    hit::{self, Match, QueryHit, Snippet, SymbolMatch},
+    normalise::{self, NormaliserOverrides},
+    path,
+    profiling::{Profiler, ProfilingReport, Stage},
+    query_hook::QueryHook,
+    query_results::QueryResults,
+    query_stats::{
+        QueryExecution, QueryFrequencies, QueryFrequency, QueryMetrics, QueryMetricsRecorder,
+        QueryResponse, SlowQuery, SlowQueryLog,
+    },
+    rank::{self, Explanation, RankedFile, ScoreModifier, ScoredFile, TermScore, TermStats},
+    replica::Replica,
+    sampling::SampleReport,
+    self_test::{self, SelfTestReport},
+    semaphore::{Semaphore, SemaphorePermit},
+    snapshot::{self, ConflictPolicy, ExportedFile, ImportOutcome, MergeResult, Snapshot},
+    storage::{
+        self, AvlStorage, BloomFilter, Duplicates, FileMeta, IndexEntryList, LinePositions,
+        ValueRef,
+    },
+    storage_metrics::StorageMetrics,
+    tap::TokenTap,
+    tokenise, CompactString, Error, PathCase, Result,
+};
+
+/// Maximum number of the terms proposed by [`Indexer::correct`].
+const MAX_CORRECTIONS: usize = 5;
 
 /// Indexer builds a text index over the text files under the provided paths.
 ///
@@ -18,12 +73,87 @@ use crate::{normalise, storage::AvlStorage, tokenise, Result};
 /// to every token in the order specified. There are several predefined normalisers, see [`normalise`]
 /// module documentation for more information.
 ///
+/// The tokeniser and the normalisers make up the default [`Analyser`] of the indexer, files under
+/// specific paths can be analysed differently, see [`Indexer::set_root_analyser`].
+///
+/// Terms that occur in most of the files can be found and treated as stop words at runtime, see
+/// [`Indexer::suggest_stop_words`].
+///
+/// Files with identical contents can be indexed only once, see [`Indexer::with_deduplication`].
+///
+/// Files inside of `.zip` and `.tar` archives can be indexed as well, see [`Indexer::with_archives`].
+///
+/// The contents of the indexed files can be retained, see [`Indexer::with_document_store`].
+///
+/// Files are read from the operating system's file system unless another one is provided, see
+/// [`Indexer::with_file_system`].
+///
+/// The analysis configuration can be changed at runtime, see [`Indexer::update_config`].
+///
+/// The size of the index can be watched against a budget, see [`Indexer::with_budget`].
+///
+/// Point-in-time snapshots of the index can be taken and exported while indexing carries on, see
+/// [`Indexer::snapshot`] and [`Indexer::export_snapshot`], and merged into another index, see
+/// [`Indexer::import_snapshot`]. Read-only replicas of the index can be handed to query threads, see
+/// [`Indexer::replica`].
+///
+/// When several indexes are used, they can be told apart in the logs by their names, see
+/// [`Indexer::with_name`].
+///
+/// Query results can be ranked by relevance, see [`Indexer::query_ranked`], and queries can be
+/// customised by hooks, see [`Indexer::with_query_hook`].
+///
+/// Time spent in every stage of the indexing pipeline can be recorded by enabling profiling with
+/// [`Indexer::with_profiling`], see [`Indexer::profiling_report`]. Slow queries can be recorded by
+/// enabling [`Indexer::with_slow_query_log`], the latency and result count histograms of all the
+/// queries by enabling [`Indexer::with_query_metrics`], and the most frequent query terms by enabling
+/// [`Indexer::with_query_frequencies`]. The number of queries executed at once can be
+/// limited with [`Indexer::with_query_concurrency`]. The most recent files indexed, updated and
+/// removed can be recorded by enabling [`Indexer::with_mutation_history`].
+///
 /// Indexer is thread-safe and can be used from several threads concurrenctly without additional
 /// synchronisation required.
 pub struct Indexer {
+    name: String,
     storage: AvlStorage,
-    tokeniser_factory: Box<dyn tokenise::TokeniserFactory>,
-    token_normalisers: Vec<Box<dyn normalise::TokenNormaliser>>,
+    analyser: RwLock<Arc<Analyser>>,
+    root_analysers: RwLock<Vec<(PathBuf, Arc<Analyser>)>>,
+    stop_words: RwLock<BTreeSet<CompactString>>,
+    /// Number of changes made to the analysers and the stop words so far, so that the replicas
+    /// taken before them can be told apart, see [`Indexer::is_current`].
+    config_version: AtomicU64,
+    /// Paths of the files indexed with an outdated configuration, by their keys.
+    stale: RwLock<BTreeMap<PathBuf, PathBuf>>,
+    /// Held shared by the storage transactions and exclusively while a snapshot is taken, see
+    /// [`Indexer::snapshot`].
+    snapshot_gate: RwLock<()>,
+    profiler: Option<Profiler>,
+    stop_word_storage: Option<AvlStorage>,
+    /// Secondary index of the tokens as produced by the tokeniser, see
+    /// [`Indexer::with_exact_case_index`].
+    exact_case_storage: Option<AvlStorage>,
+    incremental_appends: bool,
+    line_diff_updates: bool,
+    archive_depth: usize,
+    archive_limits: ArchiveLimits,
+    duplicates: Option<Duplicates>,
+    collapse_duplicates: bool,
+    slow_query_log: Option<SlowQueryLog>,
+    mutation_history: Option<MutationHistory>,
+    query_metrics: Option<QueryMetricsRecorder>,
+    query_frequencies: Option<QueryFrequencies>,
+    query_limiter: Option<Semaphore>,
+    document_store: Option<Box<dyn DocumentStore>>,
+    /// Bytes of context around the matches of [`Indexer::query_hits`], see
+    /// [`Indexer::with_snippets`].
+    snippet_window: Option<usize>,
+    file_system: Arc<dyn FileSystem>,
+    walk_order: WalkOrder,
+    score_modifiers: Vec<Box<dyn ScoreModifier>>,
+    query_hooks: Vec<Arc<dyn QueryHook>>,
+    budget: Option<BudgetMonitor>,
+    /// Debug tap of the stored tokens, see [`Indexer::set_token_tap`].
+    token_tap: RwLock<Option<Arc<TokenTap>>>,
 }
 
 impl Indexer {
@@ -32,90 +162,5410 @@ impl Indexer {
     where
         F: 'static + tokenise::TokeniserFactory,
     {
+        Self::with_shared_analyser(Arc::new(Analyser::new(tokeniser_factory)))
+    }
+
+    /// Create a new [`Indexer`] with the given default analyser, shared with another indexer.
+    fn with_shared_analyser(analyser: Arc<Analyser>) -> Self {
         Self {
+            name: String::from("default"),
             storage: AvlStorage::new(),
-            tokeniser_factory: Box::new(tokeniser_factory),
-            token_normalisers: Vec::new(),
+            analyser: RwLock::new(analyser),
+            root_analysers: RwLock::new(Vec::new()),
+            stop_words: RwLock::new(BTreeSet::new()),
+            config_version: AtomicU64::new(0),
+            stale: RwLock::new(BTreeMap::new()),
+            snapshot_gate: RwLock::new(()),
+            profiler: None,
+            stop_word_storage: None,
+            exact_case_storage: None,
+            incremental_appends: false,
+            line_diff_updates: false,
+            archive_depth: 0,
+            archive_limits: ArchiveLimits::default(),
+            duplicates: None,
+            collapse_duplicates: false,
+            slow_query_log: None,
+            mutation_history: None,
+            query_metrics: None,
+            query_frequencies: None,
+            query_limiter: None,
+            document_store: None,
+            snippet_window: None,
+            file_system: Arc::new(OsFileSystem),
+            walk_order: WalkOrder::new(),
+            score_modifiers: Vec::new(),
+            query_hooks: Vec::new(),
+            budget: None,
+            token_tap: RwLock::new(None),
         }
     }
 
+    /// Set the name identifying the index in the logs, `default` by default.
+    ///
+    /// The tracing spans of the indexing operations carry the name of the index along with its
+    /// generation, so that the logs of several indexes can be told apart.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    /// Name identifying the index in the logs, see [`Indexer::with_name`].
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Add a [`normalise::TokenNormaliser`] to be used by this [`Indexer`].
-    pub fn with_normaliser<T>(mut self, normaliser: T) -> Self
+    pub fn with_normaliser<T>(self, normaliser: T) -> Self
     where
         T: 'static + normalise::TokenNormaliser,
     {
-        self.token_normalisers.push(Box::new(normaliser));
+        self.map_analyser(|analyser| analyser.with_normaliser(normaliser))
+    }
+
+    /// Set whether the tokens dropped by the normalisers leave a gap in phrases, see
+    /// [`Analyser::with_position_gaps`].
+    pub fn with_position_gaps(self, enabled: bool) -> Self {
+        self.map_analyser(|analyser| analyser.with_position_gaps(enabled))
+    }
+
+    /// Set the version of the default analysis pipeline, see [`Analyser::with_version`].
+    pub fn with_analyser_version(self, version: u32) -> Self {
+        self.map_analyser(|analyser| analyser.with_version(version))
+    }
+
+    /// Rebuild the default analyser with `f`.
+    fn map_analyser<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(Analyser) -> Analyser,
+    {
+        let analyser = match Arc::try_unwrap(self.analyser.into_inner().unwrap()) {
+            Ok(analyser) => analyser,
+            Err(_) => unreachable!("the default analyser isn't shared while the indexer is built"),
+        };
+
+        self.analyser = RwLock::new(Arc::new(f(analyser)));
         self
     }
 
-    /// Query the index to find a set of files that the given term can be found in.
+    /// Set the case sensitivity of the file paths in the index.
     ///
-    /// The input is normalised the same way as the indexed files.
-    pub fn query(&self, term: &str) -> HashSet<String> {
-        let word = self
-            .normalise(tokenise::Token::new(term.to_owned()))
-            .map_or_else(|| term.to_owned(), |t| t.value);
+    /// By default it matches the platform's native file system, see [`PathCase`].
+    pub fn with_path_case(mut self, path_case: PathCase) -> Self {
+        self.storage.set_path_case(path_case);
+        for storage in self.secondary_storages_mut() {
+            storage.set_path_case(path_case);
+        }
+        if let Some(duplicates) = &mut self.duplicates {
+            duplicates.set_path_case(path_case);
+        }
+        self
+    }
+
+    /// Keep a secondary index of the tokens dropped by the normalisers (e.g. stop words).
+    ///
+    /// Dropped tokens are stored as they were right before the normaliser that dropped them, and are
+    /// only looked up by quoted queries, see [`Indexer::query`].
+    pub fn with_stop_word_index(mut self) -> Self {
+        let mut storage = AvlStorage::new();
+        storage.set_path_case(self.storage.path_case());
+        storage.set_posting_cap(self.storage.posting_cap());
+        storage.set_metrics(self.storage.metrics());
+        self.stop_word_storage = Some(storage);
+        self
+    }
+
+    /// Keep a secondary index of the tokens as produced by the tokeniser, before any normaliser is
+    /// applied.
+    ///
+    /// This allows case-sensitive searches of identifiers (e.g. `HashMap`) with
+    /// [`Indexer::query_exact_case`], while the regular queries stay case-insensitive when the
+    /// normalisers lowercase the terms.
+    pub fn with_exact_case_index(mut self) -> Self {
+        let mut storage = AvlStorage::new();
+        storage.set_path_case(self.storage.path_case());
+        storage.set_posting_cap(self.storage.posting_cap());
+        storage.set_metrics(self.storage.metrics());
+        self.exact_case_storage = Some(storage);
+        self
+    }
+
+    /// Keep only the files a term occurs in, without its offsets, once it occurs more than
+    /// `max_occurrences` times across all the files.
+    ///
+    /// This bounds the size of the postings of extremely frequent terms (e.g. `info` in logs). Such
+    /// terms are still found by queries, see [`Indexer::capped_terms`]. Files that contain them are
+    /// indexed from scratch instead of having only their changed lines replaced, as the offsets of
+    /// the changed occurrences are unknown, see [`Indexer::update_file`]. Appends are unaffected.
+    pub fn with_posting_cap(mut self, max_occurrences: u64) -> Self {
+        self.storage.set_posting_cap(Some(max_occurrences));
+        for storage in self.secondary_storages_mut() {
+            storage.set_posting_cap(Some(max_occurrences));
+        }
+        self
+    }
 
+    /// Keep a bloom filter of the indexed terms, sized for `expected_terms` with the given false
+    /// positive rate.
+    ///
+    /// Queries for terms that were never indexed then return without looking the term up. The terms
+    /// stay in the filter once they are no longer indexed, so the false positive rate grows with the
+    /// churn of the vocabulary. Only the terms kept by the normalisers are filtered, not the ones in
+    /// the stop word index.
+    pub fn with_term_filter(mut self, expected_terms: usize, false_positive_rate: f64) -> Self {
         self.storage
-            .get(&word)
-            .map(|entries| {
-                entries
-                    .iter()
-                    .map(|(path, _)| path.as_path())
-                    .collect::<HashSet<_>>()
-                    .into_iter()
-                    .map(|p| p.to_string_lossy().into_owned())
-                    .collect()
-            })
-            .unwrap_or_default()
+            .set_term_filter(Some(BloomFilter::new(expected_terms, false_positive_rate)));
+        self
     }
 
-    /// Clear the given path from the index.
+    /// Report the insertions, purges and lookups of the index storage to the given sink, see
+    /// [`crate::storage_metrics`].
     ///
-    /// Traverses an index and removes all the entries that refer to the given path.
-    #[instrument(skip(self, path), fields(path = %path.display()))]
-    pub fn clear_from_index(&self, path: &Path) {
-        trace!("removing a file from index");
-        self.storage.purge(path);
+    /// The secondary indexes (see [`Indexer::with_stop_word_index`] and
+    /// [`Indexer::with_exact_case_index`]) report to the same sink.
+    pub fn with_storage_metrics<M>(mut self, metrics: M) -> Self
+    where
+        M: 'static + StorageMetrics,
+    {
+        let metrics = Arc::new(metrics) as Arc<dyn StorageMetrics>;
+
+        self.storage.set_metrics(Some(Arc::clone(&metrics)));
+        for storage in self.secondary_storages_mut() {
+            storage.set_metrics(Some(Arc::clone(&metrics)));
+        }
+        self
     }
 
-    /// Add the given file to the index.
+    /// Terms that reached the posting cap and only keep the files they occur in, in sorted order.
     ///
-    /// `path` has to point to a file, otherwise the function returns without an error immediately.
+    /// Returns `None` if the posting cap wasn't set with [`Indexer::with_posting_cap`].
+    pub fn capped_terms(&self) -> Option<Vec<String>> {
+        self.storage.posting_cap()?;
+
+        Some(
+            iter::once(&self.storage)
+                .chain(&self.stop_word_storage)
+                .flat_map(AvlStorage::capped_terms)
+                .map(|term| term.to_string())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    /// Index only the appended data when a file that has only grown is updated.
     ///
-    /// The input is canonicalised before processing. Pointed to file then parsed by the means of the
-    /// supplied [`tokenise::Tokeniser`] and every token is normalised by the provided set of
-    /// [`normalise::TokenNormaliser`]s before adding in the index.
-    #[instrument(skip(self, path), fields(path = %path.display()))]
-    pub fn index_file(&self, path: &Path) -> Result<()> {
-        if !fs::metadata(path)?.file_type().is_file() {
-            return Ok(());
+    /// Appends are detected by checking that the tail of the previously indexed contents is unchanged,
+    /// without reading the rest of the file, so this is only suitable for append-only files like logs:
+    /// a modification that grows a file but keeps its tail intact would go unnoticed.
+    /// See [`Indexer::update_file`].
+    pub fn with_incremental_appends(mut self) -> Self {
+        self.incremental_appends = true;
+        self
+    }
+
+    /// Only tokenise the lines that changed when a file is updated, instead of the whole file.
+    ///
+    /// This is only suitable for tokenisers that never produce a token spanning several lines, as
+    /// the tokens crossing the boundary of a changed line would be left stale. Unchanged lines are
+    /// found by comparing them with the previously indexed contents kept in the document store (see
+    /// [`Indexer::with_document_store`]), or with the hashes of the previously indexed lines if
+    /// there is none. See [`Indexer::update_file`].
+    pub fn with_line_diff_updates(mut self) -> Self {
+        self.line_diff_updates = true;
+        self
+    }
+
+    /// Index the contents shared by several files (e.g. hard links or copies) only once.
+    ///
+    /// Files are considered duplicates when their contents hash and size match those of an already
+    /// indexed file. A duplicate shares the index entries of the original file and is returned by
+    /// queries alongside it. If the original is modified or removed, its duplicates are indexed on
+    /// their own.
+    pub fn with_deduplication(mut self) -> Self {
+        self.duplicates = Some(Duplicates::new(self.storage.path_case()));
+        self
+    }
+
+    /// Report the duplicates of a file (e.g. hard links to it) as its aliases instead of separate
+    /// query results, enabling deduplication if needed (see [`Indexer::with_deduplication`]).
+    ///
+    /// The file the contents were first indexed under is reported, its duplicates are listed in the
+    /// `aliases` of [`IndexedFile`] and [`RankedFile`], and omitted from the other query results.
+    /// Note that copies of a file are collapsed the same way as links to it.
+    pub fn with_collapsed_duplicates(mut self) -> Self {
+        if self.duplicates.is_none() {
+            self = self.with_deduplication();
         }
+        self.collapse_duplicates = true;
+        self
+    }
 
-        let path = path.canonicalize()?;
-        let mut reader = BufReader::new(fs::File::open(&path)?);
-        let mut words_count = 0;
-        let start = Instant::now();
+    /// Index the files inside of `.zip` and `.tar` archives instead of the archives themselves.
+    ///
+    /// Archive entries are indexed under the archive path followed by `!` and the path of the entry
+    /// within the archive, e.g. `/data/archive.zip!/inner/path.txt`. Archives nested in other
+    /// archives are recursed into up to `max_depth` levels deep, deeper ones are indexed as regular
+    /// files. A `max_depth` of zero disables archive indexing, which is the default.
+    pub fn with_archives(mut self, max_depth: usize) -> Self {
+        self.archive_depth = max_depth;
+        self
+    }
 
-        let mut tokeniser = self.tokeniser_factory.create();
+    /// Limit the data extracted from an archive to `max_entry_size` bytes per entry and
+    /// `max_total_size` bytes for all of its entries, see [`Indexer::with_archives`].
+    ///
+    /// The entries going over the limits are logged and skipped, so that a forged or highly
+    /// compressed archive can't exhaust the memory. The limits default to 64 MiB and 1 GiB.
+    pub fn with_archive_limits(mut self, max_entry_size: u64, max_total_size: u64) -> Self {
+        self.archive_limits = ArchiveLimits {
+            max_entry_size,
+            max_total_size,
+        };
+        self
+    }
 
-        while let Some(token) = tokeniser.read_token(&mut reader)? {
-            words_count += 1;
+    /// Retain the contents of the indexed files in the given store.
+    ///
+    /// The contents are kept as they were indexed until the file is removed from the index, and can
+    /// be retrieved with [`Indexer::document`] even once the file is modified or deleted. See
+    /// [`crate::document_store`] module documentation for the available stores.
+    pub fn with_document_store<S>(mut self, store: S) -> Self
+    where
+        S: 'static + DocumentStore,
+    {
+        self.document_store = Some(Box::new(store));
+        self
+    }
+
+    /// Attach an excerpt of the line of every match to the results of [`Indexer::query_hits`], with
+    /// up to `window` bytes of context on either side of the match, see [`crate::hit::Snippet`].
+    ///
+    /// The files are analysed again to find where the matches end, so snippets are best combined
+    /// with a document store (see [`Indexer::with_document_store`]).
+    pub fn with_snippets(mut self, window: usize) -> Self {
+        self.snippet_window = Some(window);
+        self
+    }
+
+    /// Read the files from the given file system instead of the operating system's one.
+    ///
+    /// See [`crate::file_system`] module documentation for the available file systems.
+    pub fn with_file_system<F>(mut self, file_system: F) -> Self
+    where
+        F: 'static + FileSystem,
+    {
+        self.file_system = Arc::new(file_system);
+        self
+    }
+
+    /// List the files of the watched directories in the given order, see [`WalkOrder`].
+    ///
+    /// A deterministic order (e.g. sorted entries) makes the initial indexing reproducible.
+    pub fn with_walk_order(mut self, walk_order: WalkOrder) -> Self {
+        self.walk_order = walk_order;
+        self
+    }
+
+    /// File system the files are read from, see [`Indexer::with_file_system`].
+    pub(crate) fn file_system(&self) -> &dyn FileSystem {
+        &*self.file_system
+    }
+
+    /// Order the files of the watched directories are listed in, see [`Indexer::with_walk_order`].
+    pub(crate) fn walk_order(&self) -> WalkOrder {
+        self.walk_order
+    }
+
+    /// Get the contents of an indexed file as they were indexed.
+    ///
+    /// Returns `None` if the file is not indexed or the document store wasn't set with
+    /// [`Indexer::with_document_store`].
+    pub fn document(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        match &self.document_store {
+            Some(store) => store.get(
+                &self
+                    .storage
+                    .path_case()
+                    .key(&path::normalise(&*self.file_system, path)?),
+            ),
+            None => Ok(None),
+        }
+    }
+
+    /// Enable recording of per-stage timings of the indexing pipeline.
+    ///
+    /// Profiling is disabled by default as it adds a small overhead to every processed token.
+    pub fn with_profiling(mut self) -> Self {
+        self.profiler = Some(Profiler::new());
+        self
+    }
 
-            if let Some(token) = self.normalise(token) {
-                self.storage.insert(&path, token);
+    /// Record the queries that take at least `threshold` to execute, keeping up to `capacity` most
+    /// recent ones, see [`Indexer::slow_queries`].
+    pub fn with_slow_query_log(mut self, threshold: Duration, capacity: usize) -> Self {
+        self.slow_query_log = Some(SlowQueryLog::new(threshold, capacity));
+        self
+    }
+
+    /// Queries recorded as slow so far, from the oldest.
+    ///
+    /// Returns `None` if the slow query log wasn't enabled with [`Indexer::with_slow_query_log`].
+    pub fn slow_queries(&self) -> Option<Vec<SlowQuery>> {
+        self.slow_query_log.as_ref().map(SlowQueryLog::queries)
+    }
+
+    /// Record the files indexed, updated and removed, keeping up to `capacity` most recent
+    /// mutations, see [`Indexer::recent_mutations`].
+    pub fn with_mutation_history(mut self, capacity: usize) -> Self {
+        self.mutation_history = Some(MutationHistory::new(capacity));
+        self
+    }
+
+    /// Most recent mutations of the index, from the oldest.
+    ///
+    /// Returns `None` if the history wasn't enabled with [`Indexer::with_mutation_history`].
+    pub fn recent_mutations(&self) -> Option<Vec<Mutation>> {
+        self.mutation_history
+            .as_ref()
+            .map(MutationHistory::mutations)
+    }
+
+    /// Enable accumulating histograms of the latency and the number of results of the queries.
+    pub fn with_query_metrics(mut self) -> Self {
+        self.query_metrics = Some(QueryMetricsRecorder::new());
+        self
+    }
+
+    /// Enable counting the queries of every term, along with the time it was last queried, see
+    /// [`Indexer::query_frequencies`].
+    ///
+    /// Terms are counted as given, before they are normalised or rewritten by the query hooks.
+    pub fn with_query_frequencies(mut self) -> Self {
+        self.query_frequencies = Some(QueryFrequencies::new());
+        self
+    }
+
+    /// Limit the number of queries executed at once to `max_queries`, at least one.
+    ///
+    /// Queries above the limit wait for the running ones to finish, so that a burst of expensive
+    /// queries doesn't starve the indexing of CPU. The time spent waiting is reported by the query
+    /// metrics and the slow query log, see [`Indexer::with_query_metrics`].
+    pub fn with_query_concurrency(mut self, max_queries: usize) -> Self {
+        self.query_limiter = Some(Semaphore::new(max_queries.max(1)));
+        self
+    }
+
+    /// Add a [`ScoreModifier`] to be applied to the scores of ranked queries after the already added
+    /// ones, see [`Indexer::query_ranked`].
+    pub fn with_score_modifier<M>(mut self, modifier: M) -> Self
+    where
+        M: 'static + ScoreModifier,
+    {
+        self.score_modifiers.push(Box::new(modifier));
+        self
+    }
+
+    /// Add a [`QueryHook`] to be run after the already added ones.
+    ///
+    /// Hooks rewrite the terms of every query before it's executed, and post-process the results of
+    /// ranked queries, see [`Indexer::query_ranked`].
+    pub fn with_query_hook<H>(mut self, hook: H) -> Self
+    where
+        H: 'static + QueryHook,
+    {
+        self.query_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Histograms of the queries executed so far.
+    ///
+    /// Returns `None` if query metrics weren't enabled with [`Indexer::with_query_metrics`].
+    pub fn query_metrics(&self) -> Option<QueryMetrics> {
+        self.query_metrics
+            .as_ref()
+            .map(QueryMetricsRecorder::metrics)
+    }
+
+    /// Number of times every term was queried, from the most frequent one.
+    ///
+    /// The frequencies are kept in memory, and persisted along with the exported snapshots (see
+    /// [`Indexer::export_snapshot`]) from which they are loaded back by
+    /// [`Indexer::import_snapshot`]. Callers can also persist them on their own and load them back
+    /// with [`Indexer::load_query_frequencies`].
+    ///
+    /// Returns `None` if the frequencies weren't enabled with [`Indexer::with_query_frequencies`].
+    pub fn query_frequencies(&self) -> Option<Vec<QueryFrequency>> {
+        self.query_frequencies
+            .as_ref()
+            .map(QueryFrequencies::frequencies)
+    }
+
+    /// Add previously retrieved query frequencies to the ones counted so far.
+    ///
+    /// Does nothing if the frequencies weren't enabled with [`Indexer::with_query_frequencies`].
+    pub fn load_query_frequencies(&self, frequencies: &[QueryFrequency]) {
+        if let Some(query_frequencies) = &self.query_frequencies {
+            query_frequencies.load(frequencies);
+        }
+    }
+
+    /// Discard the query metrics recorded so far.
+    pub fn reset_query_metrics(&self) {
+        if let Some(query_metrics) = &self.query_metrics {
+            query_metrics.reset();
+        }
+    }
+
+    /// Generation of the index: the number of modifications applied to it so far.
+    pub fn generation(&self) -> u64 {
+        self.storage.generation()
+            + self
+                .stop_word_storage
+                .as_ref()
+                .map_or(0, AvlStorage::generation)
+    }
+
+    /// Aggregated timings of the indexing pipeline stages recorded so far.
+    ///
+    /// Returns `None` if profiling wasn't enabled with [`Indexer::with_profiling`].
+    pub fn profiling_report(&self) -> Option<ProfilingReport> {
+        self.profiler.as_ref().map(Profiler::report)
+    }
+
+    /// Discard the profiling data recorded so far.
+    pub fn reset_profiling(&self) {
+        if let Some(profiler) = &self.profiler {
+            profiler.reset();
+        }
+    }
+
+    /// Watch the size of the index against the given budget, see [`crate::budget`] module
+    /// documentation.
+    pub fn with_budget(mut self, budget: IndexBudget) -> Self {
+        self.budget = Some(BudgetMonitor::new(budget));
+        self
+    }
+
+    /// Count the indexed documents and terms, and estimate the memory they use.
+    ///
+    /// This walks the whole index, including the stop word index if it's enabled.
+    pub fn usage(&self) -> IndexUsage {
+        let mut usage = self.storage.usage();
+
+        for storage in self.secondary_storages() {
+            usage.merge(storage.usage());
+        }
+        usage
+    }
+
+    /// Status of the index with respect to its budget as of the last check, see
+    /// [`Indexer::with_budget`].
+    ///
+    /// The index is always healthy if no budget is set.
+    pub fn health(&self) -> IndexHealth {
+        self.budget
+            .as_ref()
+            .map_or(IndexHealth::Healthy, BudgetMonitor::health)
+    }
+
+    /// Check the usage of the index against its budget right away, regardless of the check
+    /// interval.
+    pub fn check_budget(&self) -> IndexHealth {
+        match &self.budget {
+            Some(budget) => {
+                let raised = budget.check(self.usage());
+                self.degrade(budget, &raised);
+                budget.health()
             }
+            None => IndexHealth::Healthy,
         }
+    }
 
-        trace!(duration = ?start.elapsed(), %words_count, "indexed a file");
+    /// Downgrade the hottest terms if the memory budget was just exceeded, see
+    /// [`IndexBudget::with_hot_term_downgrade`].
+    fn degrade(&self, budget: &BudgetMonitor, raised: &[BudgetAlert]) {
+        let count = match budget.hot_term_downgrade() {
+            Some(count) if raised.iter().any(|a| a.resource == Resource::MemoryBytes) => count,
+            _ => return,
+        };
+
+        let terms = self.storage.downgrade_hottest(count);
+        warn!(
+            ?terms,
+            "dropped the offsets of the hottest terms to save memory"
+        );
+    }
+
+    /// Check whether the files should be indexed without the offsets of their terms, see
+    /// [`IndexBudget::with_memory_degradation`].
+    fn is_memory_degraded(&self) -> bool {
+        self.budget
+            .as_ref()
+            .is_some_and(BudgetMonitor::is_memory_degraded)
+    }
+
+    /// Receive the alerts about the budget being exceeded from now on.
+    ///
+    /// Returns `None` if the budget wasn't set with [`Indexer::with_budget`].
+    pub fn subscribe_budget_alerts(&self) -> Option<mpsc::Receiver<BudgetAlert>> {
+        self.budget.as_ref().map(BudgetMonitor::subscribe)
+    }
+
+    /// Use the given analyser instead of the default one for the files under `root`.
+    ///
+    /// If several roots contain a file, the analyser of the innermost one is used. Files that are
+    /// already indexed are not affected until they are indexed again.
+    pub fn set_root_analyser(&self, root: &Path, analyser: Analyser) -> Result<()> {
+        self.set_shared_root_analyser(root, Arc::new(analyser))
+    }
+
+    /// Use the given analyser for the files under `root` like [`Indexer::set_root_analyser`] does,
+    /// sharing it with other roots.
+    pub(crate) fn set_shared_root_analyser(
+        &self,
+        root: &Path,
+        analyser: Arc<Analyser>,
+    ) -> Result<()> {
+        let root = path::normalise(&*self.file_system, root)?;
+        let mut root_analysers = self.root_analysers.write().unwrap();
 
+        root_analysers.retain(|(path, _)| *path != root);
+        root_analysers.push((root, analyser));
+        self.bump_config_version();
         Ok(())
     }
 
-    /// Normalise the given token by applying sequentially all configured normalisers.
-    fn normalise(&self, token: tokenise::Token) -> Option<tokenise::Token> {
-        self.token_normalisers
+    /// Stop using a dedicated analyser for the files under `root`.
+    pub fn remove_root_analyser(&self, root: &Path) -> Result<()> {
+        let root = path::normalise(&*self.file_system, root)?;
+
+        self.root_analysers
+            .write()
+            .unwrap()
+            .retain(|(path, _)| *path != root);
+        self.bump_config_version();
+        Ok(())
+    }
+
+    /// Query the index to find a set of files that the given term can be found in.
+    ///
+    /// The input is normalised the same way as the indexed files, by every analyser in use (see
+    /// [`Indexer::set_root_analyser`]), and the results are merged. If the normalisers drop the term
+    /// altogether (e.g. it's a stop word), nothing is found, unless the term is quoted (`"the"`) and
+    /// the index keeps the dropped tokens (see [`Indexer::with_stop_word_index`]).
+    /// [`Indexer::is_filtered`] allows to tell such terms apart from the ones that are simply missing.
+    pub fn query(&self, term: &str) -> HashSet<String> {
+        self.query_with_stats(term).paths
+    }
+
+    /// Query the index like [`Indexer::query`] does, producing the files lazily, sorted by path.
+    ///
+    /// The duplicates of a file (see [`Indexer::with_deduplication`]) are produced right after it,
+    /// out of path order, unless they are collapsed into it. The results are read from the index as
+    /// it is when the query is run, see [`QueryResults`]. As their number isn't known upfront, the
+    /// query is recorded in the query statistics with the number of files of its most frequent term.
+    pub fn query_iter(&self, term: &str) -> QueryResults {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let found = self.lookup(&self.rewrite(term));
+        let duplicates = match &self.duplicates {
+            Some(duplicates) if !self.collapse_duplicates => Some(duplicates.snapshot()),
+            _ => None,
+        };
+
+        let results = found.iter().map(|found| found.entries.documents).max();
+        self.record_query(term, start, wait, results.unwrap_or_default());
+        QueryResults::new(
+            found
+                .into_iter()
+                .map(|found| IndexEntryList::clone(&found.entries))
+                .collect(),
+            duplicates,
+        )
+    }
+
+    /// Count the files [`Indexer::query`] finds for the given term, without building their paths.
+    ///
+    /// A term with a single normalised form is counted straight from the number of files kept in its
+    /// index entries, unless the duplicates are reported as separate results (see
+    /// [`Indexer::with_deduplication`]).
+    pub fn count(&self, term: &str) -> usize {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let found = self.lookup(&self.rewrite(term));
+        let expand_duplicates = self.duplicates.is_some() && !self.collapse_duplicates;
+
+        let count = match found.as_slice() {
+            [found] if !expand_duplicates => found.entries.documents,
+            _ => {
+                let mut paths = found
+                    .iter()
+                    .flat_map(|found| found.entries.iter().map(|(path, _)| path.as_path()))
+                    .collect::<Vec<_>>();
+                paths.sort_unstable();
+                paths.dedup();
+
+                let duplicates = if expand_duplicates {
+                    paths
+                        .iter()
+                        .map(|path| self.duplicates_of(path).len())
+                        .sum()
+                } else {
+                    0
+                };
+                paths.len() + duplicates
+            }
+        };
+
+        self.record_query(term, start, wait, count);
+        count
+    }
+
+    /// Check whether [`Indexer::query`] finds any file for the given term, without building their
+    /// paths.
+    pub fn contains(&self, term: &str) -> bool {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let contains = self
+            .lookup(&self.rewrite(term))
+            .iter()
+            .any(|found| found.entries.documents > 0);
+
+        self.record_query(term, start, wait, contains as usize);
+        contains
+    }
+
+    /// Query the index like [`Indexer::query`] does, only finding the files under the given
+    /// directory.
+    ///
+    /// The directory is compared with the paths the files were indexed under, component by
+    /// component, so it should be normalised the same way. The postings are looked up from the first
+    /// path under the directory on, so the query doesn't visit the files outside of it, unless
+    /// duplicates are tracked (see [`Indexer::with_deduplication`]), as the original of a file under
+    /// the directory may be outside of it.
+    pub fn query_in(&self, term: &str, dir: &Path) -> HashSet<String> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let found = self.lookup(&self.rewrite(term));
+        let candidates = if self.duplicates.is_some() {
+            paths_of(&found)
+        } else {
+            let mut paths = found
+                .iter()
+                .flat_map(|found| {
+                    found
+                        .entries
+                        .iter_under(dir)
+                        .map(|(path, _)| path.to_path_buf())
+                })
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths.dedup();
+            paths
+        };
+
+        let paths = candidates
             .iter()
-            .try_fold(token, |token, norm| norm.normalise(token))
+            .flat_map(|path| iter::once(path.clone()).chain(self.results_and_aliases(path).0))
+            .filter(|path| path.starts_with(dir))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<HashSet<_>>();
+
+        self.record_query(term, start, wait, paths.len());
+        paths
+    }
+
+    /// Query the index like [`Indexer::query`] does, returning a page of the files sorted by path.
+    ///
+    /// The order is stable, so that the results can be paged through by increasing the offset, as
+    /// long as the index doesn't change in-between.
+    pub fn query_with_options(&self, term: &str, options: &QueryOptions) -> QueryPage {
+        QueryPage::new(
+            self.query_response(term, &options.normalisers).paths,
+            options,
+        )
+    }
+
+    /// Find the files in which every one of the given terms is found, each of them being treated
+    /// like with [`Indexer::query`].
+    ///
+    /// All the terms are looked up in the same version of the index, so unlike intersecting the
+    /// results of several queries, the files indexed in-between can't be missed or counted in. The
+    /// version is taken without waiting for the indexing in progress (unlike [`Indexer::replica`]),
+    /// so a file being indexed meanwhile may be seen partially indexed, like by [`Indexer::query`].
+    /// Nothing is found if no terms are given.
+    pub fn query_all(&self, terms: &[&str]) -> HashSet<String> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let paths = self.current_replica().query_all(terms);
+
+        self.record_query(&terms.join(" "), start, wait, paths.len());
+        paths
+    }
+
+    /// Find the files in which any of the given terms is found, looking all of them up in the same
+    /// version of the index, see [`Indexer::query_all`].
+    pub fn query_any(&self, terms: &[&str]) -> HashSet<String> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let paths = self.current_replica().query_any(terms);
+
+        self.record_query(&terms.join(" "), start, wait, paths.len());
+        paths
+    }
+
+    /// Query the index like [`Indexer::query`] does, additionally returning the metadata of the
+    /// query execution, see [`QueryExecution`].
+    pub fn query_with_stats(&self, term: &str) -> QueryResponse {
+        self.query_response(term, &NormaliserOverrides::default())
+    }
+
+    /// Query the index like [`Indexer::query_with_stats`] does, with the normalisers overridden.
+    fn query_response(&self, term: &str, overrides: &NormaliserOverrides) -> QueryResponse {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let generation = self.generation();
+        let (normalised, found) = self.lookup_with(&self.rewrite(term), overrides);
+        let paths = paths_of(&found)
+            .iter()
+            .flat_map(|path| iter::once(path.clone()).chain(self.results_and_aliases(path).0))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<HashSet<_>>();
+        let execution = QueryExecution {
+            terms: normalised
+                .into_keys()
+                .map(|(_, term)| term.to_string())
+                .collect(),
+            postings_scanned: found.iter().map(|found| found.entries.iter().count()).sum(),
+            generation,
+            elapsed: start.elapsed(),
+            wait,
+        };
+
+        self.record_query(term, start, wait, paths.len());
+        QueryResponse { paths, execution }
+    }
+
+    /// Query the index to find a set of files that the given term can be found in exactly as spelled,
+    /// without normalising it.
+    ///
+    /// Nothing is found unless the index keeps the tokens as produced by the tokeniser, see
+    /// [`Indexer::with_exact_case_index`].
+    pub fn query_exact_case(&self, term: &str) -> HashSet<String> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let paths = self
+            .exact_case_storage
+            .as_ref()
+            .and_then(|storage| storage.get(term))
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|(path, _)| path.to_path_buf())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|path| iter::once(path.clone()).chain(self.results_and_aliases(path).0))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<HashSet<_>>();
+
+        self.record_query(term, start, wait, paths.len());
+        paths
+    }
+
+    /// Query the index to find a set of files that contain a term starting with the given prefix.
+    ///
+    /// The prefix is normalised with the default analyser like a query term, and kept as is if the
+    /// normalisers drop it. Only the terms with the prefix are visited in the ordered term
+    /// dictionary.
+    pub fn query_prefix(&self, prefix: &str) -> HashSet<String> {
+        let normalised = self.normalise_pattern(prefix);
+        self.query_expanded(prefix, &normalised, |_| true)
+    }
+
+    /// Query the index to find a set of files that contain a term matching the given wildcard
+    /// pattern, where `*` matches any number of characters and `?` a single one, e.g. `ind*x`.
+    ///
+    /// The pattern is normalised like with [`Indexer::query_prefix`], and only the terms starting
+    /// with the literal part before the first wildcard are matched against it.
+    pub fn query_wildcard(&self, pattern: &str) -> HashSet<String> {
+        let normalised = self.normalise_pattern(pattern);
+
+        self.query_expanded(pattern, wildcard_prefix(&normalised), |term| {
+            wildcard_match(&normalised, term)
+        })
+    }
+
+    /// Query the index like [`Indexer::query_wildcard`] does, giving up with [`Error::Timeout`] as
+    /// soon as the token is cancelled, e.g. when a pattern starting with a wildcard scans a large
+    /// term dictionary past its deadline.
+    pub fn query_wildcard_cancellable(
+        &self,
+        pattern: &str,
+        cancellation: &CancellationToken,
+    ) -> Result<HashSet<String>> {
+        let normalised = self.normalise_pattern(pattern);
+
+        self.query_expanded_until(
+            pattern,
+            wildcard_prefix(&normalised),
+            |term| wildcard_match(&normalised, term),
+            cancellation,
+        )
+    }
+
+    /// Propose the indexed terms closest to the given one, e.g. to suggest a correction when it's
+    /// not found.
+    ///
+    /// The term is normalised like with [`Indexer::query_prefix`], and the terms within an edit
+    /// distance of 1 (for terms of up to 4 characters) or 2 (for longer ones) are proposed, see
+    /// [`Indexer::query_fuzzy`]. Up to 5 terms are returned, the closest first, ties broken by the
+    /// number of files the terms occur in and then alphabetically. The term itself is never
+    /// proposed.
+    pub fn correct(&self, term: &str) -> Vec<String> {
+        let normalised = self.normalise_pattern(term);
+        let max_distance = if normalised.chars().count() <= 4 {
+            1
+        } else {
+            2
+        };
+        let mut distance = BoundedLevenshtein::new(&normalised, max_distance);
+
+        let candidates = self.storage.expand_terms("", |candidate| {
+            candidate != normalised && distance.matches(candidate)
+        });
+        let mut corrections = candidates
+            .into_iter()
+            .filter_map(|(term, entries)| {
+                let distance = distance.distance(&term)?;
+                Some((distance, cmp::Reverse(entries.documents), term))
+            })
+            .collect::<Vec<_>>();
+        corrections.sort();
+
+        corrections
+            .into_iter()
+            .take(MAX_CORRECTIONS)
+            .map(|(_, _, term)| term.to_string())
+            .collect()
+    }
+
+    /// Query the index to find a set of files that contain a term within `max_distance` edits
+    /// (insertions, deletions or substitutions of a character) of the given one.
+    ///
+    /// The term is normalised like with [`Indexer::query_prefix`]. The whole term dictionary is
+    /// scanned, but the edit distances are computed incrementally over the prefixes the sorted terms
+    /// share, and the terms are given up on as soon as their prefix is too far from the query term.
+    pub fn query_fuzzy(&self, term: &str, max_distance: usize) -> HashSet<String> {
+        let normalised = self.normalise_pattern(term);
+        let mut distance = BoundedLevenshtein::new(&normalised, max_distance);
+
+        self.query_expanded(term, "", |candidate| distance.matches(candidate))
+    }
+
+    /// Query the index like [`Indexer::query_fuzzy`] does, giving up with [`Error::Timeout`] as soon
+    /// as the token is cancelled.
+    pub fn query_fuzzy_cancellable(
+        &self,
+        term: &str,
+        max_distance: usize,
+        cancellation: &CancellationToken,
+    ) -> Result<HashSet<String>> {
+        let normalised = self.normalise_pattern(term);
+        let mut distance = BoundedLevenshtein::new(&normalised, max_distance);
+
+        self.query_expanded_until(
+            term,
+            "",
+            |candidate| distance.matches(candidate),
+            cancellation,
+        )
+    }
+
+    /// Normalise a prefix or a wildcard pattern with the default analyser, see
+    /// [`Indexer::query_prefix`].
+    fn normalise_pattern(&self, pattern: &str) -> String {
+        match self
+            .default_analyser()
+            .normalise_or_dropped(tokenise::Token::new(pattern))
+        {
+            Ok(token) | Err(token) => token.value.as_str().to_owned(),
+        }
+    }
+
+    /// Find the files containing the terms with the given prefix accepted by `matches`.
+    fn query_expanded<F>(&self, query: &str, prefix: &str, matches: F) -> HashSet<String>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let expanded = self.storage.expand_terms(prefix, matches);
+
+        self.expanded_paths(query, expanded, start, wait)
+    }
+
+    /// Find the files like [`Indexer::query_expanded`] does, giving up once the token is
+    /// cancelled.
+    fn query_expanded_until<F>(
+        &self,
+        query: &str,
+        prefix: &str,
+        matches: F,
+        cancellation: &CancellationToken,
+    ) -> Result<HashSet<String>>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let expanded = self
+            .storage
+            .expand_terms_until(prefix, matches, cancellation)
+            .inspect_err(|_| debug!(query, elapsed = ?start.elapsed(), "query was cancelled"))?;
+
+        Ok(self.expanded_paths(query, expanded, start, wait))
+    }
+
+    /// Collect the files containing the expanded terms of a query, and record the query.
+    fn expanded_paths(
+        &self,
+        query: &str,
+        expanded: Vec<(CompactString, IndexEntryList)>,
+        start: Instant,
+        wait: Duration,
+    ) -> HashSet<String> {
+        let found = expanded
+            .into_iter()
+            .map(|(term, entries)| Found {
+                storage: &self.storage,
+                term,
+                entries: IndexEntryList::clone(&entries),
+            })
+            .collect::<Vec<_>>();
+        trace!(query, terms = found.len(), "expanded the query terms");
+
+        let paths = paths_of(&found)
+            .iter()
+            .flat_map(|path| iter::once(path.clone()).chain(self.results_and_aliases(path).0))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<HashSet<_>>();
+
+        self.record_query(query, start, wait, paths.len());
+        paths
+    }
+
+    /// Query the index like [`Indexer::query`] does, additionally counting the hits per category of
+    /// every given facet.
+    ///
+    /// Facet counts are accumulated while traversing the index entries, see [`crate::facet`] module
+    /// documentation for the predefined facets.
+    pub fn query_faceted(&self, term: &str, facets: &[&dyn Facet]) -> FacetedResults {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let mut results = FacetedResults {
+            paths: HashSet::new(),
+            facets: facets
+                .iter()
+                .map(|facet| FacetCounts {
+                    name: facet.name().to_owned(),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+
+        for path in self.matching_paths(term) {
+            let (duplicates, _) = self.results_and_aliases(&path);
+
+            for path in iter::once(path).chain(duplicates) {
+                for (facet, counts) in facets.iter().zip(results.facets.iter_mut()) {
+                    if let Some(value) = facet.value(&path) {
+                        *counts.counts.entry(value).or_default() += 1;
+                    }
+                }
+
+                results.paths.insert(path.to_string_lossy().into_owned());
+            }
+        }
+
+        self.record_query(term, start, wait, results.paths.len());
+        results
+    }
+
+    /// Query the index like [`Indexer::query`] does, returning the files along with the offsets of
+    /// the matches in them and their lines and columns, sorted by path, see [`QueryHit`].
+    ///
+    /// The lines and columns are computed from the contents of the files, taken from the document
+    /// store if it's set (see [`Indexer::with_document_store`]), and read from the files otherwise.
+    /// The files that changed since they were indexed are reported without them. Duplicates of a
+    /// file share its matches.
+    pub fn query_hits(&self, term: &str) -> Vec<QueryHit> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let mut matches = BTreeMap::<Arc<PathBuf>, Vec<(u64, &CompactString)>>::new();
+        let found = self.lookup(&self.rewrite(term));
+
+        for found in &found {
+            for (path, offsets) in found.entries.iter() {
+                matches
+                    .entry(path.to_arc())
+                    .or_default()
+                    .extend(offsets.iter().map(|(&offset, _)| (offset, &found.term)));
+            }
+        }
+
+        let mut hits = Vec::new();
+        for (path, mut file_matches) in matches {
+            file_matches.sort();
+            let offsets = file_matches
+                .iter()
+                .map(|&(offset, _)| offset)
+                .collect::<Vec<_>>();
+            let contents = self.unchanged_contents(&path);
+            let positions = match &contents {
+                Some(contents) => hit::line_columns(contents, &offsets),
+                None => vec![None; offsets.len()],
+            };
+            let snippets = match (&contents, self.snippet_window) {
+                (Some(contents), Some(window)) => self.snippets(&path, contents, &offsets, window),
+                _ => vec![None; offsets.len()],
+            };
+            let file_matches = file_matches
+                .into_iter()
+                .zip(positions)
+                .zip(snippets)
+                .map(|(((offset, term), position), snippet)| Match {
+                    term: term.to_string(),
+                    offset,
+                    position,
+                    snippet,
+                })
+                .collect::<Vec<_>>();
+
+            for duplicate in self.results_and_aliases(&path).0 {
+                hits.push(QueryHit {
+                    path: Arc::new(duplicate),
+                    matches: file_matches.clone(),
+                });
+            }
+            hits.push(QueryHit {
+                path,
+                matches: file_matches,
+            });
+        }
+
+        hits.sort_by(|a, b| a.path.cmp(&b.path));
+        self.record_query(term, start, wait, hits.len());
+        hits
+    }
+
+    /// Locate the occurrences of an identifier, e.g. to jump to a symbol from an editor.
+    ///
+    /// The name is normalised like with [`Indexer::query_prefix`]. Terms equal to it are exact
+    /// matches, and terms made of several parts separated by punctuation (e.g. `parse_query` or
+    /// `query::parse`), one of which is equal to it, are partial matches. The whole term dictionary
+    /// is scanned. Exact matches are listed first, each group sorted by path and offset.
+    pub fn find_symbol(&self, name: &str) -> Vec<SymbolMatch> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let normalised = self.normalise_pattern(name);
+        let is_part = |term: &str| {
+            term.split(|c: char| !c.is_alphanumeric())
+                .any(|part| part == normalised)
+        };
+        let found = self.storage.expand_terms("", |term| {
+            !normalised.is_empty() && (term == normalised || is_part(term))
+        });
+
+        let mut occurrences = BTreeMap::<Arc<PathBuf>, Vec<(u64, &CompactString)>>::new();
+        for (term, entries) in &found {
+            for (path, offsets) in entries.iter() {
+                occurrences
+                    .entry(path.to_arc())
+                    .or_default()
+                    .extend(offsets.iter().map(|(&offset, _)| (offset, term)));
+            }
+        }
+
+        let mut symbols = Vec::new();
+        for (path, mut file_occurrences) in occurrences {
+            file_occurrences.sort();
+            let offsets = file_occurrences
+                .iter()
+                .map(|&(offset, _)| offset)
+                .collect::<Vec<_>>();
+            let positions = match self.unchanged_contents(&path) {
+                Some(contents) => hit::line_columns(&contents, &offsets),
+                None => vec![None; offsets.len()],
+            };
+            let paths = iter::once(Arc::clone(&path))
+                .chain(self.results_and_aliases(&path).0.into_iter().map(Arc::new))
+                .collect::<Vec<_>>();
+
+            for path in paths {
+                symbols.extend(file_occurrences.iter().zip(&positions).map(
+                    |(&(offset, term), &position)| SymbolMatch {
+                        path: Arc::clone(&path),
+                        term: term.to_string(),
+                        offset,
+                        position,
+                        exact: term.as_str() == normalised,
+                    },
+                ));
+            }
+        }
+
+        symbols.sort_by(|a, b| {
+            b.exact
+                .cmp(&a.exact)
+                .then_with(|| a.path.cmp(&b.path))
+                .then(a.offset.cmp(&b.offset))
+        });
+        self.record_query(name, start, wait, symbols.len());
+        symbols
+    }
+
+    /// Query the index for the files in which two terms occur within `n` tokens of each other,
+    /// written as `left NEAR/n right` (e.g. `error NEAR/5 timeout`).
+    ///
+    /// Both terms are normalised like with [`Indexer::query`], and their positions are taken from the
+    /// index, so the files are neither read nor analysed again. The files indexed without the
+    /// offsets of their terms are left out. The tokens dropped by the normalisers count towards the
+    /// distance, unless they leave no gaps, see [`Indexer::with_position_gaps`].
+    pub fn query_near(&self, query: &str) -> Result<HashSet<String>> {
+        let (left, distance, right) =
+            parse_near(query).ok_or_else(|| Error::InvalidQuery(query.to_owned()))?;
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let right = self.positions_by_path(right);
+        let mut paths = HashSet::new();
+
+        for (path, left) in self.positions_by_path(left) {
+            let right = match right.get(&path) {
+                Some(right) => right,
+                None => continue,
+            };
+
+            if within(&left, right, distance) {
+                paths.extend(
+                    iter::once(path.clone())
+                        .chain(self.results_and_aliases(&path).0)
+                        .map(|path| path.to_string_lossy().into_owned()),
+                );
+            }
+        }
+
+        self.record_query(query, start, wait, paths.len());
+        Ok(paths)
+    }
+
+    /// Query the index like [`Indexer::query`] does, returning the files along with the hashes of
+    /// their contents as they were indexed, sorted by path.
+    pub fn query_files(&self, term: &str) -> Vec<IndexedFile> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let mut files = self
+            .matching_paths(term)
+            .iter()
+            .filter_map(|path| self.storage.meta(path).map(|meta| (path, meta)))
+            .flat_map(|(path, meta)| self.indexed_file(path, meta.content_hash))
+            .collect::<Vec<_>>();
+
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        self.record_query(term, start, wait, files.len());
+        files
+    }
+
+    /// Query the index like [`Indexer::query`] does, returning the files ordered from the most
+    /// relevant one.
+    ///
+    /// Files are scored by tf-idf of the term (see [`crate::rank`]), adjusted by the score modifiers
+    /// (see [`Indexer::with_score_modifier`]). Duplicates of a file share its score, files with equal
+    /// scores are ordered by path. The query hooks can then re-order or annotate the results, see
+    /// [`Indexer::with_query_hook`].
+    pub fn query_ranked(&self, term: &str) -> Vec<RankedFile> {
+        let (_permit, wait) = self.query_permit();
+        let start = Instant::now();
+        let terms = self.rewrite(term);
+        let mut scores = BTreeMap::<PathBuf, f64>::new();
+
+        for found in self.lookup(&terms) {
+            let files = found.entries.documents;
+            let total = found.storage.term_vector_count();
+
+            for (path, _) in found.entries.iter() {
+                let occurrences = found
+                    .storage
+                    .term_vector(path)
+                    .and_then(|terms| terms.get(&found.term).as_deref().copied())
+                    .unwrap_or_default();
+
+                *scores.entry(path.to_path_buf()).or_default() +=
+                    rank::tf_idf(occurrences, files, total);
+            }
+        }
+
+        let mut files = scores
+            .into_iter()
+            .flat_map(|(path, score)| {
+                let modified = self.storage.meta(&path).and_then(|meta| meta.modified);
+                let (duplicates, aliases) = self.results_and_aliases(&path);
+
+                iter::once(path).chain(duplicates).map(move |path| {
+                    let file = ScoredFile {
+                        path: &path,
+                        modified,
+                    };
+                    let score = self
+                        .score_modifiers
+                        .iter()
+                        .fold(score, |score, modifier| modifier.modify(&file, score));
+
+                    RankedFile {
+                        path: path.to_string_lossy().into_owned(),
+                        score,
+                        aliases: aliases.clone(),
+                        annotations: Vec::new(),
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        files.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        for hook in &self.query_hooks {
+            hook.post_process(&terms, &mut files);
+        }
+        self.record_query(term, start, wait, files.len());
+        files
+    }
+
+    /// Break down the score [`Indexer::query_ranked`] gives to a file for the given query, e.g. to
+    /// see how the normalisers and the stop words affect the ranking.
+    ///
+    /// Returns `None` if none of the query terms occur in the file. Duplicates are explained by the
+    /// entries of their original file, and the query hooks are not applied.
+    pub fn explain(&self, query: &str, path: &Path) -> Result<Option<Explanation>> {
+        let path = path::normalise(&*self.file_system, path)?;
+        let original = self
+            .duplicates
+            .as_ref()
+            .and_then(|duplicates| duplicates.original_of(&path))
+            .unwrap_or_else(|| path.clone());
+        let mut terms = Vec::new();
+
+        for found in self.lookup(&self.rewrite(query)) {
+            let occurrences = found
+                .storage
+                .term_vector(&original)
+                .and_then(|terms| terms.get(&found.term).as_deref().copied())
+                .unwrap_or_default();
+            if occurrences == 0 {
+                continue;
+            }
+
+            let files = found.entries.documents;
+            let total_files = found.storage.term_vector_count();
+            let (tf, idf) = (rank::tf(occurrences), rank::idf(files, total_files));
+            terms.push(TermScore {
+                term: found.term.to_string(),
+                occurrences,
+                tf,
+                files,
+                total_files,
+                idf,
+                score: tf * idf,
+            });
+        }
+        if terms.is_empty() {
+            return Ok(None);
+        }
+
+        let base_score = terms.iter().map(|term| term.score).sum();
+        let file = ScoredFile {
+            path: &path,
+            modified: self.storage.meta(&original).and_then(|meta| meta.modified),
+        };
+        let mut score = base_score;
+        let modifiers = self
+            .score_modifiers
+            .iter()
+            .map(|modifier| {
+                score = modifier.modify(&file, score);
+                (modifier.name().to_owned(), score)
+            })
+            .collect();
+
+        Ok(Some(Explanation {
+            path: path.to_string_lossy().into_owned(),
+            terms,
+            base_score,
+            modifiers,
+            score,
+        }))
+    }
+
+    /// Page through the terms of the index in sorted order.
+    ///
+    /// Returns up to `limit` terms that follow `after` (or the first ones if it's `None`), along with
+    /// the number of files each of them was found in. The last term of a page is passed as `after` to
+    /// get the next one. Terms dropped by the normalisers (see [`Indexer::with_stop_word_index`]) are
+    /// not listed.
+    pub fn terms(&self, after: Option<&str>, limit: usize) -> Vec<(String, usize)> {
+        self.storage
+            .terms(after, limit)
+            .into_iter()
+            .map(|(term, files)| (term.to_string(), files))
+            .collect()
+    }
+
+    /// Get the statistics of a term of the index, `None` if it's not found in any file.
+    ///
+    /// The term is looked up as is, like the terms listed by [`Indexer::terms`], rather than
+    /// normalised. This is meant for building custom ranking or analytics on top of the index.
+    pub fn term_stats(&self, term: &str) -> Option<TermStats> {
+        self.storage.term_stats(term)
+    }
+
+    /// Export the distribution of the number of files the terms occur in, see
+    /// [`FrequencyHistogram`].
+    ///
+    /// Like with [`Indexer::terms`], the terms dropped by the normalisers are not counted.
+    pub fn frequency_histogram(&self) -> FrequencyHistogram {
+        FrequencyHistogram::new(
+            self.storage
+                .terms(None, usize::MAX)
+                .into_iter()
+                .map(|(_, files)| files as u64),
+        )
+    }
+
+    /// List all the indexed files along with the hashes of their contents, sorted by path.
+    pub fn indexed_files(&self) -> Vec<IndexedFile> {
+        let mut files = self
+            .storage
+            .files()
+            .into_iter()
+            .flat_map(|(path, meta)| self.indexed_file(&path, meta.content_hash))
+            .collect::<Vec<_>>();
+
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    }
+
+    /// Get the bookkeeping data recorded when the given file was last indexed, `None` if it isn't
+    /// indexed.
+    ///
+    /// Duplicates of a file (see [`Indexer::with_collapsed_duplicates`]) share its metadata.
+    pub fn metadata(&self, path: &Path) -> Result<Option<DocumentMetadata>> {
+        let path = path::normalise(&*self.file_system, path)?;
+        let meta = match self.storage.meta(&self.resolve(&path)?) {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+
+        Ok(Some(DocumentMetadata {
+            path: path.to_string_lossy().into_owned(),
+            size: meta.len,
+            content_hash: meta.content_hash,
+            modified: meta.modified,
+            indexed_at: meta.indexed_at,
+            analyser_version: meta.analyser_version,
+            membership_only: meta.membership_only,
+        }))
+    }
+
+    /// Get the term vector of an indexed file: every term found in it along with its number of
+    /// occurrences, sorted by term.
+    ///
+    /// Returns an empty vector if the file is not indexed.
+    pub fn term_vector(&self, path: &Path) -> Result<Vec<(String, u64)>> {
+        Ok(self
+            .storage
+            .term_vector(&self.resolve(path)?)
+            .map(|terms| {
+                terms
+                    .iter()
+                    .map(|(term, &count)| (term.to_string(), count))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Find the byte ranges to highlight in an indexed file for the given query.
+    ///
+    /// The query is made of whitespace separated terms and quoted phrases (`"quick fox"`), which are
+    /// normalised by the analyser of the file. Every occurrence of a term is highlighted, as well as
+    /// every occurrence of a phrase, as a single range from its first to its last term. Terms dropped
+    /// by the normalisers leave gaps within phrases (see [`Indexer::with_position_gaps`]), and are
+    /// only matched on their own when quoted, like with [`Indexer::query`].
+    ///
+    /// The contents are taken from the document store if it's set (see
+    /// [`Indexer::with_document_store`]), so that the ranges match the indexed contents, and read from
+    /// the file otherwise. The ranges are sorted and don't overlap.
+    pub fn highlight(&self, path: &Path, query: &str) -> Result<Vec<Range<u64>>> {
+        let path = path::normalise(&*self.file_system, path)?;
+        let contents = self.indexed_contents(&path)?;
+        let analyser = self.analyser_for(&path);
+
+        let spans = self.spans(&analyser, &contents)?;
+        let kept = positioned(&spans);
+        let mut ranges = Vec::new();
+
+        for (clause, quoted) in split_query(query) {
+            let terms = self.spans(&analyser, clause.as_bytes())?;
+            let phrase = positioned(&terms);
+
+            match terms.as_slice() {
+                [Err(dropped)] if quoted => ranges.extend(
+                    spans
+                        .iter()
+                        .map(|(Ok(token) | Err(token))| token)
+                        .filter(|token| token.value == dropped.value)
+                        .map(|token| token.source_range()),
+                ),
+                _ if phrase.is_empty() => (),
+                _ => ranges.extend(
+                    kept.windows(phrase.len())
+                        .filter(|window| {
+                            window
+                                .iter()
+                                .zip(&phrase)
+                                .all(|((at, token), (offset, term))| {
+                                    token.value == term.value
+                                        && at - window[0].0 == offset - phrase[0].0
+                                })
+                        })
+                        .map(|window| {
+                            window[0].1.offset..window[phrase.len() - 1].1.source_range().end
+                        }),
+                ),
+            }
+        }
+
+        ranges.sort_by_key(|range| range.start);
+        Ok(ranges.into_iter().fold(Vec::new(), |mut merged, range| {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+            merged
+        }))
+    }
+
+    /// Find up to `limit` indexed files most similar to the given one.
+    ///
+    /// Similarity is the cosine similarity of the files' term frequency vectors, the result is ordered
+    /// from the most similar file and doesn't include the given file itself.
+    pub fn similar(&self, path: &Path, limit: usize) -> Result<Vec<(String, f64)>> {
+        Ok(self
+            .storage
+            .similar(&self.resolve(path)?, limit)
+            .into_iter()
+            .map(|(path, score)| (path.to_string_lossy().into_owned(), score))
+            .collect())
+    }
+
+    /// Check whether the given query term is dropped by the normalisers of every analyser in use, or
+    /// is a stop word added with [`Indexer::add_stop_words`], and thus is never indexed.
+    pub fn is_filtered(&self, term: &str) -> bool {
+        let analyser = self.default_analyser();
+        let root_analysers = self.root_analysers.read().unwrap();
+        let stop_words = self.stop_words.read().unwrap();
+
+        iter::once(analyser.as_ref())
+            .chain(root_analysers.iter().map(|(_, a)| a.as_ref()))
+            .all(|analyser| {
+                analyser
+                    .normalise(tokenise::Token::new(term))
+                    .is_none_or(|token| stop_words.contains(token.value.as_str()))
+            })
+    }
+
+    /// Tokenise up to `sample_size` indexed files, spread evenly across the index, with a candidate
+    /// analyser and compare the resulting dictionary with the terms the same files are currently
+    /// indexed under, see [`crate::sampling`] module documentation.
+    ///
+    /// The index is left untouched. The contents are taken from the document store if it's set (see
+    /// [`Indexer::with_document_store`]), and read from the files otherwise.
+    #[instrument(skip(self, candidate), fields(index = %self.name))]
+    pub fn sample_analyser(&self, candidate: &Analyser, sample_size: usize) -> SampleReport {
+        let paths = self.storage.term_vector_paths();
+        let step = (paths.len() / sample_size.max(1)).max(1);
+        let mut candidate_terms = HashMap::<CompactString, u64>::new();
+        let mut current_terms = HashMap::<CompactString, u64>::new();
+        let (mut files, mut unreadable) = (0, 0);
+
+        for path in paths.iter().step_by(step).take(sample_size) {
+            let spans = self
+                .indexed_contents(path)
+                .and_then(|contents| self.spans(candidate, &contents));
+            let spans = match spans {
+                Ok(spans) => spans,
+                Err(e) => {
+                    debug!(path = %path.display(), error = %e, "failed to read a sampled file");
+                    unreadable += 1;
+                    continue;
+                }
+            };
+
+            for token in spans.into_iter().filter_map(|token| token.ok()) {
+                *candidate_terms.entry(token.value).or_default() += 1;
+            }
+            if let Some(term_vector) = self.storage.term_vector(path) {
+                for (term, count) in term_vector.iter() {
+                    *current_terms.entry(term.clone()).or_default() += count;
+                }
+            }
+            files += 1;
+        }
+
+        SampleReport::new(files, unreadable, candidate_terms, current_terms)
+    }
+
+    /// Suggest the terms found in more than `min_ratio` (from 0 to 1) of the files as stop words.
+    ///
+    /// Every suggested term is returned along with the ratio of the files it is found in, from the
+    /// most frequent one. Only the files that have any terms are counted, so a small corpus is likely
+    /// to produce many suggestions. See [`Indexer::add_stop_words`] to apply them.
+    pub fn suggest_stop_words(&self, min_ratio: f64) -> Vec<(String, f64)> {
+        let files = self.storage.term_vector_count();
+        if files == 0 {
+            return Vec::new();
+        }
+
+        let mut suggested = self
+            .storage
+            .terms(None, usize::MAX)
+            .into_iter()
+            .map(|(term, count)| (term.to_string(), count as f64 / files as f64))
+            .filter(|&(_, ratio)| ratio > min_ratio)
+            .collect::<Vec<_>>();
+
+        suggested.sort_by(|(a_term, a), (b_term, b)| {
+            b.partial_cmp(a)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_term.cmp(b_term))
+        });
+        suggested
+    }
+
+    /// Set the debug tap of the tokens stored from now on, or remove it with `None`, see
+    /// [`TokenTap`].
+    ///
+    /// The tokens are passed to the tap after all the normalisers, as they are stored. Only the
+    /// indexed parts of the files are tapped, e.g. the changed lines of an updated file.
+    pub fn set_token_tap(&self, tap: Option<TokenTap>) {
+        *self.token_tap.write().unwrap() = tap.map(Arc::new);
+    }
+
+    /// Treat the given terms as stop words from now on, pruning their postings from the index.
+    ///
+    /// The terms are expected to be normalised, as returned by [`Indexer::suggest_stop_words`] or
+    /// [`Indexer::terms`]. They are dropped after all the normalisers, and if the index keeps the
+    /// dropped tokens (see [`Indexer::with_stop_word_index`]), their occurrences are moved there, so
+    /// that they are still found by quoted queries.
+    pub fn add_stop_words(&self, terms: &[&str]) {
+        let mut stop_words = self.stop_words.write().unwrap();
+
+        for &term in terms {
+            let term = CompactString::from(term);
+
+            let (membership_only, postings) = self.storage.take_term(&term);
+            for (path, offsets, count) in postings {
+                if let Some(storage) = &self.stop_word_storage {
+                    storage.put_term(&term, &path, &offsets, count, membership_only);
+                }
+            }
+            stop_words.insert(term);
+        }
+        self.bump_config_version();
+    }
+
+    /// Add the terms found in more than `min_ratio` of the files as stop words.
+    ///
+    /// This combines [`Indexer::suggest_stop_words`] and [`Indexer::add_stop_words`], returning the
+    /// added terms.
+    pub fn infer_stop_words(&self, min_ratio: f64) -> Vec<String> {
+        let terms = self
+            .suggest_stop_words(min_ratio)
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect::<Vec<_>>();
+
+        self.add_stop_words(&terms.iter().map(String::as_str).collect::<Vec<_>>());
+        terms
+    }
+
+    /// Stop words added with [`Indexer::add_stop_words`], in sorted order.
+    pub fn stop_words(&self) -> Vec<String> {
+        self.stop_words
+            .read()
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Change the analysis configuration at runtime, without losing the index.
+    ///
+    /// Replacing the default analyser makes the files analysed by it (i.e. not under a root with a
+    /// dedicated analyser, see [`Indexer::set_root_analyser`]) stale. Replacing the stop words prunes
+    /// the postings of the added ones like [`Indexer::add_stop_words`] does, while the occurrences of
+    /// the removed ones are moved back from the stop word index if it's enabled (see
+    /// [`Indexer::with_stop_word_index`]), otherwise all the files become stale.
+    ///
+    /// Stale files stay searchable with their outdated terms until they are indexed again, either by
+    /// the usual means or with [`Indexer::reindex_stale`], which can also be requested with
+    /// [`ConfigUpdate::with_reindex`].
+    pub fn update_config(&self, update: ConfigUpdate) {
+        let mut all_stale = false;
+
+        if let Some(analyser) = update.analyser {
+            *self.analyser.write().unwrap() = Arc::new(analyser);
+            self.bump_config_version();
+            self.mark_stale(|path| self.root_analyser(path).is_none());
+        }
+
+        if let Some(terms) = update.stop_words {
+            let terms = terms
+                .iter()
+                .map(|term| CompactString::from(term.as_str()))
+                .collect::<BTreeSet<_>>();
+            let removed = self
+                .stop_words
+                .read()
+                .unwrap()
+                .difference(&terms)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            self.stop_words
+                .write()
+                .unwrap()
+                .retain(|term| terms.contains(term));
+            self.add_stop_words(&terms.iter().map(CompactString::as_str).collect::<Vec<_>>());
+
+            match &self.stop_word_storage {
+                Some(stop_word_storage) => {
+                    for term in removed {
+                        let (membership_only, postings) = stop_word_storage.take_term(&term);
+                        for (path, offsets, count) in postings {
+                            self.storage
+                                .put_term(&term, &path, &offsets, count, membership_only);
+                        }
+                    }
+                }
+                None => all_stale = !removed.is_empty(),
+            }
+        }
+
+        if all_stale {
+            self.mark_stale(|_| true);
+        }
+
+        if update.reindex {
+            self.reindex_stale();
+        }
+    }
+
+    /// Check whether any of the files was indexed with an outdated configuration, see
+    /// [`Indexer::update_config`].
+    pub fn is_stale(&self) -> bool {
+        !self.stale.read().unwrap().is_empty()
+    }
+
+    /// Paths of the files indexed with an outdated configuration, in sorted order.
+    pub fn stale_files(&self) -> Vec<String> {
+        self.stale
+            .read()
+            .unwrap()
+            .values()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Index the stale files again, returning the number of files indexed.
+    ///
+    /// Stale archive entries are indexed along with their whole archive. Files that fail to index
+    /// are logged and stay stale.
+    pub fn reindex_stale(&self) -> usize {
+        let stale = self
+            .stale
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.reindex(&stale)
+    }
+
+    /// Paths of the files indexed with another version of their analyser than the current one (see
+    /// [`Analyser::with_version`]), in sorted order.
+    ///
+    /// Unlike [`Indexer::stale_files`], this relies on the versions recorded in the index, so it also
+    /// finds the files indexed by a previous run and imported from a snapshot, as long as the analyser
+    /// versions are bumped along with their configuration.
+    pub fn outdated_files(&self) -> Vec<String> {
+        let mut paths = self
+            .outdated_paths()
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        paths.sort();
+        paths
+    }
+
+    /// Index the outdated files (see [`Indexer::outdated_files`]) again, returning the number of
+    /// files indexed.
+    ///
+    /// Outdated archive entries are indexed along with their whole archive. Files that fail to index
+    /// are logged and stay outdated.
+    pub fn reindex_outdated(&self) -> usize {
+        self.reindex(&self.outdated_paths())
+    }
+
+    fn outdated_paths(&self) -> Vec<PathBuf> {
+        self.storage
+            .files()
+            .into_iter()
+            .filter(|(path, meta)| meta.analyser_version != self.analyser_for(path).version())
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Purge and index again the indexed files whose paths match the given glob pattern (e.g.
+    /// `**/*.md`), returning the number of files indexed.
+    ///
+    /// Only the files already in the index are considered, the file system isn't walked. This allows
+    /// to apply a changed analyser (see [`Indexer::set_root_analyser`]) to some of the files without
+    /// indexing all of them again. Matching archive entries are indexed along with their whole
+    /// archive. Files that fail to index are logged and skipped.
+    pub fn reindex_matching(&self, pattern: &str) -> Result<usize> {
+        let pattern = Glob::new(pattern)?.compile_matcher();
+        let mut paths = self.storage.term_vector_paths();
+        if let Some(storage) = &self.stop_word_storage {
+            paths.extend(storage.term_vector_paths());
+        }
+        paths.retain(|path| pattern.is_match(path));
+
+        Ok(self.reindex(&paths))
+    }
+
+    /// Mark the indexed files for which `f` returns true as stale.
+    fn mark_stale<F>(&self, f: F)
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let mut stale = self.stale.write().unwrap();
+
+        for (path, _) in self.storage.files() {
+            if f(&path) {
+                stale.insert(self.storage.path_case().key(&path).into_owned(), path);
+            }
+        }
+    }
+
+    /// Index the given files again, returning the number of files indexed.
+    ///
+    /// Archive entries are indexed again along with their whole archive.
+    fn reindex(&self, paths: &[PathBuf]) -> usize {
+        let sources = paths
+            .iter()
+            .map(|path| self.archive_of(path).unwrap_or_else(|| path.clone()))
+            .collect::<BTreeSet<_>>();
+        let mut indexed = 0;
+
+        for path in sources {
+            match self.index_file(&path) {
+                Ok(()) => indexed += 1,
+                Err(e) => warn!(error = %e, path = %path.display(), "failed to index a file again"),
+            }
+        }
+        indexed
+    }
+
+    /// Take a point-in-time snapshot of the index, see [`crate::snapshot`] module documentation.
+    ///
+    /// Taking a snapshot is cheap, as it shares the index data with the indexer. It waits for the
+    /// storage transactions in progress to complete though, so that no file is seen half-indexed.
+    /// Only the terms kept by the normalisers are included, not the ones in the stop word index.
+    pub fn snapshot(&self) -> Snapshot {
+        let _gate = self.snapshot_gate.write().unwrap();
+        Snapshot::new(self.storage.snapshot())
+    }
+
+    /// Take a read-only replica of the index, see [`crate::replica`] module documentation.
+    ///
+    /// The replica is taken like a snapshot (see [`Indexer::snapshot`]), along with the current
+    /// query configuration.
+    pub fn replica(&self) -> Replica {
+        let _gate = self.snapshot_gate.write().unwrap();
+        self.current_replica()
+    }
+
+    /// Whether the given replica of the index is up to date: neither the index nor its query
+    /// configuration (i.e. the analysers and the stop words) changed since it was taken.
+    pub fn is_current(&self, replica: &Replica) -> bool {
+        replica.generation == self.generation()
+            && replica.config_version == self.config_version.load(atomic::Ordering::Acquire)
+    }
+
+    /// Record a change to the query configuration, made before calling this, so that the replicas
+    /// taken before it aren't current anymore.
+    fn bump_config_version(&self) {
+        self.config_version.fetch_add(1, atomic::Ordering::Release);
+    }
+
+    /// Take a replica of the index without waiting for the indexing in progress, see
+    /// [`Indexer::replica`].
+    ///
+    /// The storages are snapshotted one after the other, so a file being indexed meanwhile may be
+    /// seen partially indexed, like by the queries run against the indexer itself.
+    fn current_replica(&self) -> Replica {
+        Replica {
+            generation: self.generation(),
+            config_version: self.config_version.load(atomic::Ordering::Acquire),
+            storage: self.storage.snapshot(),
+            stop_word_storage: self.stop_word_storage.as_ref().map(AvlStorage::snapshot),
+            duplicates: self.duplicates.as_ref().map(Duplicates::snapshot),
+            collapse_duplicates: self.collapse_duplicates,
+            analysers: self.analysers(),
+            stop_words: self.stop_words.read().unwrap().clone(),
+            query_hooks: self.query_hooks.clone(),
+        }
+    }
+
+    /// Serialise a point-in-time snapshot of the index to `writer`, see [`Snapshot::export`].
+    ///
+    /// The snapshot is taken like with [`Indexer::snapshot`], so indexing carries on while it is
+    /// being serialised. The query frequencies are exported along with it, if they are enabled (see
+    /// [`Indexer::with_query_frequencies`]).
+    pub fn export_snapshot<W: Write>(&self, writer: W) -> Result<()> {
+        let snapshot = self
+            .snapshot()
+            .with_query_frequencies(self.query_frequencies().unwrap_or_default());
+
+        trace!(index = %self.name, generation = snapshot.generation(), "exporting a snapshot");
+        snapshot.export(writer)
+    }
+
+    /// Merge a snapshot serialised with [`Indexer::export_snapshot`] into the index.
+    ///
+    /// The documents of the snapshot that aren't indexed yet are added, the ones that are get
+    /// resolved according to `policy`, see [`MergeResult`] for the outcome of each of them. The
+    /// imported documents keep the terms they were indexed with, whatever the analysis configuration
+    /// of this index. The query frequencies of the snapshot are added to the ones counted so far, see
+    /// [`Indexer::load_query_frequencies`].
+    pub fn import_snapshot<R: Read>(
+        &self,
+        reader: R,
+        policy: ConflictPolicy,
+    ) -> Result<MergeResult> {
+        let export = snapshot::read_export(reader)?;
+
+        self.load_query_frequencies(&export.query_frequencies);
+        Ok(self.merge_exported(export.files, policy))
+    }
+
+    /// Replace the whole index with a snapshot serialised with [`Indexer::export_snapshot`], e.g. to
+    /// roll back a bad bulk ingestion, see [`crate::rotation`].
+    ///
+    /// The snapshot is read in full before the index is touched. The indexed documents that are
+    /// missing from the snapshot are removed, and reported as such after the documents of the
+    /// snapshot, which replace the indexed ones. The query frequencies of the snapshot are left out,
+    /// so that rolling back doesn't count the queries made before the snapshot twice.
+    pub fn restore_snapshot<R: Read>(&self, reader: R) -> Result<MergeResult> {
+        let files = snapshot::read_export(reader)?.files;
+        let path_case = self.storage.path_case();
+        let kept = files
+            .iter()
+            .map(|file| path_case.key(&file.path).into_owned())
+            .collect::<HashSet<_>>();
+        let removed = self
+            .storage
+            .files()
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| !kept.contains(path_case.key(path).as_ref()))
+            .collect::<Vec<_>>();
+
+        for path in &removed {
+            self.purge(path);
+        }
+        let mut result = self.merge_exported(files, ConflictPolicy::Replace);
+        result.documents.extend(
+            removed
+                .into_iter()
+                .map(|path| (path, ImportOutcome::Removed)),
+        );
+        Ok(result)
+    }
+
+    /// Merge the documents of an exported snapshot into the index, see [`Indexer::import_snapshot`].
+    fn merge_exported(&self, files: Vec<ExportedFile>, policy: ConflictPolicy) -> MergeResult {
+        let mut result = MergeResult::default();
+
+        for ExportedFile { path, meta, terms } in files {
+            let outcome = match (self.storage.meta(&path), policy) {
+                (None, _) => ImportOutcome::Added,
+                (Some(_), ConflictPolicy::Replace) => ImportOutcome::Replaced,
+                (Some(existing), ConflictPolicy::KeepNewest)
+                    if meta.indexed_at > existing.indexed_at =>
+                {
+                    ImportOutcome::Replaced
+                }
+                (Some(_), _) => ImportOutcome::Kept,
+            };
+
+            if outcome == ImportOutcome::Replaced {
+                self.purge(&path);
+            }
+            if outcome != ImportOutcome::Kept {
+                self.transaction("import", &path, || {
+                    for term in &terms {
+                        self.storage.put_term(
+                            &term.term,
+                            &path,
+                            &term.postings,
+                            term.count,
+                            term.membership_only,
+                        );
+                    }
+                    self.storage.set_meta(&path, meta);
+                });
+            }
+
+            trace!(index = %self.name, path = %path.display(), %outcome, "imported a document");
+            result.documents.push((path, outcome));
+        }
+        result
+    }
+
+    /// Check the consistency of the index.
+    ///
+    /// The postings of every term are cross-checked against the term vector of every file, see
+    /// [`crate::health`] module documentation. The check is only exact when nothing is being indexed
+    /// concurrently.
+    pub fn verify(&self) -> HealthReport {
+        let mut report = self.storage.verify();
+
+        for storage in self.secondary_storages() {
+            report.merge(storage.verify());
+        }
+        report
+    }
+
+    /// Index a small built-in corpus into a scratch index and check the results of canned queries
+    /// against it, see [`crate::self_test`] module documentation.
+    ///
+    /// The scratch index shares the default analyser and the stop words of this one, which is left
+    /// untouched, so that deployments and custom analyser configurations can be validated.
+    #[instrument(skip(self), fields(index = %self.name))]
+    pub fn self_test(&self) -> SelfTestReport {
+        let file_system = MemoryFileSystem::new();
+        for (path, text) in self_test::CORPUS {
+            file_system.write(Path::new(path), text.as_bytes());
+        }
+
+        let scratch = Self::with_shared_analyser(self.default_analyser())
+            .with_name(&format!("{}-selftest", self.name))
+            .with_file_system(file_system);
+        *scratch.stop_words.write().unwrap() = self.stop_words.read().unwrap().clone();
+
+        let mut report = SelfTestReport::default();
+        for (path, _) in self_test::CORPUS {
+            let result = scratch.index_file(Path::new(path));
+            report.check(
+                format!("index {}", path),
+                result.err().map(|e| e.to_string()),
+            );
+        }
+        for (term, expected) in self_test::QUERIES {
+            let failure = unexpected_paths(scratch.query(term), expected);
+            report.check(format!("query {:?}", term), failure);
+        }
+
+        let (removed, term) = self_test::REMOVED;
+        let failure = match scratch.clear_from_index(Path::new(removed)) {
+            Ok(()) => unexpected_paths(scratch.query(term), self_test::REMAINING),
+            Err(e) => Some(e.to_string()),
+        };
+        report.check(format!("remove {}", removed), failure);
+
+        let health = scratch.verify();
+        report.check(
+            String::from("verify"),
+            (!health.is_consistent())
+                .then(|| format!("{} inconsistencies", health.inconsistencies.len())),
+        );
+
+        if !report.passed() {
+            warn!(%report, "self-test failed");
+        }
+        report
+    }
+
+    /// Clear the given path from the index.
+    ///
+    /// Traverses an index and removes all the entries that refer to the given path.
+    ///
+    /// The path doesn't have to exist anymore, it is normalised the same way as it was when indexed.
+    #[instrument(
+        skip(self, path),
+        fields(
+            index = %self.name,
+            generation = self.storage.generation(),
+            path = %path.display(),
+            doc = field::Empty,
+        )
+    )]
+    pub fn clear_from_index(&self, path: &Path) -> Result<()> {
+        trace!("removing a file from index");
+        let path = path::normalise(&*self.file_system, path)?;
+        let start = Instant::now();
+
+        self.record_doc(&path);
+        self.purge(&path);
+        self.record_mutation(MutationKind::Remove, &path, start, 0);
+        Ok(())
+    }
+
+    /// Clear the given directory and all the files beneath it from the index.
+    ///
+    /// The set of files to remove is taken from the index itself rather than from the file system,
+    /// so this works for directories that have already been removed.
+    #[instrument(
+        skip(self, path),
+        fields(
+            index = %self.name,
+            generation = self.storage.generation(),
+            path = %path.display(),
+        )
+    )]
+    pub fn clear_dir_from_index(&self, path: &Path) -> Result<()> {
+        trace!("removing a directory from index");
+        let path = path::normalise(&*self.file_system, path)?;
+        let start = Instant::now();
+
+        self.purge_dir(&path);
+        if self.archive_kind(&path).is_some() {
+            self.purge_dir(&archive::entries_root(&path));
+        }
+        self.record_mutation(MutationKind::RemoveDir, &path, start, 0);
+        Ok(())
+    }
+
+    /// Add the given file to the index.
+    ///
+    /// `path` has to point to a file, otherwise the function returns without an error immediately.
+    ///
+    /// The input is normalised (canonicalised) before processing and any entries previously indexed for
+    /// this file are replaced. Pointed to file then parsed by the means of the supplied
+    /// [`tokenise::Tokeniser`] and every token is normalised by the provided set of
+    /// [`normalise::TokenNormaliser`]s before adding in the index.
+    ///
+    /// If archive indexing is enabled (see [`Indexer::with_archives`]) and the file is an archive, its
+    /// entries are indexed instead.
+    ///
+    /// If the file's size or modification time changed while it was being read, its entries are
+    /// removed from the index and [`Error::ModifiedDuringIndexing`] is returned, so that the caller
+    /// can index it again once it settles.
+    #[instrument(
+        skip(self, path),
+        fields(
+            index = %self.name,
+            generation = self.storage.generation(),
+            path = %path.display(),
+            doc = field::Empty,
+        )
+    )]
+    pub fn index_file(&self, path: &Path) -> Result<()> {
+        let metadata = self.file_system.metadata(path)?;
+        if !metadata.is_file {
+            return Ok(());
+        }
+
+        let stamp_before = FileStamp::new(&metadata);
+        let path = path::normalise(&*self.file_system, path)?;
+        self.record_doc(&path);
+        self.purge(&path);
+        let start = Instant::now();
+
+        let contents = self.timed(Stage::Read, || self.file_system.read(&path))?;
+        let words_count = match self.archive_kind(&path) {
+            Some(kind) => self.index_archive(&path, kind, &contents, 1, stamp_before.modified)?,
+            None if self.deduplicate(&path, &contents, metadata.file_id) => {
+                self.keep_document(&path, &contents);
+                0
+            }
+            None => {
+                let membership_only = self.is_memory_degraded();
+                self.keep_document(&path, &contents);
+                self.transaction("put", &path, || {
+                    let tap = self.token_tap_for(&path);
+                    let (words_count, positions) =
+                        self.analyse(&path, &contents, 0, 0, |token| {
+                            self.store(&path, token, membership_only, tap.as_deref())
+                        })?;
+                    self.storage.set_meta(
+                        &path,
+                        FileMeta::new(&contents, &positions)
+                            .with_modified(stamp_before.modified)
+                            .with_membership_only(membership_only)
+                            .with_analyser_version(self.analyser_for(&path).version()),
+                    );
+                    Ok::<_, Error>(words_count)
+                })?
+            }
+        };
+
+        self.check_unmodified(&path, stamp_before)?;
+        trace!(duration = ?start.elapsed(), %words_count, "indexed a file");
+        self.record_mutation(MutationKind::Index, &path, start, words_count);
+
+        Ok(())
+    }
+
+    /// Run the given file through the indexing pipeline without storing it, reporting the tokens and
+    /// the postings that would be written, see [`DryRun`].
+    ///
+    /// The file is analysed like a text file by the analyser of its path, even if it's an archive
+    /// (see [`Indexer::with_archives`]) or a duplicate of an indexed file (see
+    /// [`Indexer::with_deduplication`]). Neither the index nor the statistics are touched.
+    pub fn dry_run_file(&self, path: &Path) -> Result<DryRun> {
+        let path = path::normalise(&*self.file_system, path)?;
+        let contents = self.file_system.read(&path)?;
+        let analyser = self.analyser_for(&path);
+        let stop_words = self.stop_words.read().unwrap();
+        let mut reader = contents.as_slice();
+        let mut tokeniser = analyser.tokeniser();
+        let mut tokens = Vec::new();
+
+        while let Some(token) = tokeniser.read_token(&mut reader)? {
+            let range = token.source_range();
+            let (term, target) = match analyser.normalise_or_dropped(token) {
+                Ok(token) if !stop_words.contains(token.value.as_str()) => {
+                    (token.value, dry_run::Target::Index)
+                }
+                Ok(token) | Err(token) if self.stop_word_storage.is_some() => {
+                    (token.value, dry_run::Target::StopWordIndex)
+                }
+                Ok(token) | Err(token) => (token.value, dry_run::Target::Dropped),
+            };
+
+            tokens.push(DryRunToken {
+                text: String::from_utf8_lossy(
+                    contents
+                        .get(range.start as usize..range.end as usize)
+                        .unwrap_or_default(),
+                )
+                .into_owned(),
+                range,
+                term: term.to_string(),
+                target,
+            });
+        }
+
+        Ok(DryRun::new(
+            path,
+            analyser.version(),
+            self.is_memory_degraded(),
+            tokens,
+        ))
+    }
+
+    /// Bring the index of the given file up to date after it was modified.
+    ///
+    /// The file is indexed again like with [`Indexer::index_file`], unless line diff updates are
+    /// enabled (see [`Indexer::with_line_diff_updates`]). In that case only the lines that changed
+    /// since the file was indexed are tokenised: the new contents are compared line by line with the
+    /// previously indexed ones, and the entries of the lines in between the unchanged beginning and
+    /// end of the file are replaced.
+    ///
+    /// If incremental appends are enabled (see [`Indexer::with_incremental_appends`]) and the file
+    /// has only grown since it was indexed, only the appended data is read.
+    ///
+    /// Files that aren't indexed yet, archives and files that have duplicates (see
+    /// [`Indexer::with_deduplication`]) are indexed like with [`Indexer::index_file`]. So are the files
+    /// that contain terms that reached the posting cap (see [`Indexer::with_posting_cap`]), unless
+    /// they were only appended to.
+    #[instrument(
+        skip(self, path),
+        fields(
+            index = %self.name,
+            generation = self.storage.generation(),
+            path = %path.display(),
+            doc = field::Empty,
+        )
+    )]
+    pub fn update_file(&self, path: &Path) -> Result<()> {
+        let metadata = self.file_system.metadata(path)?;
+        if !metadata.is_file {
+            return Ok(());
+        }
+
+        let stamp_before = FileStamp::new(&metadata);
+        let path = path::normalise(&*self.file_system, path)?;
+        self.record_doc(&path);
+        let meta = match self.storage.meta(&path) {
+            Some(meta)
+                if self.archive_kind(&path).is_none()
+                    && self.duplicates_of(&path).is_empty()
+                    && !self.is_stale_file(&path)
+                    && meta.analyser_version == self.analyser_for(&path).version()
+                    && !meta.membership_only
+                    && !self.is_memory_degraded() =>
+            {
+                meta
+            }
+            _ => return self.index_file(&path),
+        };
+        let start = Instant::now();
+
+        if self.incremental_appends && meta.len <= metadata.len {
+            if let Some(words_count) = self.index_append(&path, &meta, stamp_before.modified)? {
+                self.check_unmodified(&path, stamp_before)?;
+                trace!(duration = ?start.elapsed(), %words_count, "indexed an append to a file");
+                self.record_mutation(MutationKind::Append, &path, start, words_count);
+
+                return Ok(());
+            }
+        }
+
+        if !self.line_diff_updates || self.has_capped_terms(&path) {
+            return self.index_file(&path);
+        }
+
+        let contents = self.timed(Stage::Read, || self.file_system.read(&path))?;
+        let words_count =
+            self.index_changed_lines(&path, &meta, &contents, stamp_before.modified)?;
+
+        self.check_unmodified(&path, stamp_before)?;
+        trace!(duration = ?start.elapsed(), %words_count, "indexed changes to a file");
+        self.record_mutation(MutationKind::Update, &path, start, words_count);
+
+        Ok(())
+    }
+
+    /// Index the data appended to the file since it was indexed.
+    ///
+    /// Returns `None` without doing anything if the previously indexed contents were modified.
+    fn index_append(
+        &self,
+        path: &Path,
+        meta: &FileMeta,
+        modified: Option<SystemTime>,
+    ) -> Result<Option<usize>> {
+        // The last indexed line is included, as its tokens might continue in the appended data.
+        let check_start = meta.check_start();
+        let contents = self.timed(Stage::Read, || {
+            self.file_system.read_from(path, check_start)
+        })?;
+        let indexed_len = (meta.len - check_start) as usize;
+        if contents.len() < indexed_len || hash::fnv1a(&contents[..indexed_len]) != meta.tail_hash {
+            trace!("file was not only appended to");
+            return Ok(None);
+        }
+
+        let last_line = (meta.last_line - check_start) as usize;
+        let position = meta
+            .lines
+            .get(&meta.last_line)
+            .map_or(meta.end_position, |line| line.position);
+        let words_count = self.transaction("append", path, || {
+            self.analyse(
+                path,
+                &contents[last_line..indexed_len],
+                meta.last_line,
+                position,
+                |token| self.unstore(path, token),
+            )?;
+            let tap = self.token_tap_for(path);
+            let (words_count, positions) = self.analyse(
+                path,
+                &contents[last_line..],
+                meta.last_line,
+                position,
+                |token| self.store(path, token, false, tap.as_deref()),
+            )?;
+            self.storage.set_meta(
+                path,
+                meta.appended(&contents, check_start, &positions)
+                    .with_modified(modified),
+            );
+            Ok::<_, Error>(words_count)
+        })?;
+        self.keep_appended_document(path, meta.len, check_start, &contents);
+        // The hash of the whole contents isn't known, so no duplicates of the file can be detected.
+        if let Some(duplicates) = &self.duplicates {
+            duplicates.remove(path);
+        }
+
+        Ok(Some(words_count))
+    }
+
+    /// Register the contents of a file for deduplication, if it is enabled.
+    ///
+    /// Returns `true` if the same contents are already indexed under another path, in which case the
+    /// file is registered as its duplicate and must not be indexed. A hard link to an original file,
+    /// i.e. a file with the same `file_id`, is a duplicate of it. Otherwise the hash of the contents
+    /// only points to a candidate original, which is a duplicate if its contents are the same.
+    fn deduplicate(&self, path: &Path, contents: &[u8], file_id: Option<FileId>) -> bool {
+        let duplicates = match &self.duplicates {
+            // Empty files have no entries to share.
+            Some(duplicates) if !contents.is_empty() => duplicates,
+            _ => return false,
+        };
+
+        let hash = hash::fnv1a(contents);
+        let same_len = |original: &PathBuf| {
+            self.storage
+                .meta(original)
+                .is_some_and(|meta| meta.len == contents.len() as u64)
+        };
+        let original = file_id
+            .and_then(|file_id| duplicates.original_by_file(file_id))
+            .filter(same_len)
+            .or_else(|| {
+                duplicates
+                    .original(hash)
+                    .filter(|original| same_len(original) && self.has_contents(original, contents))
+            });
+
+        match original {
+            Some(original) => {
+                trace!(original = %original.display(), "file is a duplicate");
+                duplicates.insert_duplicate(path, &original);
+                true
+            }
+            None => {
+                duplicates.insert_original(path, hash, file_id);
+                false
+            }
+        }
+    }
+
+    /// Whether the indexed file at `path` has the given contents, as kept in the document store if
+    /// it's set, or as read from the file system otherwise.
+    fn has_contents(&self, path: &Path, contents: &[u8]) -> bool {
+        let kept = match &self.document_store {
+            Some(store) => store.get(&self.storage.path_case().key(path)),
+            None => self.file_system.read(path).map(Some).map_err(Error::from),
+        };
+
+        match kept {
+            Ok(kept) => kept.as_deref() == Some(contents),
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "failed to read a candidate original");
+                false
+            }
+        }
+    }
+
+    /// Index every entry of an archive located at `path`, recursing into nested archives.
+    ///
+    /// `depth` is the nesting level of the archive, starting from 1. Nested archives that fail to
+    /// be read are logged and skipped. Entries are recorded with the modification time of the
+    /// archive itself.
+    ///
+    /// Returns the number of tokens read.
+    fn index_archive(
+        &self,
+        path: &Path,
+        kind: ArchiveKind,
+        contents: &[u8],
+        depth: usize,
+        modified: Option<SystemTime>,
+    ) -> Result<usize> {
+        let mut words_count = 0;
+
+        for (name, contents) in kind.entries(contents, self.archive_limits)? {
+            let entry = archive::entry_path(path, &name);
+
+            match ArchiveKind::of(&entry) {
+                Some(nested) if depth < self.archive_depth => {
+                    match self.index_archive(&entry, nested, &contents, depth + 1, modified) {
+                        Ok(count) => words_count += count,
+                        Err(e) => {
+                            warn!(error = %e, entry = %entry.display(), "failed to index a nested archive")
+                        }
+                    }
+                }
+                _ => {
+                    let membership_only = self.is_memory_degraded();
+                    words_count += self.transaction("put", &entry, || {
+                        let tap = self.token_tap_for(&entry);
+                        let (words_count, positions) =
+                            self.analyse(&entry, &contents, 0, 0, |token| {
+                                self.store(&entry, token, membership_only, tap.as_deref())
+                            })?;
+                        self.storage.set_meta(
+                            &entry,
+                            FileMeta::new(&contents, &positions)
+                                .with_modified(modified)
+                                .with_membership_only(membership_only)
+                                .with_analyser_version(self.analyser_for(&entry).version()),
+                        );
+                        Ok::<_, Error>(words_count)
+                    })?;
+                    self.keep_document(&entry, &contents);
+                }
+            }
+        }
+
+        Ok(words_count)
+    }
+
+    /// Replace the entries of the lines that differ between the indexed and the given contents.
+    ///
+    /// The lines are compared by their hashes, and by their contents too if the previously indexed
+    /// ones are kept in the document store. The tokens of the unchanged lines after the replaced ones
+    /// are moved to their new offsets and positions.
+    fn index_changed_lines(
+        &self,
+        path: &Path,
+        meta: &FileMeta,
+        contents: &[u8],
+        modified: Option<SystemTime>,
+    ) -> Result<usize> {
+        let old = meta
+            .lines
+            .iter()
+            .map(|(&offset, line)| (offset, line.hash))
+            .collect::<Vec<_>>();
+        let old_positions = meta
+            .lines
+            .iter()
+            .map(|(_, line)| line.position)
+            .collect::<Vec<_>>();
+        let new = storage::split_lines(contents)
+            .map(|(offset, line)| (offset, hash::fnv1a(line)))
+            .collect::<Vec<_>>();
+        let previous = self
+            .document_store
+            .as_ref()
+            .and_then(|store| store.get(&self.storage.path_case().key(path)).ok()?)
+            .filter(|previous| previous.len() as u64 == meta.len);
+
+        fn line_offset(lines: &[(u64, u64)], i: usize, len: u64) -> u64 {
+            lines.get(i).map_or(len, |l| l.0)
+        }
+        fn line<'a>(lines: &[(u64, u64)], i: usize, contents: &'a [u8]) -> &'a [u8] {
+            let end = line_offset(lines, i + 1, contents.len() as u64);
+            &contents[lines[i].0 as usize..end as usize]
+        }
+        let same = |i: usize, j: usize| {
+            old[i].1 == new[j].1
+                && previous
+                    .as_deref()
+                    .is_none_or(|previous| line(&old, i, previous) == line(&new, j, contents))
+        };
+
+        let prefix = (0..old.len().min(new.len()))
+            .take_while(|&i| same(i, i))
+            .count();
+        let suffix = (1..=(old.len() - prefix).min(new.len() - prefix))
+            .take_while(|&i| same(old.len() - i, new.len() - i))
+            .count();
+
+        let start = line_offset(&old, prefix, meta.len);
+        let old_end = line_offset(&old, old.len() - suffix, meta.len);
+        let new_end = line_offset(&new, new.len() - suffix, contents.len() as u64);
+        let old_position = |i: usize| old_positions.get(i).copied().unwrap_or(meta.end_position);
+        trace!(%start, %old_end, %new_end, "replacing changed lines");
+
+        let words_count = self.transaction("update", path, || {
+            // The tokens after the changed lines are moved by the difference in the positions they
+            // take, so they are only stored once the whole changed lines are analysed.
+            let mut tokens = Vec::new();
+            let (words_count, changed) = self.analyse(
+                path,
+                &contents[start as usize..new_end as usize],
+                start,
+                old_position(prefix),
+                |token| tokens.push(token),
+            )?;
+            let delta = new_end as i64 - old_end as i64;
+            let position_delta = changed.end as i64 - old_position(old.len() - suffix) as i64;
+
+            self.storage
+                .splice(path, start..old_end, delta, position_delta);
+            for storage in self.secondary_storages() {
+                storage.splice(path, start..old_end, delta, position_delta);
+            }
+            let tap = self.token_tap_for(path);
+            for token in tokens {
+                self.store(path, token, false, tap.as_deref());
+            }
+
+            let moved = |position: &u64| (*position as i64 + position_delta) as u64;
+            let positions = LinePositions {
+                lines: old_positions[..prefix]
+                    .iter()
+                    .copied()
+                    .chain(changed.lines)
+                    .chain(old_positions[old.len() - suffix..].iter().map(moved))
+                    .collect(),
+                end: moved(&meta.end_position),
+            };
+            self.storage.set_meta(
+                path,
+                FileMeta::new(contents, &positions)
+                    .with_modified(modified)
+                    .with_analyser_version(self.analyser_for(path).version()),
+            );
+            Ok::<_, Error>(words_count)
+        })?;
+        self.keep_document(path, contents);
+        if let Some(duplicates) = &self.duplicates {
+            duplicates.remove(path);
+            let file_id = self
+                .file_system
+                .metadata(path)
+                .ok()
+                .and_then(|metadata| metadata.file_id);
+            duplicates.insert_original(path, hash::fnv1a(contents), file_id);
+        }
+
+        Ok(words_count)
+    }
+
+    /// Run the contents of the file at `path` through its analyser, passing the resulting tokens to `f`.
+    ///
+    /// `base` is the offset of the contents in the file, and `position` the position reached before
+    /// them, see [`storage::LineMeta::position`]. Tokens dropped by the normalisers are passed as
+    /// errors if the stop word index is enabled, and skipped otherwise.
+    ///
+    /// Returns the number of tokens read, and the positions reached before every line of the
+    /// contents and at their end.
+    fn analyse<F>(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        base: u64,
+        mut position: u64,
+        mut f: F,
+    ) -> Result<(usize, LinePositions)>
+    where
+        F: FnMut(AnalysedToken),
+    {
+        let analyser = self.analyser_for(path);
+        let stop_words = self.stop_words.read().unwrap();
+        let mut reader = contents;
+        let mut tokeniser = analyser.tokeniser();
+        let mut words_count = 0;
+        let mut gap = 0;
+        let mut lines = storage::split_lines(contents)
+            .map(|(offset, _)| offset)
+            .peekable();
+        let mut positions = LinePositions::default();
+
+        while let Some(mut token) =
+            self.timed(Stage::Tokenise, || tokeniser.read_token(&mut reader))?
+        {
+            words_count += 1;
+            while lines.next_if(|&line| line <= token.offset).is_some() {
+                positions.lines.push(position + u64::from(gap));
+            }
+            token.offset += base;
+            // The dropped tokens are given the position they would have if they were kept.
+            let increment = token.position_increment;
+            let token_position = position + u64::from(gap) + u64::from(increment);
+
+            let exact = self.exact_case_storage.as_ref().map(|_| token.clone());
+            let token = if self.stop_word_storage.is_some() || exact.is_some() {
+                self.timed(Stage::Normalise, || analyser.normalise_or_dropped(token))
+            } else {
+                match self.timed(Stage::Normalise, || analyser.normalise(token)) {
+                    Some(token) => Ok(token),
+                    None => {
+                        analyser.skip_position(&mut gap, increment);
+                        continue;
+                    }
+                }
+            };
+            let token = match token {
+                Ok(token) if stop_words.contains(token.value.as_str()) => Err(token),
+                token => token,
+            };
+            let token = analyser.position(&mut gap, token);
+            if token.is_ok() {
+                position = token_position;
+            }
+
+            self.timed(Stage::Store, || {
+                f(AnalysedToken {
+                    token,
+                    exact,
+                    position: token_position,
+                })
+            });
+        }
+
+        positions
+            .lines
+            .extend(lines.map(|_| position + u64::from(gap)));
+        positions.end = position + u64::from(gap);
+        Ok((words_count, positions))
+    }
+
+    /// Insert a token produced by [`Indexer::analyse`] in the corresponding storage.
+    ///
+    /// If `membership_only` is set, the offset of the token isn't kept. The token is passed to `tap`
+    /// if it's set, see [`Indexer::token_tap_for`].
+    fn store(
+        &self,
+        path: &Path,
+        token: AnalysedToken,
+        membership_only: bool,
+        tap: Option<&TokenTap>,
+    ) {
+        let position = token.position;
+        let insert = |storage: &AvlStorage, token| {
+            if membership_only {
+                storage.insert_path_only(path, token);
+            } else {
+                storage.insert(path, token, position);
+            }
+        };
+
+        if let (Some(storage), Some(exact)) = (&self.exact_case_storage, token.exact) {
+            insert(storage, exact);
+        }
+        if let Some(tap) = tap {
+            match (&token.token, &self.stop_word_storage) {
+                (Ok(token), _) => tap.token(path, token, dry_run::Target::Index),
+                (Err(dropped), Some(_)) => tap.token(path, dropped, dry_run::Target::StopWordIndex),
+                (Err(_), None) => (),
+            }
+        }
+        match (token.token, &self.stop_word_storage) {
+            (Ok(token), _) => insert(&self.storage, token),
+            (Err(dropped), Some(storage)) => insert(storage, dropped),
+            (Err(_), None) => (),
+        }
+    }
+
+    /// The token tap, if it's set and matches the given file, see [`Indexer::set_token_tap`].
+    ///
+    /// This is checked once per file rather than for every token.
+    fn token_tap_for(&self, path: &Path) -> Option<Arc<TokenTap>> {
+        self.token_tap
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|tap| tap.matches(path))
+            .map(Arc::clone)
+    }
+
+    /// Remove a token produced by [`Indexer::analyse`] from the corresponding storages.
+    fn unstore(&self, path: &Path, token: AnalysedToken) {
+        if let (Some(storage), Some(exact)) = (&self.exact_case_storage, token.exact) {
+            storage.remove(path, &exact);
+        }
+        match (token.token, &self.stop_word_storage) {
+            (Ok(token), _) => self.storage.remove(path, &token),
+            (Err(dropped), Some(storage)) => storage.remove(path, &dropped),
+            (Err(_), None) => (),
+        }
+    }
+
+    /// Storages kept alongside the main one, see [`Indexer::with_stop_word_index`] and
+    /// [`Indexer::with_exact_case_index`].
+    fn secondary_storages(&self) -> impl Iterator<Item = &AvlStorage> {
+        self.stop_word_storage
+            .iter()
+            .chain(self.exact_case_storage.iter())
+    }
+
+    fn secondary_storages_mut(&mut self) -> impl Iterator<Item = &mut AvlStorage> {
+        self.stop_word_storage
+            .iter_mut()
+            .chain(self.exact_case_storage.iter_mut())
+    }
+
+    /// Finish indexing a file, making sure it wasn't modified since `stamp_before` was taken.
+    ///
+    /// See [`Indexer::index_file`] for how modifications are handled.
+    fn check_unmodified(&self, path: &Path, stamp_before: FileStamp) -> Result<()> {
+        if let Some(profiler) = &self.profiler {
+            profiler.record_file();
+        }
+        if let Some(budget) = &self.budget {
+            let raised = budget.check_due(|| self.usage());
+            self.degrade(budget, &raised);
+        }
+
+        if FileStamp::new(&self.file_system.metadata(path)?) != stamp_before {
+            trace!("file was modified while being indexed");
+            self.purge(path);
+            return Err(Error::ModifiedDuringIndexing(path.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Run `f`, accounting its duration to the given stage if profiling is enabled.
+    fn timed<T, F>(&self, stage: Stage, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        match &self.profiler {
+            Some(profiler) => profiler.time(stage, f),
+            None => f(),
+        }
+    }
+
+    /// Index all the files listed in the given manifest file.
+    ///
+    /// A manifest lists one path per line (e.g. the output of `find . -type f`), relative paths are
+    /// resolved against the manifest's directory. This allows to skip the directory walk when the set
+    /// of files is already known. Files that fail to index are logged and skipped.
+    #[instrument(
+        skip(self, manifest),
+        fields(
+            index = %self.name,
+            generation = self.storage.generation(),
+            manifest = %manifest.display(),
+        )
+    )]
+    pub fn index_manifest(&self, manifest: &Path) -> Result<()> {
+        for path in path::read_manifest(&*self.file_system, manifest)? {
+            if let Err(e) = self.index_file(&path) {
+                warn!(error = %e, path = %path.display(), "failed to index a file");
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the query concurrency limit to allow another query, if it's set.
+    ///
+    /// Returns the permit to hold while the query runs, along with the time spent waiting for it.
+    fn query_permit(&self) -> (Option<SemaphorePermit<'_>>, Duration) {
+        let start = Instant::now();
+        let permit = self.query_limiter.as_ref().map(Semaphore::acquire);
+        let wait = start.elapsed();
+
+        if permit.is_some() {
+            trace!(?wait, "acquired a query permit");
+        }
+        (permit, wait)
+    }
+
+    /// Record a query that started at `start` after waiting `wait` for a permit in the query metrics
+    /// and the slow query log, if they are enabled.
+    fn record_query(&self, term: &str, start: Instant, wait: Duration, results: usize) {
+        let duration = start.elapsed();
+
+        if let Some(query_metrics) = &self.query_metrics {
+            query_metrics.record(duration, wait, results);
+        }
+        if let Some(query_frequencies) = &self.query_frequencies {
+            query_frequencies.record(term);
+        }
+        if let Some(slow_query_log) = &self.slow_query_log {
+            slow_query_log.record(duration, || SlowQuery {
+                term: term.to_owned(),
+                duration,
+                wait,
+                results,
+                generation: self.generation(),
+            });
+        }
+    }
+
+    /// Record a mutation of the index that started at `start`, see [`Indexer::with_mutation_history`].
+    fn record_mutation(&self, kind: MutationKind, path: &Path, start: Instant, tokens: usize) {
+        if let Some(mutation_history) = &self.mutation_history {
+            mutation_history.record(kind, path, start, tokens, self.generation());
+        }
+    }
+
+    /// Pass a query term through the query hooks, see [`Indexer::with_query_hook`].
+    fn rewrite(&self, term: &str) -> Vec<String> {
+        rewrite(term, &self.query_hooks)
+    }
+
+    /// Find the index entries for the given query terms, one list per distinct term produced by the
+    /// analysers.
+    ///
+    /// See [`Indexer::query`] for how the terms are treated.
+    fn lookup(&self, terms: &[String]) -> Vec<Found<'_>> {
+        self.lookup_with(terms, &NormaliserOverrides::default()).1
+    }
+
+    /// Find the index entries for the given query terms like [`Indexer::lookup`] does, with the
+    /// normalisers overridden, along with the terms they were normalised to.
+    fn lookup_with(
+        &self,
+        terms: &[String],
+        overrides: &NormaliserOverrides,
+    ) -> (NormalisedQuery, Vec<Found<'_>>) {
+        let (normalised, found) = lookup(
+            terms,
+            &self.analysers(),
+            &self.stop_words.read().unwrap(),
+            self.stop_word_storage.is_some(),
+            overrides,
+            |dropped, term| {
+                let storage = match &self.stop_word_storage {
+                    Some(storage) if dropped => storage,
+                    _ => &self.storage,
+                };
+                Some((storage, storage.get(term)?))
+            },
+        );
+        let found = found
+            .into_iter()
+            .map(|(storage, term, entries)| Found {
+                storage,
+                term,
+                entries,
+            })
+            .collect();
+        (normalised, found)
+    }
+
+    /// Every analyser in use along with its root, the default one first.
+    fn analysers(&self) -> Vec<RootAnalyser> {
+        iter::once((None, self.default_analyser()))
+            .chain(
+                self.root_analysers
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|(root, analyser)| (Some(root.clone()), Arc::clone(analyser))),
+            )
+            .collect()
+    }
+
+    /// Cut the snippets of the matches at the given offsets out of the contents of a file, see
+    /// [`Indexer::with_snippets`].
+    ///
+    /// The ends of the matches are found by analysing the contents again, the offsets no token
+    /// starts at have no snippet.
+    fn snippets(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        offsets: &[u64],
+        window: usize,
+    ) -> Vec<Option<Snippet>> {
+        let lengths = match self.spans(&self.analyser_for(path), contents) {
+            Ok(spans) => spans
+                .into_iter()
+                .map(|(Ok(token) | Err(token))| (token.offset, token.len))
+                .collect::<HashMap<_, _>>(),
+            Err(e) => {
+                debug!(path = %path.display(), error = %e, "failed to analyse a file for snippets");
+                HashMap::new()
+            }
+        };
+
+        offsets
+            .iter()
+            .map(|offset| {
+                let len = lengths.get(offset)?;
+                let end = ((offset + len) as usize).min(contents.len());
+                Some(hit::snippet(contents, *offset as usize..end, window))
+            })
+            .collect()
+    }
+
+    /// Contents of an indexed file, taken from the document store if it's set (see
+    /// [`Indexer::with_document_store`]), and read from the file otherwise.
+    fn indexed_contents(&self, path: &Path) -> Result<Vec<u8>> {
+        match self.document(path)? {
+            Some(contents) => Ok(contents),
+            None => Ok(self.file_system.read(path)?),
+        }
+    }
+
+    /// Contents of an indexed file like [`Indexer::indexed_contents`], `None` if they can't be read or
+    /// changed since the file was indexed.
+    fn unchanged_contents(&self, path: &Path) -> Option<Vec<u8>> {
+        self.indexed_contents(path).ok().filter(|contents| {
+            self.storage
+                .meta(path)
+                .is_some_and(|meta| meta.content_hash == hash::fnv1a(contents))
+        })
+    }
+
+    /// Positions of the given query term in every file it's found in, sorted, without their
+    /// duplicates.
+    fn positions_by_path(&self, term: &str) -> BTreeMap<PathBuf, Vec<u64>> {
+        let mut positions = BTreeMap::<PathBuf, Vec<u64>>::new();
+
+        for found in self.lookup(&self.rewrite(term)) {
+            for (path, found) in found.entries.iter() {
+                positions
+                    .entry(path.to_path_buf())
+                    .or_default()
+                    .extend(found.iter().map(|(_, &position)| position));
+            }
+        }
+        for positions in positions.values_mut() {
+            positions.sort_unstable();
+        }
+        positions
+    }
+
+    /// Distinct paths of the files the given query term is found in, without their duplicates.
+    fn matching_paths(&self, term: &str) -> Vec<PathBuf> {
+        paths_of(&self.lookup(&self.rewrite(term)))
+    }
+
+    /// Run the contents through the given analyser like [`Indexer::analyse`] does, returning every
+    /// token including the ones dropped by the normalisers.
+    ///
+    /// The source ranges of the tokens are kept through normalisation, and the kept tokens carry the
+    /// position increments of the dropped ones, see [`tokenise::Token`].
+    fn spans(
+        &self,
+        analyser: &Analyser,
+        contents: &[u8],
+    ) -> Result<Vec<std::result::Result<tokenise::Token, tokenise::Token>>> {
+        let stop_words = self.stop_words.read().unwrap();
+        let mut reader = contents;
+        let mut tokeniser = analyser.tokeniser();
+        let mut spans = Vec::new();
+        let mut gap = 0;
+
+        while let Some(token) = tokeniser.read_token(&mut reader)? {
+            let token = match analyser.normalise_or_dropped(token) {
+                Ok(token) if stop_words.contains(token.value.as_str()) => Err(token),
+                token => token,
+            };
+            spans.push(analyser.position(&mut gap, token));
+        }
+
+        Ok(spans)
+    }
+
+    /// Analyser used for the files that aren't under any root with a dedicated analyser.
+    fn default_analyser(&self) -> Arc<Analyser> {
+        Arc::clone(&self.analyser.read().unwrap())
+    }
+
+    /// Analyser of the innermost root containing the given path, or the default one.
+    fn analyser_for(&self, path: &Path) -> Arc<Analyser> {
+        self.root_analyser(path)
+            .unwrap_or_else(|| self.default_analyser())
+    }
+
+    /// Analyser of the innermost root containing the given path, if there is one.
+    fn root_analyser(&self, path: &Path) -> Option<Arc<Analyser>> {
+        self.root_analysers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|(_, analyser)| Arc::clone(analyser))
+    }
+
+    /// Remove an already normalised path from all the storages.
+    ///
+    /// Entries of an archive are removed along with the archive itself, duplicates of a file that
+    /// shared its entries are indexed again on their own.
+    fn purge(&self, path: &Path) {
+        self.transaction("purge", path, || {
+            self.storage.purge(path);
+            for storage in self.secondary_storages() {
+                storage.purge(path);
+            }
+        });
+        self.stale
+            .write()
+            .unwrap()
+            .remove(self.storage.path_case().key(path).as_ref());
+        self.drop_document(path);
+
+        if let Some(duplicates) = &self.duplicates {
+            for duplicate in duplicates.remove(path) {
+                if let Err(e) = self.index_file(&duplicate) {
+                    warn!(error = %e, path = %duplicate.display(), "failed to index a duplicate");
+                }
+            }
+        }
+
+        if self.archive_kind(path).is_some() {
+            self.purge_dir(&archive::entries_root(path));
+        }
+    }
+
+    /// Remove an already normalised path and everything beneath it from all the storages.
+    fn purge_dir(&self, path: &Path) {
+        // Duplicates under the path are purged first, so that they aren't indexed again when their
+        // originals are purged.
+        if let Some(duplicates) = &self.duplicates {
+            for key in duplicates.paths_under(path) {
+                self.purge(&key);
+            }
+        }
+
+        if self.document_store.is_some() {
+            for key in self.storage.keys_under(path) {
+                self.drop_document(&key);
+            }
+        }
+
+        self.transaction("purge_dir", path, || {
+            self.storage.purge_dir(path);
+            for storage in self.secondary_storages() {
+                storage.purge_dir(path);
+            }
+        });
+
+        let prefix = self.storage.path_case().key(path);
+        self.stale
+            .write()
+            .unwrap()
+            .retain(|key, _| !key.starts_with(&prefix));
+    }
+
+    /// Check whether the file at the given normalised path is stale, see [`Indexer::is_stale`].
+    fn is_stale_file(&self, path: &Path) -> bool {
+        self.stale
+            .read()
+            .unwrap()
+            .contains_key(self.storage.path_case().key(path).as_ref())
+    }
+
+    /// Record the index key of the file being processed in the current span.
+    fn record_doc(&self, path: &Path) {
+        Span::current().record(
+            "doc",
+            field::display(self.storage.path_case().key(path).display()),
+        );
+    }
+
+    /// Apply a modification of the storage to the given normalised path, then emit an event for it.
+    ///
+    /// Snapshots are not taken while the modification is applied, see [`Indexer::snapshot`]. The
+    /// event carries the generation the storage reached, so that the modifications can be matched
+    /// with the state of the index.
+    fn transaction<T, F>(&self, kind: &'static str, path: &Path, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let result = {
+            let _gate = self.snapshot_gate.read().unwrap();
+            f()
+        };
+
+        trace!(
+            kind,
+            doc = %self.storage.path_case().key(path).display(),
+            generation = self.storage.generation(),
+            "storage transaction"
+        );
+        result
+    }
+
+    /// Keep the contents of the given file in the document store, if it's set.
+    fn keep_document(&self, path: &Path, contents: &[u8]) {
+        if let Some(store) = &self.document_store {
+            let result = self.timed(Stage::Store, || {
+                store.put(&self.storage.path_case().key(path), contents)
+            });
+
+            if let Err(e) = result {
+                warn!(error = %e, path = %path.display(), "failed to keep the document");
+            }
+        }
+    }
+
+    /// Extend the contents of the given file in the document store, if it's set, with the `contents`
+    /// read from offset `base` after the file grew from `len` bytes.
+    fn keep_appended_document(&self, path: &Path, len: u64, base: u64, contents: &[u8]) {
+        let store = match &self.document_store {
+            Some(store) => store,
+            None => return,
+        };
+
+        match store.get(&self.storage.path_case().key(path)) {
+            Ok(Some(mut document)) if document.len() as u64 == len => {
+                document.truncate(base as usize);
+                document.extend_from_slice(contents);
+                self.keep_document(path, &document);
+            }
+            // The stored contents don't match the indexed ones, so they are rather dropped.
+            result => {
+                if let Err(e) = result {
+                    warn!(error = %e, path = %path.display(), "failed to get the document");
+                }
+                self.drop_document(path);
+            }
+        }
+    }
+
+    /// Remove the contents of the given file from the document store, if it's set.
+    fn drop_document(&self, path: &Path) {
+        if let Some(store) = &self.document_store {
+            if let Err(e) = store.remove(&self.storage.path_case().key(path)) {
+                warn!(error = %e, path = %path.display(), "failed to remove the document");
+            }
+        }
+    }
+
+    /// Normalise the given path, resolving duplicates to their original files.
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        let path = path::normalise(&*self.file_system, path)?;
+
+        Ok(self
+            .duplicates
+            .as_ref()
+            .and_then(|duplicates| duplicates.original_of(&path))
+            .unwrap_or(path))
+    }
+
+    /// Paths of the duplicates sharing the entries of the given file.
+    fn duplicates_of(&self, path: &Path) -> Vec<PathBuf> {
+        self.duplicates
+            .as_ref()
+            .map(|duplicates| duplicates.duplicates_of(path))
+            .unwrap_or_default()
+    }
+
+    /// Split the duplicates of a file into the ones reported as separate results and the ones
+    /// reported as its aliases, see [`Indexer::with_collapsed_duplicates`].
+    fn results_and_aliases(&self, path: &Path) -> (Vec<PathBuf>, Vec<String>) {
+        let duplicates = self.duplicates_of(path);
+
+        if self.collapse_duplicates {
+            let aliases = duplicates
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            (Vec::new(), aliases)
+        } else {
+            (duplicates, Vec::new())
+        }
+    }
+
+    /// Build the [`IndexedFile`]s of the given file and its duplicates.
+    fn indexed_file(&self, path: &Path, content_hash: u64) -> Vec<IndexedFile> {
+        let (duplicates, aliases) = self.results_and_aliases(path);
+
+        iter::once(path.to_path_buf())
+            .chain(duplicates)
+            .map(|path| IndexedFile {
+                path: path.to_string_lossy().into_owned(),
+                content_hash,
+                aliases: aliases.clone(),
+            })
+            .collect()
+    }
+
+    /// Check whether any of the terms of the given file reached the posting cap.
+    fn has_capped_terms(&self, path: &Path) -> bool {
+        iter::once(&self.storage)
+            .chain(self.secondary_storages())
+            .any(|storage| storage.has_capped_terms(path))
+    }
+
+    /// Path of the archive the given indexed file is an entry of, if it's an archive entry.
+    ///
+    /// Archives are told apart from the directories whose names end in `!` by being indexable
+    /// archive files.
+    fn archive_of(&self, path: &Path) -> Option<PathBuf> {
+        archive::archive_of(path, |archive| {
+            self.archive_kind(archive).is_some()
+                && self
+                    .file_system
+                    .metadata(archive)
+                    .is_ok_and(|metadata| metadata.is_file)
+        })
+    }
+
+    /// Kind of the archive at the given path, if archive indexing is enabled.
+    fn archive_kind(&self, path: &Path) -> Option<ArchiveKind> {
+        if self.archive_depth == 0 {
+            return None;
+        }
+
+        ArchiveKind::of(path)
+    }
+}
+
+/// Changes of the analysis configuration of an [`Indexer`], see [`Indexer::update_config`].
+#[derive(Default)]
+pub struct ConfigUpdate {
+    analyser: Option<Analyser>,
+    stop_words: Option<Vec<String>>,
+    pub(crate) reindex: bool,
+}
+
+impl ConfigUpdate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the default analyser, e.g. to change the parameters of its normalisers.
+    pub fn with_analyser(mut self, analyser: Analyser) -> Self {
+        self.analyser = Some(analyser);
+        self
+    }
+
+    /// Replace the stop words added at runtime (see [`Indexer::add_stop_words`]) with the given ones.
+    pub fn with_stop_words(mut self, terms: &[&str]) -> Self {
+        self.stop_words = Some(terms.iter().map(|&term| term.to_owned()).collect());
+        self
+    }
+
+    /// Index the files made stale by the update again right away, see [`Indexer::reindex_stale`].
+    pub fn with_reindex(mut self) -> Self {
+        self.reindex = true;
+        self
+    }
+}
+
+/// Analyser along with the root of the files it analyses, `None` for the default analyser.
+pub(crate) type RootAnalyser = (Option<PathBuf>, Arc<Analyser>);
+
+/// Terms a query was normalised to, see [`normalise_query`].
+///
+/// The terms to be looked up in the stop word storage are flagged. Every term is mapped to the roots
+/// of the analysers that produced it, as it can only be found in the files analysed by them.
+pub(crate) type NormalisedQuery = BTreeMap<(bool, CompactString), BTreeSet<Option<PathBuf>>>;
+
+/// Pass a query term through the given query hooks, see [`Indexer::with_query_hook`].
+pub(crate) fn rewrite(term: &str, query_hooks: &[Arc<dyn QueryHook>]) -> Vec<String> {
+    let mut terms = vec![term.to_owned()];
+    for hook in query_hooks {
+        hook.rewrite(&mut terms);
+    }
+    terms
+}
+
+/// Find the index entries of the given query terms, one list per distinct term produced by the
+/// analysers, see [`Indexer::query`].
+///
+/// This is shared by the [`Indexer`] and its [`Replica`]s: `get` looks a normalised term up in the
+/// main storage, or in the stop word storage if the term is flagged as dropped, and returns its
+/// entries along with the storage they were found in. Only the entries of the files analysed by the
+/// analysers that produced a term are kept, see [`NormalisedQuery`].
+pub(crate) fn lookup<S, F>(
+    terms: &[String],
+    analysers: &[RootAnalyser],
+    stop_words: &BTreeSet<CompactString>,
+    stop_word_index: bool,
+    overrides: &NormaliserOverrides,
+    mut get: F,
+) -> (NormalisedQuery, Vec<(S, CompactString, IndexEntryList)>)
+where
+    F: FnMut(bool, &str) -> Option<(S, ValueRef<CompactString, IndexEntryList>)>,
+{
+    let normalised = normalise_query(terms, analysers, stop_words, stop_word_index, overrides);
+    let found = normalised
+        .iter()
+        .filter_map(|((dropped, term), roots)| {
+            let (storage, entries) = get(*dropped, term)?;
+            Some((
+                storage,
+                term.clone(),
+                within_roots(&entries, roots, analysers),
+            ))
+        })
+        .collect();
+    (normalised, found)
+}
+
+/// Normalise the query terms by every given analyser, with the normalisers overridden for the query,
+/// see [`Indexer::query`] and [`QueryOptions::with_normalisers`].
+///
+/// The terms to be looked up in the stop word storage are flagged, provided that there is one.
+fn normalise_query(
+    terms: &[String],
+    analysers: &[RootAnalyser],
+    stop_words: &BTreeSet<CompactString>,
+    stop_word_index: bool,
+    overrides: &NormaliserOverrides,
+) -> NormalisedQuery {
+    let skip_stop_words = overrides.skips("StopWords");
+    let normalise =
+        |analyser: &Analyser, term: &str, overrides: &NormaliserOverrides| match analyser
+            .normalise_or_dropped_with(tokenise::Token::new(term), overrides)
+        {
+            Ok(token) if !skip_stop_words && stop_words.contains(token.value.as_str()) => {
+                Err(token)
+            }
+            token => token,
+        };
+
+    // Terms dropped by the normalisers, as well as the stop words added at runtime, are looked up
+    // in the stop word storage.
+    let mut normalised = NormalisedQuery::new();
+    for term in terms {
+        let (term, quoted) = match term.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            Some(unquoted) if !unquoted.is_empty() => (unquoted, true),
+            _ => (term.as_str(), false),
+        };
+        let mut filtered = true;
+
+        for (root, analyser) in analysers {
+            let mut insert = |dropped: bool, token: tokenise::Token| {
+                normalised
+                    .entry((dropped, token.value))
+                    .or_default()
+                    .insert(root.clone());
+            };
+
+            match normalise(analyser, term, overrides) {
+                Ok(token) => {
+                    // Terms kept only thanks to the overrides are found among the dropped tokens.
+                    if stop_word_index && !overrides.is_empty() {
+                        if let Err(dropped) = normalise(analyser, term, &Default::default()) {
+                            insert(true, dropped);
+                        }
+                    }
+                    insert(false, token)
+                }
+                Err(dropped) if quoted && stop_word_index => insert(true, dropped),
+                Err(_) => continue,
+            };
+            filtered = false;
+        }
+
+        if filtered {
+            debug!(term, "query term is filtered out by the normalisers");
+        }
+    }
+
+    normalised
+}
+
+/// Index entries of the files analysed by the analysers of the given roots, among every analyser in
+/// use, see [`NormalisedQuery`].
+///
+/// A file is analysed by the analyser of the innermost root containing it, or by the default one.
+fn within_roots(
+    entries: &IndexEntryList,
+    roots: &BTreeSet<Option<PathBuf>>,
+    analysers: &[RootAnalyser],
+) -> IndexEntryList {
+    if analysers.iter().all(|(root, _)| roots.contains(root)) {
+        return entries.clone();
+    }
+
+    let root_of = |path: &Path| {
+        analysers
+            .iter()
+            .filter_map(|(root, _)| root.as_deref())
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .map(Path::to_path_buf)
+    };
+    entries
+        .iter()
+        .filter(|(path, _)| !roots.contains(&root_of(path)))
+        .fold(entries.clone(), |entries, (path, _)| entries.remove(path))
+}
+
+/// Describe how the results of a self-test query differ from the expected paths, if they do.
+fn unexpected_paths(found: HashSet<String>, expected: &[&str]) -> Option<String> {
+    let mut found = found.into_iter().collect::<Vec<_>>();
+    found.sort();
+
+    if found
+        .iter()
+        .map(String::as_str)
+        .eq(expected.iter().copied())
+    {
+        None
+    } else {
+        Some(format!("expected {:?}, found {:?}", expected, found))
+    }
+}
+
+/// An indexed file along with the hash of its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IndexedFile {
+    pub path: String,
+    /// 64-bit FNV-1a hash of the file contents as they were indexed.
+    pub content_hash: u64,
+    /// Paths of the duplicates of the file, if they are collapsed, see
+    /// [`Indexer::with_collapsed_duplicates`].
+    pub aliases: Vec<String>,
+}
+
+/// Paging and normalisation of the results of [`Indexer::query_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Maximum number of files returned, all of them if `None`.
+    pub limit: Option<usize>,
+    /// Number of files skipped from the start of the results.
+    pub offset: usize,
+    /// Normalisers skipped or substituted for the query term.
+    pub normalisers: NormaliserOverrides,
+}
+
+impl QueryOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return up to `limit` files.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skip the first `offset` files.
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Skip or substitute some of the normalisers the query term goes through, e.g. to look a term
+    /// up as cased in the files of an analyser that doesn't lower case.
+    ///
+    /// A term that the regular normalisers drop but the overridden ones keep is also looked up
+    /// among the dropped tokens, provided that the index keeps them (see
+    /// [`Indexer::with_stop_word_index`]).
+    pub fn with_normalisers(mut self, overrides: NormaliserOverrides) -> Self {
+        self.normalisers = overrides;
+        self
+    }
+}
+
+/// Page of the results of a query, see [`Indexer::query_with_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryPage {
+    /// Files of the page, sorted by path.
+    pub paths: Vec<String>,
+    /// Number of files found in total, over all the pages.
+    pub total: usize,
+}
+
+impl QueryPage {
+    /// Cut the page requested by the options out of all the files found.
+    pub(crate) fn new(paths: HashSet<String>, options: &QueryOptions) -> Self {
+        let mut paths = paths.into_iter().collect::<Vec<_>>();
+        let total = paths.len();
+
+        paths.sort();
+        Self {
+            paths: paths
+                .into_iter()
+                .skip(options.offset)
+                .take(options.limit.unwrap_or(usize::MAX))
+                .collect(),
+            total,
+        }
+    }
+}
+
+/// Bookkeeping data of an indexed file, see [`Indexer::metadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentMetadata {
+    pub path: String,
+    /// Number of bytes indexed.
+    pub size: u64,
+    /// 64-bit FNV-1a hash of the file contents as they were indexed.
+    pub content_hash: u64,
+    /// Modification time of the file when it was indexed, if known.
+    pub modified: Option<SystemTime>,
+    /// Time the file was last indexed at, appends included.
+    pub indexed_at: SystemTime,
+    /// Version of the analyser the file was indexed with, see [`Analyser::with_version`].
+    pub analyser_version: u32,
+    /// Whether the file was indexed without the offsets of its terms, see
+    /// [`crate::budget::IndexBudget::with_memory_degradation`].
+    pub membership_only: bool,
+}
+
+/// Index term a query term was normalised to, along with its postings.
+struct Found<'a> {
+    /// Storage the term was found in, the main one or the stop word one.
+    storage: &'a AvlStorage,
+    term: CompactString,
+    entries: IndexEntryList,
+}
+
+/// Distinct paths of the files in the given index entries.
+fn paths_of(found: &[Found<'_>]) -> Vec<PathBuf> {
+    let mut paths = found
+        .iter()
+        .flat_map(|found| found.entries.iter().map(|(path, _)| path.to_path_buf()))
+        .collect::<Vec<_>>();
+
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Literal part of a wildcard pattern before its first wildcard, see [`Indexer::query_wildcard`].
+fn wildcard_prefix(pattern: &str) -> &str {
+    pattern
+        .find(['*', '?'])
+        .map_or(pattern, |end| &pattern[..end])
+}
+
+/// Check whether the whole term matches the wildcard pattern, see [`Indexer::query_wildcard`].
+fn wildcard_match(pattern: &str, term: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let term = term.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and of the term character it was matched up to.
+    let mut star = None;
+
+    while t < term.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == term[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character and try again.
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Edit distance check of the terms of a sorted dictionary against a query term, see
+/// [`Indexer::query_fuzzy`].
+///
+/// The dynamic programming rows of the previous term are kept, so that only the rows past the
+/// prefix it shares with the next one are computed.
+struct BoundedLevenshtein {
+    query: Vec<char>,
+    max_distance: usize,
+    /// Characters of the previous term, up to the first row that exceeded the maximum distance.
+    chars: Vec<char>,
+    /// Row of the edit distances from every prefix of the query to every prefix of `chars`,
+    /// starting with the empty one.
+    rows: Vec<Vec<usize>>,
+}
+
+impl BoundedLevenshtein {
+    fn new(query: &str, max_distance: usize) -> Self {
+        let query = query.chars().collect::<Vec<_>>();
+        let first = (0..=query.len()).collect();
+
+        Self {
+            query,
+            max_distance,
+            chars: Vec::new(),
+            rows: vec![first],
+        }
+    }
+
+    fn matches(&mut self, term: &str) -> bool {
+        self.distance(term).is_some()
+    }
+
+    /// Edit distance from the query to the given term, `None` if it's over the maximum.
+    fn distance(&mut self, term: &str) -> Option<usize> {
+        let shared = self
+            .chars
+            .iter()
+            .zip(term.chars())
+            .take_while(|(a, b)| *a == b)
+            .count();
+        self.chars.truncate(shared);
+        self.rows.truncate(shared + 1);
+
+        for c in term.chars().skip(shared) {
+            let previous = &self.rows[self.rows.len() - 1];
+            let mut row = Vec::with_capacity(previous.len());
+            row.push(previous[0] + 1);
+            for (i, &q) in self.query.iter().enumerate() {
+                let substitution = previous[i] + usize::from(q != c);
+                row.push(substitution.min(previous[i + 1] + 1).min(row[i] + 1));
+            }
+
+            // Longer terms only get further away once every prefix of the query is too far.
+            let too_far = row.iter().all(|&distance| distance > self.max_distance);
+            self.chars.push(c);
+            self.rows.push(row);
+            if too_far {
+                return None;
+            }
+        }
+
+        Some(self.rows[self.rows.len() - 1][self.query.len()]).filter(|&d| d <= self.max_distance)
+    }
+}
+
+/// Token produced by [`Indexer::analyse`].
+struct AnalysedToken {
+    /// Normalised token, or the dropped one in the state it was passed to the normaliser that
+    /// dropped it.
+    token: std::result::Result<tokenise::Token, tokenise::Token>,
+    /// Token as produced by the tokeniser, if the exact case index is enabled.
+    exact: Option<tokenise::Token>,
+    /// Position of the token in the file, see [`storage::Postings`].
+    position: u64,
+}
+
+/// Kept tokens produced by [`Indexer::spans`] along with their positions.
+fn positioned(
+    spans: &[std::result::Result<tokenise::Token, tokenise::Token>],
+) -> Vec<(u64, &tokenise::Token)> {
+    spans
+        .iter()
+        .filter_map(|token| token.as_ref().ok())
+        .scan(0, |position, token| {
+            *position += u64::from(token.position_increment);
+            Some((*position, token))
+        })
+        .collect()
+}
+
+/// Split a proximity query (`left NEAR/n right`) into its terms and the distance between them.
+fn parse_near(query: &str) -> Option<(&str, u64, &str)> {
+    match query.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [left, operator, right] => {
+            let distance = operator.strip_prefix("NEAR/")?.parse().ok()?;
+            Some((left, distance, right))
+        }
+        _ => None,
+    }
+}
+
+/// Check whether any of the `left` positions is within `distance` of any of the `right` ones, both
+/// being sorted.
+fn within(left: &[u64], right: &[u64], distance: u64) -> bool {
+    left.iter().any(|&position| {
+        let first = right.partition_point(|&other| other.saturating_add(distance) < position);
+        right
+            .get(first)
+            .is_some_and(|&other| other <= position.saturating_add(distance))
+    })
+}
+
+/// Split a query into terms and quoted phrases, the latter are flagged as quoted.
+fn split_query(query: &str) -> Vec<(&str, bool)> {
+    let mut clauses = Vec::new();
+    let mut rest = query.trim_start();
+
+    while !rest.is_empty() {
+        let quoted = rest
+            .strip_prefix('"')
+            .and_then(|quoted| Some((quoted, quoted.find('"')?)));
+        let (clause, is_quoted, next) = match quoted {
+            Some((quoted, end)) => (&quoted[..end], true, &quoted[end + 1..]),
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                (&rest[..end], false, &rest[end..])
+            }
+        };
+
+        if !clause.is_empty() {
+            clauses.push((clause, is_quoted));
+        }
+        rest = next.trim_start();
+    }
+
+    clauses
+}
+
+/// Size and modification time of a file, used to detect modifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn new(metadata: &Metadata) -> Self {
+        Self {
+            len: metadata.len,
+            modified: metadata.modified,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        env, fmt, fs,
+        io::Write,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use tracing::{field, span, Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    use super::{wildcard_match, within, BoundedLevenshtein, ConfigUpdate, Indexer, QueryOptions};
+    use crate::{
+        analyse::Analyser,
+        budget::{BudgetAlert, IndexBudget, Resource},
+        cancel::CancellationToken,
+        document_store::MemoryDocumentStore,
+        dry_run::Target,
+        file_system::{FileSystem, MemoryFileSystem, OsFileSystem},
+        hash,
+        history::MutationKind,
+        normalise::{self, NormaliserOverrides},
+        query_hook::QueryHook,
+        rank::{PathBoost, RankedFile, RecencyBoost},
+        snapshot::{ConflictPolicy, ImportOutcome},
+        storage::LineMeta,
+        tap::TokenTap,
+        tokenise::{self, Token},
+        Error,
+    };
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = env::temp_dir().join("indexing-indexer-tests");
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path.canonicalize().unwrap()
+    }
+
+    fn offsets(indexer: &Indexer, term: &str) -> Vec<u64> {
+        indexer
+            .storage
+            .get(term)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .flat_map(|(_, offsets)| offsets.iter().map(|(&offset, _)| offset))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn indexer() -> Indexer {
+        Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+            .with_normaliser(normalise::LowerCase)
+            .with_normaliser(normalise::StopWords::new(&["the"]))
+    }
+
+    #[test]
+    fn stop_words_are_filtered_without_secondary_index() {
+        let path = temp_file("stop-words-filtered.txt", "The cat");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        assert!(indexer.is_filtered("the"));
+        assert!(indexer.query("the").is_empty());
+        assert!(indexer.query("\"the\"").is_empty());
+        assert_eq!(indexer.query("cat").len(), 1);
+    }
+
+    #[test]
+    fn quoted_stop_words_are_found_in_secondary_index() {
+        let path = temp_file("stop-words-secondary.txt", "The cat");
+        let indexer = indexer().with_stop_word_index();
+        indexer.index_file(&path).unwrap();
+
+        assert!(indexer.query("the").is_empty());
+        assert!(indexer
+            .query("\"The\"")
+            .contains(path.to_string_lossy().as_ref()));
+
+        indexer.clear_from_index(&path).unwrap();
+
+        assert!(indexer.query("\"the\"").is_empty());
+    }
+
+    #[test]
+    fn query_execution_is_described() {
+        let indexer = indexer();
+        indexer
+            .index_file(&temp_file("query-stats-a.txt", "cat dog"))
+            .unwrap();
+        indexer
+            .index_file(&temp_file("query-stats-b.txt", "cat"))
+            .unwrap();
+
+        let response = indexer.query_with_stats("Cat");
+        assert_eq!(response.paths.len(), 2);
+        assert_eq!(response.execution.terms, ["cat"]);
+        assert_eq!(response.execution.postings_scanned, 2);
+        assert_eq!(response.execution.generation, indexer.generation());
+
+        let response = indexer.query_with_stats("mouse");
+        assert!(response.paths.is_empty());
+        assert_eq!(response.execution.terms, ["mouse"]);
+        assert_eq!(response.execution.postings_scanned, 0);
+    }
+
+    #[test]
+    fn query_frequencies_are_exported_with_snapshots() {
+        let source = indexer().with_query_frequencies();
+        source
+            .index_file(&temp_file("frequencies.txt", "alpha"))
+            .unwrap();
+        source.query("alpha");
+        source.query("alpha");
+        source.query("missing");
+        let mut exported = Vec::new();
+        source.export_snapshot(&mut exported).unwrap();
+
+        let target = indexer().with_query_frequencies();
+        target.query("alpha");
+        target
+            .import_snapshot(exported.as_slice(), ConflictPolicy::KeepNewest)
+            .unwrap();
+
+        let frequencies = target.query_frequencies().unwrap();
+        assert_eq!(
+            frequencies
+                .iter()
+                .map(|frequency| (frequency.term.as_str(), frequency.count))
+                .collect::<Vec<_>>(),
+            [("alpha", 3), ("missing", 1)]
+        );
+        assert_eq!(
+            frequencies[1].last_queried,
+            source.query_frequencies().unwrap()[1].last_queried
+        );
+
+        let restored = indexer().with_query_frequencies();
+        restored.restore_snapshot(exported.as_slice()).unwrap();
+        assert!(restored.query_frequencies().unwrap().is_empty());
+        indexer()
+            .import_snapshot(exported.as_slice(), ConflictPolicy::KeepNewest)
+            .unwrap();
+    }
+
+    #[test]
+    fn imported_snapshots_resolve_conflicts_by_policy() {
+        let a = temp_file("import-a.txt", "alpha");
+        let b = temp_file("import-b.txt", "exported");
+        let source = indexer();
+        source.index_file(&a).unwrap();
+        source.index_file(&b).unwrap();
+        let mut exported = Vec::new();
+        source.export_snapshot(&mut exported).unwrap();
+
+        let b = temp_file("import-b.txt", "existing");
+        let target = indexer();
+        target.index_file(&b).unwrap();
+
+        let result = target
+            .import_snapshot(exported.as_slice(), ConflictPolicy::KeepNewest)
+            .unwrap();
+        assert_eq!(
+            result.documents,
+            [(a, ImportOutcome::Added), (b, ImportOutcome::Kept)]
+        );
+        assert_eq!(target.query("alpha").len(), 1);
+        assert!(target.query("exported").is_empty());
+
+        let result = target
+            .import_snapshot(exported.as_slice(), ConflictPolicy::KeepExisting)
+            .unwrap();
+        assert_eq!(result.count(ImportOutcome::Kept), 2);
+
+        let result = target
+            .import_snapshot(exported.as_slice(), ConflictPolicy::Replace)
+            .unwrap();
+        assert_eq!(result.count(ImportOutcome::Replaced), 2);
+        assert!(target.query("existing").is_empty());
+        assert_eq!(target.query("exported").len(), 1);
+        assert!(target.verify().is_consistent());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn snapshots_keep_raw_paths_and_capped_terms() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let file_system = Arc::new(MemoryFileSystem::new());
+        let raw = Path::new(OsStr::from_bytes(b"/snapshot/caf\xe9.txt"));
+        file_system.write(raw, b"quick fox fox");
+        file_system.write(Path::new("/snapshot/b.txt"), b"fox");
+        let source = indexer()
+            .with_posting_cap(2)
+            .with_file_system(Arc::clone(&file_system));
+        source.index_file(raw).unwrap();
+        source.index_file(Path::new("/snapshot/b.txt")).unwrap();
+        let mut exported = Vec::new();
+        source.export_snapshot(&mut exported).unwrap();
+
+        let target = indexer().with_posting_cap(2);
+        let result = target
+            .import_snapshot(exported.as_slice(), ConflictPolicy::Replace)
+            .unwrap();
+        assert!(result
+            .documents
+            .iter()
+            .any(|(path, outcome)| path == raw && *outcome == ImportOutcome::Added));
+        assert!(target.metadata(raw).unwrap().is_some());
+        assert!(target.storage.get("fox").unwrap().membership_only);
+        assert!(!target.storage.get("quick").unwrap().membership_only);
+        assert_eq!(offsets(&target, "quick"), offsets(&source, "quick"));
+        assert!(target.verify().is_consistent());
+    }
+
+    #[test]
+    fn term_filter_keeps_indexed_terms_searchable() {
+        let path = temp_file("term-filter.txt", "The cat sat");
+        let indexer = indexer().with_term_filter(100, 0.01);
+        indexer.index_file(&path).unwrap();
+
+        assert_eq!(indexer.query("cat").len(), 1);
+        assert_eq!(indexer.query("SAT").len(), 1);
+        assert!(indexer.query("dog").is_empty());
+    }
+
+    #[test]
+    fn exact_case_index_finds_identifiers_as_spelled() {
+        let path = temp_file(
+            "exact-case.rs",
+            "let map = HashMap::new();\nlet The = hashmap;",
+        );
+        let indexer = indexer().with_exact_case_index();
+        indexer.index_file(&path).unwrap();
+
+        assert_eq!(indexer.query("hashmap::new();").len(), 1);
+        assert_eq!(indexer.query_exact_case("HashMap::new();").len(), 1);
+        assert!(indexer.query_exact_case("hashmap::new();").is_empty());
+        assert_eq!(indexer.query_exact_case("The").len(), 1);
+
+        fs::write(&path, "let map = BTreeMap::new();\n").unwrap();
+        indexer.update_file(&path).unwrap();
+
+        assert!(indexer.query_exact_case("HashMap::new();").is_empty());
+        assert_eq!(indexer.query_exact_case("BTreeMap::new();").len(), 1);
+        assert!(indexer.verify().is_consistent());
+    }
+
+    #[test]
+    fn self_test_validates_the_analyser_configuration() {
+        let indexer = indexer();
+        let report = indexer.self_test();
+        assert!(report.passed(), "{}", report);
+        assert!(indexer.indexed_files().is_empty());
+
+        let indexer = indexer.with_normaliser(normalise::StopWords::new(&["fox"]));
+        let report = indexer.self_test();
+        assert!(!report.passed());
+        assert_eq!(
+            report
+                .checks
+                .iter()
+                .filter(|check| !check.passed)
+                .map(|check| check.name.as_str())
+                .collect::<Vec<_>>(),
+            ["query \"fox\""]
+        );
+    }
+
+    #[test]
+    fn prefix_and_wildcard_queries_expand_to_matching_terms() {
+        let index = temp_file("prefix-index.txt", "Index indexer");
+        let indices = temp_file("prefix-indices.txt", "indices inbox");
+        let indexer = indexer();
+        indexer.index_file(&index).unwrap();
+        indexer.index_file(&indices).unwrap();
+
+        assert_eq!(indexer.query_prefix("IND").len(), 2);
+        assert_eq!(indexer.query_prefix("indexe").len(), 1);
+        assert!(indexer.query_prefix("inx").is_empty());
+
+        assert_eq!(indexer.query_wildcard("ind*x").len(), 1);
+        assert_eq!(indexer.query_wildcard("ind*").len(), 2);
+        assert_eq!(indexer.query_wildcard("in?ox").len(), 1);
+        assert_eq!(indexer.query_wildcard("*ces").len(), 1);
+        assert!(indexer.query_wildcard("in?x").is_empty());
+    }
+
+    #[test]
+    fn metadata_is_recorded_and_exported() {
+        let path = temp_file("metadata.txt", "one two");
+        let source = indexer().with_analyser_version(3);
+        assert_eq!(source.metadata(&path).unwrap(), None);
+        source.index_file(&path).unwrap();
+
+        let meta = source.metadata(&path).unwrap().unwrap();
+        assert_eq!(meta.size, 7);
+        assert_eq!(meta.content_hash, hash::fnv1a(b"one two"));
+        assert_eq!(meta.modified, fs::metadata(&path).unwrap().modified().ok());
+        assert_eq!(meta.analyser_version, 3);
+
+        let mut exported = Vec::new();
+        source.export_snapshot(&mut exported).unwrap();
+        let target = indexer();
+        target
+            .import_snapshot(exported.as_slice(), ConflictPolicy::Replace)
+            .unwrap();
+        assert_eq!(target.metadata(&path).unwrap(), Some(meta));
+    }
+
+    #[test]
+    fn query_pages_are_sorted_by_path() {
+        let paths = ["page-c.txt", "page-a.txt", "page-b.txt"]
+            .iter()
+            .map(|name| temp_file(name, "paged"))
+            .collect::<Vec<_>>();
+        let indexer = indexer();
+        paths
+            .iter()
+            .for_each(|path| indexer.index_file(path).unwrap());
+
+        let name = |path: &String| Path::new(path).file_name().unwrap().to_owned();
+        let page = indexer.query_with_options("paged", &QueryOptions::new().with_limit(2));
+        assert_eq!(page.total, 3);
+        assert_eq!(
+            page.paths.iter().map(name).collect::<Vec<_>>(),
+            ["page-a.txt", "page-b.txt"]
+        );
+
+        let page =
+            indexer.query_with_options("paged", &QueryOptions::new().with_offset(2).with_limit(2));
+        assert_eq!(
+            page.paths.iter().map(name).collect::<Vec<_>>(),
+            ["page-c.txt"]
+        );
+        assert!(indexer
+            .query_with_options("paged", &QueryOptions::new().with_offset(3))
+            .paths
+            .is_empty());
+    }
+
+    #[test]
+    fn near_queries_bound_the_distance_between_terms() {
+        let path = temp_file("near.txt", "Error then a long wait before the timeout");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        assert_eq!(indexer.query_near("error NEAR/7 timeout").unwrap().len(), 1);
+        assert_eq!(indexer.query_near("TIMEOUT NEAR/7 error").unwrap().len(), 1);
+        assert!(indexer
+            .query_near("error NEAR/6 timeout")
+            .unwrap()
+            .is_empty());
+        assert!(indexer
+            .query_near("error NEAR/9 unicorn")
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            indexer.query_near("error NEAR timeout"),
+            Err(Error::InvalidQuery(_))
+        ));
+
+        // The positions are the indexed ones, like the terms.
+        fs::write(&path, "changed").unwrap();
+        assert_eq!(indexer.query_near("error NEAR/7 timeout").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn positions_follow_appends_and_changed_lines() {
+        fn postings(indexer: &Indexer, path: &Path) -> Vec<(String, Vec<(u64, u64)>)> {
+            indexer
+                .term_vector(path)
+                .unwrap()
+                .into_iter()
+                .map(|(term, _)| {
+                    let entries = indexer.storage.get(&term).unwrap();
+                    let postings = entries
+                        .iter()
+                        .flat_map(|(_, postings)| postings.iter())
+                        .map(|(&offset, &position)| (offset, position))
+                        .collect();
+                    (term, postings)
+                })
+                .collect()
+        }
+
+        let path = temp_file("positions.txt", "the error\nwas the first\n");
+        let updated = indexer()
+            .with_incremental_appends()
+            .with_line_diff_updates();
+        updated.index_file(&path).unwrap();
+
+        for contents in [
+            "the error\nwas the first\nthen a timeout",
+            "the error\nwas the first\nthen a timeout\nand another",
+            "the error\nwas followed by the second\nthen a timeout\nand another",
+            "the error\nthen a timeout\nand another",
+        ] {
+            fs::write(&path, contents).unwrap();
+            updated.update_file(&path).unwrap();
+
+            let reindexed = indexer();
+            reindexed.index_file(&path).unwrap();
+            assert_eq!(postings(&updated, &path), postings(&reindexed, &path));
+        }
+        assert_eq!(updated.query_near("error NEAR/3 timeout").unwrap().len(), 1);
+        assert!(updated
+            .query_near("error NEAR/2 timeout")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn within_matches_positions_on_either_side() {
+        assert!(within(&[1, 10], &[13], 3));
+        assert!(within(&[10], &[2, 7], 3));
+        assert!(!within(&[10], &[2, 14], 3));
+        assert!(!within(&[], &[1], 3));
+    }
+
+    #[test]
+    fn within_saturates_the_largest_distance() {
+        assert!(within(&[5], &[1], u64::MAX));
+        assert!(within(&[1], &[5], u64::MAX));
+        assert!(within(&[u64::MAX], &[0], u64::MAX));
+    }
+
+    #[test]
+    fn multi_term_queries_intersect_or_merge_the_results() {
+        let both = temp_file("multi-both.txt", "The alpha bravo");
+        let alpha = temp_file("multi-alpha.txt", "Alpha");
+        let indexer = indexer();
+        indexer.index_file(&both).unwrap();
+        indexer.index_file(&alpha).unwrap();
+
+        let all = indexer.query_all(&["ALPHA", "bravo"]);
+        assert_eq!(all.len(), 1);
+        assert!(all.contains(both.to_str().unwrap()));
+        assert!(indexer.query_all(&["alpha", "the"]).is_empty());
+        assert!(indexer.query_all(&[]).is_empty());
+
+        assert_eq!(indexer.query_any(&["alpha", "bravo"]).len(), 2);
+        assert_eq!(indexer.query_any(&["bravo", "charlie"]).len(), 1);
+    }
+
+    #[test]
+    fn dry_runs_report_tokens_without_indexing() {
+        let path = temp_file("dry-run.txt", "The Cat cat");
+        let indexer = indexer().with_stop_word_index();
+        let dry_run = indexer.dry_run_file(&path).unwrap();
+
+        let tokens = dry_run
+            .tokens
+            .iter()
+            .map(|token| (token.text.as_str(), token.term.as_str(), token.target))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                ("The", "the", Target::StopWordIndex),
+                ("Cat", "cat", Target::Index),
+                ("cat", "cat", Target::Index)
+            ]
+        );
+        let postings = dry_run
+            .postings
+            .iter()
+            .map(|posting| (posting.term.as_str(), posting.offsets.as_slice()))
+            .collect::<Vec<_>>();
+        assert_eq!(postings, [("cat", &[4, 8][..]), ("the", &[0][..])]);
+
+        assert!(indexer.query("cat").is_empty());
+        assert_eq!(indexer.generation(), 0);
+    }
+
+    #[test]
+    fn token_taps_receive_the_tokens_of_matching_files() {
+        let tapped = temp_file("tap-tapped.txt", "The Quick fox");
+        let other = temp_file("untapped.txt", "quick");
+        let indexer = indexer().with_stop_word_index();
+        let tokens = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = Arc::clone(&tokens);
+        let tap = TokenTap::new("**/tap-*.txt", move |path: &Path, token: &Token, target| {
+            let file = path.file_name().unwrap().to_string_lossy().into_owned();
+            let value = token.value.to_string();
+            sink.lock()
+                .unwrap()
+                .push((file, value, token.offset, target));
+        })
+        .unwrap();
+        indexer.set_token_tap(Some(tap));
+        indexer.index_file(&tapped).unwrap();
+        indexer.index_file(&other).unwrap();
+
+        let expected = |value: &str, offset, target| {
+            (
+                "tap-tapped.txt".to_owned(),
+                value.to_owned(),
+                offset,
+                target,
+            )
+        };
+        assert_eq!(
+            *tokens.lock().unwrap(),
+            [
+                expected("the", 0, Target::StopWordIndex),
+                expected("quick", 4, Target::Index),
+                expected("fox", 10, Target::Index)
+            ]
+        );
+
+        indexer.set_token_tap(None);
+        indexer.index_file(&tapped).unwrap();
+        assert_eq!(tokens.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn symbols_are_located_exact_matches_first() {
+        let path = temp_file(
+            "symbols.txt",
+            "let parse = parse_query\nparse query::Parse parsed",
+        );
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        let symbols = indexer
+            .find_symbol("Parse")
+            .iter()
+            .map(|symbol| (symbol.term.clone(), symbol.exact, symbol.to_string()))
+            .collect::<Vec<_>>();
+        let location = |line_column: &str| format!("{}:{}", path.display(), line_column);
+
+        assert_eq!(
+            symbols,
+            [
+                ("parse".to_owned(), true, location("1:5")),
+                ("parse".to_owned(), true, location("2:1")),
+                ("parse_query".to_owned(), false, location("1:13")),
+                ("query::parse".to_owned(), false, location("2:7"))
+            ]
+        );
+        assert!(indexer.find_symbol("").is_empty());
+    }
+
+    #[test]
+    fn spans_carry_the_index_generation_and_document() {
+        type Spans = Arc<Mutex<Vec<(&'static Metadata<'static>, Vec<(&'static str, String)>)>>>;
+
+        /// Subscriber keeping the fields recorded on every span.
+        struct SpanFields {
+            spans: Spans,
+            entered: Mutex<Vec<span::Id>>,
+        }
+
+        struct Fields<'a>(&'a mut Vec<(&'static str, String)>);
+
+        impl field::Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+                self.0.push((field.name(), format!("{:?}", value)));
+            }
+        }
+
+        impl Subscriber for SpanFields {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, attributes: &span::Attributes<'_>) -> span::Id {
+                let mut spans = self.spans.lock().unwrap();
+                let mut fields = Vec::new();
+                attributes.record(&mut Fields(&mut fields));
+                spans.push((attributes.metadata(), fields));
+                span::Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+                let mut spans = self.spans.lock().unwrap();
+                values.record(&mut Fields(&mut spans[id.into_u64() as usize - 1].1));
+            }
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, _: &Event<'_>) {}
+
+            fn enter(&self, id: &span::Id) {
+                self.entered.lock().unwrap().push(id.clone());
+            }
+
+            fn exit(&self, _: &span::Id) {
+                self.entered.lock().unwrap().pop();
+            }
+
+            fn current_span(&self) -> Current {
+                match self.entered.lock().unwrap().last() {
+                    Some(id) => {
+                        let metadata = self.spans.lock().unwrap()[id.into_u64() as usize - 1].0;
+                        Current::new(id.clone(), metadata)
+                    }
+                    None => Current::none(),
+                }
+            }
+        }
+
+        let file = temp_file("spans.txt", "quick fox");
+        let indexer = indexer().with_name("spans");
+        let spans = Spans::default();
+        let subscriber = SpanFields {
+            spans: Arc::clone(&spans),
+            entered: Mutex::new(Vec::new()),
+        };
+        tracing::subscriber::with_default(subscriber, || indexer.index_file(&file).unwrap());
+
+        let spans = spans.lock().unwrap();
+        let (_, fields) = spans
+            .iter()
+            .find(|(metadata, _)| metadata.name() == "index_file")
+            .unwrap();
+        let field = |name| {
+            fields
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(field("index"), Some("spans"));
+        assert_eq!(field("generation"), Some("0"));
+        assert_eq!(field("doc"), Some(file.to_string_lossy().as_ref()));
+    }
+
+    #[test]
+    fn recent_mutations_are_recorded() {
+        let first = temp_file("history-first.txt", "quick brown fox");
+        let second = temp_file("history-second.txt", "lazy dog");
+        let indexer = indexer().with_mutation_history(2);
+        indexer.index_file(&first).unwrap();
+        indexer.index_file(&second).unwrap();
+        indexer.clear_from_index(&first).unwrap();
+
+        let mutations = indexer.recent_mutations().unwrap();
+        assert_eq!(
+            mutations
+                .iter()
+                .map(|mutation| (mutation.kind, mutation.tokens))
+                .collect::<Vec<_>>(),
+            [(MutationKind::Index, 2), (MutationKind::Remove, 0)]
+        );
+        assert_eq!(mutations[0].path, second);
+        assert_eq!(mutations[1].generation, indexer.generation());
+        assert!(self::indexer().recent_mutations().is_none());
+    }
+
+    #[test]
+    fn counts_match_the_query_results() {
+        let indexer = indexer().with_deduplication();
+        for (name, contents) in [
+            ("count-a.txt", "quick fox"),
+            ("count-b.txt", "quick fox"),
+            ("count-c.txt", "lazy fox"),
+        ] {
+            indexer.index_file(&temp_file(name, contents)).unwrap();
+        }
+
+        for term in ["fox", "lazy", "missing"] {
+            assert_eq!(indexer.count(term), indexer.query(term).len());
+            assert_eq!(indexer.contains(term), !indexer.query(term).is_empty());
+        }
+        assert_eq!(indexer.count("fox"), 3);
+        assert!(!indexer.contains("missing"));
+    }
+
+    #[test]
+    fn query_results_are_streamed_in_path_order() {
+        struct Either;
+
+        impl QueryHook for Either {
+            fn rewrite(&self, terms: &mut Vec<String>) {
+                if terms == &["either"] {
+                    *terms = vec!["quick".to_owned(), "lazy".to_owned()];
+                }
+            }
+        }
+
+        let file_system = Arc::new(MemoryFileSystem::new());
+        for i in 0..200 {
+            let contents = match (i % 2, i % 3) {
+                (0, 0) => format!("quick lazy {}", i),
+                (0, _) => format!("quick {}", i),
+                (_, 0) => format!("lazy {}", i),
+                _ => format!("fox {}", i),
+            };
+            file_system.write(
+                Path::new(&format!("/stream/{:03}.txt", i)),
+                contents.as_bytes(),
+            );
+        }
+        file_system.write(Path::new("/stream/copy.txt"), b"quick lazy 0");
+        let indexer = indexer()
+            .with_deduplication()
+            .with_query_hook(Either)
+            .with_file_system(Arc::clone(&file_system));
+        for path in file_system.walk(Path::new("/stream")).skip(1) {
+            indexer.index_file(&path.unwrap()).unwrap();
+        }
+
+        let streamed = indexer.query_iter("either").collect::<Vec<_>>();
+        assert_eq!(
+            streamed[..3],
+            ["/stream/000.txt", "/stream/copy.txt", "/stream/002.txt"]
+        );
+        let mut sorted = streamed.clone();
+        sorted.sort();
+        let mut found = indexer.query("either").into_iter().collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(sorted, found);
+        assert_eq!(found.len(), 134);
+
+        let mut results = indexer.query_iter("lazy");
+        assert_eq!(results.next().as_deref(), Some("/stream/000.txt"));
+        indexer
+            .clear_from_index(Path::new("/stream/003.txt"))
+            .unwrap();
+        assert_eq!(results.nth(1).as_deref(), Some("/stream/003.txt"));
+        assert!(indexer.query_iter("missing").next().is_none());
+    }
+
+    #[test]
+    fn streamed_duplicates_follow_their_original() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/dup/b.txt"), b"alpha");
+        file_system.write(Path::new("/dup/c.txt"), b"alpha beta");
+        file_system.write(Path::new("/dup/a.txt"), b"alpha");
+        let indexer = indexer()
+            .with_deduplication()
+            .with_file_system(Arc::clone(&file_system));
+        for path in ["/dup/b.txt", "/dup/c.txt", "/dup/a.txt"] {
+            indexer.index_file(Path::new(path)).unwrap();
+        }
+
+        assert_eq!(
+            indexer.query_iter("alpha").collect::<Vec<_>>(),
+            ["/dup/b.txt", "/dup/a.txt", "/dup/c.txt"]
+        );
+        assert_eq!(
+            indexer.query_iter("beta").collect::<Vec<_>>(),
+            ["/dup/c.txt"]
+        );
+    }
+
+    #[test]
+    fn scoped_queries_only_find_the_files_under_the_directory() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        for path in [
+            "/scope/a/x.txt",
+            "/scope/a/nested/y.txt",
+            "/scope/a-b/z.txt",
+            "/scope/b/w.txt",
+        ] {
+            file_system.write(Path::new(path), b"quick fox");
+        }
+        let indexer = indexer().with_file_system(Arc::clone(&file_system));
+        for path in file_system.walk(Path::new("/scope")).skip(1) {
+            indexer.index_file(&path.unwrap()).unwrap();
+        }
+
+        let mut found = indexer
+            .query_in("fox", Path::new("/scope/a"))
+            .into_iter()
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, ["/scope/a/nested/y.txt", "/scope/a/x.txt"]);
+        assert_eq!(indexer.query_in("fox", Path::new("/scope")).len(), 4);
+        assert!(indexer.query_in("fox", Path::new("/other")).is_empty());
+    }
+
+    #[test]
+    fn query_hits_locate_every_match() {
+        let path = temp_file("query-hits.txt", "one Cat\ntwo\n  cat");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        let hits = indexer.query_hits("CAT");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(*hits[0].path, fs::canonicalize(&path).unwrap());
+        let positions = hits[0]
+            .matches
+            .iter()
+            .map(|m| (m.term.as_str(), m.offset, m.position.unwrap().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [("cat", 4, "1:5".into()), ("cat", 14, "3:3".into())]
+        );
+
+        fs::write(&path, "changed").unwrap();
+        let hits = indexer.query_hits("cat");
+        assert!(hits[0].matches.iter().all(|m| m.position.is_none()));
+    }
+
+    #[test]
+    fn query_hits_carry_snippets_when_enabled() {
+        let path = temp_file("query-snippets.txt", "header\nthe Quick brown fox");
+        let indexer = indexer().with_snippets(6);
+        indexer.index_file(&path).unwrap();
+
+        let hits = indexer.query_hits("quick");
+        let snippet = hits[0].matches[0].snippet.as_ref().unwrap();
+        assert_eq!(snippet.to_string(), "the [Quick] brown...");
+    }
+
+    #[test]
+    fn fuzzy_queries_tolerate_typos() {
+        let path = temp_file("fuzzy.txt", "kitten sitting mitten");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        assert_eq!(indexer.query_fuzzy("Kiten", 1).len(), 1);
+        assert!(indexer.query_fuzzy("sittn", 0).is_empty());
+        assert_eq!(indexer.query_fuzzy("sittn", 2).len(), 1);
+    }
+
+    #[test]
+    fn cancelled_queries_time_out() {
+        let path = temp_file("cancelled.txt", "kitten sitting mitten");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        let cancellation = CancellationToken::with_timeout(Duration::from_secs(60));
+        let found = indexer
+            .query_wildcard_cancellable("*itt*", &cancellation)
+            .unwrap();
+        assert_eq!(found, indexer.query_wildcard("*itt*"));
+
+        let expired = CancellationToken::with_deadline(Instant::now());
+        assert!(matches!(
+            indexer.query_fuzzy_cancellable("kiten", 1, &expired),
+            Err(Error::Timeout)
+        ));
+
+        cancellation.clone().cancel();
+        assert!(matches!(
+            indexer.query_wildcard_cancellable("*itt*", &cancellation),
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[test]
+    fn edit_distances_reuse_shared_prefixes() {
+        let mut distance = BoundedLevenshtein::new("kitten", 3);
+        let terms = ["kitchen", "kitten", "kittens", "mitten", "sitting", "zebra"];
+        let matches = terms
+            .iter()
+            .filter(|term| distance.matches(term))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            matches,
+            [&"kitchen", &"kitten", &"kittens", &"mitten", &"sitting"]
+        );
+
+        let mut distance = BoundedLevenshtein::new("kitten", 1);
+        let matches = terms
+            .iter()
+            .filter(|term| distance.matches(term))
+            .collect::<Vec<_>>();
+        assert_eq!(matches, [&"kitten", &"kittens", &"mitten"]);
+    }
+
+    #[test]
+    fn corrections_are_the_closest_and_most_frequent_terms() {
+        let indexer = indexer();
+        for (name, contents) in [
+            ("correct-1.txt", "indexer index indexes"),
+            ("correct-2.txt", "indexes indices"),
+            ("correct-3.txt", "ox"),
+        ] {
+            indexer.index_file(&temp_file(name, contents)).unwrap();
+        }
+
+        assert_eq!(indexer.correct("Indexs"), ["indexes", "index", "indexer"]);
+        assert_eq!(indexer.correct("index"), ["indexes", "indexer"]);
+        assert_eq!(indexer.correct("fox"), ["ox"]);
+        assert!(indexer.correct("unrelated").is_empty());
+    }
+
+    #[test]
+    fn wildcards_match_whole_terms() {
+        assert!(wildcard_match("ind*x", "index"));
+        assert!(wildcard_match("ind*x", "indx"));
+        assert!(wildcard_match("*a*b", "xaab"));
+        assert!(wildcard_match("?", "é"));
+        assert!(!wildcard_match("ind*x", "indexer"));
+        assert!(!wildcard_match("a?", "a"));
+    }
+
+    #[test]
+    fn analyser_samples_compare_dictionaries_without_mutating_the_index() {
+        let path = temp_file("sample-analyser.txt", "The Cat saw the cat");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        let candidate = Analyser::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _);
+        let report = indexer.sample_analyser(&candidate, 10);
+
+        assert_eq!((report.files, report.unreadable), (1, 0));
+        assert_eq!(report.tokens, 5);
+        assert_eq!((report.candidate_terms, report.current_terms), (5, 2));
+        assert_eq!(report.shared_terms, 2);
+        assert_eq!(report.added[0], (String::from("Cat"), 1));
+        assert!(report.removed.is_empty());
+        assert_eq!(indexer.verify().terms, 2);
+    }
+
+    #[test]
+    fn appended_data_is_indexed_incrementally() {
+        let path = temp_file("incremental-append.log", "one two\nthr");
+        let indexer = indexer().with_incremental_appends();
+        indexer.index_file(&path).unwrap();
+
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"ee four\n")
+            .unwrap();
+        indexer.update_file(&path).unwrap();
+
+        let path = path.to_string_lossy();
+        assert!(indexer.query("thr").is_empty());
+        assert!(indexer.query("three").contains(path.as_ref()));
+        assert!(indexer.query("four").contains(path.as_ref()));
+        assert!(indexer.query("one").contains(path.as_ref()));
+        assert_eq!(
+            indexer.term_vector(Path::new(path.as_ref())).unwrap(),
+            [("four", 1), ("one", 1), ("three", 1), ("two", 1)]
+                .iter()
+                .map(|&(term, count)| (term.to_owned(), count))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rewritten_file_is_indexed_from_scratch() {
+        let path = temp_file("incremental-rewrite.log", "one two\n");
+        let indexer = indexer().with_incremental_appends();
+        indexer.index_file(&path).unwrap();
+
+        fs::write(&path, "uno two\nthree\n").unwrap();
+        indexer.update_file(&path).unwrap();
+
+        assert!(indexer.query("one").is_empty());
+        assert_eq!(indexer.query("uno").len(), 1);
+        assert_eq!(indexer.query("three").len(), 1);
+    }
+
+    #[test]
+    fn archive_entries_are_indexed_under_archive_path() {
+        let path = temp_file("archive.zip", "");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+        zip.start_file("inner/file.txt", Default::default())
+            .unwrap();
+        zip.write_all(b"zipped words").unwrap();
+        zip.finish().unwrap();
+        drop(zip);
+
+        let indexer = indexer().with_archives(1);
+        indexer.index_file(&path).unwrap();
+
+        let entry = format!("{}!/inner/file.txt", path.display());
+        assert_eq!(
+            indexer.query("zipped"),
+            [entry.clone()].iter().cloned().collect()
+        );
+
+        assert_eq!(indexer.reindex(&[PathBuf::from(&entry)]), 1);
+        assert_eq!(indexer.query("zipped"), [entry].iter().cloned().collect());
+
+        indexer.clear_from_index(&path).unwrap();
+
+        assert!(indexer.query("zipped").is_empty());
+    }
+
+    #[test]
+    fn paths_ending_in_bang_are_not_mistaken_for_archive_entries() {
+        for dir in ["notes!", "fake.zip!"] {
+            fs::create_dir_all(env::temp_dir().join("indexing-indexer-tests").join(dir)).unwrap();
+        }
+        let notes = temp_file("notes!/a.txt", "noted");
+        let fake = temp_file("fake.zip!/b.txt", "faked");
+        let indexer = indexer().with_archives(1);
+        indexer.index_file(&notes).unwrap();
+        indexer.index_file(&fake).unwrap();
+        fs::write(&notes, "renoted").unwrap();
+        fs::write(&fake, "refaked").unwrap();
+
+        assert_eq!(indexer.reindex(&[notes, fake]), 2);
+        assert_eq!(indexer.query("renoted").len(), 1);
+        assert_eq!(indexer.query("refaked").len(), 1);
+    }
+
+    #[test]
+    fn content_hashes_follow_the_indexed_contents() {
+        let path = temp_file("content-hash.txt", "quick fox\n");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        let hashes = |indexer: &Indexer| {
+            let indexed = indexer.indexed_files();
+            let found = indexer.query_files("fox");
+            assert_eq!(indexed, found);
+            assert_eq!(indexed[0].path, path.to_string_lossy());
+            indexed[0].content_hash
+        };
+        assert_eq!(hashes(&indexer), hash::fnv1a(b"quick fox\n"));
+
+        fs::write(&path, "quick fox\nlazy dog\n").unwrap();
+        indexer.update_file(&path).unwrap();
+        assert_eq!(hashes(&indexer), hash::fnv1a(b"quick fox\nlazy dog\n"));
+    }
+
+    #[test]
+    fn duplicates_share_entries_of_original() {
+        let original = temp_file("duplicate-original.txt", "same words");
+        let duplicate = temp_file("duplicate-copy.txt", "same words");
+        let indexer = indexer().with_deduplication();
+        indexer.index_file(&original).unwrap();
+        indexer.index_file(&duplicate).unwrap();
+
+        assert_eq!(indexer.query("same").len(), 2);
+        assert!(indexer.storage.term_vector(&duplicate).is_none());
+
+        fs::write(&original, "other words").unwrap();
+        indexer.update_file(&original).unwrap();
+
+        assert!(indexer
+            .query("same")
+            .contains(duplicate.to_string_lossy().as_ref()));
+        assert!(indexer
+            .query("other")
+            .contains(original.to_string_lossy().as_ref()));
+        assert_eq!(indexer.query("words").len(), 2);
+    }
+
+    #[test]
+    fn duplicates_are_confirmed_by_contents_or_file_identity() {
+        let original = temp_file("duplicate-confirmed-original.txt", "quick fox");
+        let colliding = temp_file("duplicate-confirmed-colliding.txt", "lazy dogs");
+        let indexer = indexer().with_deduplication();
+        indexer.index_file(&original).unwrap();
+
+        // Pretend the contents of the original hash the same as different contents of the same length.
+        let duplicates = indexer.duplicates.as_ref().unwrap();
+        duplicates.remove(&original);
+        duplicates.insert_original(&original, hash::fnv1a(b"lazy dogs"), None);
+
+        indexer.index_file(&colliding).unwrap();
+        assert!(indexer.duplicates_of(&original).is_empty());
+        assert!(indexer
+            .query("lazy")
+            .contains(colliding.to_string_lossy().as_ref()));
+
+        #[cfg(unix)]
+        {
+            let link = original.with_file_name("duplicate-confirmed-link.txt");
+            let _ = fs::remove_file(&link);
+            fs::hard_link(&original, &link).unwrap();
+
+            // The hash points elsewhere, but the link is the same file as the original.
+            let file_id = OsFileSystem.metadata(&original).unwrap().file_id;
+            assert!(file_id.is_some());
+            duplicates.remove(&original);
+            duplicates.insert_original(&original, 0, file_id);
+
+            indexer.index_file(&link).unwrap();
+            assert_eq!(indexer.duplicates_of(&original), [link]);
+            assert_eq!(indexer.query("quick").len(), 2);
+        }
+    }
+
+    #[test]
+    fn root_analyser_overrides_default() {
+        let root = env::temp_dir().join("indexing-indexer-tests/root-analyser");
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("file.txt");
+        fs::write(&path, "The Cat").unwrap();
+
+        let indexer = indexer();
+        indexer
+            .set_root_analyser(
+                &root,
+                Analyser::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _),
+            )
+            .unwrap();
+        indexer.index_file(&path).unwrap();
+
+        assert!(!indexer.is_filtered("the"));
+        assert_eq!(indexer.query("The").len(), 1);
+        assert_eq!(indexer.query("Cat").len(), 1);
+        assert!(indexer.query("cat").is_empty());
+    }
+
+    #[test]
+    fn query_all_doesnt_wait_for_the_indexing_in_progress() {
+        let path = temp_file("query-all-in-progress.txt", "quick brown fox");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+
+        // Taking a replica would wait for the transaction to finish.
+        let _transaction = indexer.snapshot_gate.read().unwrap();
+        assert_eq!(indexer.query_all(&["quick", "fox"]).len(), 1);
+        assert_eq!(indexer.query_any(&["quick", "dog"]).len(), 1);
+    }
+
+    #[test]
+    fn query_terms_only_match_the_files_of_their_analyser() {
+        let root = env::temp_dir().join("indexing-indexer-tests/analyser-scopes");
+        fs::create_dir_all(&root).unwrap();
+        let cased = root.join("cased.txt");
+        fs::write(&cased, "cat").unwrap();
+        let plain = temp_file("analyser-scopes.txt", "Cat");
+
+        let indexer = indexer();
+        indexer
+            .set_root_analyser(
+                &root,
+                Analyser::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _),
+            )
+            .unwrap();
+        indexer.index_file(&cased).unwrap();
+        indexer.index_file(&plain).unwrap();
+
+        // Only the default analyser lowercases `Cat`, the files under the root are case sensitive.
+        let plain = plain.to_string_lossy().into_owned();
+        assert_eq!(indexer.query("Cat"), HashSet::from([plain.clone()]));
+        assert_eq!(indexer.replica().query("Cat"), HashSet::from([plain]));
+        assert_eq!(indexer.query("cat").len(), 2);
+        assert_eq!(indexer.replica().query("cat").len(), 2);
+    }
+
+    #[test]
+    fn query_normalisers_can_be_overridden() {
+        let root = env::temp_dir().join("indexing-indexer-tests/normaliser-overrides");
+        fs::create_dir_all(&root).unwrap();
+        let cased = root.join("cased.txt");
+        fs::write(&cased, "Quick").unwrap();
+        let plain = temp_file("normaliser-overrides.txt", "quick the");
+
+        let indexer = indexer().with_stop_word_index();
+        indexer
+            .set_root_analyser(
+                &root,
+                Analyser::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _),
+            )
+            .unwrap();
+        indexer.index_file(&cased).unwrap();
+        indexer.index_file(&plain).unwrap();
+
+        let query = |term: &str, overrides: NormaliserOverrides| {
+            indexer
+                .query_with_options(term, &QueryOptions::new().with_normalisers(overrides))
+                .paths
+        };
+        let paths = |path: &Path| vec![path.to_string_lossy().into_owned()];
+
+        assert_eq!(query("Quick", NormaliserOverrides::new()).len(), 2);
+        assert_eq!(
+            query(
+                "Quick",
+                NormaliserOverrides::new().with_skipped("LowerCase")
+            ),
+            paths(&cased)
+        );
+        assert!(query("the", NormaliserOverrides::new()).is_empty());
+        assert_eq!(
+            query("the", NormaliserOverrides::new().with_skipped("StopWords")),
+            paths(&plain)
+        );
+        assert_eq!(query("QUICK", NormaliserOverrides::new()), paths(&plain));
+        assert!(query(
+            "QUICK",
+            NormaliserOverrides::new().with_substitute("LowerCase", normalise::Unicode::NFC)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn changed_lines_are_replaced() {
+        let path = temp_file("changed-lines.txt", "one two\nthree\nfour five\nsix");
+        let updated = indexer().with_line_diff_updates();
+        updated.index_file(&path).unwrap();
+
+        fs::write(&path, "one two\nthirty three\nfour five\nsix").unwrap();
+        updated.update_file(&path).unwrap();
+
+        let reindexed = indexer();
+        reindexed.index_file(&path).unwrap();
+
+        assert_eq!(
+            updated.term_vector(&path).unwrap(),
+            reindexed.term_vector(&path).unwrap()
+        );
+        for term in &["one", "thirty", "three", "five", "six"] {
+            assert_eq!(updated.query(term), reindexed.query(term));
+        }
+        for term in &["four", "six"] {
+            assert_eq!(offsets(&updated, term), offsets(&reindexed, term));
+        }
+    }
+
+    #[test]
+    fn tokens_crossing_lines_are_updated() {
+        let spaces = || Indexer::new(|| Box::new(tokenise::RegexTokeniser::new(" ").unwrap()) as _);
+        let path = temp_file("crossing-lines.txt", "alpha beta\ngamma delta");
+        let updated = spaces();
+        updated.index_file(&path).unwrap();
+
+        fs::write(&path, "alpha beta\nomega delta").unwrap();
+        updated.update_file(&path).unwrap();
+
+        let reindexed = spaces();
+        reindexed.index_file(&path).unwrap();
+
+        assert!(offsets(&updated, "beta\ngamma").is_empty());
+        assert_eq!(offsets(&updated, "beta\nomega"), [6]);
+        assert_eq!(
+            updated.term_vector(&path).unwrap(),
+            reindexed.term_vector(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn changed_lines_are_compared_with_kept_contents() {
+        let path = temp_file("changed-lines-kept.txt", "one\ntwo\n");
+        let indexer = indexer()
+            .with_line_diff_updates()
+            .with_document_store(MemoryDocumentStore::new());
+        indexer.index_file(&path).unwrap();
+
+        // Pretend the hash of the indexed second line collides with the hash of the new one.
+        let mut meta = indexer.storage.meta(&path).unwrap();
+        meta.lines = meta.lines.update(&4, |line| LineMeta {
+            hash: hash::fnv1a(b"six\n"),
+            ..*line
+        });
+        indexer.storage.set_meta(&path, meta);
+
+        fs::write(&path, "one\nsix\n").unwrap();
+        indexer.update_file(&path).unwrap();
+
+        assert!(indexer.query("two").is_empty());
+        assert_eq!(indexer.query("six").len(), 1);
+        assert_eq!(offsets(&indexer, "six"), [4]);
+    }
+
+    #[test]
+    fn inferred_stop_words_are_moved_to_secondary_index() {
+        let indexer = indexer().with_stop_word_index();
+        let a = temp_file("inferred-stop-words-a.txt", "info started info\n");
+        let b = temp_file("inferred-stop-words-b.txt", "info stopped\n");
+        indexer.index_file(&a).unwrap();
+        indexer.index_file(&b).unwrap();
+
+        assert_eq!(indexer.suggest_stop_words(0.9), [("info".to_owned(), 1.0)]);
+        assert_eq!(indexer.infer_stop_words(0.9), ["info"]);
+        assert!(indexer.query("info").is_empty());
+        assert!(indexer.is_filtered("INFO"));
+        assert_eq!(indexer.query("\"info\"").len(), 2);
+        assert!(indexer.verify().is_consistent());
+
+        indexer.index_file(&b).unwrap();
+
+        assert_eq!(indexer.query("\"info\"").len(), 2);
+        assert!(indexer
+            .terms(None, 10)
+            .iter()
+            .all(|(term, _)| term != "info"));
+    }
+
+    #[test]
+    fn documents_outlive_their_files() {
+        let indexer = indexer()
+            .with_incremental_appends()
+            .with_document_store(MemoryDocumentStore::new());
+        let path = temp_file("document-store.txt", "first line\n");
+        indexer.index_file(&path).unwrap();
+
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"second line\n")
+            .unwrap();
+        indexer.update_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            indexer.document(&path).unwrap().as_deref(),
+            Some(&b"first line\nsecond line\n"[..])
+        );
+
+        indexer.clear_from_index(&path).unwrap();
+
+        assert!(indexer.document(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn highlight_spans_terms_and_phrases() {
+        let indexer = indexer();
+        let path = temp_file(
+            "highlight.txt",
+            "The quick Fox jumps over the lazy dog, the fox",
+        );
+        indexer.index_file(&path).unwrap();
+
+        let highlight = |query| {
+            indexer
+                .highlight(&path, query)
+                .unwrap()
+                .into_iter()
+                .map(|range| (range.start, range.end))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(highlight("fox"), [(10, 13), (43, 46)]);
+        assert_eq!(highlight("\"over the lazy\" quick"), [(4, 9), (20, 33)]);
+        assert_eq!(highlight("\"the\""), [(0, 3), (25, 28), (39, 42)]);
+        assert!(highlight("the").is_empty());
+    }
+
+    #[test]
+    fn highlight_ranges_cover_source_text() {
+        let indexer = indexer();
+        // Lower case of "İ" is one byte longer than the letter itself.
+        let path = temp_file("highlight-source.txt", "Visit İSTANBUL today");
+        indexer.index_file(&path).unwrap();
+
+        let ranges = indexer.highlight(&path, "İstanbul").unwrap();
+
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].start, ranges[0].end), (6, 15));
+    }
+
+    #[test]
+    fn dropped_terms_leave_gaps_in_phrases() {
+        let path = temp_file("position-gaps.txt", "to be or not to be\nbe");
+        let stop_words = || normalise::StopWords::new(&["to", "or", "not"]);
+        let highlight = |indexer: Indexer, query| {
+            indexer.index_file(&path).unwrap();
+            indexer
+                .highlight(&path, query)
+                .unwrap()
+                .into_iter()
+                .map(|range| (range.start, range.end))
+                .collect::<Vec<_>>()
+        };
+        let gaps = || {
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_normaliser(stop_words())
+        };
+
+        assert_eq!(highlight(gaps(), "\"to be or not to be\""), [(3, 18)]);
+        assert_eq!(highlight(gaps(), "\"be be\""), [(16, 21)]);
+        assert_eq!(
+            highlight(gaps().with_position_gaps(false), "\"be be\""),
+            [(3, 21)]
+        );
+    }
+
+    #[test]
+    fn ranked_query_boosts_recent_files() {
+        let often = temp_file("ranked-often.txt", "error error");
+        let once = temp_file("ranked-once.txt", "error");
+        let day_ago = SystemTime::now() - Duration::from_secs(24 * 3600);
+        fs::File::options()
+            .write(true)
+            .open(&often)
+            .unwrap()
+            .set_modified(day_ago)
+            .unwrap();
+
+        let ranked = |indexer: Indexer| {
+            indexer.index_file(&often).unwrap();
+            indexer.index_file(&once).unwrap();
+            indexer
+                .query_ranked("error")
+                .into_iter()
+                .map(|file| PathBuf::from(file.path))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ranked(indexer()), [often.clone(), once.clone()]);
+        assert_eq!(
+            ranked(indexer().with_score_modifier(RecencyBoost::new(Duration::from_secs(3600)))),
+            [once, often]
+        );
+    }
+
+    #[test]
+    fn explanations_break_down_ranked_scores() {
+        let both = temp_file("explain-both.txt", "fox fox dog");
+        let fox = temp_file("explain-fox.txt", "fox cat");
+        let cat = temp_file("explain-cat.txt", "cat");
+        let boost = PathBoost::new(both.parent().unwrap(), &[("explain-both.txt", 2.0)]).unwrap();
+        let indexer = indexer().with_score_modifier(boost);
+        for path in [&both, &fox, &cat] {
+            indexer.index_file(path).unwrap();
+        }
+
+        let explanation = indexer.explain("fox", &both).unwrap().unwrap();
+        let ranked = indexer.query_ranked("fox");
+        assert_eq!(explanation.path, ranked[0].path);
+        assert_eq!(explanation.score, ranked[0].score);
+
+        let term = &explanation.terms[0];
+        assert_eq!(
+            (
+                term.term.as_str(),
+                term.occurrences,
+                term.files,
+                term.total_files
+            ),
+            ("fox", 2, 2, 3)
+        );
+        assert_eq!(term.tf, 1.0 + 2f64.ln());
+        assert_eq!(explanation.base_score, term.score);
+        assert_eq!(
+            explanation.modifiers,
+            [("PathBoost".to_owned(), 2.0 * term.score)]
+        );
+
+        let explanation = indexer.explain("fox", &fox).unwrap().unwrap();
+        assert_eq!(explanation.score, ranked[1].score);
+        assert!(explanation
+            .to_string()
+            .starts_with(&format!("{}: ", fox.display())));
+        assert!(indexer.explain("fox", &cat).unwrap().is_none());
+    }
+
+    #[test]
+    fn query_hooks_rewrite_terms_and_annotate_results() {
+        struct Abbreviations;
+
+        impl QueryHook for Abbreviations {
+            fn rewrite(&self, terms: &mut Vec<String>) {
+                if terms.iter().any(|term| term == "db") {
+                    terms.push("database".to_owned());
+                }
+            }
+
+            fn post_process(&self, terms: &[String], results: &mut Vec<RankedFile>) {
+                for file in results.iter_mut() {
+                    file.annotations.push(terms.join(" | "));
+                }
+            }
+        }
+
+        let short = temp_file("hooks-short.txt", "db");
+        let long = temp_file("hooks-long.txt", "database");
+        let indexer = indexer().with_query_hook(Abbreviations);
+        indexer.index_file(&short).unwrap();
+        indexer.index_file(&long).unwrap();
+
+        assert_eq!(indexer.query("db").len(), 2);
+        assert_eq!(indexer.query("database").len(), 1);
+
+        let ranked = indexer.query_ranked("db");
+        assert_eq!(ranked.len(), 2);
+        assert!(ranked
+            .iter()
+            .all(|file| file.annotations == ["db | database"]));
+    }
+
+    #[test]
+    fn collapsed_duplicates_are_listed_as_aliases() {
+        let original = temp_file("collapsed-original.txt", "linked contents");
+        let link = original.with_file_name("collapsed-link.txt");
+        let _ = fs::remove_file(&link);
+        fs::hard_link(&original, &link).unwrap();
+
+        let indexer = indexer().with_collapsed_duplicates();
+        indexer.index_file(&original).unwrap();
+        indexer.index_file(&link).unwrap();
+
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+
+        assert_eq!(
+            indexer.query("linked").into_iter().collect::<Vec<_>>(),
+            [path(&original)]
+        );
+
+        let files = indexer.query_files("linked");
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].aliases, [path(&link)]);
+
+        let ranked = indexer.query_ranked("linked");
+        assert_eq!(ranked.len(), 1);
+        assert_eq!(ranked[0].aliases, [path(&link)]);
+    }
+
+    #[test]
+    fn files_are_read_from_the_given_file_system() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        let path = Path::new("/memory/notes.txt");
+        file_system.write(path, b"remember the milk");
+
+        let indexer = indexer().with_file_system(Arc::clone(&file_system));
+        indexer.index_file(path).unwrap();
+
+        assert_eq!(
+            indexer.query("milk").into_iter().collect::<Vec<_>>(),
+            ["/memory/notes.txt"]
+        );
+
+        file_system.write(path, b"remember the bread");
+        indexer.update_file(path).unwrap();
+
+        assert!(indexer.query("milk").is_empty());
+        assert_eq!(indexer.query("bread").len(), 1);
+        assert!(indexer
+            .index_file(Path::new("/memory/missing.txt"))
+            .is_err());
+    }
+
+    #[test]
+    fn config_updates_mark_files_stale() {
+        let path = temp_file("config-update.txt", "The Cat sat");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+        indexer.add_stop_words(&["sat"]);
+
+        indexer.update_config(ConfigUpdate::new().with_stop_words(&["cat"]));
+
+        assert!(indexer.query("cat").is_empty());
+        assert!(indexer.query("sat").is_empty());
+        assert!(indexer.is_stale());
+        assert_eq!(indexer.reindex_stale(), 1);
+        assert_eq!(indexer.query("sat").len(), 1);
+        assert!(!indexer.is_stale());
+
+        indexer.update_config(
+            ConfigUpdate::new()
+                .with_analyser(Analyser::new(|| {
+                    Box::new(tokenise::SpaceTokeniser::new()) as _
+                }))
+                .with_stop_words(&[]),
+        );
+
+        assert_eq!(indexer.stale_files(), [path.to_string_lossy().into_owned()]);
+        assert!(indexer.query("The").is_empty());
+
+        indexer.update_file(&path).unwrap();
+
+        assert_eq!(indexer.query("The").len(), 1);
+        assert_eq!(indexer.query("Cat").len(), 1);
+        assert!(!indexer.is_stale());
+    }
+
+    #[test]
+    fn outdated_files_are_found_by_analyser_version() {
+        let path = temp_file("outdated.txt", "The Cat");
+        let indexer = indexer();
+        indexer.index_file(&path).unwrap();
+        assert!(indexer.outdated_files().is_empty());
+
+        indexer.update_config(ConfigUpdate::new().with_analyser(
+            Analyser::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _).with_version(1),
+        ));
+
+        assert_eq!(
+            indexer.outdated_files(),
+            [path.to_string_lossy().into_owned()]
+        );
+        assert_eq!(indexer.reindex_outdated(), 1);
+        assert!(indexer.outdated_files().is_empty());
+        assert_eq!(indexer.query("The").len(), 1);
+        assert_eq!(
+            indexer.metadata(&path).unwrap().unwrap().analyser_version,
+            1
+        );
+    }
+
+    #[test]
+    fn reindex_matching_only_touches_matching_files() {
+        let markdown = temp_file("reindex-matching.md", "Heading");
+        let text = temp_file("reindex-matching.txt", "Heading");
+        let indexer = indexer();
+        indexer.index_file(&markdown).unwrap();
+        indexer.index_file(&text).unwrap();
+
+        indexer
+            .set_root_analyser(
+                markdown.parent().unwrap(),
+                Analyser::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _),
+            )
+            .unwrap();
+
+        assert_eq!(indexer.query("heading").len(), 2);
+        assert_eq!(
+            indexer.reindex_matching("**/reindex-matching.md").unwrap(),
+            1
+        );
+        // The Markdown file is now only found by its original spelling.
+        assert_eq!(
+            indexer.query("heading").into_iter().collect::<Vec<_>>(),
+            [text.to_string_lossy().into_owned()]
+        );
+        assert!(indexer.reindex_matching("[").is_err());
+    }
+
+    #[test]
+    fn exceeding_the_budget_degrades_health() {
+        let first = temp_file("budget-first.txt", "one two");
+        let second = temp_file("budget-second.txt", "three");
+        let indexer = indexer().with_budget(
+            IndexBudget::new()
+                .with_max_documents(1)
+                .with_check_interval(Duration::ZERO),
+        );
+        let alerts = indexer.subscribe_budget_alerts().unwrap();
+
+        indexer.index_file(&first).unwrap();
+        assert!(indexer.health().is_healthy());
+
+        indexer.index_file(&second).unwrap();
+        assert!(!indexer.health().is_healthy());
+        assert_eq!(
+            alerts.try_recv().unwrap(),
+            BudgetAlert {
+                resource: Resource::Documents,
+                usage: 2,
+                limit: 1,
+            }
+        );
+
+        indexer.clear_from_index(&second).unwrap();
+        assert!(indexer.check_budget().is_healthy());
+    }
+
+    #[test]
+    fn exceeding_the_memory_budget_drops_offsets() {
+        let first = temp_file("degraded-first.txt", "red fish blue fish");
+        let second = temp_file("degraded-second.txt", "green frog");
+        let indexer = indexer().with_budget(
+            IndexBudget::new()
+                .with_max_memory(1)
+                .with_memory_degradation()
+                .with_hot_term_downgrade(1)
+                .with_check_interval(Duration::ZERO),
+        );
+
+        indexer.index_file(&first).unwrap();
+        assert!(indexer.storage.get("fish").unwrap().membership_only);
+        assert!(!indexer.storage.get("blue").unwrap().membership_only);
+
+        indexer.index_file(&second).unwrap();
+        let frog = indexer.storage.get("frog").unwrap();
+        assert!(!frog.membership_only);
+        assert!(frog
+            .iter()
+            .all(|(_, offsets)| offsets.iter().next().is_none()));
+        assert!(indexer.storage.meta(&second).unwrap().membership_only);
+
+        assert_eq!(indexer.query("frog").len(), 1);
+        assert!(indexer.verify().is_consistent());
     }
 }
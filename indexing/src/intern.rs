@@ -1,17 +1,25 @@
 //! This module enables a user to intern strings or any other type that implements `Ord` and `Clone`.
 
-use std::{borrow::Borrow, ops::Deref, ptr, sync::Arc};
+use std::{
+    borrow::Borrow,
+    ops::Deref,
+    path::{Path, PathBuf},
+    ptr,
+    sync::Arc,
+};
 
-use crate::storage::MvccAvl;
+use crate::storage::{Avl, MvccAvl};
 
 /// Interned value pool.
-pub struct InternPool<T> {
-    values: MvccAvl<T, Arc<T>>,
+///
+/// Values are looked up by a key of type `K`, which is the value itself by default.
+pub struct InternPool<T, K = T> {
+    values: MvccAvl<K, Arc<T>>,
 }
 
-impl<T> InternPool<T>
+impl<T, K> InternPool<T, K>
 where
-    T: Clone + Ord,
+    K: Clone + Ord,
 {
     /// Create a new instance of [`InternPool`].
     pub fn new() -> Self {
@@ -20,20 +28,38 @@ where
         }
     }
 
-    /// Intern a value.
+    /// Get the value previously interned under the given key, without interning anything.
+    pub(crate) fn get<Q>(&self, key: &Q) -> Option<InternRef<T>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.values
+            .snapshot()
+            .get(key)
+            .map(|reference| InternRef(Arc::clone(&reference)))
+    }
+
+    /// Get the current version of the key-value mapping of the pool.
+    pub(crate) fn snapshot(&self) -> Avl<K, Arc<T>> {
+        self.values.snapshot()
+    }
+
+    /// Intern a value under the given key.
     ///
-    /// The [`InternRef`] returned will point to a unique piece of memory for every distinct
-    /// value supplied.
-    pub(crate) fn intern<K>(&self, value: &K) -> InternRef<T>
+    /// The [`InternRef`] returned will point to a unique piece of memory for every distinct key
+    /// supplied, holding the value produced by `value` the first time the key was seen.
+    pub(crate) fn intern_by<Q, F>(&self, key: &Q, value: F) -> InternRef<T>
     where
-        T: Borrow<K>,
-        K: ?Sized + Ord + ToOwned<Owned = T>,
+        K: Borrow<Q>,
+        Q: ?Sized + Ord + ToOwned<Owned = K>,
+        F: FnOnce() -> T,
     {
-        if let Some(reference) = self.values.snapshot().get(value).as_deref() {
-            InternRef(Arc::clone(reference))
+        if let Some(reference) = self.get(key) {
+            reference
         } else {
-            let interned = Arc::new(value.to_owned());
-            self.values.insert(value.to_owned(), interned.clone());
+            let interned = Arc::new(value());
+            self.values.insert(key.to_owned(), interned.clone());
             InternRef(interned)
         }
     }
@@ -51,6 +77,21 @@ impl<T> PartialEq for InternRef<T> {
     }
 }
 
+impl<T> InternRef<T> {
+    /// Share the interned value.
+    pub(crate) fn to_arc(&self) -> Arc<T> {
+        Arc::clone(&self.0)
+    }
+}
+
+// Interned paths are ordered by their value, so that the entries keyed by them can be looked up by
+// a plain path.
+impl Borrow<Path> for InternRef<PathBuf> {
+    fn borrow(&self) -> &Path {
+        &self.0
+    }
+}
+
 impl<T> Deref for InternRef<T> {
     type Target = T;
 
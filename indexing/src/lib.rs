@@ -10,15 +10,58 @@
 //! The index can be automatically maintained by the means of [`LiveIndexer`] which
 //! watches the files and performs an indexing/purging as a reaction on watch events.
 
+pub mod analyse;
+pub mod audit;
+pub mod budget;
+pub mod cancel;
+pub mod document_store;
+pub mod drops;
+pub mod dry_run;
+pub mod facet;
+pub mod file_system;
+pub mod health;
+pub mod history;
+pub mod hit;
 pub mod normalise;
+pub mod path_format;
+pub mod profiling;
+pub mod query_hook;
+pub mod query_pool;
+pub mod query_results;
+pub mod query_stats;
+pub mod rank;
+pub mod replica;
+pub mod rotation;
+pub mod sampling;
+pub mod self_test;
+pub mod snapshot;
+pub mod storage_metrics;
+pub mod tap;
 pub mod tokenise;
 
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+#[cfg(feature = "lock-contention")]
+pub mod contention;
+
+mod archive;
+mod bounded_log;
+mod compact_str;
 mod error;
+mod hash;
 mod indexer;
 mod intern;
 mod live_indexer;
+mod path;
+mod semaphore;
 mod storage;
 
+pub use compact_str::CompactString;
 pub use error::{Error, Result};
-pub use indexer::Indexer;
-pub use live_indexer::LiveIndexer;
+pub use indexer::{ConfigUpdate, DocumentMetadata, IndexedFile, Indexer, QueryOptions, QueryPage};
+pub use live_indexer::{
+    ErrorPolicy, LiveIndexer, LiveIndexerOptions, RelativePath, RootCoverage, WatchGroup,
+    WatchGroupStatus, WatchHandle, WatchOptions,
+};
+pub use path::PathCase;
@@ -1,16 +1,29 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    io,
     path::{Path, PathBuf},
-    sync::{mpsc, Arc, Mutex},
+    sync::{
+        mpsc::{self, RecvTimeoutError, TryRecvError},
+        Arc, Mutex, RwLock,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use notify::{self, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
-use tracing::{error, info, instrument, trace, warn};
-use walkdir::WalkDir;
+use tracing::{error, info, info_span, instrument, trace, warn};
 
-use crate::{Indexer, Result};
+use crate::{
+    analyse::Analyser,
+    audit::{AuditEntry, AuditLog},
+    drops::{DropCounter, DropKind, DropReport},
+    file_system::{FileSystem, Metadata},
+    path,
+    path_format::{PathFormat, PathFormatter},
+    rotation::SnapshotRotation,
+    ConfigUpdate, Error, Indexer, Result,
+};
 
 /// LiveIndexer is a wrapper around [`Indexer`] which automatically manages the index for the watched paths.
 ///
@@ -18,11 +31,36 @@ use crate::{Indexer, Result};
 /// for those paths (adding newly created files to the index, removing deleted files from the index or
 /// updating the index of modified files).
 ///
-/// Instances of `LiveIndexer` can be created with
+/// Instances of `LiveIndexer` can be created with [`LiveIndexer::start`], or with
+/// [`LiveIndexer::start_with`] to enable additional features. They are cheap to clone,
+/// all the clones share the same index, watcher and workers, which shut down once the last clone is
+/// dropped.
+///
+/// Every watched path can be given its own [`WatchOptions`], see [`LiveIndexer::watch_with`]. Several
+/// paths sharing their options can be managed as a unit, see [`LiveIndexer::add_group`].
+///
+/// When the paths to watch come from untrusted callers (e.g. a service API), the live indexer can be
+/// restricted to an allow-list of roots, see [`LiveIndexer::restrict_to`].
+///
+/// For debugging and tests, the events can be processed synchronously on the caller thread instead,
+/// see [`LiveIndexerOptions::with_synchronous_mode`].
+///
+/// The underlying [`Indexer`] is accessible through [`LiveIndexer::indexer`] for the APIs that
+/// `LiveIndexer` doesn't forward.
+#[derive(Clone)]
 pub struct LiveIndexer {
     indexer: Arc<Indexer>,
-    indexing_queue: mpsc::Sender<IndexingAction>,
-    watcher: Mutex<RecommendedWatcher>,
+    indexing_queue: IndexingQueue,
+    watcher: Arc<Mutex<RecommendedWatcher>>,
+    file_watches: Arc<Mutex<FileWatches>>,
+    audit_log: Option<Arc<AuditLog>>,
+    drops: Arc<DropCounter>,
+    coverage: Arc<Coverage>,
+    allowed_roots: Arc<AllowedRoots>,
+    groups: Arc<Mutex<BTreeMap<String, GroupState>>>,
+    /// Dropped along with the last clone to stop the snapshot rotation, see
+    /// [`LiveIndexerOptions::with_snapshot_rotation`].
+    _rotation_stop: Option<Arc<Mutex<mpsc::Sender<()>>>>,
 }
 
 impl LiveIndexer {
@@ -30,64 +68,469 @@ impl LiveIndexer {
     ///
     /// This sets up the file watcher, so that new paths can be watched by invoking [`LiveIndexer::watch`] method.
     ///
-    /// The returned value is `self` wrapped in an [`std::sync::Arc`] that can be safely accessed from different threads.
+    /// The returned value can be cloned and safely accessed from different threads.
     pub fn start(indexer: Indexer) -> Result<Self> {
+        Self::start_with(indexer, LiveIndexerOptions::new())
+    }
+
+    /// Start the live indexer like [`LiveIndexer::start`] does, with the given options.
+    pub fn start_with(indexer: Indexer, options: LiveIndexerOptions) -> Result<Self> {
         let (tx, watcher_event_rx) = mpsc::channel();
         let indexer = Arc::new(indexer);
+        let audit_log = options
+            .audit_log_capacity
+            .map(|capacity| Arc::new(AuditLog::new(capacity)));
+        let drops = Arc::new(DropCounter::default());
+        let coverage = Arc::new(Coverage::default());
+        let allowed_roots = Arc::new(AllowedRoots::default());
+        let file_watches = Arc::new(Mutex::new(FileWatches::default()));
 
-        let indexing_queue = spawn_indexing_worker(Arc::clone(&indexer));
-        spawn_watching_worker(indexing_queue.clone(), watcher_event_rx);
+        let indexing_queue = if options.synchronous {
+            let worker = IndexingWorker::new(
+                Arc::clone(&indexer),
+                Arc::clone(&allowed_roots),
+                audit_log.clone(),
+                Arc::clone(&drops),
+                Arc::clone(&coverage),
+                options.error_policy,
+            );
+
+            IndexingQueue::Synchronous(Arc::new(Mutex::new(SynchronousWorker {
+                worker,
+                watcher_event_rx,
+            })))
+        } else {
+            let indexing_queue = spawn_indexing_worker(
+                Arc::clone(&indexer),
+                Arc::clone(&allowed_roots),
+                audit_log.clone(),
+                Arc::clone(&drops),
+                Arc::clone(&coverage),
+                options.worker_niceness,
+                options.error_policy,
+            );
+            spawn_watching_worker(
+                indexing_queue.clone(),
+                watcher_event_rx,
+                Arc::clone(&file_watches),
+                audit_log.clone(),
+                Arc::clone(&drops),
+            );
+
+            IndexingQueue::Worker(indexing_queue)
+        };
+        let rotation_stop = match options.snapshot_rotation {
+            Some((rotation, interval)) if !options.synchronous => Some(Arc::new(Mutex::new(
+                spawn_rotation_worker(Arc::clone(&indexer), rotation, interval),
+            ))),
+            _ => None,
+        };
 
         Ok(Self {
             indexer,
             indexing_queue,
-            watcher: Mutex::new(notify::watcher(tx, Duration::from_secs(1))?),
+            watcher: Arc::new(Mutex::new(notify::watcher(tx, Duration::from_secs(1))?)),
+            file_watches,
+            audit_log,
+            drops,
+            coverage,
+            allowed_roots,
+            groups: Arc::new(Mutex::new(BTreeMap::new())),
+            _rotation_stop: rotation_stop,
         })
     }
 
+    /// Only allow watching, unwatching and indexing the paths under the given roots from now on.
+    ///
+    /// Paths are canonicalised before they are checked, so that neither `..` components nor symlinks
+    /// lead outside of the roots, and the rejected ones fail with [`Error::PathNotAllowed`]. Files
+    /// listed in a manifest (see [`LiveIndexer::index_manifest`]) that are outside of the roots are
+    /// logged and skipped.
+    ///
+    /// Restrictions are shared by all the clones of the live indexer and can only be narrowed:
+    /// calling this again fails if any of the new roots is outside of the currently allowed ones.
+    /// Paths watched before the call are not affected.
+    pub fn restrict_to<P>(&self, roots: &[P]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.allowed_roots
+            .restrict_to(self.indexer.file_system(), roots)
+    }
+
     /// Build an index for the given path and watch it for changes.
-    #[instrument(skip(self, path), fields(path = %path.as_ref().display()))]
-    pub fn watch<P>(&self, path: P) -> Result<()>
+    ///
+    /// The path can be either a directory, which is watched recursively, or a single file, which is
+    /// indexed on its own and watched through its directory, so that the watch survives the file
+    /// being saved atomically. The returned handle allows to unwatch the path and report on
+    /// it, and can unwatch it once dropped, see [`WatchHandle`].
+    pub fn watch<P>(&self, path: P) -> Result<WatchHandle>
+    where
+        P: AsRef<Path>,
+    {
+        self.watch_with(path, WatchOptions::new())
+    }
+
+    /// Build an index for the given path and watch it for changes like [`LiveIndexer::watch`] does,
+    /// applying the given options to the files under it.
+    ///
+    /// Watching a path again replaces its options.
+    #[instrument(
+        skip(self, path, options),
+        fields(index = %self.indexer.name(), path = %path.as_ref().display())
+    )]
+    pub fn watch_with<P>(&self, path: P, mut options: WatchOptions) -> Result<WatchHandle>
     where
         P: AsRef<Path>,
     {
         info!("watching a new path");
 
         let path = path.as_ref();
+        let analyser = options.analyser.take().map(Arc::new);
+        self.watch_root(path, &options, analyser)?;
 
-        self.watcher
-            .lock()
-            .unwrap()
-            .watch(path, RecursiveMode::Recursive)?;
-        self.indexing_queue
-            .send(IndexingAction::AddDir {
-                path: path.to_owned(),
-            })
-            .unwrap();
+        Ok(WatchHandle {
+            live_indexer: self.clone(),
+            given_path: path.to_owned(),
+            path: path::normalise(self.indexer.file_system(), path)?,
+            unwatch_on_drop: false,
+        })
+    }
+
+    /// Watch a path with the given options and analyser, see [`LiveIndexer::watch_with`].
+    fn watch_root(
+        &self,
+        path: &Path,
+        options: &WatchOptions,
+        analyser: Option<Arc<Analyser>>,
+    ) -> Result<()> {
+        let file_system = self.indexer.file_system();
+        self.allowed_roots.check(file_system, path)?;
+        let filter = RootFilter::new(file_system, path, options)?;
+        match analyser {
+            Some(analyser) => self.indexer.set_shared_root_analyser(path, analyser)?,
+            None => self.indexer.remove_root_analyser(path)?,
+        }
+        let is_file = file_system.metadata(path)?.is_file;
+
+        // Changes to the files of other file systems are reported by their owners, see
+        // `LiveIndexer::inject`.
+        if file_system.is_watchable() {
+            let mut file_watches = self.file_watches.lock().unwrap();
+            let mut watcher = self.watcher.lock().unwrap();
+
+            if is_file {
+                file_watches.watch_file(&mut watcher, &filter.root)?;
+            } else {
+                file_watches.watch_dir(&mut watcher, path, &filter.root)?;
+            }
+        }
+        self.indexing_queue.send(IndexingAction::AddRoot { filter });
+
+        let path = path.to_owned();
+        let action = if is_file {
+            IndexingAction::Add { path }
+        } else {
+            IndexingAction::AddDir { path }
+        };
+        self.indexing_queue.send(action);
 
         Ok(())
     }
 
     /// Remove a previously set watcher and the given path from the index.
-    #[instrument(skip(self, path), fields(path = %path.as_ref().display()))]
+    #[instrument(
+        skip(self, path),
+        fields(index = %self.indexer.name(), path = %path.as_ref().display())
+    )]
     pub fn unwatch<P>(&self, path: P) -> Result<()>
     where
         P: AsRef<Path>,
     {
         info!("unwatching a path");
 
-        let path = path.as_ref();
+        self.unwatch_root(path.as_ref())
+    }
+
+    fn unwatch_root(&self, path: &Path) -> Result<()> {
+        let file_system = self.indexer.file_system();
+        self.allowed_roots.check(file_system, path)?;
+
+        if file_system.is_watchable() {
+            let root = path::normalise(file_system, path)?;
+            let mut file_watches = self.file_watches.lock().unwrap();
+            let mut watcher = self.watcher.lock().unwrap();
+
+            if file_watches.is_file(&root) {
+                file_watches.unwatch_file(&mut watcher, &root)?;
+            } else {
+                file_watches.unwatch_dir(&mut watcher, path, &root)?;
+            }
+        }
+        self.indexer.remove_root_analyser(path)?;
+        self.indexing_queue.send(IndexingAction::RemoveRoot {
+            path: path.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    /// Watch every path of the given group with the options of the group, see
+    /// [`LiveIndexer::watch_with`].
+    ///
+    /// The group can then be paused, resumed and removed as a unit, and reported on with
+    /// [`LiveIndexer::groups`]. Adding a group again replaces it, the paths that are no longer part
+    /// of it are unwatched.
+    #[instrument(skip(self, group), fields(index = %self.indexer.name(), group = %group.name))]
+    pub fn add_group(&self, mut group: WatchGroup) -> Result<()> {
+        info!(roots = group.roots.len(), "adding a watch group");
+
+        let file_system = self.indexer.file_system();
+        let roots = group
+            .roots
+            .iter()
+            .map(|root| path::normalise(file_system, root).map_err(Error::from))
+            .collect::<Result<Vec<_>>>()?;
+        let mut groups = self.groups.lock().unwrap();
+
+        if let Some(previous) = groups.remove(&group.name) {
+            for root in previous.roots.iter().filter(|root| !roots.contains(root)) {
+                self.unwatch_root(root)?;
+            }
+        }
+
+        let analyser = group.options.analyser.take().map(Arc::new);
+        for root in &roots {
+            self.watch_root(root, &group.options, analyser.clone())?;
+        }
+        groups.insert(
+            group.name,
+            GroupState {
+                roots,
+                paused: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Unwatch every path of the given group and remove them from the index.
+    #[instrument(skip(self), fields(index = %self.indexer.name()))]
+    pub fn remove_group(&self, name: &str) -> Result<()> {
+        info!("removing a watch group");
+
+        let group = self
+            .groups
+            .lock()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| Error::UnknownWatchGroup(name.to_owned()))?;
+
+        group
+            .roots
+            .iter()
+            .try_for_each(|root| self.unwatch_root(root))
+    }
+
+    /// Stop indexing the files of the given group as they are created or modified, until the group
+    /// is resumed with [`LiveIndexer::resume_group`].
+    ///
+    /// The removed files are still removed from the index, so that no results point to missing
+    /// files.
+    #[instrument(skip(self), fields(index = %self.indexer.name()))]
+    pub fn pause_group(&self, name: &str) -> Result<()> {
+        info!("pausing a watch group");
+        self.set_paused(name, true)
+    }
+
+    /// Resume indexing the files of a group paused with [`LiveIndexer::pause_group`].
+    ///
+    /// The paths of the group are indexed again, to catch up with the changes made in the meantime.
+    #[instrument(skip(self), fields(index = %self.indexer.name()))]
+    pub fn resume_group(&self, name: &str) -> Result<()> {
+        info!("resuming a watch group");
+        self.set_paused(name, false)
+    }
+
+    fn set_paused(&self, name: &str, paused: bool) -> Result<()> {
+        let mut groups = self.groups.lock().unwrap();
+        let group = groups
+            .get_mut(name)
+            .ok_or_else(|| Error::UnknownWatchGroup(name.to_owned()))?;
+        if group.paused == paused {
+            return Ok(());
+        }
+
+        group.paused = paused;
+        for root in &group.roots {
+            self.indexing_queue.send(IndexingAction::PauseRoot {
+                root: root.clone(),
+                paused,
+            });
+            if !paused {
+                self.indexing_queue
+                    .send(IndexingAction::AddDir { path: root.clone() });
+            }
+        }
+        Ok(())
+    }
 
-        self.watcher.lock().unwrap().unwatch(path)?;
-        self.indexing_queue
-            .send(IndexingAction::RemoveDir {
-                path: path.to_owned(),
+    /// Status of every watch group added with [`LiveIndexer::add_group`], sorted by name.
+    pub fn groups(&self) -> Vec<WatchGroupStatus> {
+        let files = self.indexer.indexed_files();
+        let drops = self.drops.report();
+
+        self.groups
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, group)| WatchGroupStatus {
+                name: name.clone(),
+                roots: group.roots.clone(),
+                paused: group.paused,
+                files: files
+                    .iter()
+                    .filter(|file| is_under(Path::new(&file.path), &group.roots))
+                    .count(),
+                dropped: group
+                    .roots
+                    .iter()
+                    .map(|root| drops.dropped_under(root))
+                    .sum(),
             })
-            .unwrap();
+            .collect()
+    }
+
+    /// Index all the files listed in the given manifest file, without walking any directories.
+    ///
+    /// The listed files are not watched for changes. See [`Indexer::index_manifest`] for the format
+    /// of the manifest.
+    #[instrument(
+        skip(self, manifest),
+        fields(index = %self.indexer.name(), manifest = %manifest.as_ref().display())
+    )]
+    pub fn index_manifest<P>(&self, manifest: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        info!("indexing files from a manifest");
+
+        self.allowed_roots
+            .check(self.indexer.file_system(), manifest.as_ref())?;
+        self.indexing_queue.send(IndexingAction::AddManifest {
+            path: manifest.as_ref().to_owned(),
+        });
 
         Ok(())
     }
 
+    /// Change the analysis configuration of the index, see [`Indexer::update_config`].
+    ///
+    /// If requested with [`ConfigUpdate::with_reindex`], the stale files are indexed again in the
+    /// background rather than before this returns.
+    pub fn update_config(&self, mut update: ConfigUpdate) {
+        let reindex = std::mem::take(&mut update.reindex);
+
+        self.indexer.update_config(update);
+        if reindex {
+            self.indexing_queue.send(IndexingAction::ReindexStale);
+        }
+    }
+
+    /// Watcher events received and indexing actions taken, from the oldest.
+    ///
+    /// Returns `None` if the audit log wasn't enabled with [`LiveIndexerOptions::with_audit_log`].
+    pub fn audit_log(&self) -> Option<Vec<AuditEntry>> {
+        self.audit_log.as_ref().map(|audit_log| audit_log.entries())
+    }
+
+    /// Counts of the watcher events and indexing actions given up on or merged into later ones, per
+    /// path.
+    ///
+    /// A file missing from the index with no dropped events most likely points to a bug rather than
+    /// to a lost change.
+    pub fn dropped_events(&self) -> DropReport {
+        self.drops.report()
+    }
+
+    /// Coverage of every watched path: how many files were discovered under it since it was
+    /// watched, and what became of them, sorted by path.
+    pub fn coverage(&self) -> Vec<RootCoverage> {
+        let files = self.indexer.indexed_files();
+        let mut coverage = self.coverage.roots.lock().unwrap().clone();
+
+        for root in &mut coverage {
+            root.documents = files
+                .iter()
+                .filter(|file| Path::new(&file.path).starts_with(&root.root))
+                .count();
+        }
+        coverage
+    }
+
+    /// The [`Indexer`] maintained by this live indexer.
+    pub fn indexer(&self) -> Arc<Indexer> {
+        Arc::clone(&self.indexer)
+    }
+
+    /// Perform the indexing actions for the watcher events received so far, along with the requeued
+    /// files and the retries that are due, on the caller thread.
+    ///
+    /// Only has an effect in the synchronous mode (see [`LiveIndexerOptions::with_synchronous_mode`]),
+    /// otherwise the events are processed by the workers as they arrive. Returns the number of
+    /// processed watcher events.
+    pub fn process_events(&self) -> usize {
+        match &self.indexing_queue {
+            IndexingQueue::Worker(_) => 0,
+            IndexingQueue::Synchronous(worker) => {
+                let mut worker = worker.lock().unwrap();
+                let SynchronousWorker {
+                    worker,
+                    watcher_event_rx,
+                } = &mut *worker;
+                let mut processed = 0;
+
+                while let Ok(event) = watcher_event_rx.try_recv() {
+                    if let Some(audit_log) = &self.audit_log {
+                        audit_event(audit_log, &event);
+                    }
+                    count_drops(&self.drops, &event);
+
+                    let actions = self.file_watches.lock().unwrap().filter(actions_for(event));
+                    for action in actions {
+                        worker.perform_settled(action);
+                    }
+                    processed += 1;
+                }
+
+                worker.settle();
+                processed
+            }
+        }
+    }
+
+    /// Handle a watcher event as if it was sent by the file watcher.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn inject(&self, event: DebouncedEvent) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_event(audit_log, &event);
+        }
+        count_drops(&self.drops, &event);
+
+        let actions = self.file_watches.lock().unwrap().filter(actions_for(event));
+        for action in actions {
+            self.indexing_queue.send(action);
+        }
+    }
+
+    /// Wait until the actions queued so far are performed, except for the pending retries.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+
+        self.indexing_queue.send(IndexingAction::Flush { done });
+        let _ = flushed.recv();
+    }
+
     /// Passes the query down to the [`Indexer`] returning the set of file paths that got a hit for the
     /// given term.
     ///
@@ -95,124 +538,2033 @@ impl LiveIndexer {
     pub fn query(&self, term: &str) -> HashSet<String> {
         self.indexer.query(term)
     }
+
+    /// Query the index like [`LiveIndexer::query`] does, reporting the files relative to the
+    /// innermost watched path containing them, sorted by watched path and file path.
+    ///
+    /// This keeps the layout of the user directories out of the logs and UIs the results are shown
+    /// in. Files that aren't under any watched path (e.g. indexed from a manifest) are reported with
+    /// their full path.
+    pub fn query_relative(&self, term: &str) -> Vec<RelativePath> {
+        let roots = self.watched_roots();
+        let mut paths = self
+            .indexer
+            .query(term)
+            .into_iter()
+            .map(|path| RelativePath::new(&roots, PathBuf::from(path)))
+            .collect::<Vec<_>>();
+
+        paths.sort();
+        paths
+    }
+
+    /// Build a formatter of the result paths in the given format, relative to the currently
+    /// watched paths, see [`crate::path_format`].
+    pub fn path_formatter(&self, format: PathFormat) -> PathFormatter {
+        PathFormatter::new(format).with_roots(self.watched_roots())
+    }
+
+    /// Normalised paths currently watched.
+    fn watched_roots(&self) -> Vec<PathBuf> {
+        self.coverage
+            .roots
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|coverage| coverage.root.clone())
+            .collect()
+    }
 }
 
-/// Action to be performed by indexing worker.
-///
-/// See [`spawn_indexing_worker`].
-enum IndexingAction {
-    Add { path: PathBuf },
-    AddDir { path: PathBuf },
-    Remove { path: PathBuf },
-    RemoveDir { path: PathBuf },
+/// Options of a [`LiveIndexer`], see [`LiveIndexer::start_with`].
+#[derive(Debug, Clone, Default)]
+pub struct LiveIndexerOptions {
+    audit_log_capacity: Option<usize>,
+    worker_niceness: Option<i32>,
+    error_policy: ErrorPolicy,
+    synchronous: bool,
+    snapshot_rotation: Option<(SnapshotRotation, Duration)>,
 }
 
-/// Spawn an indexing worker.
-///
-/// This worker performs mutating indexing operations on the index (index/clear) in a separate thread.
+impl LiveIndexerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record every watcher event received and every indexing action taken, keeping up to `capacity`
+    /// most recent records, see [`LiveIndexer::audit_log`].
+    pub fn with_audit_log(mut self, capacity: usize) -> Self {
+        self.audit_log_capacity = Some(capacity);
+        self
+    }
+
+    /// Run the indexing worker at the given niceness, so that background indexing doesn't compete
+    /// for CPU with the application (higher values mean lower priority).
+    ///
+    /// There is always exactly one indexing worker thread. Only Linux is supported, where niceness
+    /// is set per thread, elsewhere a warning is logged and the worker runs at normal priority.
+    pub fn with_worker_niceness(mut self, niceness: i32) -> Self {
+        self.worker_niceness = Some(niceness);
+        self
+    }
+
+    /// Handle the errors of the indexing worker according to the given policy.
+    pub fn with_error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Perform the indexing actions synchronously on the caller thread, without spawning any
+    /// workers, so that they happen in a deterministic order.
+    ///
+    /// Watching, unwatching and indexing a manifest return once the files are indexed, and the
+    /// watcher events are only processed when [`LiveIndexer::process_events`] is called. Retries of
+    /// the failed files are performed by the calls made once their backoff elapses. The worker
+    /// niceness is ignored.
+    pub fn with_synchronous_mode(mut self) -> Self {
+        self.synchronous = true;
+        self
+    }
+
+    /// Rotate the snapshots of the index every `interval`, as long as it changed since the last
+    /// rotation, see [`crate::rotation`].
+    ///
+    /// Rotation runs on a dedicated worker, so it's disabled in synchronous mode, where
+    /// [`SnapshotRotation::rotate`] can be called directly instead.
+    pub fn with_snapshot_rotation(
+        mut self,
+        rotation: SnapshotRotation,
+        interval: Duration,
+    ) -> Self {
+        self.snapshot_rotation = Some((rotation, interval));
+        self
+    }
+}
+
+/// Handling of the errors the indexing worker runs into, see [`LiveIndexerOptions::with_error_policy`].
 ///
-/// Returns an [`mpsc::Sender`] that allows to enqueue tasks for this worker.
+/// By default, files that fail to be indexed are not retried.
+#[derive(Debug, Clone)]
+pub struct ErrorPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ErrorPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retry indexing a file up to `max_retries` times when it fails with a transient error (see
+    /// [`Error::is_transient`]), e.g. when the file is briefly locked by an editor or an antivirus.
+    ///
+    /// The first retry happens after `initial_backoff`, and the wait doubles with every next one.
+    /// Other actions are performed while waiting.
+    pub fn with_retries(mut self, max_retries: u32, initial_backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Cap the wait between retries, 10 seconds by default.
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Time to wait before the given retry, counted from zero.
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << retry.min(31))
+            .min(self.max_backoff)
+    }
+}
+
+/// Options of a path watched with [`LiveIndexer::watch_with`].
+#[derive(Default)]
+pub struct WatchOptions {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    max_file_size: Option<u64>,
+    analyser: Option<Analyser>,
+    estimate_progress: bool,
+    progress_callback: Option<ProgressCallback>,
+}
+
+/// Callback receiving the coverage of a watched path, see [`WatchOptions::with_progress_callback`].
+type ProgressCallback = Arc<dyn Fn(&RootCoverage) + Send + Sync>;
+
+impl WatchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only index the files matching the given glob pattern, relative to the watched path.
+    ///
+    /// A file is indexed if it matches any of the patterns, all the files are indexed if there are
+    /// none.
+    pub fn with_include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_owned());
+        self
+    }
+
+    /// Skip the files matching the given glob pattern, relative to the watched path, even if they
+    /// match an included one.
+    pub fn with_exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_owned());
+        self
+    }
+
+    /// Skip the files larger than the given number of bytes.
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// Analyse the files under the watched path with the given analyser instead of the indexer's
+    /// default one, see [`Indexer::set_root_analyser`].
+    pub fn with_analyser(mut self, analyser: Analyser) -> Self {
+        self.analyser = Some(analyser);
+        self
+    }
+
+    /// Count the files under the watched path and their sizes before indexing them, so that the
+    /// progress of the indexing can be estimated, see [`RootCoverage::progress`].
+    ///
+    /// This walks the watched path twice, but only the metadata of the files is read by the first
+    /// walk.
+    pub fn with_progress_estimate(mut self) -> Self {
+        self.estimate_progress = true;
+        self
+    }
+
+    /// Call `f` with the coverage of the watched path every time a file under it is processed.
+    ///
+    /// The callback runs on the indexing worker, so it should return quickly.
+    pub fn with_progress_callback<F>(mut self, f: F) -> Self
+    where
+        F: 'static + Fn(&RootCoverage) + Send + Sync,
+    {
+        self.progress_callback = Some(Arc::new(f));
+        self
+    }
+}
+
+/// Handle of a path watched with [`LiveIndexer::watch`] or [`LiveIndexer::watch_with`].
 ///
-/// NOTE: since the only normal condition for this worker to shutdown is when all the senders
-/// are dropped, it is safe to `.unwrap()` sends on the returned by this function sender.
-fn spawn_indexing_worker(indexer: Arc<Indexer>) -> mpsc::Sender<IndexingAction> {
-    fn add_dir(indexer: &Indexer, path: &Path) -> Result<()> {
-        for entry in WalkDir::new(path.canonicalize()?) {
-            let entry = entry?;
+/// Dropping the handle leaves the path watched, unless [`WatchHandle::unwatch_on_drop`] is set, in
+/// which case the path is unwatched and its files are removed from the index, so that the lifetime
+/// of a watched path can be tied to the component using it. The handle keeps the live indexer
+/// running.
+pub struct WatchHandle {
+    live_indexer: LiveIndexer,
+    /// Path as it was passed to the live indexer.
+    given_path: PathBuf,
+    /// Normalised path, as it's reported on.
+    path: PathBuf,
+    unwatch_on_drop: bool,
+}
 
-            if let Err(e) = indexer.index_file(entry.path()) {
-                warn!(error = %e, "failed to index a file");
+impl WatchHandle {
+    /// Unwatch the path and remove its files from the index once the handle is dropped.
+    pub fn unwatch_on_drop(mut self) -> Self {
+        self.unwatch_on_drop = true;
+        self
+    }
+
+    /// Normalised watched path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Coverage of the watched path, see [`LiveIndexer::coverage`].
+    ///
+    /// Returns `None` if the path is no longer watched, or the indexing worker hasn't started
+    /// watching it yet.
+    pub fn status(&self) -> Option<RootCoverage> {
+        self.live_indexer
+            .coverage()
+            .into_iter()
+            .find(|coverage| coverage.root == self.path)
+    }
+
+    /// Unwatch the path and remove its files from the index, see [`LiveIndexer::unwatch`].
+    pub fn unwatch(mut self) -> Result<()> {
+        self.unwatch_on_drop = false;
+        self.live_indexer.unwatch(&self.given_path)
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        if self.unwatch_on_drop {
+            if let Err(e) = self.live_indexer.unwatch(&self.given_path) {
+                warn!(error = %e, path = %self.path.display(), "failed to unwatch a dropped path");
             }
         }
-        Ok(())
     }
+}
 
-    fn remove_dir(indexer: &Indexer, path: &Path) -> Result<()> {
-        for entry in WalkDir::new(path.canonicalize()?) {
-            let entry = entry?;
+/// Named set of paths watched with the same [`WatchOptions`], see [`LiveIndexer::add_group`].
+pub struct WatchGroup {
+    name: String,
+    roots: Vec<PathBuf>,
+    options: WatchOptions,
+}
 
-            indexer.clear_from_index(entry.path());
+impl WatchGroup {
+    pub fn new(name: &str, options: WatchOptions) -> Self {
+        Self {
+            name: name.to_owned(),
+            roots: Vec::new(),
+            options,
         }
-        Ok(())
     }
 
-    let (tx, indexing_queue_rx) = mpsc::channel();
+    /// Add a path to the group, either a directory or a single file like with
+    /// [`LiveIndexer::watch`].
+    pub fn with_root<P>(mut self, root: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.roots.push(root.as_ref().to_owned());
+        self
+    }
+}
 
-    thread::spawn(move || {
-        while let Ok(action) = indexing_queue_rx.recv() {
-            let r = match action {
-                IndexingAction::Add { path } => indexer.index_file(&path),
-                IndexingAction::AddDir { path } => add_dir(&indexer, &path),
-                IndexingAction::Remove { path } => Ok(indexer.clear_from_index(&path)),
-                IndexingAction::RemoveDir { path } => remove_dir(&indexer, &path),
-            };
+/// Status of a watch group, see [`LiveIndexer::groups`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchGroupStatus {
+    pub name: String,
+    /// Normalised paths of the group.
+    pub roots: Vec<PathBuf>,
+    /// Whether the group is paused, see [`LiveIndexer::pause_group`].
+    pub paused: bool,
+    /// Number of indexed files under the paths of the group.
+    pub files: usize,
+    /// Number of events dropped under the paths of the group, see [`LiveIndexer::dropped_events`].
+    pub dropped: u64,
+}
 
-            if let Err(e) = r {
-                warn!(error = %e, "indexing error");
-            }
+impl std::fmt::Display for WatchGroupStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} roots, {} files, {} dropped events",
+            self.name,
+            self.roots.len(),
+            self.files,
+            self.dropped
+        )?;
+        if self.paused {
+            write!(f, " (paused)")?;
         }
-    });
-
-    tx
+        Ok(())
+    }
 }
 
-/// Spawn filesystem watching worker.
+/// What became of the files discovered under a watched path, see [`LiveIndexer::coverage`].
 ///
-/// This worker listens for file events in a separate thread and queues corresponding [`IndexingAction`]s
-/// to the indexing worker.
-fn spawn_watching_worker(
-    indexing_queue: mpsc::Sender<IndexingAction>,
-    watcher_event_rx: mpsc::Receiver<notify::DebouncedEvent>,
-) {
-    thread::spawn(move || {
-        while let Ok(event) = watcher_event_rx.recv() {
-            match event {
-                DebouncedEvent::Write(path) => {
-                    trace!(path = %path.display(), "file write event");
-
-                    indexing_queue
-                        .send(IndexingAction::Remove { path: path.clone() })
-                        .unwrap();
-                    indexing_queue.send(IndexingAction::Add { path }).unwrap();
-                }
-
-                DebouncedEvent::Create(path) => {
-                    trace!(path = %path.display(), "file create event");
+/// Files are counted every time they are discovered, whether by walking the path or through a
+/// watcher event, so a file that is modified several times is counted as many times.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RootCoverage {
+    /// Normalised watched path.
+    pub root: PathBuf,
+    pub discovered: u64,
+    pub indexed: u64,
+    /// Files skipped as they don't match the include and exclude patterns.
+    pub filtered: u64,
+    /// Files skipped as they are larger than the maximum size.
+    pub too_large: u64,
+    /// Files that aren't valid text.
+    pub binary: u64,
+    /// Files that failed to be indexed for another reason, after retries.
+    pub failed: u64,
+    /// Number of the files currently indexed under the path.
+    pub documents: usize,
+    /// Number of the files found under the path before indexing it, if the progress is estimated,
+    /// see [`WatchOptions::with_progress_estimate`].
+    pub estimated_files: Option<u64>,
+    /// Total size of the files found under the path before indexing it, if the progress is
+    /// estimated.
+    pub estimated_bytes: Option<u64>,
+    /// Total size of the files processed, whatever became of them.
+    pub processed_bytes: u64,
+    /// Time the estimate was made at.
+    estimated_at: Option<Instant>,
+}
 
-                    indexing_queue.send(IndexingAction::Add { path }).unwrap();
-                }
+impl RootCoverage {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            ..Self::default()
+        }
+    }
 
-                DebouncedEvent::Remove(path) => {
-                    trace!(path = %path.display(), "file remove event");
+    /// Number of the files processed, whatever became of them.
+    pub fn processed(&self) -> u64 {
+        self.indexed + self.filtered + self.too_large + self.binary + self.failed
+    }
 
-                    indexing_queue
-                        .send(IndexingAction::Remove { path })
-                        .unwrap();
-                }
+    /// Estimated share of the path indexed, from 0 to 1, `None` if the progress isn't estimated.
+    ///
+    /// The share is counted in bytes, unless the files are all empty. Files discovered through the
+    /// watcher events while the path is indexed are counted as well, so this is only an estimate.
+    pub fn progress(&self) -> Option<f64> {
+        let progress = match (self.estimated_bytes?, self.estimated_files?) {
+            (0, 0) => 1.0,
+            (0, files) => self.processed() as f64 / files as f64,
+            (bytes, _) => self.processed_bytes as f64 / bytes as f64,
+        };
+        Some(progress.min(1.0))
+    }
 
-                DebouncedEvent::Rename(path_old, path_new) => {
-                    trace!(old = %path_old.display(), new = %path_new.display(), "file rename event");
+    /// Estimated time left until the path is indexed, based on the pace so far, `None` if the
+    /// progress isn't estimated or nothing was processed yet.
+    pub fn eta(&self) -> Option<Duration> {
+        let progress = self.progress().filter(|&progress| progress > 0.0)?;
+        let elapsed = self.estimated_at?.elapsed();
 
-                    indexing_queue
-                        .send(IndexingAction::Remove { path: path_old })
-                        .unwrap();
-                    indexing_queue
-                        .send(IndexingAction::Add { path: path_new })
-                        .unwrap();
-                }
+        Some(elapsed.mul_f64((1.0 - progress) / progress))
+    }
 
-                DebouncedEvent::Error(e, p) => {
-                    error!(error = %e, path = ?p.as_ref().map(|p| p.display()), "watcher sent an error");
-                }
+    fn count(&mut self, outcome: Outcome, len: u64) {
+        if outcome != Outcome::Discovered {
+            self.processed_bytes += len;
+        }
+        let count = match outcome {
+            Outcome::Discovered => &mut self.discovered,
+            Outcome::Indexed => &mut self.indexed,
+            Outcome::Filtered => &mut self.filtered,
+            Outcome::TooLarge => &mut self.too_large,
+            Outcome::Binary => &mut self.binary,
+            Outcome::Failed => &mut self.failed,
+        };
+        *count += 1;
+    }
+}
 
-                // These events are ignored. They could be useful for additional robustness in the future.
-                DebouncedEvent::Rescan => (),
-                DebouncedEvent::Chmod(_) => (),
-                DebouncedEvent::NoticeWrite(_) => (),
-                DebouncedEvent::NoticeRemove(_) => (),
-            };
+impl std::fmt::Display for RootCoverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} documents, {} discovered, {} indexed, {} filtered, {} too large, {} binary, {} failed",
+            self.root.display(),
+            self.documents,
+            self.discovered,
+            self.indexed,
+            self.filtered,
+            self.too_large,
+            self.binary,
+            self.failed
+        )?;
+        match (self.progress(), self.eta()) {
+            (Some(progress), Some(eta)) => write!(
+                f,
+                ", {:.1}% done, {}s left",
+                progress * 100.0,
+                eta.as_secs()
+            ),
+            (Some(progress), None) => write!(f, ", {:.1}% done", progress * 100.0),
+            _ => Ok(()),
         }
+    }
+}
 
-        info!("file watcher is shutting down");
+/// Path of a file relative to the watched path containing it, see [`LiveIndexer::query_relative`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RelativePath {
+    /// Normalised watched path, `None` if the file isn't under any.
+    pub root: Option<PathBuf>,
+    /// Path of the file relative to the root, or the full path if there is no root. Empty if the
+    /// watched path is the file itself.
+    pub path: PathBuf,
+}
+
+impl RelativePath {
+    /// Make the path relative to the innermost of the roots containing it.
+    pub(crate) fn new(roots: &[PathBuf], path: PathBuf) -> Self {
+        let root = roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count());
+
+        match root.and_then(|root| Some((root, path.strip_prefix(root).ok()?))) {
+            Some((root, relative)) => Self {
+                root: Some(root.clone()),
+                path: relative.to_owned(),
+            },
+            None => Self { root: None, path },
+        }
+    }
+}
+
+/// Event in the life of a file under a watched path, counted in its [`RootCoverage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Discovered,
+    Indexed,
+    Filtered,
+    TooLarge,
+    Binary,
+    Failed,
+}
+
+/// Coverage of the watched paths, shared by the live indexer and its worker.
+#[derive(Default)]
+struct Coverage {
+    roots: Mutex<Vec<RootCoverage>>,
+}
+
+impl Coverage {
+    /// Start counting the files under a watched path from scratch.
+    fn reset(&self, root: &Path) {
+        let mut roots = self.roots.lock().unwrap();
+        roots.retain(|coverage| coverage.root != root);
+        roots.push(RootCoverage::new(root.to_owned()));
+        roots.sort_by(|a, b| a.root.cmp(&b.root));
+    }
+
+    fn remove(&self, root: &Path) {
+        self.roots
+            .lock()
+            .unwrap()
+            .retain(|coverage| coverage.root != root);
+    }
+
+    /// Record the number of the files under a watched path and their total size.
+    fn estimate(&self, root: &Path, files: u64, bytes: u64) {
+        if let Some(coverage) = self
+            .roots
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|coverage| coverage.root == root)
+        {
+            coverage.estimated_files = Some(files);
+            coverage.estimated_bytes = Some(bytes);
+            coverage.estimated_at = Some(Instant::now());
+        }
+    }
+
+    /// Count an outcome of a file of the given size, returning the updated coverage of the path.
+    fn count(&self, root: &Path, outcome: Outcome, len: u64) -> Option<RootCoverage> {
+        self.roots
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|coverage| coverage.root == root)
+            .map(|coverage| {
+                coverage.count(outcome, len);
+                coverage.clone()
+            })
+    }
+}
+
+/// Paths of a watch group and whether it's paused.
+struct GroupState {
+    roots: Vec<PathBuf>,
+    paused: bool,
+}
+
+/// Filter of the files under a watched path, built from its [`WatchOptions`].
+struct RootFilter {
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    max_file_size: Option<u64>,
+    estimate_progress: bool,
+    progress_callback: Option<ProgressCallback>,
+}
+
+impl RootFilter {
+    fn new(file_system: &dyn FileSystem, root: &Path, options: &WatchOptions) -> Result<Self> {
+        Ok(Self {
+            root: path::normalise(file_system, root)?,
+            include: glob_set(&options.include)?,
+            exclude: glob_set(&options.exclude)?,
+            max_file_size: options.max_file_size,
+            estimate_progress: options.estimate_progress,
+            progress_callback: options.progress_callback.clone(),
+        })
+    }
+
+    /// Check why the file at the given normalised path shouldn't be indexed, if it should not.
+    fn skips(&self, path: &Path, metadata: &Metadata) -> Option<Outcome> {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let included = match &self.include {
+            Some(include) => include.is_match(relative),
+            None => true,
+        };
+        let excluded = match &self.exclude {
+            Some(exclude) => exclude.is_match(relative),
+            None => false,
+        };
+
+        if !included || excluded {
+            Some(Outcome::Filtered)
+        } else if self.max_file_size.is_some_and(|max| metadata.len > max) {
+            Some(Outcome::TooLarge)
+        } else {
+            None
+        }
+    }
+}
+
+/// Build a set of the given glob patterns, or `None` if there are none.
+fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// Roots a [`LiveIndexer`] is restricted to, see [`LiveIndexer::restrict_to`].
+#[derive(Default)]
+struct AllowedRoots {
+    /// Normalised roots, or `None` if any path is allowed.
+    roots: RwLock<Option<Vec<PathBuf>>>,
+}
+
+impl AllowedRoots {
+    fn restrict_to<P>(&self, file_system: &dyn FileSystem, roots: &[P]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut allowed = self.roots.write().unwrap();
+        let roots = roots
+            .iter()
+            .map(|root| path::normalise(file_system, root.as_ref()).map_err(Error::from))
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(allowed) = allowed.as_ref() {
+            if let Some(root) = roots.iter().find(|root| !is_under(root, allowed)) {
+                return Err(Error::PathNotAllowed(root.clone()));
+            }
+        }
+
+        *allowed = Some(roots);
+        Ok(())
+    }
+
+    /// Check that the given path is under one of the allowed roots.
+    fn check(&self, file_system: &dyn FileSystem, path: &Path) -> Result<()> {
+        match self.roots.read().unwrap().as_ref() {
+            Some(roots) => {
+                let path = path::normalise(file_system, path)?;
+                if is_under(&path, roots) {
+                    Ok(())
+                } else {
+                    Err(Error::PathNotAllowed(path))
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check whether a normalised path is under any of the given roots.
+fn is_under(path: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+/// Watches of the operating system's file system, see [`LiveIndexer::watch`].
+///
+/// A single file isn't watched on its own, as the watch would be lost once the file is saved
+/// atomically, i.e. replaced by another file renamed over it. Its directory is watched instead,
+/// unless it's under a watched directory already, and the events of the other entries of the
+/// directory are filtered out.
+#[derive(Default)]
+struct FileWatches {
+    /// Normalised paths of the watched files by their directory.
+    files: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    /// Normalised paths of the watched directories.
+    dirs: Vec<PathBuf>,
+    /// Directories watched for the files in them.
+    watched: BTreeSet<PathBuf>,
+}
+
+impl FileWatches {
+    /// Watch the file at the normalised path `file` through its directory.
+    fn watch_file(&mut self, watcher: &mut RecommendedWatcher, file: &Path) -> notify::Result<()> {
+        let dir = file.parent().unwrap_or(file).to_owned();
+
+        if !self.watched.contains(&dir) && !is_under(&dir, &self.dirs) {
+            watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            self.watched.insert(dir.clone());
+        }
+        self.files.entry(dir).or_default().insert(file.to_owned());
+        Ok(())
+    }
+
+    /// Stop watching the file at the normalised path `file`.
+    fn unwatch_file(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        file: &Path,
+    ) -> notify::Result<()> {
+        let dir = file.parent().unwrap_or(file);
+
+        if let Some(files) = self.files.get_mut(dir) {
+            files.remove(file);
+            if files.is_empty() {
+                self.files.remove(dir);
+                if self.watched.remove(dir) {
+                    watcher.unwatch(dir)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the normalised path is a watched file.
+    fn is_file(&self, path: &Path) -> bool {
+        let dir = path.parent().unwrap_or(path);
+        self.files
+            .get(dir)
+            .is_some_and(|files| files.contains(path))
+    }
+
+    /// Watch the directory at `path`, normalised to `root`, recursively.
+    ///
+    /// The directories of the watched files under it no longer need to be watched on their own.
+    fn watch_dir(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        path: &Path,
+        root: &Path,
+    ) -> notify::Result<()> {
+        let covered = self
+            .watched
+            .iter()
+            .filter(|dir| dir.starts_with(root))
+            .cloned()
+            .collect::<Vec<_>>();
+        for dir in covered {
+            watcher.unwatch(&dir)?;
+            self.watched.remove(&dir);
+        }
+
+        watcher.watch(path, RecursiveMode::Recursive)?;
+        if !self.dirs.iter().any(|dir| dir == root) {
+            self.dirs.push(root.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Stop watching the directory at `path`, normalised to `root`.
+    ///
+    /// The directories of the watched files that were under it are watched on their own again.
+    fn unwatch_dir(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        path: &Path,
+        root: &Path,
+    ) -> notify::Result<()> {
+        watcher.unwatch(path)?;
+        self.dirs.retain(|dir| dir != root);
+
+        let uncovered = self
+            .files
+            .keys()
+            .filter(|dir| !self.watched.contains(*dir) && !is_under(dir, &self.dirs))
+            .cloned()
+            .collect::<Vec<_>>();
+        for dir in uncovered {
+            watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            self.watched.insert(dir);
+        }
+        Ok(())
+    }
+
+    /// Drop the actions for the entries of the directories watched for the files in them, other
+    /// than the files themselves.
+    fn filter(&self, actions: Vec<IndexingAction>) -> Vec<IndexingAction> {
+        if self.watched.is_empty() {
+            return actions;
+        }
+
+        actions
+            .into_iter()
+            .filter(|action| match action {
+                IndexingAction::Add { path }
+                | IndexingAction::Update { path }
+                | IndexingAction::AddDir { path }
+                | IndexingAction::RemoveDir { path } => {
+                    let dir = path.parent().unwrap_or(path);
+                    !self.watched.contains(dir) || self.is_file(path)
+                }
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+/// Filters of all the watched paths.
+#[derive(Default)]
+struct WatchRoots {
+    filters: Vec<RootFilter>,
+    /// Normalised paths of the paused watch groups, see [`LiveIndexer::pause_group`].
+    paused: Vec<PathBuf>,
+}
+
+impl WatchRoots {
+    /// Add the filter of a watched path, replacing the previous one.
+    fn insert(&mut self, filter: RootFilter) {
+        self.remove(&filter.root);
+        self.filters.push(filter);
+    }
+
+    /// Remove the filter of a watched path.
+    fn remove(&mut self, root: &Path) {
+        self.filters.retain(|filter| filter.root != root);
+        self.paused.retain(|paused| paused != root);
+    }
+
+    /// Get the filter of a watched path.
+    fn get(&self, root: &Path) -> Option<&RootFilter> {
+        self.filters.iter().find(|filter| filter.root == root)
+    }
+
+    /// Pause or resume indexing the files under a watched path.
+    fn set_paused(&mut self, root: PathBuf, paused: bool) {
+        self.paused.retain(|path| *path != root);
+        if paused {
+            self.paused.push(root);
+        }
+    }
+
+    /// Check whether the given path is under a paused watched path.
+    fn is_paused(&self, file_system: &dyn FileSystem, path: &Path) -> Result<bool> {
+        if self.paused.is_empty() {
+            return Ok(false);
+        }
+
+        Ok(is_under(&path::normalise(file_system, path)?, &self.paused))
+    }
+
+    /// Check whether the given file should be indexed according to the options of the innermost
+    /// watched path containing it.
+    ///
+    /// Anything that isn't a file is accepted, so that it's handled by the [`Indexer`].
+    fn check(&self, file_system: &dyn FileSystem, path: &Path) -> Result<Verdict> {
+        if self.filters.is_empty() {
+            return Ok(Verdict::default());
+        }
+
+        let metadata = match file_system.metadata(path) {
+            Ok(metadata) if metadata.is_file => metadata,
+            _ => return Ok(Verdict::default()),
+        };
+        let path = path::normalise(file_system, path)?;
+
+        Ok(self
+            .filters
+            .iter()
+            .filter(|filter| path.starts_with(&filter.root))
+            .max_by_key(|filter| filter.root.components().count())
+            .map_or_else(Verdict::default, |filter| Verdict {
+                root: Some(filter.root.clone()),
+                skipped: filter.skips(&path, &metadata),
+                len: metadata.len,
+                progress_callback: filter.progress_callback.clone(),
+            }))
+    }
+}
+
+/// Verdict of the watch options on a file, see [`WatchRoots::check`].
+#[derive(Default)]
+struct Verdict {
+    /// Innermost watched path containing the file, if it's a file.
+    root: Option<PathBuf>,
+    /// Why the file is skipped, `None` if it should be indexed.
+    skipped: Option<Outcome>,
+    /// Size of the file.
+    len: u64,
+    progress_callback: Option<ProgressCallback>,
+}
+
+/// Maximum number of times a file modified while being indexed is requeued.
+const MAX_REINDEX_ATTEMPTS: u32 = 3;
+
+/// Action to be performed by indexing worker.
+///
+/// See [`spawn_indexing_worker`].
+enum IndexingAction {
+    Add {
+        path: PathBuf,
+    },
+    /// Update the index of a modified file.
+    Update {
+        path: PathBuf,
+    },
+    /// Index a file again after it was modified while being indexed.
+    Reindex {
+        path: PathBuf,
+        attempt: u32,
+    },
+    /// Index or update a file again after it failed with a transient error.
+    Retry {
+        path: PathBuf,
+        update: bool,
+        retry: u32,
+    },
+    AddDir {
+        path: PathBuf,
+    },
+    AddManifest {
+        path: PathBuf,
+    },
+    /// Start filtering the files under a watched path.
+    AddRoot {
+        filter: RootFilter,
+    },
+    /// Pause or resume indexing the files under a watched path.
+    PauseRoot {
+        root: PathBuf,
+        paused: bool,
+    },
+    /// Stop filtering the files under a watched path and remove them from the index.
+    RemoveRoot {
+        path: PathBuf,
+    },
+    /// Remove a deleted file or directory from the index.
+    ///
+    /// The filter of a deleted watched path is kept, so that it applies once the path is created
+    /// again.
+    RemoveDir {
+        path: PathBuf,
+    },
+    /// Index the files made stale by a configuration update again.
+    ReindexStale,
+    /// Notify the sender once the actions queued before this one are performed.
+    #[cfg(any(test, feature = "test-util"))]
+    Flush {
+        done: mpsc::Sender<()>,
+    },
+}
+
+/// Destination of the indexing actions of a [`LiveIndexer`].
+#[derive(Clone)]
+enum IndexingQueue {
+    /// Actions are queued to the indexing worker, see [`spawn_indexing_worker`].
+    Worker(mpsc::Sender<IndexingAction>),
+    /// Actions are performed on the caller thread, see [`LiveIndexerOptions::with_synchronous_mode`].
+    Synchronous(Arc<Mutex<SynchronousWorker>>),
+}
+
+impl IndexingQueue {
+    fn send(&self, action: IndexingAction) {
+        match self {
+            // The worker only shuts down once all the senders are dropped, see
+            // `spawn_indexing_worker`.
+            Self::Worker(indexing_queue) => indexing_queue.send(action).unwrap(),
+            Self::Synchronous(worker) => worker.lock().unwrap().worker.perform_settled(action),
+        }
+    }
+}
+
+/// Indexing worker of the synchronous mode, along with the watcher events waiting to be processed.
+struct SynchronousWorker {
+    worker: IndexingWorker,
+    watcher_event_rx: mpsc::Receiver<DebouncedEvent>,
+}
+
+/// Spawn an indexing worker.
+///
+/// This worker performs mutating indexing operations on the index (index/clear) in a separate thread.
+///
+/// Returns an [`mpsc::Sender`] that allows to enqueue tasks for this worker.
+///
+/// NOTE: since the only normal condition for this worker to shutdown is when all the senders
+/// are dropped, it is safe to `.unwrap()` sends on the returned by this function sender.
+///
+/// Files that get modified while being indexed are requeued (up to [`MAX_REINDEX_ATTEMPTS`] times)
+/// behind the actions that are already waiting in the queue. Files that fail with a transient error
+/// are retried according to the [`ErrorPolicy`], once their backoff elapses.
+///
+/// The worker thread runs at the given niceness, if any, see [`set_thread_niceness`].
+fn spawn_indexing_worker(
+    indexer: Arc<Indexer>,
+    allowed_roots: Arc<AllowedRoots>,
+    audit_log: Option<Arc<AuditLog>>,
+    drops: Arc<DropCounter>,
+    coverage: Arc<Coverage>,
+    niceness: Option<i32>,
+    error_policy: ErrorPolicy,
+) -> mpsc::Sender<IndexingAction> {
+    let (tx, indexing_queue_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let _span = info_span!("indexing_worker", index = %indexer.name()).entered();
+
+        if let Some(niceness) = niceness {
+            if let Err(e) = set_thread_niceness(niceness) {
+                warn!(error = %e, %niceness, "failed to set the indexing worker niceness");
+            }
+        }
+
+        let mut worker = IndexingWorker::new(
+            indexer,
+            allowed_roots,
+            audit_log,
+            drops,
+            coverage,
+            error_policy,
+        );
+
+        loop {
+            // Requeued actions and due retries are only picked up once the queue is drained.
+            let action = match indexing_queue_rx.try_recv() {
+                Ok(action) => action,
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => match worker.next_requeued() {
+                    Some(action) => action,
+                    None => match worker.next_retry_time() {
+                        Some(time) => {
+                            let timeout = time.saturating_duration_since(Instant::now());
+                            match indexing_queue_rx.recv_timeout(timeout) {
+                                Ok(action) => action,
+                                Err(RecvTimeoutError::Timeout) => continue,
+                                Err(RecvTimeoutError::Disconnected) => break,
+                            }
+                        }
+                        None => match indexing_queue_rx.recv() {
+                            Ok(action) => action,
+                            Err(_) => break,
+                        },
+                    },
+                },
+            };
+
+            worker.perform_logged(action);
+        }
     });
+
+    tx
+}
+
+/// Set the niceness of the calling thread.
+#[cfg(target_os = "linux")]
+fn set_thread_niceness(niceness: i32) -> io::Result<()> {
+    // Linux keeps the niceness per thread, and `0` refers to the calling thread rather than to the
+    // whole process, contrary to POSIX.
+    // SAFETY: the call has no memory safety requirements.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, niceness) } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Set the niceness of the calling thread.
+#[cfg(not(target_os = "linux"))]
+fn set_thread_niceness(_niceness: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "per-thread niceness is not supported on this platform",
+    ))
+}
+
+/// State of the indexing worker, see [`spawn_indexing_worker`].
+struct IndexingWorker {
+    indexer: Arc<Indexer>,
+    allowed_roots: Arc<AllowedRoots>,
+    roots: WatchRoots,
+    requeued: VecDeque<IndexingAction>,
+    /// Actions to retry, along with the time they are due at.
+    retries: Vec<(Instant, IndexingAction)>,
+    error_policy: ErrorPolicy,
+    audit_log: Option<Arc<AuditLog>>,
+    drops: Arc<DropCounter>,
+    coverage: Arc<Coverage>,
+}
+
+impl IndexingWorker {
+    fn new(
+        indexer: Arc<Indexer>,
+        allowed_roots: Arc<AllowedRoots>,
+        audit_log: Option<Arc<AuditLog>>,
+        drops: Arc<DropCounter>,
+        coverage: Arc<Coverage>,
+        error_policy: ErrorPolicy,
+    ) -> Self {
+        Self {
+            indexer,
+            allowed_roots,
+            roots: WatchRoots::default(),
+            requeued: VecDeque::new(),
+            retries: Vec::new(),
+            error_policy,
+            audit_log,
+            drops,
+            coverage,
+        }
+    }
+
+    /// Perform an action, logging its error if it fails.
+    fn perform_logged(&mut self, action: IndexingAction) {
+        if let Err(e) = self.perform(action) {
+            warn!(error = %e, "indexing error");
+        }
+    }
+
+    /// Perform an action followed by the actions it requeued, see [`IndexingWorker::settle`].
+    fn perform_settled(&mut self, action: IndexingAction) {
+        self.perform_logged(action);
+        self.settle();
+    }
+
+    /// Perform the requeued actions and the retries that are due, until there are none left.
+    fn settle(&mut self) {
+        while let Some(action) = self.next_requeued() {
+            self.perform_logged(action);
+        }
+    }
+
+    fn perform(&mut self, action: IndexingAction) -> Result<()> {
+        match action {
+            IndexingAction::Add { path } => self.index_file(&path, 0, 0, false),
+            IndexingAction::Update { path } => self.index_file(&path, 0, 0, true),
+            IndexingAction::Reindex { path, attempt } => self.index_file(&path, attempt, 0, false),
+            IndexingAction::Retry {
+                path,
+                update,
+                retry,
+            } => self.index_file(&path, 0, retry, update),
+            IndexingAction::AddDir { path } => {
+                let result = self.add_dir(&path);
+                self.audit("add_dir", &path, &result);
+                result
+            }
+            IndexingAction::AddManifest { path } => {
+                let result = self.add_manifest(&path);
+                self.audit("add_manifest", &path, &result);
+                result
+            }
+            IndexingAction::AddRoot { filter } => {
+                self.audit("add_root", &filter.root, &Ok(()));
+                self.coverage.reset(&filter.root);
+                self.roots.insert(filter);
+                Ok(())
+            }
+            IndexingAction::PauseRoot { root, paused } => {
+                self.audit(if paused { "pause" } else { "resume" }, &root, &Ok(()));
+                self.roots.set_paused(root, paused);
+                Ok(())
+            }
+            IndexingAction::RemoveRoot { path } => {
+                if let Ok(root) = path::normalise(self.indexer.file_system(), &path) {
+                    self.roots.remove(&root);
+                    self.coverage.remove(&root);
+                }
+
+                let result = self.indexer.clear_dir_from_index(&path);
+                self.audit("remove_root", &path, &result);
+                result
+            }
+            IndexingAction::RemoveDir { path } => {
+                let result = self.indexer.clear_dir_from_index(&path);
+                self.audit("remove", &path, &result);
+                result
+            }
+            IndexingAction::ReindexStale => {
+                let reindexed = self.indexer.reindex_stale();
+                trace!(%reindexed, "indexed the stale files again");
+                Ok(())
+            }
+            #[cfg(any(test, feature = "test-util"))]
+            IndexingAction::Flush { done } => {
+                // Files requeued after being modified while indexed are waited for as well.
+                if self.requeued.is_empty() {
+                    let _ = done.send(());
+                } else {
+                    self.requeued.push_back(IndexingAction::Flush { done });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Index or update the given file, unless it's filtered out by the options of its watched path.
+    ///
+    /// Filtered out files are removed from the index, as they might have been indexed before.
+    ///
+    /// `attempt` counts the times the file was modified while being indexed, and `retry` the times
+    /// it failed with a transient error.
+    fn index_file(&mut self, path: &Path, attempt: u32, retry: u32, update: bool) -> Result<()> {
+        if self.roots.is_paused(self.indexer.file_system(), path)? {
+            trace!(path = %path.display(), "file is under a paused watch group");
+            return Ok(());
+        }
+        let verdict = self.roots.check(self.indexer.file_system(), path)?;
+        if attempt == 0 && retry == 0 {
+            self.count(&verdict, Outcome::Discovered);
+        }
+        if let Some(skipped) = verdict.skipped {
+            trace!(path = %path.display(), "file is filtered out by the watch options");
+            self.count(&verdict, skipped);
+
+            let result = self.indexer.clear_from_index(path);
+            self.audit("filter", path, &result);
+            return result;
+        }
+
+        let (kind, result) = if update {
+            ("update", self.indexer.update_file(path))
+        } else {
+            ("index", self.indexer.index_file(path))
+        };
+        self.audit(kind, path, &result);
+
+        let failed = result.is_err();
+        let result = self.retry_if_transient(path, result, retry, update);
+        let result = self.requeue_if_modified(result, attempt);
+        match &result {
+            Ok(()) if failed => (),
+            Ok(()) => self.count(&verdict, Outcome::Indexed),
+            Err(e) if e.is_invalid_text() => self.count(&verdict, Outcome::Binary),
+            Err(_) => self.count(&verdict, Outcome::Failed),
+        }
+        result
+    }
+
+    /// Count an outcome in the coverage of the watched path containing the file, if any.
+    ///
+    /// The progress callback of the watched path is called once the file is processed.
+    fn count(&self, verdict: &Verdict, outcome: Outcome) {
+        let coverage = match &verdict.root {
+            Some(root) => self.coverage.count(root, outcome, verdict.len),
+            None => return,
+        };
+        if let (Some(coverage), Some(callback)) = (coverage, &verdict.progress_callback) {
+            if outcome != Outcome::Discovered {
+                callback(&coverage);
+            }
+        }
+    }
+
+    fn retry_if_transient(
+        &mut self,
+        path: &Path,
+        result: Result<()>,
+        retry: u32,
+        update: bool,
+    ) -> Result<()> {
+        match result {
+            Err(e) if e.is_transient() && retry < self.error_policy.max_retries => {
+                let backoff = self.error_policy.backoff(retry);
+                trace!(path = %path.display(), error = %e, %retry, ?backoff, "retrying a file after a transient error");
+
+                self.retries.push((
+                    Instant::now() + backoff,
+                    IndexingAction::Retry {
+                        path: path.to_owned(),
+                        update,
+                        retry: retry + 1,
+                    },
+                ));
+                Ok(())
+            }
+            Err(e) if e.is_transient() => {
+                self.drops.record(path, DropKind::Dropped);
+                Err(e)
+            }
+            r => r,
+        }
+    }
+
+    /// Take the next requeued action, or the next retry that is due.
+    fn next_requeued(&mut self) -> Option<IndexingAction> {
+        if let Some(action) = self.requeued.pop_front() {
+            return Some(action);
+        }
+
+        let now = Instant::now();
+        let due = self.retries.iter().position(|(time, _)| *time <= now)?;
+        Some(self.retries.swap_remove(due).1)
+    }
+
+    /// Time the earliest retry is due at.
+    fn next_retry_time(&self) -> Option<Instant> {
+        self.retries.iter().map(|(time, _)| *time).min()
+    }
+
+    fn requeue_if_modified(&mut self, result: Result<()>, attempt: u32) -> Result<()> {
+        match result {
+            Err(Error::ModifiedDuringIndexing(path)) if attempt < MAX_REINDEX_ATTEMPTS => {
+                trace!(path = %path.display(), %attempt, "requeueing a file modified while being indexed");
+
+                self.requeued.push_back(IndexingAction::Reindex {
+                    path,
+                    attempt: attempt + 1,
+                });
+                Ok(())
+            }
+            Err(Error::ModifiedDuringIndexing(path)) => {
+                self.drops.record(&path, DropKind::Dropped);
+                Err(Error::ModifiedDuringIndexing(path))
+            }
+            r => r,
+        }
+    }
+
+    fn add_dir(&mut self, path: &Path) -> Result<()> {
+        let indexer = Arc::clone(&self.indexer);
+        let file_system = indexer.file_system();
+
+        let root = path::normalise(file_system, path)?;
+        if self
+            .roots
+            .get(&root)
+            .is_some_and(|filter| filter.estimate_progress)
+        {
+            self.estimate(&root);
+        }
+
+        for entry in file_system.walk_ordered(&root, indexer.walk_order()) {
+            let path = entry?;
+
+            if let Err(e) = self.index_file(&path, 0, 0, false) {
+                warn!(error = %e, "failed to index a file");
+            }
+        }
+        Ok(())
+    }
+
+    /// Count the files under a watched path and their total size, see
+    /// [`WatchOptions::with_progress_estimate`].
+    fn estimate(&self, root: &Path) {
+        let file_system = self.indexer.file_system();
+        let (mut files, mut bytes) = (0, 0);
+
+        for path in file_system.walk(root).filter_map(|entry| entry.ok()) {
+            match file_system.metadata(&path) {
+                Ok(metadata) if metadata.is_file => {
+                    files += 1;
+                    bytes += metadata.len;
+                }
+                _ => (),
+            }
+        }
+        trace!(root = %root.display(), %files, %bytes, "estimated the files to index");
+        self.coverage.estimate(root, files, bytes);
+    }
+
+    fn add_manifest(&mut self, manifest: &Path) -> Result<()> {
+        let file_system = self.indexer.file_system();
+
+        for path in path::read_manifest(file_system, manifest)? {
+            let result = self
+                .allowed_roots
+                .check(self.indexer.file_system(), &path)
+                .and_then(|_| self.index_file(&path, 0, 0, false));
+
+            if let Err(e) = result {
+                warn!(error = %e, path = %path.display(), "failed to index a file");
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the outcome of an action in the audit log, if it's enabled.
+    fn audit(&self, kind: &'static str, path: &Path, result: &Result<()>) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.action(kind, path, result);
+        }
+    }
+}
+
+/// Spawn snapshot rotation worker.
+///
+/// This worker rotates the snapshots of the index every `interval` in a separate thread, until the
+/// returned sender is dropped.
+fn spawn_rotation_worker(
+    indexer: Arc<Indexer>,
+    rotation: SnapshotRotation,
+    interval: Duration,
+) -> mpsc::Sender<()> {
+    let (stop, stopped) = mpsc::channel();
+
+    thread::spawn(move || {
+        let _span = info_span!("rotation_worker", index = %indexer.name()).entered();
+        let mut rotated = None;
+
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let generation = indexer.generation();
+            if rotated == Some(generation) {
+                continue;
+            }
+            match rotation.rotate(&indexer) {
+                Ok(_) => rotated = Some(generation),
+                Err(e) => {
+                    error!(error = %e, dir = %rotation.dir().display(), "failed to rotate the snapshots")
+                }
+            }
+        }
+    });
+
+    stop
+}
+
+/// Spawn filesystem watching worker.
+///
+/// This worker listens for file events in a separate thread and queues corresponding [`IndexingAction`]s
+/// to the indexing worker.
+fn spawn_watching_worker(
+    indexing_queue: mpsc::Sender<IndexingAction>,
+    watcher_event_rx: mpsc::Receiver<notify::DebouncedEvent>,
+    file_watches: Arc<Mutex<FileWatches>>,
+    audit_log: Option<Arc<AuditLog>>,
+    drops: Arc<DropCounter>,
+) {
+    thread::spawn(move || {
+        while let Ok(event) = watcher_event_rx.recv() {
+            if let Some(audit_log) = &audit_log {
+                audit_event(audit_log, &event);
+            }
+            count_drops(&drops, &event);
+
+            let actions = file_watches.lock().unwrap().filter(actions_for(event));
+            for action in actions {
+                indexing_queue.send(action).unwrap();
+            }
+        }
+
+        info!("file watcher is shutting down");
+    });
+}
+
+/// Indexing actions to perform in reaction on a watcher event.
+fn actions_for(event: DebouncedEvent) -> Vec<IndexingAction> {
+    match event {
+        DebouncedEvent::Write(path) => {
+            trace!(path = %path.display(), "file write event");
+
+            vec![IndexingAction::Update { path }]
+        }
+
+        DebouncedEvent::Create(path) => {
+            trace!(path = %path.display(), "file create event");
+
+            vec![IndexingAction::Add { path }]
+        }
+
+        // The removed path may have been a directory, so everything beneath it is purged too.
+        DebouncedEvent::Remove(path) => {
+            trace!(path = %path.display(), "file remove event");
+
+            vec![IndexingAction::RemoveDir { path }]
+        }
+
+        DebouncedEvent::Rename(path_old, path_new) => {
+            trace!(old = %path_old.display(), new = %path_new.display(), "file rename event");
+
+            vec![
+                IndexingAction::RemoveDir { path: path_old },
+                IndexingAction::AddDir { path: path_new },
+            ]
+        }
+
+        DebouncedEvent::Error(e, p) => {
+            error!(error = %e, path = ?p.as_ref().map(|p| p.display()), "watcher sent an error");
+            Vec::new()
+        }
+
+        // These events are ignored. They could be useful for additional robustness in the future.
+        DebouncedEvent::Rescan
+        | DebouncedEvent::Chmod(_)
+        | DebouncedEvent::NoticeWrite(_)
+        | DebouncedEvent::NoticeRemove(_) => Vec::new(),
+    }
+}
+
+/// Count the watcher events that are merged into later ones or lose track of changes.
+fn count_drops(drops: &DropCounter, event: &DebouncedEvent) {
+    match event {
+        DebouncedEvent::NoticeWrite(path) | DebouncedEvent::NoticeRemove(path) => {
+            drops.record(path, DropKind::Merged)
+        }
+        DebouncedEvent::Error(_, Some(path)) => drops.record(path, DropKind::Dropped),
+        DebouncedEvent::Rescan => drops.record_rescan(),
+        _ => (),
+    }
+}
+
+/// Record a watcher event in the audit log.
+fn audit_event(audit_log: &AuditLog, event: &DebouncedEvent) {
+    match event {
+        DebouncedEvent::Write(path) => audit_log.event("write", Some(path), None),
+        DebouncedEvent::Create(path) => audit_log.event("create", Some(path), None),
+        DebouncedEvent::Remove(path) => audit_log.event("remove", Some(path), None),
+        DebouncedEvent::Rename(path_old, path_new) => {
+            audit_log.event("rename_from", Some(path_old), None);
+            audit_log.event("rename_to", Some(path_new), None);
+        }
+        DebouncedEvent::Error(e, path) => {
+            audit_log.event("error", path.as_deref(), Some(e.to_string()))
+        }
+        DebouncedEvent::Rescan => audit_log.event("rescan", None, None),
+        DebouncedEvent::Chmod(path) => audit_log.event("chmod", Some(path), None),
+        DebouncedEvent::NoticeWrite(path) => audit_log.event("notice_write", Some(path), None),
+        DebouncedEvent::NoticeRemove(path) => audit_log.event("notice_remove", Some(path), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs, io,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use notify::DebouncedEvent;
+
+    use super::{
+        AllowedRoots, ErrorPolicy, LiveIndexer, LiveIndexerOptions, RelativePath, WatchGroup,
+        WatchOptions, MAX_REINDEX_ATTEMPTS,
+    };
+    use crate::{
+        file_system::{FileSystem, MemoryFileSystem, Metadata, OsFileSystem},
+        tokenise, Indexer,
+    };
+
+    /// Memory file system whose files grow every time they are read, as if they were written to
+    /// while being indexed, as long as `growths` allows.
+    struct GrowingFileSystem {
+        files: MemoryFileSystem,
+        growths: Mutex<u32>,
+        reads: Mutex<u32>,
+    }
+
+    impl FileSystem for GrowingFileSystem {
+        fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+            self.files.metadata(path)
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            let contents = self.files.read(path)?;
+            let mut growths = self.growths.lock().unwrap();
+
+            *self.reads.lock().unwrap() += 1;
+            if *growths > 0 {
+                *growths -= 1;
+                self.files.write(path, &[&contents[..], b" more"].concat());
+            }
+            Ok(contents)
+        }
+
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            self.files.canonicalize(path)
+        }
+
+        fn walk(&self, root: &Path) -> Box<dyn Iterator<Item = io::Result<PathBuf>> + '_> {
+            self.files.walk(root)
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = ErrorPolicy::new()
+            .with_retries(10, Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(500));
+
+        let backoffs = (0..5)
+            .map(|retry| policy.backoff(retry).as_millis())
+            .collect::<Vec<_>>();
+
+        assert_eq!(backoffs, [100, 200, 400, 500, 500]);
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn allowed_roots_reject_paths_outside() {
+        let dir = env::temp_dir().join("indexing-allowed-roots-tests");
+        let (allowed, other) = (dir.join("allowed"), dir.join("other"));
+        fs::create_dir_all(&allowed).unwrap();
+        fs::create_dir_all(&other).unwrap();
+
+        let roots = AllowedRoots::default();
+        assert!(roots.check(&OsFileSystem, &other).is_ok());
+
+        roots.restrict_to(&OsFileSystem, &[&allowed]).unwrap();
+
+        assert!(roots
+            .check(&OsFileSystem, &allowed.join("file.txt"))
+            .is_ok());
+        assert!(roots.check(&OsFileSystem, &other).is_err());
+        assert!(roots
+            .check(&OsFileSystem, &allowed.join("../other"))
+            .is_err());
+        assert!(roots.restrict_to(&OsFileSystem, &[&dir]).is_err());
+        assert!(roots
+            .restrict_to(&OsFileSystem, &[allowed.join("inner")])
+            .is_ok());
+        assert!(roots
+            .check(&OsFileSystem, &allowed.join("file.txt"))
+            .is_err());
+    }
+
+    #[test]
+    fn injected_events_are_indexed_by_flush() {
+        let dir = env::temp_dir().join("indexing-injected-events-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("injected.txt");
+        fs::write(&path, "injected").unwrap();
+
+        let indexer = LiveIndexer::start(Indexer::new(|| {
+            Box::new(tokenise::SpaceTokeniser::new()) as _
+        }))
+        .unwrap();
+
+        indexer.inject(DebouncedEvent::Create(path.clone()));
+        indexer.flush();
+        assert_eq!(indexer.query("injected").len(), 1);
+
+        indexer.inject(DebouncedEvent::Remove(path));
+        indexer.flush();
+        assert!(indexer.query("injected").is_empty());
+    }
+
+    #[test]
+    fn synchronous_mode_indexes_on_the_caller_thread() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/memory/a.txt"), b"alpha");
+        file_system.write(Path::new("/memory/b.txt"), b"beta");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new()
+                .with_audit_log(16)
+                .with_synchronous_mode(),
+        )
+        .unwrap();
+
+        indexer.watch("/memory").unwrap();
+        assert_eq!(indexer.query("alpha").len(), 1);
+        assert_eq!(indexer.query("beta").len(), 1);
+
+        file_system.remove(Path::new("/memory/a.txt"));
+        indexer.inject(DebouncedEvent::Remove(PathBuf::from("/memory/a.txt")));
+        assert!(indexer.query("alpha").is_empty());
+        assert_eq!(indexer.process_events(), 0);
+
+        let actions = indexer
+            .audit_log()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.kind, entry.path.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            [
+                ("add_root", PathBuf::from("/memory")),
+                ("index", PathBuf::from("/memory")),
+                ("index", PathBuf::from("/memory/a.txt")),
+                ("index", PathBuf::from("/memory/b.txt")),
+                ("add_dir", PathBuf::from("/memory")),
+                ("remove", PathBuf::from("/memory/a.txt")),
+                ("remove", PathBuf::from("/memory/a.txt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn deleted_roots_keep_their_options() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/memory/sub/a.txt"), b"alpha");
+        file_system.write(Path::new("/memory/sub/b.log"), b"beta");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+        indexer.watch("/memory").unwrap();
+        indexer
+            .watch_with("/memory/sub", WatchOptions::new().with_exclude("*.log"))
+            .unwrap();
+        assert_eq!(indexer.query("alpha").len(), 1);
+        assert!(indexer.query("beta").is_empty());
+
+        file_system.remove(Path::new("/memory/sub"));
+        indexer.inject(DebouncedEvent::Remove(PathBuf::from("/memory/sub")));
+        assert!(indexer.query("alpha").is_empty());
+
+        file_system.write(Path::new("/memory/sub/a.txt"), b"alpha");
+        file_system.write(Path::new("/memory/sub/b.log"), b"beta");
+        for path in ["/memory/sub/a.txt", "/memory/sub/b.log"] {
+            indexer.inject(DebouncedEvent::Create(PathBuf::from(path)));
+        }
+        assert_eq!(indexer.query("alpha").len(), 1);
+        assert!(indexer.query("beta").is_empty());
+        assert_eq!(indexer.coverage().len(), 2);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn niceness_is_set_per_thread() {
+        fn niceness() -> i32 {
+            // SAFETY: the call has no memory safety requirements.
+            unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }
+        }
+
+        // Raising the niceness needs no privileges, 19 is the highest one.
+        let before = niceness();
+        let nicer = (before + 1).min(19);
+        let (set, after) = thread::spawn(move || (super::set_thread_niceness(nicer), niceness()))
+            .join()
+            .unwrap();
+
+        set.unwrap();
+        assert_eq!(after, nicer);
+        assert_eq!(niceness(), before);
+    }
+
+    #[test]
+    fn clones_share_the_live_index() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/cloned/a.txt"), b"alpha");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new(),
+        )
+        .unwrap();
+        let clone = indexer.clone();
+        assert!(Arc::ptr_eq(&indexer.indexer(), &clone.indexer()));
+
+        indexer.watch("/cloned").unwrap();
+        indexer.flush();
+        assert_eq!(clone.query("alpha").len(), 1);
+
+        // An event handled through the clone is seen through the original and its indexer.
+        file_system.write(Path::new("/cloned/b.txt"), b"bravo");
+        clone.inject(DebouncedEvent::Create(PathBuf::from("/cloned/b.txt")));
+        clone.flush();
+        assert_eq!(indexer.query("bravo").len(), 1);
+        assert_eq!(indexer.indexer().query("bravo").len(), 1);
+
+        drop(indexer);
+        file_system.remove(Path::new("/cloned/a.txt"));
+        clone.inject(DebouncedEvent::Remove(PathBuf::from("/cloned/a.txt")));
+        clone.flush();
+        assert!(clone.indexer().query("alpha").is_empty());
+    }
+
+    #[test]
+    fn files_modified_while_being_indexed_are_requeued_then_given_up_on() {
+        let file_system = Arc::new(GrowingFileSystem {
+            files: MemoryFileSystem::new(),
+            growths: Mutex::new(0),
+            reads: Mutex::new(0),
+        });
+        file_system
+            .files
+            .write(Path::new("/growing/a.txt"), b"alpha");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+        indexer.watch("/growing").unwrap();
+        assert_eq!(indexer.query("alpha").len(), 1);
+
+        // Modified once: indexed again behind the queue, and found.
+        let bravo = Path::new("/growing/b.txt");
+        file_system.files.write(bravo, b"bravo");
+        *file_system.growths.lock().unwrap() = 1;
+        *file_system.reads.lock().unwrap() = 0;
+        indexer.inject(DebouncedEvent::Create(bravo.to_owned()));
+        assert_eq!(*file_system.reads.lock().unwrap(), 2);
+        assert_eq!(indexer.query("bravo").len(), 1);
+        assert_eq!(indexer.dropped_events().dropped_under(bravo), 0);
+
+        // Modified at every attempt: given up on after the last one, and recorded as dropped.
+        let charlie = Path::new("/growing/c.txt");
+        file_system.files.write(charlie, b"charlie");
+        *file_system.growths.lock().unwrap() = u32::MAX;
+        *file_system.reads.lock().unwrap() = 0;
+        indexer.inject(DebouncedEvent::Create(charlie.to_owned()));
+        assert_eq!(*file_system.reads.lock().unwrap(), MAX_REINDEX_ATTEMPTS + 1);
+        assert!(indexer.query("charlie").is_empty());
+        assert_eq!(indexer.dropped_events().dropped_under(charlie), 1);
+    }
+
+    #[test]
+    fn watch_groups_share_options_and_pause_as_a_unit() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/one/a.txt"), b"alpha");
+        file_system.write(Path::new("/one/a.log"), b"noise");
+        file_system.write(Path::new("/two/b.txt"), b"beta");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+
+        indexer
+            .add_group(
+                WatchGroup::new("docs", WatchOptions::new().with_exclude("*.log"))
+                    .with_root("/one")
+                    .with_root("/two"),
+            )
+            .unwrap();
+        assert_eq!(indexer.query("alpha").len(), 1);
+        assert_eq!(indexer.query("beta").len(), 1);
+        assert!(indexer.query("noise").is_empty());
+
+        indexer.pause_group("docs").unwrap();
+        file_system.write(Path::new("/two/c.txt"), b"gamma");
+        indexer.inject(DebouncedEvent::Create(PathBuf::from("/two/c.txt")));
+        assert!(indexer.query("gamma").is_empty());
+
+        let status = indexer.groups();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].roots.len(), 2);
+        assert!(status[0].paused);
+        assert_eq!(status[0].files, 2);
+
+        indexer.resume_group("docs").unwrap();
+        assert_eq!(indexer.query("gamma").len(), 1);
+        assert_eq!(indexer.groups()[0].files, 3);
+
+        indexer.remove_group("docs").unwrap();
+        assert!(indexer.query("alpha").is_empty());
+        assert!(indexer.groups().is_empty());
+        assert!(indexer.pause_group("docs").is_err());
+    }
+
+    #[test]
+    fn coverage_is_reported_per_watched_path() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/one/a.txt"), b"alpha");
+        file_system.write(Path::new("/one/a.log"), b"noise");
+        file_system.write(Path::new("/one/large.txt"), b"too many bytes");
+        file_system.write(Path::new("/one/binary.txt"), &[0xff, 0xfe]);
+        file_system.write(Path::new("/two/b.txt"), b"beta");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+
+        indexer
+            .watch_with(
+                "/one",
+                WatchOptions::new()
+                    .with_exclude("*.log")
+                    .with_max_file_size(8),
+            )
+            .unwrap();
+        indexer.watch("/two").unwrap();
+
+        let coverage = indexer.coverage();
+        assert_eq!(coverage.len(), 2);
+        let one = &coverage[0];
+        assert_eq!(one.root, Path::new("/one"));
+        assert_eq!((one.discovered, one.indexed, one.documents), (4, 1, 1));
+        assert_eq!((one.filtered, one.too_large, one.binary), (1, 1, 1));
+        assert_eq!(one.failed, 0);
+        assert_eq!((coverage[1].discovered, coverage[1].indexed), (1, 1));
+
+        indexer.unwatch("/two").unwrap();
+        assert_eq!(indexer.coverage().len(), 1);
+    }
+
+    #[test]
+    fn progress_is_estimated_before_indexing() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/progress/a.txt"), b"alpha");
+        file_system.write(Path::new("/progress/nested/b.txt"), b"bravo charlie d");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+
+        let progress = Arc::clone(&reported);
+        let options = WatchOptions::new()
+            .with_progress_estimate()
+            .with_progress_callback(move |coverage| {
+                progress.lock().unwrap().push(coverage.progress().unwrap());
+            });
+        indexer.watch_with("/progress", options).unwrap();
+
+        assert_eq!(*reported.lock().unwrap(), [0.25, 1.0]);
+        let coverage = &indexer.coverage()[0];
+        assert_eq!(
+            (coverage.estimated_files, coverage.estimated_bytes),
+            (Some(2), Some(20))
+        );
+        assert_eq!((coverage.processed(), coverage.processed_bytes), (2, 20));
+        assert_eq!(coverage.eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn query_results_can_be_relative_to_the_watched_paths() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/home/user/notes/a.txt"), b"alpha");
+        file_system.write(Path::new("/home/user/notes/work/b.txt"), b"alpha");
+        file_system.write(Path::new("/home/user/code/c.txt"), b"alpha");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+        indexer.watch("/home/user/notes").unwrap();
+        indexer.watch("/home/user/notes/work").unwrap();
+        indexer.watch("/home/user/code/c.txt").unwrap();
+
+        let relative = |root: &str, path: &str| RelativePath {
+            root: Some(PathBuf::from(root)),
+            path: PathBuf::from(path),
+        };
+        assert_eq!(
+            indexer.query_relative("alpha"),
+            [
+                relative("/home/user/code/c.txt", ""),
+                relative("/home/user/notes", "a.txt"),
+                relative("/home/user/notes/work", "b.txt")
+            ]
+        );
+
+        indexer.unwatch("/home/user/notes/work").unwrap();
+        indexer
+            .indexer()
+            .index_file(Path::new("/home/user/notes/work/b.txt"))
+            .unwrap();
+        assert_eq!(
+            indexer.query_relative("alpha")[2],
+            relative("/home/user/notes", "work/b.txt")
+        );
+    }
+
+    #[test]
+    fn watch_handles_unwatch_on_drop() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/one/a.txt"), b"alpha");
+        file_system.write(Path::new("/two/b.txt"), b"beta");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+
+        let kept = indexer.watch("/one").unwrap();
+        let scoped = indexer.watch("/two").unwrap().unwatch_on_drop();
+        assert_eq!(scoped.path(), Path::new("/two"));
+        assert_eq!(scoped.status().unwrap().indexed, 1);
+
+        drop(scoped);
+        assert!(indexer.query("beta").is_empty());
+        assert_eq!(indexer.coverage().len(), 1);
+
+        drop(kept);
+        assert_eq!(indexer.query("alpha").len(), 1);
+
+        let handle = indexer.watch("/two").unwrap();
+        handle.unwatch().unwrap();
+        assert!(indexer.query("beta").is_empty());
+    }
+
+    #[test]
+    fn single_files_stay_watched_across_atomic_saves() {
+        let dir = env::temp_dir().join("indexing-live-indexer-tests/atomic-saves");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file.txt");
+        fs::write(&file, "alpha").unwrap();
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+        indexer.watch(&file).unwrap();
+        assert_eq!(indexer.query("alpha").len(), 1);
+
+        let wait_for = |term: &str| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while indexer.query(term).is_empty() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+                indexer.process_events();
+            }
+        };
+
+        // The second save replaces a file the first one renamed over the original.
+        fs::write(dir.join("sibling.txt"), "delta").unwrap();
+        for contents in ["beta", "gamma"] {
+            let saved = dir.join("file.txt.tmp");
+            fs::write(&saved, contents).unwrap();
+            fs::rename(&saved, &file).unwrap();
+            wait_for(contents);
+        }
+
+        assert!(indexer.query("alpha").is_empty());
+        assert!(indexer.query("beta").is_empty());
+        assert_eq!(indexer.query("gamma").len(), 1);
+        assert!(indexer.query("delta").is_empty());
+    }
+
+    #[test]
+    fn merged_and_lost_events_are_counted_per_path() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/memory/a.txt"), b"alpha");
+
+        let indexer = LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap();
+
+        indexer.watch("/memory").unwrap();
+        indexer.inject(DebouncedEvent::NoticeWrite(PathBuf::from("/memory/a.txt")));
+        indexer.inject(DebouncedEvent::Write(PathBuf::from("/memory/a.txt")));
+        indexer.inject(DebouncedEvent::Rescan);
+
+        let report = indexer.dropped_events();
+        assert_eq!(report.paths.len(), 1);
+        assert_eq!(report.paths[0].path, Path::new("/memory/a.txt"));
+        assert_eq!((report.paths[0].merged, report.paths[0].dropped), (1, 0));
+        assert_eq!(report.rescans, 1);
+    }
 }
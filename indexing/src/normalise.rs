@@ -6,8 +6,11 @@
 //!  * [`Unicode`] - performs unicode normalisation of tokens
 //!
 //! Additionally, arbitrary normalisers can be defined by implementing [`TokenNormaliser`] trait.
+//!
+//! The normalisers applied to the terms of a single query can be skipped or substituted by name, see
+//! [`NormaliserOverrides`].
 
-use std::{collections::HashSet, ops::Not};
+use std::{any, collections::HashSet, fmt, ops::Not, sync::Arc};
 
 use unicode_normalization::UnicodeNormalization;
 
@@ -20,6 +23,12 @@ use super::tokenise::Token;
 /// See [`crate::indexer::Indexer`] documentation for how tokenisers can be used with an indexer.
 pub trait TokenNormaliser: Send + Sync {
     fn normalise(&self, token: Token) -> Option<Token>;
+
+    /// Name the normaliser is overridden by, its type name by default (e.g. `LowerCase`).
+    fn name(&self) -> &str {
+        let name = any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 /// Unicode normaliser.
@@ -42,10 +51,7 @@ impl TokenNormaliser for Unicode {
             Unicode::NFKD => token.value.nfkd().collect(),
         };
 
-        Some(Token {
-            value,
-            offset: token.offset,
-        })
+        Some(Token { value, ..token })
     }
 }
 
@@ -66,7 +72,10 @@ impl StopWords {
 
 impl TokenNormaliser for StopWords {
     fn normalise(&self, token: Token) -> Option<Token> {
-        self.stop_words.contains(&token.value).not().then(|| token)
+        self.stop_words
+            .contains(token.value.as_str())
+            .not()
+            .then_some(token)
     }
 }
 
@@ -77,9 +86,92 @@ pub struct LowerCase;
 
 impl TokenNormaliser for LowerCase {
     fn normalise(&self, token: Token) -> Option<Token> {
-        Some(Token {
-            value: token.value.to_lowercase(),
-            offset: token.offset,
-        })
+        let value = if token.value.is_ascii() {
+            let mut value = token.value;
+            value.make_ascii_lowercase();
+            value
+        } else {
+            token.value.to_lowercase().into()
+        };
+
+        Some(Token { value, ..token })
+    }
+}
+
+/// Changes to the normalisers applied to the terms of a single query, see
+/// [`crate::QueryOptions::with_normalisers`].
+///
+/// The normalisers of every analyser are matched by name (see [`TokenNormaliser::name`]), e.g. a
+/// case-sensitive lookup skips `LowerCase`. Skipping `StopWords` also skips the stop words added at
+/// runtime (see [`crate::Indexer::add_stop_words`]).
+#[derive(Clone, Default)]
+pub struct NormaliserOverrides {
+    skipped: Vec<String>,
+    substitutes: Vec<(String, Arc<dyn TokenNormaliser>)>,
+}
+
+impl NormaliserOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skip the normalisers with the given name.
+    pub fn with_skipped(mut self, name: &str) -> Self {
+        self.skipped.push(name.to_owned());
+        self
+    }
+
+    /// Apply `substitute` instead of the normalisers with the given name.
+    pub fn with_substitute<T>(mut self, name: &str, substitute: T) -> Self
+    where
+        T: 'static + TokenNormaliser,
+    {
+        self.substitutes
+            .push((name.to_owned(), Arc::new(substitute)));
+        self
+    }
+
+    /// Check whether nothing is overridden.
+    pub fn is_empty(&self) -> bool {
+        self.skipped.is_empty() && self.substitutes.is_empty()
+    }
+
+    /// Check whether the normalisers with the given name are skipped.
+    pub(crate) fn skips(&self, name: &str) -> bool {
+        self.skipped.iter().any(|skipped| skipped == name)
+    }
+
+    /// The normaliser to apply in place of the given one, `None` if it's skipped.
+    pub(crate) fn resolve<'a>(
+        &'a self,
+        normaliser: &'a dyn TokenNormaliser,
+    ) -> Option<&'a dyn TokenNormaliser> {
+        let name = normaliser.name();
+        if self.skips(name) {
+            return None;
+        }
+
+        Some(
+            self.substitutes
+                .iter()
+                .find(|(substituted, _)| substituted == name)
+                .map_or(normaliser, |(_, substitute)| substitute.as_ref()),
+        )
+    }
+}
+
+impl fmt::Debug for NormaliserOverrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NormaliserOverrides")
+            .field("skipped", &self.skipped)
+            .field(
+                "substitutes",
+                &self
+                    .substitutes
+                    .iter()
+                    .map(|(name, substitute)| (name, substitute.name()))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
     }
 }
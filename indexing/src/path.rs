@@ -0,0 +1,168 @@
+//! This module defines the normalisation of file paths used as index keys.
+//!
+//! Files are added to the index by direct calls, by directory walks and as a reaction on watcher
+//! events, and removed in the same ways. All of those entry points must agree on the key the file is
+//! stored under, otherwise a purge can miss entries indexed under a different spelling of the same
+//! path (relative vs. absolute, through a symlink, etc.).
+//!
+//! On case-insensitive file systems differently cased paths refer to the same file, so the keys
+//! can additionally be case folded, see [`PathCase`].
+
+use std::{
+    borrow::Cow,
+    env,
+    io::{self, BufRead},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::file_system::FileSystem;
+
+/// Case sensitivity of the index path keys.
+///
+/// The default matches the platform's native file system: case-insensitive on Windows and macOS,
+/// case-sensitive elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathCase {
+    /// Paths differing only in case refer to different files.
+    Sensitive,
+    /// Paths differing only in case refer to the same file.
+    Insensitive,
+}
+
+impl PathCase {
+    /// Build the index key for an already normalised path.
+    ///
+    /// Non UTF-8 paths are never folded.
+    pub(crate) fn key<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        match (self, path.to_str()) {
+            (PathCase::Insensitive, Some(s)) => Cow::Owned(PathBuf::from(s.to_lowercase())),
+            _ => Cow::Borrowed(path),
+        }
+    }
+}
+
+impl Default for PathCase {
+    fn default() -> Self {
+        if cfg!(any(windows, target_os = "macos")) {
+            PathCase::Insensitive
+        } else {
+            PathCase::Sensitive
+        }
+    }
+}
+
+/// Normalise the given path into an index key.
+///
+/// Existing paths are canonicalised. For paths that no longer exist (e.g. a removed file reported by
+/// the watcher) the longest existing ancestor is canonicalised and the rest of the path is appended
+/// to it, so that the result matches the key the file was indexed under while it existed.
+pub(crate) fn normalise(fs: &dyn FileSystem, path: &Path) -> io::Result<PathBuf> {
+    if let Ok(canonical) = fs.canonicalize(path) {
+        return Ok(canonical);
+    }
+
+    let absolute = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        env::current_dir()?.join(path)
+    };
+    let lexical = lexical_clean(&absolute);
+
+    let mut missing = Vec::new();
+    let mut ancestor = lexical.as_path();
+
+    while let (Some(parent), Some(name)) = (ancestor.parent(), ancestor.file_name()) {
+        missing.push(name);
+        ancestor = parent;
+
+        if let Ok(canonical) = fs.canonicalize(ancestor) {
+            return Ok(missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name)));
+        }
+    }
+
+    Ok(lexical)
+}
+
+/// Read the list of file paths from a manifest file.
+///
+/// A manifest lists one path per line, e.g. the output of `find . -type f`. Empty lines are skipped
+/// and relative paths are resolved against the directory the manifest is located in.
+pub(crate) fn read_manifest(fs: &dyn FileSystem, manifest: &Path) -> io::Result<Vec<PathBuf>> {
+    let base = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let contents = fs.read(manifest)?;
+
+    contents
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| line.map(|line| base.join(line.trim_end_matches('\r'))))
+        .collect()
+}
+
+/// Resolve `.` and `..` components of the path without accessing the file system.
+pub(crate) fn lexical_clean(path: &Path) -> PathBuf {
+    let mut clean = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                clean.pop();
+            }
+            component => clean.push(component),
+        }
+    }
+
+    clean
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path};
+
+    use super::{lexical_clean, normalise, read_manifest, PathCase};
+    use crate::file_system::OsFileSystem;
+
+    #[test]
+    fn lexical_clean_resolves_dots() {
+        assert_eq!(
+            lexical_clean(Path::new("/a/./b/../c/d/..")),
+            Path::new("/a/c")
+        );
+    }
+
+    #[test]
+    fn insensitive_keys_are_case_folded() {
+        let sensitive = PathCase::Sensitive.key(Path::new("/Foo/Bar.txt"));
+        let insensitive = PathCase::Insensitive.key(Path::new("/Foo/Bar.txt"));
+
+        assert_eq!(sensitive, Path::new("/Foo/Bar.txt"));
+        assert_eq!(insensitive, Path::new("/foo/bar.txt"));
+    }
+
+    #[test]
+    fn missing_file_normalises_under_canonical_parent() {
+        let dir = env::temp_dir().join("indexing-path-normalise-test");
+        fs::create_dir_all(&dir).unwrap();
+
+        let spelled = dir.join("sub").join("..").join("gone.txt");
+        let expected = dir.canonicalize().unwrap().join("gone.txt");
+
+        assert_eq!(normalise(&OsFileSystem, &spelled).unwrap(), expected);
+    }
+
+    #[test]
+    fn manifest_paths_are_relative_to_manifest() {
+        let dir = env::temp_dir().join("indexing-path-manifest-test");
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("manifest.txt");
+        fs::write(&manifest, "./a.txt\n\n/abs/b.txt\r\n").unwrap();
+
+        assert_eq!(
+            read_manifest(&OsFileSystem, &manifest).unwrap(),
+            [dir.join("./a.txt"), Path::new("/abs/b.txt").to_owned()]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! This module defines how the paths of the query results are presented to users.
+//!
+//! Results carry the normalised absolute paths the files are indexed under. A [`PathFormatter`]
+//! turns them into one of the [`PathFormat`]s, so that the command line and the API outputs of a
+//! service present them the same way. Formatters relative to the watched paths are built by
+//! [`crate::LiveIndexer::path_formatter`].
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{live_indexer::RelativePath, Error};
+
+/// Presentation of the result paths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathFormat {
+    /// Absolute path, as indexed.
+    #[default]
+    Absolute,
+    /// Path relative to the innermost watched path containing the file, or the absolute path if
+    /// there is none.
+    Relative,
+    /// Absolute path with the home directory contracted to `~`.
+    Tilde,
+    /// `file://` URI.
+    Uri,
+}
+
+impl FromStr for PathFormat {
+    type Err = Error;
+
+    /// Parse a format from its lower case name, e.g. `tilde`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "absolute" => Ok(PathFormat::Absolute),
+            "relative" => Ok(PathFormat::Relative),
+            "tilde" => Ok(PathFormat::Tilde),
+            "uri" => Ok(PathFormat::Uri),
+            _ => Err(Error::UnknownPathFormat(s.to_owned())),
+        }
+    }
+}
+
+/// Formatter of the result paths in a given [`PathFormat`].
+#[derive(Debug, Clone)]
+pub struct PathFormatter {
+    format: PathFormat,
+    home: Option<PathBuf>,
+    roots: Vec<PathBuf>,
+}
+
+impl PathFormatter {
+    /// Create a formatter, taking the home directory from the environment (`HOME`, or `USERPROFILE`
+    /// on Windows).
+    ///
+    /// With no watched paths set, relative paths are the same as absolute ones, see
+    /// [`PathFormatter::with_roots`].
+    pub fn new(format: PathFormat) -> Self {
+        Self {
+            format,
+            home: env::var_os("HOME")
+                .or_else(|| env::var_os("USERPROFILE"))
+                .map(PathBuf::from),
+            roots: Vec::new(),
+        }
+    }
+
+    /// Contract the given home directory rather than the one from the environment, none if `None`.
+    pub fn with_home(mut self, home: Option<PathBuf>) -> Self {
+        self.home = home;
+        self
+    }
+
+    /// Make the paths relative to the given normalised watched paths.
+    pub fn with_roots(mut self, roots: Vec<PathBuf>) -> Self {
+        self.roots = roots;
+        self
+    }
+
+    pub fn path_format(&self) -> PathFormat {
+        self.format
+    }
+
+    /// Format a result path.
+    ///
+    /// A watched file is formatted relative to itself as its file name.
+    pub fn format<P>(&self, path: P) -> String
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        match self.format {
+            PathFormat::Absolute => path.to_string_lossy().into_owned(),
+            PathFormat::Relative => {
+                let relative = RelativePath::new(&self.roots, path.to_owned());
+                match (relative.root, relative.path.as_os_str().is_empty()) {
+                    (Some(root), true) => root
+                        .file_name()
+                        .map_or_else(|| root.to_string_lossy(), |name| name.to_string_lossy())
+                        .into_owned(),
+                    _ => relative.path.to_string_lossy().into_owned(),
+                }
+            }
+            PathFormat::Tilde => match self
+                .home
+                .as_ref()
+                .and_then(|home| path.strip_prefix(home).ok())
+            {
+                Some(rest) if rest.as_os_str().is_empty() => "~".to_owned(),
+                Some(rest) => Path::new("~").join(rest).to_string_lossy().into_owned(),
+                None => path.to_string_lossy().into_owned(),
+            },
+            PathFormat::Uri => file_uri(path),
+        }
+    }
+}
+
+/// Build the `file://` URI of an absolute path, percent-encoding the bytes other than the
+/// unreserved characters and separators.
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+
+    // Windows paths start with a drive letter rather than a separator.
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(char::from(byte))
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{PathFormat, PathFormatter};
+
+    #[test]
+    fn paths_are_formatted_in_every_format() {
+        let format = |format: &str, path: &str| {
+            PathFormatter::new(format.parse().unwrap())
+                .with_home(Some(PathBuf::from("/home/user")))
+                .with_roots(vec![
+                    PathBuf::from("/home/user/notes"),
+                    PathBuf::from("/srv/todo.txt"),
+                ])
+                .format(path)
+        };
+
+        assert_eq!(
+            format("absolute", "/home/user/a b.txt"),
+            "/home/user/a b.txt"
+        );
+        assert_eq!(
+            format("relative", "/home/user/notes/work/a.txt"),
+            "work/a.txt"
+        );
+        assert_eq!(format("relative", "/srv/todo.txt"), "todo.txt");
+        assert_eq!(format("relative", "/srv/other.txt"), "/srv/other.txt");
+        assert_eq!(format("tilde", "/home/user/notes/a.txt"), "~/notes/a.txt");
+        assert_eq!(
+            format("tilde", "/home/username/a.txt"),
+            "/home/username/a.txt"
+        );
+        assert_eq!(
+            format("uri", "/home/user/notes/a b#1.txt"),
+            "file:///home/user/notes/a%20b%231.txt"
+        );
+        assert!("unknown".parse::<PathFormat>().is_err());
+    }
+}
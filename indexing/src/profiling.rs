@@ -0,0 +1,190 @@
+//! This module implements an opt-in self-instrumentation layer for the indexing pipeline.
+//!
+//! When enabled (see [`crate::Indexer::with_profiling`]), the time spent in every stage of indexing
+//! a file is accumulated and can be retrieved at runtime as a [`ProfilingReport`].
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Stage of the indexing pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading file contents from the file system.
+    Read,
+    /// Splitting the contents into tokens.
+    Tokenise,
+    /// Applying normalisers to the tokens.
+    Normalise,
+    /// Inserting tokens into the index storage.
+    Store,
+}
+
+impl Stage {
+    const ALL: [Stage; 4] = [Stage::Read, Stage::Tokenise, Stage::Normalise, Stage::Store];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Aggregated timings of a single [`Stage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageTimings {
+    /// Number of times the stage was entered.
+    pub calls: u64,
+    /// Total time spent in the stage.
+    pub total: Duration,
+}
+
+impl StageTimings {
+    /// Mean time spent in the stage per call.
+    pub fn mean(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::default()
+        } else {
+            Duration::from_nanos((self.total.as_nanos() / self.calls as u128) as u64)
+        }
+    }
+}
+
+/// Snapshot of the accumulated profiling data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfilingReport {
+    /// Number of files that went through the pipeline.
+    pub files: u64,
+    pub read: StageTimings,
+    pub tokenise: StageTimings,
+    pub normalise: StageTimings,
+    pub store: StageTimings,
+}
+
+impl ProfilingReport {
+    /// Timings of the given stage.
+    pub fn stage(&self, stage: Stage) -> StageTimings {
+        match stage {
+            Stage::Read => self.read,
+            Stage::Tokenise => self.tokenise,
+            Stage::Normalise => self.normalise,
+            Stage::Store => self.store,
+        }
+    }
+}
+
+impl fmt::Display for ProfilingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "files: {}", self.files)?;
+
+        for stage in Stage::ALL.iter().copied() {
+            let timings = self.stage(stage);
+            writeln!(
+                f,
+                "{:>10?}: {:>10} calls, {:>12?} total, {:>10?} mean",
+                stage,
+                timings.calls,
+                timings.total,
+                timings.mean()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lock-free accumulator of per-stage timings.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    files: AtomicU64,
+    calls: [AtomicU64; 4],
+    nanos: [AtomicU64; 4],
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a file has been processed.
+    pub fn record_file(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Run `f` accounting the time it took to the given stage.
+    pub fn time<T, F>(&self, stage: Stage, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let start = Instant::now();
+        let result = f();
+        self.record(stage, start.elapsed());
+        result
+    }
+
+    /// Account the given duration to the stage.
+    pub fn record(&self, stage: Stage, duration: Duration) {
+        self.calls[stage.index()].fetch_add(1, Ordering::Relaxed);
+        self.nanos[stage.index()].fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Build a report from the data accumulated so far.
+    pub fn report(&self) -> ProfilingReport {
+        let timings = |stage: Stage| StageTimings {
+            calls: self.calls[stage.index()].load(Ordering::Relaxed),
+            total: Duration::from_nanos(self.nanos[stage.index()].load(Ordering::Relaxed)),
+        };
+
+        ProfilingReport {
+            files: self.files.load(Ordering::Relaxed),
+            read: timings(Stage::Read),
+            tokenise: timings(Stage::Tokenise),
+            normalise: timings(Stage::Normalise),
+            store: timings(Stage::Store),
+        }
+    }
+
+    /// Discard all the accumulated data.
+    pub fn reset(&self) {
+        self.files.store(0, Ordering::Relaxed);
+        self.calls
+            .iter()
+            .chain(self.nanos.iter())
+            .for_each(|counter| counter.store(0, Ordering::Relaxed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Profiler, Stage};
+
+    #[test]
+    fn records_are_aggregated_per_stage() {
+        let profiler = Profiler::new();
+
+        profiler.record(Stage::Read, Duration::from_millis(3));
+        profiler.record(Stage::Read, Duration::from_millis(1));
+        profiler.record(Stage::Store, Duration::from_millis(5));
+
+        let report = profiler.report();
+
+        assert_eq!(report.read.calls, 2);
+        assert_eq!(report.read.total, Duration::from_millis(4));
+        assert_eq!(report.read.mean(), Duration::from_millis(2));
+        assert_eq!(report.store.calls, 1);
+        assert_eq!(report.tokenise.calls, 0);
+    }
+
+    #[test]
+    fn reset_discards_records() {
+        let profiler = Profiler::new();
+
+        profiler.record_file();
+        profiler.record(Stage::Normalise, Duration::from_millis(1));
+        profiler.reset();
+
+        assert_eq!(profiler.report(), Default::default());
+    }
+}
@@ -0,0 +1,23 @@
+//! This module defines [`QueryHook`]s that customise the execution of queries.
+//!
+//! Hooks (see [`crate::Indexer::with_query_hook`]) keep application-specific query logic, e.g.
+//! synonyms, abbreviations or re-ranking by external signals, out of the indexer itself.
+
+use crate::rank::RankedFile;
+
+/// Hook run before and after every query.
+///
+/// Both methods do nothing by default, so that a hook only implements the one it needs.
+pub trait QueryHook: Send + Sync {
+    /// Rewrite the terms of a query before it's executed.
+    ///
+    /// A query starts with the single term it was given, terms can be changed, removed or added
+    /// (e.g. synonyms), and the files containing any of the resulting terms are found. Terms are
+    /// treated the same way as the original one, see [`crate::Indexer::query`].
+    fn rewrite(&self, _terms: &mut Vec<String>) {}
+
+    /// Post-process the results of a ranked query executed with the given (rewritten) terms.
+    ///
+    /// Results can be re-ordered, removed or annotated, see [`RankedFile::annotations`].
+    fn post_process(&self, _terms: &[String], _results: &mut Vec<RankedFile>) {}
+}
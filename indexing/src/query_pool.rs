@@ -0,0 +1,188 @@
+//! This module implements a pool of threads executing queries against read-only replicas of an
+//! index, see [`QueryPool`].
+//!
+//! The pool is a library-level executor for applications that embed the index and query it from
+//! many threads of their own, the service doesn't use it. Running many queries at once on the
+//! threads they happen to arrive on lets them compete with each other and with the indexing. A
+//! [`QueryPool`] runs them on a fixed number of worker threads instead, each of them querying its
+//! own [`Replica`] of the index. A worker takes a new replica before a query whenever the index or
+//! its query configuration changed since the last one (see [`Indexer::is_current`]), so the queries
+//! see the changes made before they were submitted, including [`Indexer::update_config`].
+
+use std::{
+    collections::HashSet,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use tracing::{info_span, warn};
+
+use crate::{replica::Replica, Indexer};
+
+type Job = Box<dyn FnOnce(&Replica) + Send>;
+
+/// Fixed number of threads executing queries against replicas of an index, see the module
+/// documentation.
+///
+/// Dropping the pool waits for the queries already submitted to complete.
+pub struct QueryPool {
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl QueryPool {
+    /// Start a pool of `threads` workers querying the given index, at least one.
+    pub fn new(indexer: Arc<Indexer>, threads: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads.max(1))
+            .map(|worker| {
+                let indexer = Arc::clone(&indexer);
+                let receiver = Arc::clone(&receiver);
+
+                thread::spawn(move || {
+                    let _span =
+                        info_span!("query_worker", index = %indexer.name(), worker).entered();
+                    let mut replica = indexer.replica();
+
+                    loop {
+                        // The lock is released as soon as a job is received.
+                        let job = match receiver.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+                        if !indexer.is_current(&replica) {
+                            replica = indexer.replica();
+                        }
+                        // A failed query must not take the worker down with it.
+                        if panic::catch_unwind(AssertUnwindSafe(|| job(&replica))).is_err() {
+                            warn!("a query panicked");
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            workers,
+        }
+    }
+
+    /// Number of the worker threads.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Run `f` against a replica of the index on one of the workers.
+    pub fn execute<F, T>(&self, f: F) -> QueryHandle<T>
+    where
+        F: 'static + Send + FnOnce(&Replica) -> T,
+        T: 'static + Send,
+    {
+        let (sender, result) = mpsc::channel();
+        let job: Job = Box::new(move |replica| {
+            // The handle might have been dropped already.
+            let _ = sender.send(f(replica));
+        });
+
+        if let Some(jobs) = &self.jobs {
+            // The workers only stop once the pool is dropped.
+            let _ = jobs.send(job);
+        }
+        QueryHandle { result }
+    }
+
+    /// Query the index on one of the workers, see [`Replica::query`].
+    pub fn query(&self, term: &str) -> QueryHandle<HashSet<String>> {
+        let term = term.to_owned();
+        self.execute(move |replica| replica.query(&term))
+    }
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Result of a query submitted to a [`QueryPool`].
+pub struct QueryHandle<T> {
+    result: mpsc::Receiver<T>,
+}
+
+impl<T> QueryHandle<T> {
+    /// Wait for the query to complete, `None` if it panicked.
+    pub fn wait(self) -> Option<T> {
+        self.result.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use super::QueryPool;
+    use crate::{
+        analyse::Analyser, file_system::MemoryFileSystem, normalise, tokenise, ConfigUpdate,
+        Indexer,
+    };
+
+    #[test]
+    fn queries_see_the_changes_made_before_they_were_submitted() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/pool/a.txt"), b"alpha");
+        file_system.write(Path::new("/pool/b.txt"), b"alpha bravo");
+        let indexer = Arc::new(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+        );
+        indexer.index_file(Path::new("/pool/a.txt")).unwrap();
+
+        let pool = QueryPool::new(Arc::clone(&indexer), 2);
+        assert_eq!(pool.threads(), 2);
+        assert_eq!(pool.query("alpha").wait().unwrap().len(), 1);
+
+        indexer.index_file(Path::new("/pool/b.txt")).unwrap();
+        let handles = (0..4).map(|_| pool.query("alpha")).collect::<Vec<_>>();
+        assert!(handles
+            .into_iter()
+            .all(|handle| handle.wait().unwrap().len() == 2));
+
+        let panicked = pool.execute(|_| -> usize { panic!("query failed") });
+        assert_eq!(panicked.wait(), None);
+        assert_eq!(pool.query("bravo").wait().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn queries_see_the_configuration_changes() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/pool/a.txt"), b"Alpha");
+        let indexer = Arc::new(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(Arc::clone(&file_system)),
+        );
+        indexer.index_file(Path::new("/pool/a.txt")).unwrap();
+
+        let pool = QueryPool::new(Arc::clone(&indexer), 1);
+        assert_eq!(pool.query("Alpha").wait().unwrap().len(), 1);
+
+        // Replacing the analyser leaves the index itself as it is, the file only becomes stale.
+        let generation = indexer.generation();
+        indexer.update_config(
+            ConfigUpdate::new().with_analyser(
+                Analyser::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                    .with_normaliser(normalise::LowerCase),
+            ),
+        );
+        assert_eq!(indexer.generation(), generation);
+        assert!(pool.query("Alpha").wait().unwrap().is_empty());
+
+        indexer.reindex_stale();
+        assert_eq!(pool.query("Alpha").wait().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+//! This module defines the lazy results of a query, see [`crate::Indexer::query_iter`].
+//!
+//! [`QueryResults`] walks the index entries of the query terms as they were when the query was run,
+//! merging them in path order (each file followed by its duplicates, if any), so that the files are
+//! produced one at a time instead of being collected into a set. Callers can stop early, or stream
+//! the results elsewhere (e.g. over a network connection) without holding all of them in memory.
+//! The changes made to the index in the meantime are not seen by the iterator.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    intern::InternRef,
+    storage::{Avl, DuplicatesSnapshot, IndexEntryList, Postings},
+};
+
+/// Number of paths read from the index entries of a term at a time.
+const BATCH: usize = 64;
+
+/// Lazy iterator over the files a query term is found in, sorted by path, see the module
+/// documentation.
+///
+/// The duplicates of a file (see [`crate::Indexer::with_deduplication`]) follow it regardless of
+/// their own paths, unless they are collapsed into it, so the results are only sorted when there
+/// are no duplicates.
+pub struct QueryResults {
+    cursors: Vec<Cursor>,
+    duplicates: Option<DuplicatesSnapshot>,
+    /// Duplicates of the last file produced, yet to be produced themselves.
+    pending: VecDeque<PathBuf>,
+}
+
+impl QueryResults {
+    pub(crate) fn new(
+        entries: Vec<IndexEntryList>,
+        duplicates: Option<DuplicatesSnapshot>,
+    ) -> Self {
+        Self {
+            cursors: entries
+                .into_iter()
+                .map(|entries| Cursor::new(entries.entries))
+                .collect(),
+            duplicates,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl Iterator for QueryResults {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(path) = self.pending.pop_front() {
+            return Some(path.to_string_lossy().into_owned());
+        }
+
+        let path = self
+            .cursors
+            .iter_mut()
+            .filter_map(Cursor::peek)
+            .min()?
+            .to_path_buf();
+        // The same file is usually found for several of the terms.
+        for cursor in &mut self.cursors {
+            if cursor.peek() == Some(path.as_path()) {
+                cursor.advance();
+            }
+        }
+
+        if let Some(duplicates) = &self.duplicates {
+            self.pending.extend(duplicates.duplicates_of(&path));
+        }
+        Some(path.to_string_lossy().into_owned())
+    }
+}
+
+impl fmt::Debug for QueryResults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryResults")
+            .field("terms", &self.cursors.len())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+/// Position in the index entries of a single term.
+struct Cursor {
+    entries: Avl<InternRef<PathBuf>, Postings>,
+    buffer: VecDeque<InternRef<PathBuf>>,
+    /// Last path read from the entries, the next batch starts after it.
+    last: Option<InternRef<PathBuf>>,
+    exhausted: bool,
+}
+
+impl Cursor {
+    fn new(entries: Avl<InternRef<PathBuf>, Postings>) -> Self {
+        Self {
+            entries,
+            buffer: VecDeque::new(),
+            last: None,
+            exhausted: false,
+        }
+    }
+
+    /// Path the cursor is at, reading the next batch of paths if needed.
+    fn peek(&mut self) -> Option<&Path> {
+        if self.buffer.is_empty() && !self.exhausted {
+            self.read_batch();
+        }
+        self.buffer.front().map(|path| path.as_path())
+    }
+
+    fn advance(&mut self) {
+        self.buffer.pop_front();
+    }
+
+    fn read_batch(&mut self) {
+        let batch = match &self.last {
+            Some(last) => self
+                .entries
+                .iter_from(last)
+                .skip_while(|(path, _)| *path <= last)
+                .take(BATCH)
+                .map(|(path, _)| path.clone())
+                .collect::<VecDeque<_>>(),
+            None => self
+                .entries
+                .iter()
+                .take(BATCH)
+                .map(|(path, _)| path.clone())
+                .collect(),
+        };
+
+        self.exhausted = batch.len() < BATCH;
+        if let Some(last) = batch.back() {
+            self.last = Some(last.clone());
+        }
+        self.buffer = batch;
+    }
+}
@@ -0,0 +1,438 @@
+//! This module implements opt-in statistics of the queries executed by an [`crate::Indexer`].
+//!
+//! When enabled (see [`crate::Indexer::with_slow_query_log`]), queries that take longer than the
+//! configured threshold are recorded as [`SlowQuery`] entries and can be retrieved at runtime.
+//!
+//! Independently, histograms of the query latency and the number of results can be accumulated
+//! (see [`crate::Indexer::with_query_metrics`]) and retrieved as [`QueryMetrics`].
+//!
+//! The number of times every term was queried can be counted as well (see
+//! [`crate::Indexer::with_query_frequencies`]) and retrieved as [`QueryFrequency`] entries.
+//!
+//! The metadata of a single query execution is always available, see
+//! [`crate::Indexer::query_with_stats`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::bounded_log::BoundedLog;
+
+/// Record of a query that took longer than the slow query threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowQuery {
+    pub term: String,
+    pub duration: Duration,
+    /// Time spent waiting for other queries to finish, see [`crate::Indexer::with_query_concurrency`].
+    pub wait: Duration,
+    /// Number of files found.
+    pub results: usize,
+    /// Generation of the index the query was executed against, see [`crate::Indexer::generation`].
+    pub generation: u64,
+}
+
+impl fmt::Display for SlowQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}: {:?} (waited {:?}), {} results, generation {}",
+            self.term, self.duration, self.wait, self.results, self.generation
+        )
+    }
+}
+
+/// Metadata of a single query execution.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryExecution {
+    /// Distinct query terms after the query hooks and the normalisers were applied, including the
+    /// ones not found in the index.
+    pub terms: Vec<String>,
+    /// Number of index entries traversed to find the results.
+    pub postings_scanned: usize,
+    /// Generation of the index the query was executed against, see [`crate::Indexer::generation`].
+    pub generation: u64,
+    pub elapsed: Duration,
+    /// Time spent waiting for other queries to finish, see [`crate::Indexer::with_query_concurrency`].
+    pub wait: Duration,
+}
+
+/// Result of a query along with the metadata of its execution.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryResponse {
+    /// Set of files the term was found in.
+    pub paths: HashSet<String>,
+    pub execution: QueryExecution,
+}
+
+impl fmt::Display for QueryResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} results in {:?} ({} postings scanned for {:?}, generation {})",
+            self.paths.len(),
+            self.execution.elapsed,
+            self.execution.postings_scanned,
+            self.execution.terms,
+            self.execution.generation
+        )
+    }
+}
+
+/// Bounded log of the most recent slow queries.
+pub(crate) struct SlowQueryLog {
+    threshold: Duration,
+    queries: BoundedLog<SlowQuery>,
+}
+
+impl SlowQueryLog {
+    pub fn new(threshold: Duration, capacity: usize) -> Self {
+        Self {
+            threshold,
+            queries: BoundedLog::new(capacity),
+        }
+    }
+
+    /// Record the query if it took longer than the threshold.
+    pub fn record<F>(&self, duration: Duration, query: F)
+    where
+        F: FnOnce() -> SlowQuery,
+    {
+        if duration >= self.threshold {
+            self.queries.push_with(query);
+        }
+    }
+
+    /// All the recorded queries, from the oldest.
+    pub fn queries(&self) -> Vec<SlowQuery> {
+        self.queries.records()
+    }
+}
+
+/// Number of histogram buckets, values that don't fit in the bounded ones go to the last one.
+const HISTOGRAM_BUCKETS: usize = 24;
+
+/// Snapshot of a histogram with power of two bucket bounds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Inclusive upper bound of every bucket along with the number of values in it, the last bucket
+    /// is unbounded.
+    pub buckets: Vec<(Option<u64>, u64)>,
+    /// Number of recorded values.
+    pub count: u64,
+    /// Sum of the recorded values.
+    pub sum: u64,
+}
+
+impl Histogram {
+    /// Mean of the recorded values.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Upper bound of the bucket the given quantile (from 0 to 1) falls into.
+    ///
+    /// Returns `None` if there are no values or the quantile falls into the unbounded bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<u64> {
+        let rank = (quantile * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+
+        for &(bound, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return bound;
+            }
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bound = |bound: Option<u64>| bound.map_or_else(|| "inf".to_owned(), |b| b.to_string());
+
+        write!(
+            f,
+            "count {}, mean {:.1}, p50 <= {}, p90 <= {}, p99 <= {}",
+            self.count,
+            self.mean(),
+            bound(self.quantile(0.5)),
+            bound(self.quantile(0.9)),
+            bound(self.quantile(0.99))
+        )
+    }
+}
+
+/// Histograms of the executed queries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryMetrics {
+    /// Query latency in microseconds.
+    pub latency_micros: Histogram,
+    /// Time in microseconds a query waited for other queries to finish, see
+    /// [`crate::Indexer::with_query_concurrency`].
+    pub wait_micros: Histogram,
+    /// Number of files found by a query.
+    pub results: Histogram,
+}
+
+impl fmt::Display for QueryMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "latency (us): {}", self.latency_micros)?;
+        writeln!(f, "wait (us): {}", self.wait_micros)?;
+        writeln!(f, "results: {}", self.results)
+    }
+}
+
+/// Lock-free accumulator of a [`Histogram`].
+///
+/// Bucket `i` holds values that have `i` significant bits, i.e. values up to `2^i - 1`.
+#[derive(Default)]
+struct AtomicHistogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn record(&self, value: u64) {
+        let bucket = (64 - value.leading_zeros() as usize).min(HISTOGRAM_BUCKETS - 1);
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        Histogram {
+            buckets: self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(i, count)| {
+                    let bound = (i < HISTOGRAM_BUCKETS - 1).then(|| (1 << i) - 1);
+                    (bound, count.load(Ordering::Relaxed))
+                })
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.buckets
+            .iter()
+            .chain([&self.count, &self.sum])
+            .for_each(|counter| counter.store(0, Ordering::Relaxed));
+    }
+}
+
+/// Accumulator of [`QueryMetrics`].
+#[derive(Default)]
+pub(crate) struct QueryMetricsRecorder {
+    latency_micros: AtomicHistogram,
+    wait_micros: AtomicHistogram,
+    results: AtomicHistogram,
+}
+
+impl QueryMetricsRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an executed query.
+    pub fn record(&self, duration: Duration, wait: Duration, results: usize) {
+        self.latency_micros.record(duration.as_micros() as u64);
+        self.wait_micros.record(wait.as_micros() as u64);
+        self.results.record(results as u64);
+    }
+
+    /// Build the metrics from the data accumulated so far.
+    pub fn metrics(&self) -> QueryMetrics {
+        QueryMetrics {
+            latency_micros: self.latency_micros.snapshot(),
+            wait_micros: self.wait_micros.snapshot(),
+            results: self.results.snapshot(),
+        }
+    }
+
+    /// Discard all the accumulated data.
+    pub fn reset(&self) {
+        self.latency_micros.reset();
+        self.wait_micros.reset();
+        self.results.reset();
+    }
+}
+
+/// Number of times a term was queried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryFrequency {
+    pub term: String,
+    pub count: u64,
+    pub last_queried: SystemTime,
+}
+
+impl fmt::Display for QueryFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ago = SystemTime::now()
+            .duration_since(self.last_queried)
+            .unwrap_or_default();
+
+        write!(
+            f,
+            "{:?}: {} times, last {:?} ago",
+            self.term,
+            self.count,
+            Duration::from_secs(ago.as_secs())
+        )
+    }
+}
+
+/// Table of the [`QueryFrequency`] of every queried term.
+#[derive(Default)]
+pub(crate) struct QueryFrequencies {
+    terms: Mutex<HashMap<String, (u64, SystemTime)>>,
+}
+
+impl QueryFrequencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a query of the given term.
+    pub fn record(&self, term: &str) {
+        let now = SystemTime::now();
+        let mut terms = self.terms.lock().unwrap();
+
+        match terms.get_mut(term) {
+            Some((count, last_queried)) => {
+                *count += 1;
+                *last_queried = now;
+            }
+            None => {
+                terms.insert(term.to_owned(), (1, now));
+            }
+        }
+    }
+
+    /// All the queried terms, from the most frequent one.
+    pub fn frequencies(&self) -> Vec<QueryFrequency> {
+        let mut frequencies = self
+            .terms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(term, &(count, last_queried))| QueryFrequency {
+                term: term.clone(),
+                count,
+                last_queried,
+            })
+            .collect::<Vec<_>>();
+
+        frequencies.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)));
+        frequencies
+    }
+
+    /// Add previously retrieved frequencies to the table, e.g. to restore them after a restart.
+    pub fn load(&self, frequencies: &[QueryFrequency]) {
+        let mut terms = self.terms.lock().unwrap();
+
+        for frequency in frequencies {
+            let (count, last_queried) = terms
+                .entry(frequency.term.clone())
+                .or_insert((0, frequency.last_queried));
+
+            *count += frequency.count;
+            *last_queried = (*last_queried).max(frequency.last_queried);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{QueryFrequencies, QueryFrequency, QueryMetricsRecorder, SlowQuery, SlowQueryLog};
+
+    fn query(term: &str) -> SlowQuery {
+        SlowQuery {
+            term: term.to_owned(),
+            duration: Duration::from_millis(20),
+            wait: Duration::ZERO,
+            results: 0,
+            generation: 0,
+        }
+    }
+
+    #[test]
+    fn only_slow_queries_are_recorded() {
+        let log = SlowQueryLog::new(Duration::from_millis(10), 2);
+
+        log.record(Duration::from_millis(1), || query("fast"));
+        log.record(Duration::from_millis(20), || query("a"));
+        log.record(Duration::from_millis(20), || query("b"));
+        log.record(Duration::from_millis(20), || query("c"));
+
+        let terms = log
+            .queries()
+            .into_iter()
+            .map(|query| query.term)
+            .collect::<Vec<_>>();
+
+        assert_eq!(terms, ["b", "c"]);
+    }
+
+    #[test]
+    fn histogram_buckets_are_powers_of_two() {
+        let recorder = QueryMetricsRecorder::new();
+
+        for &results in &[0, 1, 3, 4, 100] {
+            recorder.record(Duration::from_micros(10), Duration::ZERO, results);
+        }
+
+        let metrics = recorder.metrics();
+
+        assert_eq!(metrics.results.count, 5);
+        assert_eq!(metrics.results.sum, 108);
+        assert_eq!(metrics.results.buckets[2], (Some(3), 1));
+        assert_eq!(metrics.results.quantile(0.5), Some(3));
+        assert_eq!(metrics.results.quantile(1.0), Some(127));
+        assert_eq!(metrics.latency_micros.quantile(0.5), Some(15));
+
+        recorder.reset();
+
+        assert_eq!(recorder.metrics().results.count, 0);
+    }
+
+    #[test]
+    fn query_frequencies_are_counted_and_loaded() {
+        let frequencies = QueryFrequencies::new();
+
+        frequencies.record("b");
+        frequencies.record("a");
+        frequencies.record("b");
+        frequencies.load(&[QueryFrequency {
+            term: "a".to_owned(),
+            count: 2,
+            last_queried: SystemTime::UNIX_EPOCH,
+        }]);
+
+        let counts = frequencies
+            .frequencies()
+            .into_iter()
+            .map(|frequency| (frequency.term, frequency.count))
+            .collect::<Vec<_>>();
+
+        assert_eq!(counts, [("a".to_owned(), 3), ("b".to_owned(), 2)]);
+        assert!(frequencies.frequencies()[0].last_queried > SystemTime::UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,261 @@
+//! This module defines the ranking of query results, see [`crate::Indexer::query_ranked`].
+//!
+//! Files are scored by how often the query term occurs in them, weighted by how rare the term is in
+//! the index (tf-idf). The scores can then be adjusted by [`ScoreModifier`]s (see
+//! [`crate::Indexer::with_score_modifier`]). There are several predefined modifiers:
+//!  * [`RecencyBoost`] - favours recently modified files
+//!  * [`PathBoost`] - scales the scores of the files matching glob patterns
+//!
+//! How the score of a file comes about can be looked into with [`crate::Indexer::explain`].
+
+use std::{
+    any, fmt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::Result;
+
+/// File found by a ranked query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankedFile {
+    pub path: String,
+    pub score: f64,
+    /// Paths of the duplicates of the file, if they are collapsed, see
+    /// [`crate::Indexer::with_collapsed_duplicates`].
+    pub aliases: Vec<String>,
+    /// Notes attached to the file by the query hooks, see [`crate::query_hook::QueryHook`].
+    pub annotations: Vec<String>,
+}
+
+/// Data known about a file while it's being scored.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoredFile<'a> {
+    pub path: &'a Path,
+    /// Modification time of the file when it was indexed, if the platform reports it.
+    pub modified: Option<SystemTime>,
+}
+
+/// Adjustment of the score of the files found by a ranked query.
+///
+/// Modifiers are applied one after the other, in the order they were added.
+pub trait ScoreModifier: Send + Sync {
+    fn modify(&self, file: &ScoredFile<'_>, score: f64) -> f64;
+
+    /// Name of the modifier in the score explanations, its type name by default.
+    fn name(&self) -> &str {
+        let name = any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
+
+/// Score modifier that boosts recently modified files.
+///
+/// The boost decays by half every `half_life` since the file was modified: a file modified just
+/// now has its score doubled, a file modified a half-life ago has it multiplied by 1.5, and so on.
+/// Files with unknown modification time are not boosted.
+#[derive(Debug, Clone)]
+pub struct RecencyBoost {
+    half_life: Duration,
+}
+
+impl RecencyBoost {
+    pub fn new(half_life: Duration) -> Self {
+        Self { half_life }
+    }
+}
+
+impl ScoreModifier for RecencyBoost {
+    fn modify(&self, file: &ScoredFile<'_>, score: f64) -> f64 {
+        let age = match file.modified {
+            // Files modified in the future are as recent as it gets.
+            Some(modified) => SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default(),
+            None => return score,
+        };
+
+        score * (1.0 + 0.5f64.powf(age.as_secs_f64() / self.half_life.as_secs_f64()))
+    }
+}
+
+/// Score modifier that multiplies the scores of the files matching glob patterns by the given
+/// factors.
+///
+/// Patterns are relative to the root (e.g. `src/**` with a factor of 2 and `vendor/**` with a factor
+/// of 0.3), files outside of it are not affected. A file matching several patterns has its score
+/// multiplied by all of their factors.
+#[derive(Debug, Clone)]
+pub struct PathBoost {
+    root: PathBuf,
+    patterns: GlobSet,
+    factors: Vec<f64>,
+}
+
+impl PathBoost {
+    pub fn new(root: &Path, rules: &[(&str, f64)]) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        for (pattern, _) in rules {
+            builder.add(Glob::new(pattern)?);
+        }
+
+        Ok(Self {
+            root: root.to_owned(),
+            patterns: builder.build()?,
+            factors: rules.iter().map(|&(_, factor)| factor).collect(),
+        })
+    }
+}
+
+impl ScoreModifier for PathBoost {
+    fn modify(&self, file: &ScoredFile<'_>, score: f64) -> f64 {
+        let path = match file.path.strip_prefix(&self.root) {
+            Ok(path) => path,
+            Err(_) => return score,
+        };
+
+        self.patterns
+            .matches(path)
+            .into_iter()
+            .fold(score, |score, rule| score * self.factors[rule])
+    }
+}
+
+/// Statistics of a term of the index, see [`crate::Indexer::term_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermStats {
+    /// Number of the files the term occurs in.
+    pub doc_count: usize,
+    /// Number of the occurrences of the term across all the files. The occurrences whose offsets
+    /// are not kept (see [`crate::Indexer::with_posting_cap`] and
+    /// [`crate::budget::IndexBudget::with_memory_degradation`]) are not counted.
+    pub total_occurrences: u64,
+}
+
+/// Breakdown of the score of a file found by a ranked query, see [`crate::Indexer::explain`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub path: String,
+    /// Scores of the normalised query terms found in the file.
+    pub terms: Vec<TermScore>,
+    /// Sum of the term scores.
+    pub base_score: f64,
+    /// Score modifiers in the order they were applied, along with the score each of them produced.
+    pub modifiers: Vec<(String, f64)>,
+    /// Final score, before the query hooks.
+    pub score: f64,
+}
+
+/// Score of a term in a file, see [`Explanation`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermScore {
+    pub term: String,
+    /// Number of the occurrences of the term in the file.
+    pub occurrences: u64,
+    /// Term frequency weight, growing logarithmically with the occurrences.
+    pub tf: f64,
+    /// Number of the files the term occurs in.
+    pub files: usize,
+    /// Number of the files in the index the term is stored in.
+    pub total_files: usize,
+    /// Inverse document frequency weight, higher for rare terms.
+    pub idf: f64,
+    /// Product of the weights.
+    pub score: f64,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:.3}", self.path, self.score)?;
+        for term in &self.terms {
+            write!(
+                f,
+                "\n  {}: {} occurrences (tf {:.3}) in {} of {} files (idf {:.3}) = {:.3}",
+                term.term,
+                term.occurrences,
+                term.tf,
+                term.files,
+                term.total_files,
+                term.idf,
+                term.score
+            )?;
+        }
+        for (name, score) in &self.modifiers {
+            write!(f, "\n  {} -> {:.3}", name, score)?;
+        }
+        Ok(())
+    }
+}
+
+/// Score of a term found `occurrences` times in a file, when `files` out of `total` files have it.
+pub(crate) fn tf_idf(occurrences: u64, files: usize, total: usize) -> f64 {
+    tf(occurrences) * idf(files, total)
+}
+
+/// Term frequency weight of a term found `occurrences` times in a file.
+pub(crate) fn tf(occurrences: u64) -> f64 {
+    if occurrences == 0 {
+        return 0.0;
+    }
+
+    1.0 + (occurrences as f64).ln()
+}
+
+/// Inverse document frequency weight of a term found in `files` out of `total` files.
+pub(crate) fn idf(files: usize, total: usize) -> f64 {
+    (1.0 + total as f64 / files.max(1) as f64).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{PathBoost, RecencyBoost, ScoreModifier, ScoredFile};
+
+    #[test]
+    fn recency_boost_halves_every_half_life() {
+        let boost = RecencyBoost::new(Duration::from_secs(3600));
+        let modified_ago = |secs| ScoredFile {
+            path: Path::new("/a"),
+            modified: Some(SystemTime::now() - Duration::from_secs(secs)),
+        };
+
+        assert!((boost.modify(&modified_ago(0), 1.0) - 2.0).abs() < 0.01);
+        assert!((boost.modify(&modified_ago(3600), 1.0) - 1.5).abs() < 0.01);
+        assert!((boost.modify(&modified_ago(7200), 1.0) - 1.25).abs() < 0.01);
+
+        let unknown = ScoredFile {
+            path: Path::new("/a"),
+            modified: None,
+        };
+        assert_eq!(boost.modify(&unknown, 1.0), 1.0);
+    }
+
+    #[test]
+    fn path_boost_multiplies_matching_factors() {
+        let boost = PathBoost::new(
+            Path::new("/repo"),
+            &[("src/**", 2.0), ("**/*.rs", 1.5), ("vendor/**", 0.3)],
+        )
+        .unwrap();
+        let score = |path| {
+            boost.modify(
+                &ScoredFile {
+                    path: Path::new(path),
+                    modified: None,
+                },
+                1.0,
+            )
+        };
+
+        assert_eq!(score("/repo/src/lib.rs"), 3.0);
+        assert_eq!(score("/repo/src/notes.md"), 2.0);
+        assert_eq!(score("/repo/vendor/notes.md"), 0.3);
+        assert_eq!(score("/other/src/lib.rs"), 1.0);
+    }
+}
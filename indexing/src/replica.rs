@@ -0,0 +1,235 @@
+//! This module defines read-only replicas of an index, see [`crate::Indexer::replica`].
+//!
+//! A [`Replica`] holds an immutable version of the index (see [`crate::snapshot`]) along with the
+//! query configuration of the indexer it was taken from: its analysers, stop words and query hooks.
+//! It can be cloned cheaply and handed to a pool of query threads, whose queries then never wait on
+//! the indexing in progress, nor on the queries run against the indexer itself.
+//!
+//! A replica doesn't see the changes made to the index after it was taken. Replicas are meant to be
+//! taken again periodically, e.g. once [`crate::Indexer::is_current`] tells the index or its query
+//! configuration changed.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    iter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    indexer::{self, QueryOptions, QueryPage, RootAnalyser},
+    normalise::NormaliserOverrides,
+    query_hook::QueryHook,
+    query_results::QueryResults,
+    snapshot::Snapshot,
+    storage::{DuplicatesSnapshot, IndexEntryList, StorageSnapshot},
+    CompactString,
+};
+
+/// Read-only version of an index that can be queried from any thread, see the module documentation.
+#[derive(Clone)]
+pub struct Replica {
+    pub(crate) generation: u64,
+    pub(crate) config_version: u64,
+    pub(crate) storage: StorageSnapshot,
+    pub(crate) stop_word_storage: Option<StorageSnapshot>,
+    pub(crate) duplicates: Option<DuplicatesSnapshot>,
+    pub(crate) collapse_duplicates: bool,
+    pub(crate) analysers: Vec<RootAnalyser>,
+    pub(crate) stop_words: BTreeSet<CompactString>,
+    pub(crate) query_hooks: Vec<Arc<dyn QueryHook>>,
+}
+
+impl Replica {
+    /// Generation of the index the replica was taken at, see [`crate::Indexer::generation`].
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Snapshot of the index the replica was taken from, e.g. to compare it with a newer one.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.storage.clone())
+    }
+
+    /// Query the replica like [`crate::Indexer::query`] does.
+    ///
+    /// The queries against a replica aren't limited by [`crate::Indexer::with_query_concurrency`],
+    /// nor recorded in the query statistics of the indexer.
+    pub fn query(&self, term: &str) -> HashSet<String> {
+        self.results(self.paths(term, &NormaliserOverrides::default()))
+    }
+
+    /// Query the replica like [`crate::Indexer::query_with_options`] does.
+    pub fn query_with_options(&self, term: &str, options: &QueryOptions) -> QueryPage {
+        QueryPage::new(
+            self.results(self.paths(term, &options.normalisers)),
+            options,
+        )
+    }
+
+    /// Find the files every one of the given terms is found in, see [`crate::Indexer::query_all`].
+    pub fn query_all(&self, terms: &[&str]) -> HashSet<String> {
+        let mut found = terms
+            .iter()
+            .map(|term| self.paths(term, &NormaliserOverrides::default()))
+            .collect::<Vec<_>>();
+        // Starting with the rarest term keeps the intersection small.
+        found.sort_by_key(BTreeSet::len);
+
+        let mut found = found.into_iter();
+        let paths = found.next().unwrap_or_default();
+        self.results(found.fold(paths, |paths, other| {
+            paths.intersection(&other).cloned().collect()
+        }))
+    }
+
+    /// Find the files any of the given terms is found in, see [`crate::Indexer::query_any`].
+    pub fn query_any(&self, terms: &[&str]) -> HashSet<String> {
+        self.results(
+            terms
+                .iter()
+                .flat_map(|term| self.paths(term, &NormaliserOverrides::default()))
+                .collect(),
+        )
+    }
+
+    /// Query the replica lazily like [`crate::Indexer::query_iter`] does.
+    pub fn query_iter(&self, term: &str) -> QueryResults {
+        let duplicates = match &self.duplicates {
+            Some(duplicates) if !self.collapse_duplicates => Some(duplicates.clone()),
+            _ => None,
+        };
+        QueryResults::new(
+            self.entries(term, &NormaliserOverrides::default()),
+            duplicates,
+        )
+    }
+
+    /// Distinct paths of the files the given query term is found in, without their duplicates.
+    fn paths(&self, term: &str, overrides: &NormaliserOverrides) -> BTreeSet<PathBuf> {
+        self.entries(term, overrides)
+            .iter()
+            .flat_map(|entries| entries.iter().map(|(path, _)| path.to_path_buf()))
+            .collect()
+    }
+
+    /// Index entries of the given query term, one list per distinct term produced by the analysers,
+    /// looked up like by the indexer, see [`indexer::lookup`].
+    fn entries(&self, term: &str, overrides: &NormaliserOverrides) -> Vec<IndexEntryList> {
+        let (_, found) = indexer::lookup(
+            &indexer::rewrite(term, &self.query_hooks),
+            &self.analysers,
+            &self.stop_words,
+            self.stop_word_storage.is_some(),
+            overrides,
+            |dropped, term| {
+                let storage = match &self.stop_word_storage {
+                    Some(storage) if dropped => storage,
+                    _ => &self.storage,
+                };
+                Some(((), storage.get(term)?))
+            },
+        );
+        found.into_iter().map(|(_, _, entries)| entries).collect()
+    }
+
+    /// Add the duplicates to the found files, see [`crate::Indexer::with_collapsed_duplicates`].
+    fn results(&self, paths: BTreeSet<PathBuf>) -> HashSet<String> {
+        paths
+            .iter()
+            .flat_map(|path| iter::once(path.clone()).chain(self.duplicates_of(path)))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Duplicates of a file reported as separate results, see
+    /// [`crate::Indexer::with_collapsed_duplicates`].
+    fn duplicates_of(&self, path: &Path) -> Vec<PathBuf> {
+        match &self.duplicates {
+            Some(duplicates) if !self.collapse_duplicates => duplicates.duplicates_of(path),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc, thread};
+
+    use crate::{
+        file_system::MemoryFileSystem,
+        normalise::{self, NormaliserOverrides},
+        tokenise, Indexer, QueryOptions,
+    };
+
+    #[test]
+    fn replicas_keep_their_version_of_the_index() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/replica/a.txt"), b"The Cat");
+        file_system.write(Path::new("/replica/b.txt"), b"the cat");
+        file_system.write(Path::new("/replica/c.txt"), b"a dog");
+
+        let indexer = Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+            .with_normaliser(normalise::LowerCase)
+            .with_normaliser(normalise::StopWords::new(&["the"]))
+            .with_stop_word_index()
+            .with_file_system(Arc::clone(&file_system));
+        indexer.index_file(Path::new("/replica/a.txt")).unwrap();
+
+        let replica = indexer.replica();
+        indexer.index_file(Path::new("/replica/b.txt")).unwrap();
+        indexer.index_file(Path::new("/replica/c.txt")).unwrap();
+
+        let found = thread::spawn(move || (replica.query("CAT"), replica.query("\"the\"")))
+            .join()
+            .unwrap();
+        assert_eq!(found.0.len(), 1);
+        assert_eq!(found.1.len(), 1);
+
+        let replica = indexer.replica();
+        assert_eq!(replica.generation(), indexer.generation());
+        assert_eq!(replica.query("cat"), indexer.query("cat"));
+        assert_eq!(
+            replica.query_iter("cat").collect::<Vec<_>>(),
+            ["/replica/a.txt", "/replica/b.txt"]
+        );
+        assert!(replica.query("the").is_empty());
+    }
+
+    #[test]
+    fn replicas_look_terms_up_like_the_indexer() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/replica/a.txt"), b"Quick fox");
+        file_system.write(Path::new("/replica/b.txt"), b"the fox");
+
+        let indexer = Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+            .with_normaliser(normalise::LowerCase)
+            .with_normaliser(normalise::StopWords::new(&["the"]))
+            .with_stop_word_index()
+            .with_exact_case_index()
+            .with_term_filter(100, 0.01)
+            .with_file_system(Arc::clone(&file_system));
+        indexer.index_file(Path::new("/replica/a.txt")).unwrap();
+        indexer.index_file(Path::new("/replica/b.txt")).unwrap();
+        let replica = indexer.replica();
+
+        let options = [
+            QueryOptions::new(),
+            QueryOptions::new().with_limit(1).with_offset(1),
+            QueryOptions::new()
+                .with_normalisers(NormaliserOverrides::new().with_skipped("StopWords")),
+        ];
+        for term in ["fox", "QUICK", "the", "\"the\"", "missing"] {
+            assert_eq!(replica.query(term), indexer.query(term), "{}", term);
+            for options in &options {
+                assert_eq!(
+                    replica.query_with_options(term, options),
+                    indexer.query_with_options(term, options),
+                    "{}",
+                    term
+                );
+            }
+        }
+        assert!(replica.storage.get("missing").is_none());
+    }
+}
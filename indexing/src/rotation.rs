@@ -0,0 +1,208 @@
+//! This module implements the rotation of the snapshots of an index kept on disk, so that the index
+//! can be rolled back to a previous point in time, e.g. after a bad bulk ingestion.
+//!
+//! A [`SnapshotRotation`] exports the snapshots (see [`crate::Indexer::export_snapshot`]) to a
+//! directory as numbered files, `snapshot-00000001.idx`, `snapshot-00000002.idx`, etc., and only keeps
+//! the most recent ones. The `CURRENT` manifest in the same directory names the snapshot the index
+//! is at. Both the snapshots and the manifest are written to temporary files first and renamed into
+//! place, so that a crash never leaves a partial file behind under its final name.
+//!
+//! Snapshots can be rotated periodically by the live indexer, see
+//! [`crate::LiveIndexerOptions::with_snapshot_rotation`].
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing::{info, warn};
+
+use crate::{snapshot::MergeResult, Indexer, Result};
+
+/// Name of the manifest naming the current snapshot.
+const MANIFEST: &str = "CURRENT";
+
+const PREFIX: &str = "snapshot-";
+const EXTENSION: &str = ".idx";
+
+/// Snapshots of an index kept in a directory, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRotation {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl SnapshotRotation {
+    /// Keep the `keep` most recent snapshots in `dir`, at least one.
+    pub fn new<P>(dir: P, keep: usize) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            dir: dir.into(),
+            keep: keep.max(1),
+        }
+    }
+
+    /// Directory the snapshots are kept in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Export a snapshot of the index, make it the current one and remove the snapshots past the
+    /// number to keep, returning the path of the new snapshot.
+    pub fn rotate(&self, indexer: &Indexer) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let sequence = self.sequences()?.last().map_or(1, |&(last, _)| last + 1);
+        let path = self.dir.join(file_name(sequence));
+
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        indexer.export_snapshot(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &path)?;
+        self.set_current(&path)?;
+
+        let snapshots = self.snapshots()?;
+        for old in &snapshots[..snapshots.len().saturating_sub(self.keep)] {
+            if let Err(e) = fs::remove_file(old) {
+                warn!(error = %e, path = %old.display(), "failed to remove an old snapshot");
+            }
+        }
+
+        info!(index = %indexer.name(), path = %path.display(), "rotated the snapshots");
+        Ok(path)
+    }
+
+    /// Paths of the snapshots kept, from the oldest to the most recent one.
+    pub fn snapshots(&self) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .sequences()?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    /// Path of the current snapshot, `None` if there is none yet.
+    pub fn current(&self) -> Result<Option<PathBuf>> {
+        match fs::read_to_string(self.dir.join(MANIFEST)) {
+            Ok(name) => Ok(Some(self.dir.join(name.trim()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Path of the snapshot taken right before the current one, `None` if there is none.
+    pub fn previous(&self) -> Result<Option<PathBuf>> {
+        let current = match self.current()? {
+            Some(current) => current,
+            None => return Ok(None),
+        };
+        let snapshots = self.snapshots()?;
+
+        Ok(snapshots
+            .iter()
+            .position(|path| *path == current)
+            .and_then(|position| position.checked_sub(1))
+            .map(|position| snapshots[position].clone()))
+    }
+
+    /// Replace the whole index with the given snapshot (see [`Indexer::restore_snapshot`]) and make
+    /// it the current one.
+    ///
+    /// The more recent snapshots are kept until they are rotated out, so that the roll back can
+    /// itself be undone.
+    pub fn restore(&self, indexer: &Indexer, snapshot: &Path) -> Result<MergeResult> {
+        let result = indexer.restore_snapshot(File::open(snapshot)?)?;
+
+        self.set_current(snapshot)?;
+        info!(index = %indexer.name(), path = %snapshot.display(), "restored a snapshot");
+        Ok(result)
+    }
+
+    /// Point the manifest to the given snapshot.
+    fn set_current(&self, snapshot: &Path) -> Result<()> {
+        let name = snapshot.file_name().unwrap_or_default().to_string_lossy();
+        let temporary = self.dir.join(format!("{}.tmp", MANIFEST));
+        let mut file = File::create(&temporary)?;
+
+        writeln!(file, "{}", name)?;
+        file.sync_all()?;
+        fs::rename(&temporary, self.dir.join(MANIFEST))?;
+        Ok(())
+    }
+
+    /// Sequence numbers and paths of the snapshots kept, sorted.
+    fn sequences(&self) -> Result<Vec<(u64, PathBuf)>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut sequences = Vec::new();
+
+        for entry in entries {
+            let path = entry?.path();
+            let sequence = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION))
+                .and_then(|sequence| sequence.parse().ok());
+
+            if let Some(sequence) = sequence {
+                sequences.push((sequence, path));
+            }
+        }
+        sequences.sort();
+        Ok(sequences)
+    }
+}
+
+fn file_name(sequence: u64) -> String {
+    format!("{}{:08}{}", PREFIX, sequence, EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::SnapshotRotation;
+    use crate::{snapshot::ImportOutcome, tokenise, Indexer};
+
+    #[test]
+    fn rotation_keeps_the_recent_snapshots_and_rolls_back() {
+        let dir = env::temp_dir().join("indexing-rotation-tests");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&a, "alpha").unwrap();
+        fs::write(&b, "bravo").unwrap();
+
+        let rotation = SnapshotRotation::new(dir.join("snapshots"), 2);
+        let indexer = Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _);
+        assert_eq!(rotation.current().unwrap(), None);
+
+        indexer.index_file(&a).unwrap();
+        let first = rotation.rotate(&indexer).unwrap();
+        let second = rotation.rotate(&indexer).unwrap();
+        indexer.index_file(&b).unwrap();
+        let third = rotation.rotate(&indexer).unwrap();
+
+        assert!(third.ends_with("snapshot-00000003.idx"));
+        assert_eq!(
+            rotation.snapshots().unwrap(),
+            [second.clone(), third.clone()]
+        );
+        assert!(!first.exists());
+        assert_eq!(rotation.current().unwrap(), Some(third));
+        assert_eq!(rotation.previous().unwrap(), Some(second.clone()));
+
+        let result = rotation.restore(&indexer, &second).unwrap();
+        assert_eq!(result.count(ImportOutcome::Removed), 1);
+        assert!(indexer.query("bravo").is_empty());
+        assert_eq!(indexer.query("alpha").len(), 1);
+        assert_eq!(rotation.current().unwrap(), Some(second));
+        assert_eq!(rotation.previous().unwrap(), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! This module defines the comparison of a candidate analyser with the current one over a sample of
+//! the indexed files, see [`crate::Indexer::sample_analyser`].
+//!
+//! The sampled files are tokenised with the candidate analyser without touching the index, and the
+//! resulting dictionary is compared with the terms the same files are currently indexed under. A
+//! [`SampleReport`] shows how much an analyser change would grow or shrink the dictionary, and which
+//! terms it would introduce or lose.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use crate::CompactString;
+
+/// Number of the most frequent terms listed in [`SampleReport::added`] and
+/// [`SampleReport::removed`].
+pub const EXAMPLE_TERMS: usize = 10;
+
+/// Comparison of a candidate analyser with the current one over a sample of the indexed files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleReport {
+    /// Number of the sampled files that were tokenised.
+    pub files: usize,
+    /// Number of the sampled files that couldn't be read, and were left out of the comparison.
+    pub unreadable: usize,
+    /// Number of the tokens kept by the candidate analyser.
+    pub tokens: u64,
+    /// Number of the distinct terms produced by the candidate analyser.
+    pub candidate_terms: usize,
+    /// Number of the distinct terms the sampled files are currently indexed under.
+    pub current_terms: usize,
+    /// Number of the terms found in both dictionaries.
+    pub shared_terms: usize,
+    /// Most frequent terms only produced by the candidate analyser, with their occurrences.
+    pub added: Vec<(String, u64)>,
+    /// Most frequent terms only found in the current dictionary, with their occurrences.
+    pub removed: Vec<(String, u64)>,
+}
+
+impl SampleReport {
+    /// Ratio of the shared terms to the terms of either dictionary, from 0 to 1.
+    ///
+    /// The overlap of two empty dictionaries is 1.
+    pub fn overlap(&self) -> f64 {
+        let union = self.candidate_terms + self.current_terms - self.shared_terms;
+        if union == 0 {
+            1.0
+        } else {
+            self.shared_terms as f64 / union as f64
+        }
+    }
+
+    /// Build the report from the occurrences of the terms in either dictionary.
+    pub(crate) fn new(
+        files: usize,
+        unreadable: usize,
+        candidate: HashMap<CompactString, u64>,
+        current: HashMap<CompactString, u64>,
+    ) -> Self {
+        let shared_terms = candidate
+            .keys()
+            .filter(|term| current.contains_key(*term))
+            .count();
+
+        Self {
+            files,
+            unreadable,
+            tokens: candidate.values().sum(),
+            candidate_terms: candidate.len(),
+            current_terms: current.len(),
+            shared_terms,
+            added: most_frequent_missing(&candidate, &current),
+            removed: most_frequent_missing(&current, &candidate),
+        }
+    }
+}
+
+impl fmt::Display for SampleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} files ({} unreadable), {} tokens, {} candidate terms, {} current terms, {:.1}% overlap",
+            self.files,
+            self.unreadable,
+            self.tokens,
+            self.candidate_terms,
+            self.current_terms,
+            self.overlap() * 100.0
+        )?;
+        for (term, count) in &self.added {
+            writeln!(f, " + {} ({})", term, count)?;
+        }
+        for (term, count) in &self.removed {
+            writeln!(f, " - {} ({})", term, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The [`EXAMPLE_TERMS`] most frequent terms of `terms` that are missing from `other`, ties broken
+/// alphabetically.
+fn most_frequent_missing(
+    terms: &HashMap<CompactString, u64>,
+    other: &HashMap<CompactString, u64>,
+) -> Vec<(String, u64)> {
+    let missing = terms
+        .iter()
+        .filter(|(term, _)| !other.contains_key(*term))
+        .map(|(term, &count)| (std::cmp::Reverse(count), term.as_str()))
+        .collect::<BTreeSet<_>>();
+
+    missing
+        .into_iter()
+        .take(EXAMPLE_TERMS)
+        .map(|(count, term)| (term.to_owned(), count.0))
+        .collect()
+}
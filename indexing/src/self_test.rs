@@ -0,0 +1,97 @@
+//! This module defines the built-in corpus and the report of an index self-test, see
+//! [`crate::Indexer::self_test`].
+//!
+//! The corpus is indexed into a scratch index sharing the analyser of the tested one, so that a
+//! deployment or a custom analyser configuration can be validated without touching the real index.
+//! Every check of the self-test is reported as a [`SelfTestCheck`].
+
+use std::fmt;
+
+/// Files of the built-in corpus, by path.
+///
+/// The texts are lowercase and free of common stop words, so that they are searchable with most
+/// analyser configurations.
+pub(crate) const CORPUS: &[(&str, &str)] = &[
+    ("/selftest/fox.txt", "quick brown fox jumps over lazy dog"),
+    ("/selftest/lorem.txt", "lorem ipsum dolor sit amet"),
+    ("/selftest/nested/bread.txt", "quick brown bread"),
+];
+
+/// Canned queries of the self-test along with the paths they are expected to return.
+pub(crate) const QUERIES: &[(&str, &[&str])] = &[
+    (
+        "quick",
+        &["/selftest/fox.txt", "/selftest/nested/bread.txt"],
+    ),
+    ("fox", &["/selftest/fox.txt"]),
+    ("ipsum", &["/selftest/lorem.txt"]),
+    ("unicorn", &[]),
+];
+
+/// File removed from the scratch index once the canned queries pass, along with a term that must
+/// then only return [`REMAINING`].
+pub(crate) const REMOVED: (&str, &str) = ("/selftest/fox.txt", "quick");
+
+pub(crate) const REMAINING: &[&str] = &["/selftest/nested/bread.txt"];
+
+/// Result of an index self-test.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    /// Check whether every check of the self-test passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub(crate) fn check(&mut self, name: String, failure: Option<String>) {
+        self.checks.push(SelfTestCheck {
+            name,
+            passed: failure.is_none(),
+            detail: failure,
+        });
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self.checks.iter().filter(|check| !check.passed).count();
+
+        writeln!(
+            f,
+            "self-test {}: {} checks, {} failed",
+            if failed == 0 { "passed" } else { "failed" },
+            self.checks.len(),
+            failed
+        )?;
+        self.checks
+            .iter()
+            .try_for_each(|check| writeln!(f, " - {}", check))
+    }
+}
+
+/// Single check of a self-test, e.g. a canned query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub passed: bool,
+    /// Description of the failure, `None` if the check passed.
+    pub detail: Option<String>,
+}
+
+impl fmt::Display for SelfTestCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.name,
+            if self.passed { "ok" } else { "FAILED" }
+        )?;
+        match &self.detail {
+            Some(detail) => write!(f, " ({})", detail),
+            None => Ok(()),
+        }
+    }
+}
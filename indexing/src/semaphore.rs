@@ -0,0 +1,74 @@
+//! This module implements a simple counting semaphore.
+
+use std::sync::{Condvar, Mutex};
+
+/// Counting semaphore that blocks the threads acquiring it while no permits are available.
+pub(crate) struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait for a permit to become available and take it.
+    ///
+    /// The permit is given back once the returned guard is dropped.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        let mut permits = self
+            .released
+            .wait_while(self.permits.lock().unwrap(), |permits| *permits == 0)
+            .unwrap();
+        *permits -= 1;
+
+        SemaphorePermit { semaphore: self }
+    }
+}
+
+/// Permit taken from a [`Semaphore`].
+pub(crate) struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::Semaphore;
+
+    #[test]
+    fn acquire_waits_for_release() {
+        let semaphore = Semaphore::new(1);
+        let acquired = AtomicBool::new(false);
+        let permit = semaphore.acquire();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _permit = semaphore.acquire();
+                acquired.store(true, Ordering::SeqCst);
+            });
+
+            thread::sleep(Duration::from_millis(20));
+            assert!(!acquired.load(Ordering::SeqCst));
+            drop(permit);
+        });
+
+        assert!(acquired.load(Ordering::SeqCst));
+    }
+}
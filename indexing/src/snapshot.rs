@@ -0,0 +1,667 @@
+//! This module implements point-in-time snapshots of an index and the differences between them.
+//!
+//! A [`Snapshot`] (see [`crate::Indexer::snapshot`]) is cheap to take, as it shares all the parts of
+//! the index that are not modified afterwards. Two snapshots can be compared with [`Snapshot::diff`],
+//! e.g. to audit what a batch of updates changed in the index.
+//!
+//! A snapshot can be serialised with [`Snapshot::export`] (see [`crate::Indexer::export_snapshot`]).
+//! The format is a binary one, made of little-endian integers and length-prefixed UTF-8 strings:
+//!
+//! - the `IDXSNAP5` tag and the generation of the snapshot;
+//! - the number of files, then for every file its path, its bookkeeping data and its term vector;
+//! - the number of terms, then for every term whether only the paths it occurs in are kept, and the
+//!   files it occurs in (by their position in the list of files) along with its offsets and their
+//!   token positions in each of them;
+//! - the number of queried terms, then for every one of them the number of times it was queried and
+//!   the time it was last queried, see [`crate::Indexer::with_query_frequencies`].
+//!
+//! Paths are written as their raw bytes on Unix, so that the ones that aren't valid UTF-8 are
+//! restored as they were. On other platforms, such paths can't be exported.
+//!
+//! An exported snapshot can be merged into an index with [`crate::Indexer::import_snapshot`], the
+//! documents that are already indexed are resolved according to a [`ConflictPolicy`].
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    query_stats::QueryFrequency,
+    storage::{Avl, FileMeta, IndexEntryList, LineMeta, Postings, StorageSnapshot},
+    CompactString, Error, Result,
+};
+
+/// Tag at the start of an exported snapshot, identifying the version of the format.
+const EXPORT_TAG: &[u8; 8] = b"IDXSNAP5";
+
+/// Immutable version of an index.
+#[derive(Clone)]
+pub struct Snapshot {
+    storage: StorageSnapshot,
+    query_frequencies: Vec<QueryFrequency>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(storage: StorageSnapshot) -> Self {
+        Self {
+            storage,
+            query_frequencies: Vec::new(),
+        }
+    }
+
+    /// Export the given query frequencies along with the index, see [`Snapshot::export`].
+    pub(crate) fn with_query_frequencies(mut self, query_frequencies: Vec<QueryFrequency>) -> Self {
+        self.query_frequencies = query_frequencies;
+        self
+    }
+
+    /// Generation of the index the snapshot was taken at, see [`crate::Indexer::generation`].
+    pub fn generation(&self) -> u64 {
+        self.storage.generation
+    }
+
+    /// Find the files and terms that differ between this snapshot and a `newer` one.
+    ///
+    /// A file is changed if its contents were indexed with a different hash or size, and a term is
+    /// changed if it is found in different files or at different offsets or positions.
+    pub fn diff(&self, newer: &Snapshot) -> SnapshotDiff {
+        let (old, new) = (&self.storage, &newer.storage);
+        let mut diff = SnapshotDiff::default();
+
+        if !old.files.ptr_eq(&new.files) {
+            let files = merge(
+                old.files.iter(),
+                new.files.iter(),
+                |_| true,
+                |old, new| old.len != new.len || old.content_hash != new.content_hash,
+            );
+
+            diff.added_files = files.added.into_iter().map(|key| new.path(key)).collect();
+            diff.removed_files = files.removed.into_iter().map(|key| old.path(key)).collect();
+            diff.changed_files = files.changed.into_iter().map(|key| new.path(key)).collect();
+        }
+
+        if !old.terms.ptr_eq(&new.terms) {
+            let is_posted = |entries: &IndexEntryList| entries.iter().next().is_some();
+            let terms = merge(old.terms.iter(), new.terms.iter(), is_posted, |old, new| {
+                !same_postings(old, new)
+            });
+            let to_strings = |terms: Vec<_>| terms.into_iter().map(ToString::to_string).collect();
+
+            diff.added_terms = to_strings(terms.added);
+            diff.removed_terms = to_strings(terms.removed);
+            diff.changed_terms = to_strings(terms.changed);
+        }
+
+        diff
+    }
+
+    /// Serialise the snapshot to `writer`, see the module documentation for the format.
+    ///
+    /// Only the terms that occur in at least one file are written.
+    pub fn export<W: Write>(&self, writer: W) -> Result<()> {
+        let storage = &self.storage;
+        let mut writer = BufWriter::new(writer);
+        let mut ids = HashMap::new();
+
+        writer.write_all(EXPORT_TAG)?;
+        write_u64(&mut writer, storage.generation)?;
+
+        write_u64(&mut writer, storage.files.iter().count() as u64)?;
+        for (key, meta) in storage.files.iter() {
+            let path = storage.path(key);
+            let terms = storage.term_vectors.get(key);
+
+            write_path(&mut writer, &path)?;
+            write_meta(&mut writer, meta)?;
+            write_u64(
+                &mut writer,
+                terms.as_ref().map_or(0, |t| t.iter().count()) as u64,
+            )?;
+            for (term, &count) in terms.iter().flat_map(|terms| terms.iter()) {
+                write_str(&mut writer, term)?;
+                write_u64(&mut writer, count)?;
+            }
+
+            ids.insert(path, ids.len() as u64);
+        }
+
+        // Postings of the files without bookkeeping data can't be referred to, so they are skipped.
+        let is_posted = |entries: &IndexEntryList| {
+            entries
+                .iter()
+                .any(|(path, _)| ids.contains_key(path.as_path()))
+        };
+
+        write_u64(
+            &mut writer,
+            storage
+                .terms
+                .iter()
+                .filter(|(_, entries)| is_posted(entries))
+                .count() as u64,
+        )?;
+        for (term, entries) in storage.terms.iter() {
+            let postings = entries
+                .iter()
+                .filter_map(|(path, offsets)| Some((*ids.get(path.as_path())?, offsets)))
+                .collect::<Vec<_>>();
+            if postings.is_empty() {
+                continue;
+            }
+
+            write_str(&mut writer, term)?;
+            writer.write_all(&[entries.membership_only as u8])?;
+            write_u64(&mut writer, postings.len() as u64)?;
+            for (id, offsets) in postings {
+                write_u64(&mut writer, id)?;
+                write_u64(&mut writer, offsets.iter().count() as u64)?;
+                for (&offset, &position) in offsets.iter() {
+                    write_u64(&mut writer, offset)?;
+                    write_u64(&mut writer, position)?;
+                }
+            }
+        }
+
+        write_u64(&mut writer, self.query_frequencies.len() as u64)?;
+        for frequency in &self.query_frequencies {
+            write_str(&mut writer, &frequency.term)?;
+            write_u64(&mut writer, frequency.count)?;
+            write_u64(&mut writer, encode_time(Some(frequency.last_queried)))?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_bytes<W: Write>(writer: &mut W, value: &[u8]) -> Result<()> {
+    write_u64(writer, value.len() as u64)?;
+    writer.write_all(value)?;
+    Ok(())
+}
+
+fn write_str<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    write_bytes(writer, value.as_bytes())
+}
+
+#[cfg(unix)]
+fn write_path<W: Write>(writer: &mut W, path: &Path) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    write_bytes(writer, path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_path<W: Write>(writer: &mut W, path: &Path) -> Result<()> {
+    match path.to_str() {
+        Some(path) => write_str(writer, path),
+        None => Err(Error::UnexportablePath(path.to_owned())),
+    }
+}
+
+fn write_meta<W: Write>(writer: &mut W, meta: &FileMeta) -> Result<()> {
+    write_u64(writer, meta.len)?;
+    write_u64(writer, meta.last_line)?;
+    write_u64(writer, meta.content_hash)?;
+    write_u64(writer, meta.tail_hash)?;
+    write_u64(writer, encode_time(meta.modified))?;
+    write_u64(writer, encode_time(Some(meta.indexed_at)))?;
+    writer.write_all(&[meta.membership_only as u8])?;
+    write_u64(writer, u64::from(meta.analyser_version))?;
+    write_u64(writer, meta.lines.iter().count() as u64)?;
+    for (&offset, line) in meta.lines.iter() {
+        write_u64(writer, offset)?;
+        write_u64(writer, line.hash)?;
+        write_u64(writer, line.position)?;
+    }
+    write_u64(writer, meta.end_position)?;
+    Ok(())
+}
+
+/// Encode a point in time as the number of nanoseconds since the Unix epoch plus one, or zero if it's
+/// unknown.
+fn encode_time(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos() as u64 + 1)
+}
+
+fn decode_time(time: u64) -> Option<SystemTime> {
+    time.checked_sub(1)
+        .map(|nanos| UNIX_EPOCH + Duration::from_nanos(nanos))
+}
+
+/// Contents of an exported snapshot, see [`read_export`].
+pub(crate) struct Export {
+    pub files: Vec<ExportedFile>,
+    pub query_frequencies: Vec<QueryFrequency>,
+}
+
+/// Document of an exported snapshot, see [`read_export`].
+pub(crate) struct ExportedFile {
+    pub path: PathBuf,
+    pub meta: FileMeta,
+    pub terms: Vec<ExportedTerm>,
+}
+
+/// Occurrences of a term in a document of an exported snapshot.
+pub(crate) struct ExportedTerm {
+    pub term: CompactString,
+    /// Offsets of the occurrences along with their positions, empty if they weren't kept.
+    pub postings: Postings,
+    pub count: u64,
+    /// Whether the term only keeps the paths it occurs in, see [`crate::Indexer::with_posting_cap`].
+    pub membership_only: bool,
+}
+
+/// Read a snapshot serialised with [`Snapshot::export`], grouping the postings by document.
+pub(crate) fn read_export<R: Read>(reader: R) -> Result<Export> {
+    let mut reader = BufReader::new(reader);
+    let mut tag = [0; 8];
+
+    reader.read_exact(&mut tag)?;
+    if &tag != EXPORT_TAG {
+        return Err(Error::InvalidSnapshot("unknown format"));
+    }
+    read_u64(&mut reader)?;
+
+    let mut files = Vec::new();
+    let mut counts = Vec::new();
+    for _ in 0..read_u64(&mut reader)? {
+        let path = read_path(&mut reader)?;
+        let meta = read_meta(&mut reader)?;
+        let mut terms = HashMap::new();
+
+        for _ in 0..read_u64(&mut reader)? {
+            let term = read_str(&mut reader)?;
+            terms.insert(term, read_u64(&mut reader)?);
+        }
+
+        files.push(ExportedFile {
+            path,
+            meta,
+            terms: Vec::new(),
+        });
+        counts.push(terms);
+    }
+
+    for _ in 0..read_u64(&mut reader)? {
+        let term = CompactString::from(read_str(&mut reader)?.as_str());
+        let membership_only = read_bool(&mut reader)?;
+
+        for _ in 0..read_u64(&mut reader)? {
+            let id = read_u64(&mut reader)? as usize;
+            let mut postings = Avl::new();
+            for _ in 0..read_u64(&mut reader)? {
+                postings = postings.insert(read_u64(&mut reader)?, read_u64(&mut reader)?);
+            }
+
+            let (file, counts) = match (files.get_mut(id), counts.get(id)) {
+                (Some(file), Some(counts)) => (file, counts),
+                _ => return Err(Error::InvalidSnapshot("posting of an unknown file")),
+            };
+            let count = counts.get(term.as_str()).copied().unwrap_or_default();
+            file.terms.push(ExportedTerm {
+                term: term.clone(),
+                postings,
+                count,
+                membership_only,
+            });
+        }
+    }
+
+    let mut query_frequencies = Vec::new();
+    for _ in 0..read_u64(&mut reader)? {
+        query_frequencies.push(QueryFrequency {
+            term: read_str(&mut reader)?,
+            count: read_u64(&mut reader)?,
+            last_queried: decode_time(read_u64(&mut reader)?).unwrap_or(UNIX_EPOCH),
+        });
+    }
+
+    Ok(Export {
+        files,
+        query_frequencies,
+    })
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_bool<R: Read>(reader: &mut R) -> Result<bool> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0] != 0)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(Error::InvalidSnapshot("truncated string"));
+    }
+    Ok(bytes)
+}
+
+fn read_str<R: Read>(reader: &mut R) -> Result<String> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|_| Error::InvalidSnapshot("invalid UTF-8 string"))
+}
+
+#[cfg(unix)]
+fn read_path<R: Read>(reader: &mut R) -> Result<PathBuf> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    Ok(PathBuf::from(OsString::from_vec(read_bytes(reader)?)))
+}
+
+#[cfg(not(unix))]
+fn read_path<R: Read>(reader: &mut R) -> Result<PathBuf> {
+    read_str(reader).map(PathBuf::from)
+}
+
+fn read_meta<R: Read>(reader: &mut R) -> Result<FileMeta> {
+    let len = read_u64(reader)?;
+    let last_line = read_u64(reader)?;
+    let content_hash = read_u64(reader)?;
+    let tail_hash = read_u64(reader)?;
+    let modified = decode_time(read_u64(reader)?);
+    let indexed_at = decode_time(read_u64(reader)?).unwrap_or(UNIX_EPOCH);
+    let membership_only = read_bool(reader)?;
+    let analyser_version = read_u64(reader)? as u32;
+    let mut lines = Avl::new();
+    for _ in 0..read_u64(reader)? {
+        let offset = read_u64(reader)?;
+        let line = LineMeta {
+            hash: read_u64(reader)?,
+            position: read_u64(reader)?,
+        };
+        lines = lines.insert(offset, line);
+    }
+    let end_position = read_u64(reader)?;
+
+    Ok(FileMeta {
+        len,
+        last_line,
+        content_hash,
+        tail_hash,
+        lines,
+        end_position,
+        modified,
+        indexed_at,
+        membership_only,
+        analyser_version,
+    })
+}
+
+/// How to resolve a document of an imported snapshot that is already indexed, see
+/// [`crate::Indexer::import_snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the version of the document that was indexed last.
+    KeepNewest,
+    /// Keep the document already indexed.
+    KeepExisting,
+    /// Replace the document already indexed with the imported one.
+    Replace,
+}
+
+/// What became of a document when a snapshot was imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImportOutcome {
+    /// The document wasn't indexed, it was added.
+    Added,
+    /// The document was indexed, it was replaced with the imported one.
+    Replaced,
+    /// The document was indexed, it was kept and the imported one was discarded.
+    Kept,
+    /// The document was indexed but missing from the snapshot, it was removed, see
+    /// [`crate::Indexer::restore_snapshot`].
+    Removed,
+}
+
+impl fmt::Display for ImportOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            ImportOutcome::Added => "added",
+            ImportOutcome::Replaced => "replaced",
+            ImportOutcome::Kept => "kept",
+            ImportOutcome::Removed => "removed",
+        };
+        f.write_str(outcome)
+    }
+}
+
+/// Result of merging a snapshot into an index, see [`crate::Indexer::import_snapshot`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeResult {
+    /// Every document of the snapshot along with its outcome, in the order of the snapshot, followed
+    /// by the removed ones if the snapshot was restored.
+    pub documents: Vec<(PathBuf, ImportOutcome)>,
+}
+
+impl MergeResult {
+    /// Number of documents with the given outcome.
+    pub fn count(&self, outcome: ImportOutcome) -> usize {
+        self.documents
+            .iter()
+            .filter(|(_, document_outcome)| *document_outcome == outcome)
+            .count()
+    }
+}
+
+impl fmt::Display for MergeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, outcome) in &self.documents {
+            writeln!(f, "{} {}", outcome, path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Differences between two snapshots of an index, in sorted order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub added_files: Vec<PathBuf>,
+    pub removed_files: Vec<PathBuf>,
+    pub changed_files: Vec<PathBuf>,
+    pub added_terms: Vec<String>,
+    pub removed_terms: Vec<String>,
+    pub changed_terms: Vec<String>,
+}
+
+impl SnapshotDiff {
+    /// Check whether the snapshots have no differences.
+    pub fn is_empty(&self) -> bool {
+        self.added_files.is_empty()
+            && self.removed_files.is_empty()
+            && self.changed_files.is_empty()
+            && self.added_terms.is_empty()
+            && self.removed_terms.is_empty()
+            && self.changed_terms.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files = [
+            ('+', &self.added_files),
+            ('-', &self.removed_files),
+            ('~', &self.changed_files),
+        ];
+        let terms = [
+            ('+', &self.added_terms),
+            ('-', &self.removed_terms),
+            ('~', &self.changed_terms),
+        ];
+
+        for (mark, files) in files.iter() {
+            for file in files.iter() {
+                writeln!(f, "{} file {}", mark, file.display())?;
+            }
+        }
+        for (mark, terms) in terms.iter() {
+            for term in terms.iter() {
+                writeln!(f, "{} term {}", mark, term)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keys that differ between two trees.
+struct Merged<'a, K> {
+    added: Vec<&'a K>,
+    removed: Vec<&'a K>,
+    changed: Vec<&'a K>,
+}
+
+/// Walk two sorted trees side by side, collecting the keys that differ between them.
+///
+/// Values for which `is_present` is `false` are treated as missing.
+fn merge<'a, K, V, I, P, C>(old: I, new: I, is_present: P, is_changed: C) -> Merged<'a, K>
+where
+    K: 'a + Ord,
+    V: 'a,
+    I: Iterator<Item = (&'a K, &'a V)>,
+    P: Fn(&V) -> bool,
+    C: Fn(&V, &V) -> bool,
+{
+    let mut old = old.filter(|(_, v)| is_present(v)).peekable();
+    let mut new = new.filter(|(_, v)| is_present(v)).peekable();
+    let mut merged = Merged {
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+    };
+
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (Some((old_key, _)), Some((new_key, _))) => old_key.cmp(new_key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return merged,
+        };
+
+        match order {
+            Ordering::Less => merged.removed.extend(old.next().map(|(key, _)| key)),
+            Ordering::Greater => merged.added.extend(new.next().map(|(key, _)| key)),
+            Ordering::Equal => {
+                if let (Some((key, old)), Some((_, new))) = (old.next(), new.next()) {
+                    if is_changed(old, new) {
+                        merged.changed.push(key);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Check whether two posting lists refer to the same paths at the same offsets and positions.
+fn same_postings(a: &IndexEntryList, b: &IndexEntryList) -> bool {
+    if a.entries.ptr_eq(&b.entries) {
+        return true;
+    }
+
+    a.membership_only == b.membership_only
+        && a.iter().count() == b.iter().count()
+        && a.iter()
+            .zip(b.iter())
+            .all(|((a_path, a_offsets), (b_path, b_offsets))| {
+                a_path == b_path
+                    && (a_offsets.ptr_eq(b_offsets) || a_offsets.iter().eq(b_offsets.iter()))
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{Snapshot, SnapshotDiff};
+    use crate::{
+        storage::{AvlStorage, FileMeta, LinePositions},
+        tokenise::Token,
+    };
+
+    fn index(storage: &AvlStorage, path: &str, contents: &str) {
+        storage.purge(Path::new(path));
+        for (position, word) in (1..).zip(contents.split_whitespace()) {
+            storage.insert(Path::new(path), Token::new(word), position);
+        }
+        storage.set_meta(
+            Path::new(path),
+            FileMeta::new(contents.as_bytes(), &LinePositions::default()),
+        );
+    }
+
+    #[test]
+    fn diff_lists_added_removed_and_changed() {
+        let storage = AvlStorage::new();
+        index(&storage, "/a", "one two");
+        index(&storage, "/b", "two");
+        index(&storage, "/c", "three");
+
+        let old = Snapshot::new(storage.snapshot());
+
+        assert!(old.diff(&Snapshot::new(storage.snapshot())).is_empty());
+
+        index(&storage, "/a", "one two four");
+        index(&storage, "/b", "two");
+        index(&storage, "/d", "two");
+        storage.purge(Path::new("/c"));
+
+        let diff = old.diff(&Snapshot::new(storage.snapshot()));
+
+        assert_eq!(
+            diff,
+            SnapshotDiff {
+                added_files: vec![PathBuf::from("/d")],
+                removed_files: vec![PathBuf::from("/c")],
+                changed_files: vec![PathBuf::from("/a")],
+                added_terms: vec!["four".to_owned()],
+                removed_terms: vec!["three".to_owned()],
+                changed_terms: vec!["two".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn export_is_a_point_in_time_image() {
+        let storage = AvlStorage::new();
+        index(&storage, "/a", "one two");
+        index(&storage, "/b", "two");
+
+        let snapshot = Snapshot::new(storage.snapshot());
+        let mut exported = Vec::new();
+        snapshot.export(&mut exported).unwrap();
+
+        index(&storage, "/a", "three");
+        storage.purge(Path::new("/b"));
+
+        let mut again = Vec::new();
+        snapshot.export(&mut again).unwrap();
+        let mut current = Vec::new();
+        Snapshot::new(storage.snapshot())
+            .export(&mut current)
+            .unwrap();
+
+        assert!(exported.starts_with(b"IDXSNAP5"));
+        assert_eq!(exported, again);
+        assert_ne!(exported, current);
+    }
+}
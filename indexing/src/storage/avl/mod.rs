@@ -7,11 +7,6 @@ pub use mvcc::MvccAvl;
 
 use node::Node;
 
-/// Convenience alias to use Avl as a set of elements.
-///
-/// Methods still require to pass `()` in as a value parameter.
-pub type AvlSet<T> = Avl<T, ()>;
-
 /// AVL tree implementation.
 ///
 /// This is a self-balancing tree which guarantees the difference in branches height to be no more than one.
@@ -117,10 +112,42 @@ where
             .and_then(|node| node.get(k).map(ValueRef::new))
     }
 
+    /// Check whether both trees share the same root, which means that they are equal.
+    ///
+    /// Trees that are equal but were built independently are not detected as such.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(root), Some(other_root)) => Arc::ptr_eq(root, other_root),
+            (root, other_root) => root.is_none() && other_root.is_none(),
+        }
+    }
+
     /// Get an iterator over the tree elements.
     pub fn iter(&self) -> Iter<'_, K, V> {
         Iter::new(&self.root)
     }
+
+    /// Get an iterator over the tree elements starting from the first key that is not less than `k`.
+    pub fn iter_from<Q>(&self, k: &Q) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Iter::new_from(&self.root, k)
+    }
+
+    /// Get an iterator over the tree elements whose keys start with `prefix`, in sorted order.
+    ///
+    /// Only the matching elements are visited, past seeking to the first one.
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a str) -> Prefix<'a, K, V>
+    where
+        K: Borrow<str>,
+    {
+        Prefix {
+            iter: self.iter_from(prefix),
+            prefix,
+        }
+    }
 }
 
 /// Reference to a value in the tree.
@@ -162,6 +189,30 @@ impl<'a, K, V> Iterator for Iter<'a, K, V> {
     }
 }
 
+/// Iterator over the elements whose keys start with a prefix, see [`Avl::iter_prefix`].
+pub struct Prefix<'a, K, V> {
+    iter: Iter<'a, K, V>,
+    prefix: &'a str,
+}
+
+impl<'a, K, V> Iterator for Prefix<'a, K, V>
+where
+    K: Borrow<str>,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Keys with the prefix are contiguous, so the first one without it ends the iteration.
+        let (k, v) = self.iter.next()?;
+        if k.borrow().starts_with(self.prefix) {
+            Some((k, v))
+        } else {
+            self.iter.next_stack.clear();
+            None
+        }
+    }
+}
+
 impl<'a, K, V> Iter<'a, K, V> {
     fn new(root: &'a Option<Arc<Node<K, V>>>) -> Self {
         let mut iter = Self {
@@ -172,6 +223,30 @@ impl<'a, K, V> Iter<'a, K, V> {
         iter
     }
 
+    fn new_from<Q>(root: &'a Option<Arc<Node<K, V>>>, k: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut iter = Self {
+            next_stack: Vec::new(),
+        };
+
+        // Only the nodes with keys not less than `k` are pushed, the right subtree of each of them
+        // is then traversed as usual when the node is popped.
+        let mut node = root.as_deref();
+        while let Some(current) = node {
+            if k <= current.k.borrow() {
+                iter.next_stack.push(current);
+                node = current.l.as_deref();
+            } else {
+                node = current.r.as_deref();
+            }
+        }
+
+        iter
+    }
+
     /// Dive into the left-most node of the given subtree.
     fn traverse_left(&mut self, mut node: Option<&'a Node<K, V>>) {
         while let Some(current) = node {
@@ -297,4 +372,39 @@ mod tests {
         assert_eq!(iter.next(), Some((&"d".to_owned(), &4)));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn iter_from_starts_at_lower_bound() {
+        let pairs = [("a", 1), ("c", 3), ("e", 5), ("g", 7), ("i", 9)];
+        let avl = pairs
+            .iter()
+            .fold(Avl::new(), |avl, &(k, v)| avl.insert(k.to_owned(), v));
+
+        let from_existing = avl.iter_from("e").map(|(_, v)| *v).collect::<Vec<_>>();
+        let from_missing = avl.iter_from("d").map(|(_, v)| *v).collect::<Vec<_>>();
+
+        assert_eq!(from_existing, [5, 7, 9]);
+        assert_eq!(from_missing, [5, 7, 9]);
+        assert_eq!(avl.iter_from("j").count(), 0);
+        assert_eq!(avl.iter_from("").count(), 5);
+    }
+
+    #[test]
+    fn iter_prefix_stops_past_the_prefix() {
+        let keys = ["in", "index", "indexer", "indices", "inn", "zebra"];
+        let avl = keys
+            .iter()
+            .fold(Avl::new(), |avl, &k| avl.insert(k.to_owned(), ()));
+
+        let keys = |prefix| {
+            avl.iter_prefix(prefix)
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys("ind"), ["index", "indexer", "indices"]);
+        assert_eq!(keys("index"), ["index", "indexer"]);
+        assert_eq!(keys("x"), Vec::<&str>::new());
+        assert_eq!(keys("").len(), 6);
+    }
 }
@@ -1,9 +1,11 @@
 use std::{
     borrow::Borrow,
-    sync::{Mutex, RwLock},
+    sync::{LockResult, Mutex, MutexGuard, RwLock},
 };
 
 use super::Avl;
+#[cfg(feature = "lock-contention")]
+use crate::contention::{self, Lock};
 
 /// Mutable implementation of the AVL tree.
 ///
@@ -24,6 +26,9 @@ use super::Avl;
 /// one must first create a snapshot of it by calling [`Mvcc::snapshot`]. The returned snapshot has
 /// the necessary methods to access the values of the tree, see [`Avl`] and [`Avl::get`], [`Avl::iter`]
 /// in particular.
+///
+/// With the `lock-contention` feature, the acquisitions of the locks are counted, see `contention`
+/// module.
 pub struct MvccAvl<K, V> {
     root: RwLock<Avl<K, V>>,
 
@@ -48,10 +53,10 @@ where
     ///
     /// If the given key already exists in the tree, its associated value is updated with the newly supplied one.
     pub fn insert(&self, k: K, v: V) {
-        let _write_lock = self.write_lock.lock();
+        let _write_lock = self.lock_writer();
         let new_root = self.snapshot().insert(k, v);
 
-        *self.root.write().unwrap() = new_root;
+        self.set_root(new_root);
     }
 
     /// Updates or inserts a new key-value pair in the tree.
@@ -64,10 +69,10 @@ where
     where
         F: FnOnce(Option<&V>) -> V,
     {
-        let _write_lock = self.write_lock.lock();
+        let _write_lock = self.lock_writer();
         let new_root = self.snapshot().upsert(k, f);
 
-        *self.root.write().unwrap() = new_root;
+        self.set_root(new_root);
     }
 
     /// Updates an existing value in the tree.
@@ -82,10 +87,10 @@ where
         Q: Ord + ?Sized,
         F: FnOnce(&V) -> V,
     {
-        let _write_lock = self.write_lock.lock();
+        let _write_lock = self.lock_writer();
         let new_root = self.snapshot().update(k, f);
 
-        *self.root.write().unwrap() = new_root;
+        self.set_root(new_root);
     }
 
     /// Remove the key-value pair associated with the given key from the tree.
@@ -94,16 +99,39 @@ where
         K: Borrow<Q>,
         Q: Ord + ?Sized,
     {
-        let _write_lock = self.write_lock.lock();
+        let _write_lock = self.lock_writer();
         let new_root = self.snapshot().remove(k);
 
-        *self.root.write().unwrap() = new_root;
+        self.set_root(new_root);
     }
 
     /// Create a snapshot of the tree.
     pub fn snapshot(&self) -> Avl<K, V> {
+        #[cfg(feature = "lock-contention")]
+        let root = contention::read(Lock::RootRead, &self.root);
+        #[cfg(not(feature = "lock-contention"))]
+        let root = self.root.read();
+
         // Clone right away to drop the read lock.
-        self.root.read().unwrap().clone()
+        root.unwrap().clone()
+    }
+
+    fn lock_writer(&self) -> LockResult<MutexGuard<'_, ()>> {
+        #[cfg(feature = "lock-contention")]
+        let write_lock = contention::lock(Lock::Writer, &self.write_lock);
+        #[cfg(not(feature = "lock-contention"))]
+        let write_lock = self.write_lock.lock();
+
+        write_lock
+    }
+
+    fn set_root(&self, new_root: Avl<K, V>) {
+        #[cfg(feature = "lock-contention")]
+        let root = contention::write(Lock::RootWrite, &self.root);
+        #[cfg(not(feature = "lock-contention"))]
+        let root = self.root.write();
+
+        *root.unwrap() = new_root;
     }
 }
 
@@ -35,7 +35,7 @@ where
             };
 
             return Self {
-                l: Some(l).map(Arc::new),
+                l: Some(Arc::new(l)),
                 ..self.clone()
             }
             .recompute_height()
@@ -50,7 +50,7 @@ where
             };
 
             return Self {
-                r: Some(r).map(Arc::new),
+                r: Some(Arc::new(r)),
                 ..self.clone()
             }
             .recompute_height()
@@ -257,14 +257,13 @@ where
     fn rotate_left(&self) -> Self {
         if let Some(r) = &self.r {
             Self {
-                l: Some(
+                l: Some(Arc::new(
                     Self {
                         r: r.l.clone(),
                         ..self.clone()
                     }
                     .recompute_height(),
-                )
-                .map(Arc::new),
+                )),
                 r: r.r.clone(),
                 ..r.clone_node()
             }
@@ -278,14 +277,13 @@ where
     fn rotate_right(&self) -> Self {
         if let Some(l) = &self.l {
             Self {
-                r: Some(
+                r: Some(Arc::new(
                     Self {
                         l: l.r.clone(),
                         ..self.clone()
                     }
                     .recompute_height(),
-                )
-                .map(Arc::new),
+                )),
                 l: l.l.clone(),
                 ..l.clone_node()
             }
@@ -322,21 +320,19 @@ mod tests {
             v: 1,
             h: 3,
             l: None,
-            r: Some(Node {
+            r: Some(Arc::new(Node {
                 k: 2,
                 v: 2,
                 h: 2,
                 l: None,
-                r: Some(Node {
+                r: Some(Arc::new(Node {
                     k: 3,
                     v: 3,
                     h: 1,
                     l: None,
                     r: None,
-                })
-                .map(Arc::new),
-            })
-            .map(Arc::new),
+                })),
+            })),
         };
 
         let balanced = node.rebalance_insert();
@@ -1,66 +1,1095 @@
-use std::path::{Path, PathBuf};
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc,
+    },
+};
 
 use crate::{
+    budget::IndexUsage,
+    cancel::CancellationToken,
+    health::{HealthReport, Inconsistency},
     intern::InternPool,
+    rank::TermStats,
     storage::{
-        avl::{AvlSet, MvccAvl, ValueRef},
-        IndexEntryList,
+        avl::{Avl, MvccAvl, ValueRef},
+        BloomFilter, FileMeta, IndexEntryList, Postings,
     },
+    storage_metrics::StorageMetrics,
     tokenise::Token,
+    CompactString, Error, PathCase, Result,
 };
 
 /// Index storage that uses [`Avl`] as a data container.
+///
+/// Files are identified by a path key built according to the configured [`PathCase`], while the
+/// entries refer to the path as it was spelled when the file was first indexed.
+///
+/// Alongside the term tree, a term vector (the number of occurrences of every term) is kept for
+/// every file.
+///
+/// Every modification of the storage increments its generation, see [`AvlStorage::generation`].
+///
+/// Terms that occur more times than the posting cap (if set) only keep the paths they occur in,
+/// without the offsets, see [`AvlStorage::set_posting_cap`].
+///
+/// Lookups of the terms that were never inserted can be answered without descending the term tree,
+/// see [`AvlStorage::set_term_filter`].
+///
+/// Insertions, purges and lookups are reported to the metrics sink (if set), see
+/// [`AvlStorage::set_metrics`].
 pub(crate) struct AvlStorage {
+    path_case: PathCase,
+    posting_cap: Option<u64>,
+    term_filter: Option<Arc<BloomFilter>>,
+    metrics: Option<Arc<dyn StorageMetrics>>,
     intern_pool: InternPool<PathBuf>,
-    avl: MvccAvl<String, IndexEntryList>,
-    file_words: MvccAvl<PathBuf, AvlSet<String>>,
+    avl: MvccAvl<CompactString, IndexEntryList>,
+    file_words: MvccAvl<PathBuf, TermVector>,
+    file_meta: MvccAvl<PathBuf, FileMeta>,
+    generation: AtomicU64,
+}
+
+/// Number of occurrences of every term in a file.
+pub(crate) type TermVector = Avl<CompactString, u64>;
+
+/// Immutable version of an [`AvlStorage`], see [`AvlStorage::snapshot`].
+#[derive(Clone)]
+pub(crate) struct StorageSnapshot {
+    pub generation: u64,
+    pub terms: Avl<CompactString, IndexEntryList>,
+    pub files: Avl<PathBuf, FileMeta>,
+    pub term_vectors: Avl<PathBuf, TermVector>,
+    paths: Avl<PathBuf, Arc<PathBuf>>,
+    /// Filter of the terms inserted in the storage, see [`AvlStorage::set_term_filter`].
+    ///
+    /// The filter is shared with the storage, which is fine as terms are never removed from it.
+    term_filter: Option<Arc<BloomFilter>>,
+}
+
+impl StorageSnapshot {
+    /// Get the index entries of a term, like [`AvlStorage::get`] does.
+    pub fn get(&self, word: &str) -> Option<ValueRef<CompactString, IndexEntryList>> {
+        may_contain(&self.term_filter, word)
+            .then(|| self.terms.get(word))
+            .flatten()
+    }
+
+    /// Get the path of the given file key as spelled when the file was first indexed.
+    pub fn path(&self, key: &Path) -> PathBuf {
+        self.paths
+            .get(key)
+            .map_or_else(|| key.to_owned(), |path| path.to_path_buf())
+    }
 }
 
 impl AvlStorage {
     /// Create an instance of [`AvlStorage`].
     pub fn new() -> Self {
         Self {
+            path_case: PathCase::default(),
+            posting_cap: None,
+            term_filter: None,
+            metrics: None,
             intern_pool: InternPool::new(),
             avl: MvccAvl::new(),
             file_words: MvccAvl::new(),
+            file_meta: MvccAvl::new(),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the case sensitivity of path keys.
+    ///
+    /// Must be set before anything is inserted in the storage.
+    pub fn set_path_case(&mut self, path_case: PathCase) {
+        self.path_case = path_case;
+    }
+
+    /// Case sensitivity of path keys.
+    pub fn path_case(&self) -> PathCase {
+        self.path_case
+    }
+
+    /// Set the number of occurrences of a term above which its offsets are dropped.
+    ///
+    /// Such terms are still found in every file they occur in, and the number of their occurrences in
+    /// each file is still kept in the term vector, but their offsets aren't available anymore, and
+    /// thus they can't be spliced, see [`AvlStorage::has_capped_terms`].
+    pub fn set_posting_cap(&mut self, posting_cap: Option<u64>) {
+        self.posting_cap = posting_cap;
+    }
+
+    /// Number of occurrences of a term above which its offsets are dropped.
+    pub fn posting_cap(&self) -> Option<u64> {
+        self.posting_cap
+    }
+
+    /// Set the filter of the inserted terms, checked before looking a term up.
+    ///
+    /// Must be set before anything is inserted in the storage.
+    pub fn set_term_filter(&mut self, term_filter: Option<BloomFilter>) {
+        self.term_filter = term_filter.map(Arc::new);
+    }
+
+    /// Set the sink the storage events are reported to.
+    pub fn set_metrics(&mut self, metrics: Option<Arc<dyn StorageMetrics>>) {
+        self.metrics = metrics;
+    }
+
+    /// Sink the storage events are reported to.
+    pub fn metrics(&self) -> Option<Arc<dyn StorageMetrics>> {
+        self.metrics.clone()
+    }
+
+    /// Get the terms that reached the posting cap, in sorted order.
+    pub fn capped_terms(&self) -> Vec<CompactString> {
+        self.avl
+            .snapshot()
+            .iter()
+            .filter(|(_, entries)| entries.membership_only)
+            .map(|(term, _)| term.clone())
+            .collect()
+    }
+
+    /// Check whether any term of the given path reached the posting cap.
+    pub fn has_capped_terms(&self, path: &Path) -> bool {
+        let index = self.avl.snapshot();
+
+        self.term_vector(path).is_some_and(|terms| {
+            terms.iter().any(|(term, _)| {
+                index
+                    .get(term)
+                    .is_some_and(|entries| entries.membership_only)
+            })
+        })
+    }
+
+    /// Number of modifications applied to the storage so far.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(atomic::Ordering::Relaxed)
+    }
+
+    fn modified(&self) {
+        self.generation.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    /// Take an immutable snapshot of the storage.
+    ///
+    /// The trees are snapshotted one after the other, so the snapshot is only exact when nothing is
+    /// being indexed concurrently.
+    pub fn snapshot(&self) -> StorageSnapshot {
+        StorageSnapshot {
+            generation: self.generation(),
+            terms: self.avl.snapshot(),
+            files: self.file_meta.snapshot(),
+            term_vectors: self.file_words.snapshot(),
+            paths: self.intern_pool.snapshot(),
+            term_filter: self.term_filter.clone(),
         }
     }
 
     /// Get a list of [`IndexEntry`] instances associated with this term (if any).
-    pub fn get(&self, word: &str) -> Option<ValueRef<String, IndexEntryList>> {
-        self.avl.snapshot().get(word)
+    pub fn get(&self, word: &str) -> Option<ValueRef<CompactString, IndexEntryList>> {
+        let entries = may_contain(&self.term_filter, word)
+            .then(|| self.avl.snapshot().get(word))
+            .flatten();
+
+        if let Some(metrics) = &self.metrics {
+            metrics.on_query(
+                word.len(),
+                entries.as_ref().map_or(0, |entries| entries.documents),
+            );
+        }
+        entries
+    }
+
+    /// Get the terms starting with `prefix` and accepted by `matches` that are still found in some
+    /// file, in sorted order, along with their index entries.
+    pub fn expand_terms<F>(&self, prefix: &str, matches: F) -> Vec<(CompactString, IndexEntryList)>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        // A token that is never cancelled can't time out.
+        self.expand_terms_until(prefix, matches, &CancellationToken::new())
+            .unwrap_or_default()
+    }
+
+    /// Expand the terms like [`AvlStorage::expand_terms`] does, giving up with [`Error::Timeout`]
+    /// as soon as the token is cancelled.
+    pub fn expand_terms_until<F>(
+        &self,
+        prefix: &str,
+        mut matches: F,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<(CompactString, IndexEntryList)>>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let index = self.avl.snapshot();
+        let mut expanded = Vec::new();
+
+        for (term, entries) in index.iter_prefix(prefix) {
+            if cancellation.is_cancelled() {
+                return Err(Error::Timeout);
+            }
+            if entries.iter().next().is_some() && matches(term.as_str()) {
+                expanded.push((term.clone(), entries.clone()));
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Get up to `limit` terms that follow `after` (or the first ones), in sorted order, along with
+    /// the number of files each of them was found in.
+    ///
+    /// Terms that are no longer found in any file are skipped.
+    pub fn terms(&self, after: Option<&str>, limit: usize) -> Vec<(CompactString, usize)> {
+        let index = self.avl.snapshot();
+        let terms = match after {
+            Some(after) => index.iter_from(after),
+            None => index.iter(),
+        };
+
+        terms
+            .filter(|(term, _)| after.is_none_or(|after| term.as_str() != after))
+            .map(|(term, entries)| (term.clone(), entries.documents))
+            .filter(|&(_, files)| files > 0)
+            .take(limit)
+            .collect()
+    }
+
+    /// Number of files that have a term vector.
+    pub fn term_vector_count(&self) -> usize {
+        self.file_words.snapshot().iter().count()
+    }
+
+    /// Get the bookkeeping data of the given path (if indexed).
+    pub fn meta(&self, path: &Path) -> Option<FileMeta> {
+        self.file_meta
+            .snapshot()
+            .get(self.path_case.key(path).as_ref())
+            .as_deref()
+            .cloned()
+    }
+
+    /// Set the bookkeeping data of the given path.
+    pub fn set_meta(&self, path: &Path, meta: FileMeta) {
+        self.modified();
+        self.file_meta
+            .insert(self.path_case.key(path).into_owned(), meta);
+    }
+
+    /// Get the bookkeeping data of every indexed file, along with its path as spelled when indexed.
+    pub fn files(&self) -> Vec<(PathBuf, FileMeta)> {
+        self.file_meta
+            .snapshot()
+            .iter()
+            .map(|(key, meta)| {
+                let path = self
+                    .intern_pool
+                    .get(key.as_path())
+                    .map_or_else(|| key.clone(), |path| path.to_path_buf());
+
+                (path, meta.clone())
+            })
+            .collect()
+    }
+
+    /// Get the paths of the files with a term vector, as spelled when indexed.
+    pub fn term_vector_paths(&self) -> Vec<PathBuf> {
+        self.file_words
+            .snapshot()
+            .iter()
+            .map(|(key, _)| {
+                self.intern_pool
+                    .get(key.as_path())
+                    .map_or_else(|| key.clone(), |path| path.to_path_buf())
+            })
+            .collect()
+    }
+
+    /// Get the keys of the files with bookkeeping data at the given path or beneath it.
+    pub fn keys_under(&self, path: &Path) -> Vec<PathBuf> {
+        let prefix = self.path_case.key(path);
+
+        self.file_meta
+            .snapshot()
+            .iter_from(prefix.as_ref())
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect()
     }
 
     /// Purge the given `path` from the index.
     pub fn purge(&self, path: &Path) {
-        let interned_path = self.intern_pool.intern(path);
+        self.modified();
+        let key = self.path_case.key(path);
+        self.file_meta.remove(key.as_ref());
 
-        let words = match self.file_words.snapshot().get(path) {
-            Some(words) => words,
-            None => return,
+        let (interned_path, words) = match (
+            self.intern_pool.get(key.as_ref()),
+            self.file_words.snapshot().get(key.as_ref()),
+        ) {
+            (Some(interned_path), Some(words)) => (interned_path, words),
+            _ => return,
         };
-        self.file_words.remove(path);
+        self.file_words.remove(key.as_ref());
 
         for (word, _) in words.iter() {
             self.avl.update(word, |e| e.remove(&interned_path));
         }
+        if let Some(metrics) = &self.metrics {
+            metrics.on_purge(words.iter().count());
+        }
+    }
+
+    /// Purge the given `path` and every path beneath it from the index.
+    ///
+    /// Only the paths known to the index are considered, so this works for directories that no longer
+    /// exist on the file system.
+    pub fn purge_dir(&self, path: &Path) {
+        let prefix = self.path_case.key(path);
+        let keys = self
+            .file_words
+            .snapshot()
+            .iter_from(prefix.as_ref())
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            self.purge(&key);
+        }
+    }
+
+    /// Remove a single token-path association from the index.
+    ///
+    /// Nothing is done if the token wasn't inserted for this path at the token's offset.
+    pub fn remove(&self, path: &Path, token: &Token) {
+        self.modified();
+        let key = self.path_case.key(path);
+        let interned_path = match self.intern_pool.get(key.as_ref()) {
+            Some(interned_path) => interned_path,
+            None => return,
+        };
+
+        let membership_only = match self.get(&token.value) {
+            Some(entries) if entries.membership_only => true,
+            Some(entries) if entries.contains(&interned_path, token.offset) => false,
+            _ => return,
+        };
+        let count = self
+            .term_vector(path)
+            .and_then(|terms| terms.get(&token.value).as_deref().copied())
+            .unwrap_or(0);
+
+        // Without the offsets, the path is only dropped from a capped term with its last occurrence.
+        if !membership_only {
+            self.avl.update(&token.value, |entries| {
+                entries.remove_offset(&interned_path, token.offset)
+            });
+        } else if count <= 1 {
+            self.avl
+                .update(&token.value, |entries| entries.remove(&interned_path));
+        }
+        if count > 0 {
+            self.file_words.update(key.as_ref(), |terms| {
+                if count > 1 {
+                    terms.insert(token.value.clone(), count - 1)
+                } else {
+                    terms.remove(&token.value)
+                }
+            });
+        }
+    }
+
+    /// Splice a region of the given path's contents.
+    ///
+    /// All the occurrences within the `removed` range of offsets are dropped, while the ones after it
+    /// are moved by `delta` bytes and `position_delta` positions.
+    pub fn splice(&self, path: &Path, removed: Range<u64>, delta: i64, position_delta: i64) {
+        self.modified();
+        let key = self.path_case.key(path);
+        let (interned_path, terms) = match (
+            self.intern_pool.get(key.as_ref()),
+            self.file_words.snapshot().get(key.as_ref()),
+        ) {
+            (Some(interned_path), Some(terms)) => (interned_path, terms),
+            _ => return,
+        };
+        let index = self.avl.snapshot();
+
+        for (term, _) in terms.iter() {
+            let offsets = match index.get(term) {
+                Some(entries) => match entries.entries.get(&interned_path) {
+                    Some(offsets) => offsets,
+                    None => continue,
+                },
+                None => continue,
+            };
+            if offsets.iter_from(&removed.start).next().is_none() {
+                continue;
+            }
+
+            let mut dropped = 0;
+            let spliced = offsets
+                .iter()
+                .fold(Avl::new(), |spliced, (&offset, &position)| {
+                    if offset < removed.start {
+                        spliced.insert(offset, position)
+                    } else if offset < removed.end {
+                        dropped += 1;
+                        spliced
+                    } else {
+                        spliced.insert(
+                            (offset as i64 + delta) as u64,
+                            (position as i64 + position_delta) as u64,
+                        )
+                    }
+                });
+
+            self.avl.update(term, |entries| {
+                entries.set_postings(interned_path.clone(), spliced)
+            });
+            if dropped > 0 {
+                self.file_words
+                    .update(key.as_ref(), |terms| match terms.get(term).as_deref() {
+                        Some(&count) if count > dropped => {
+                            terms.insert(term.clone(), count - dropped)
+                        }
+                        _ => terms.remove(term),
+                    });
+            }
+        }
+    }
+
+    /// Remove every occurrence of the given term from the index.
+    ///
+    /// Returns whether the term only kept the paths it occurred in (see [`IndexEntryList`]), and the
+    /// paths along with its postings and its number of occurrences in each of them, see
+    /// [`AvlStorage::put_term`].
+    pub fn take_term(&self, term: &str) -> (bool, Vec<(PathBuf, Postings, u64)>) {
+        let entries = match self.get(term) {
+            Some(entries) => entries,
+            None => return (false, Vec::new()),
+        };
+        self.modified();
+        self.avl.remove(term);
+
+        let postings = entries
+            .iter()
+            .map(|(path, offsets)| {
+                let key = self.path_case.key(path);
+                let count = self
+                    .term_vector(path)
+                    .and_then(|terms| terms.get(term).as_deref().copied())
+                    .unwrap_or(0);
+                self.file_words
+                    .update(key.as_ref(), |terms| terms.remove(term));
+
+                (path.to_path_buf(), offsets.clone(), count)
+            })
+            .collect();
+        (entries.membership_only, postings)
+    }
+
+    /// Insert the occurrences of a term in a file, as returned by [`AvlStorage::take_term`].
+    ///
+    /// If the term reached the posting cap (`membership_only`), it only keeps the paths it occurs in
+    /// from now on. Otherwise, the path is kept without offsets if they aren't known, e.g. the file
+    /// was indexed while the memory was degraded, see [`crate::budget`].
+    pub fn put_term(
+        &self,
+        term: &CompactString,
+        path: &Path,
+        postings: &Postings,
+        count: u64,
+        membership_only: bool,
+    ) {
+        if postings.iter().next().is_some() {
+            for (&offset, &position) in postings.iter() {
+                self.insert(path, Token::with_offset_at(term.clone(), offset), position);
+            }
+            return;
+        }
+
+        self.modified();
+        let key = self.path_case.key(path);
+        if let Some(term_filter) = &self.term_filter {
+            term_filter.insert(term);
+        }
+
+        self.file_words.upsert(key.clone().into_owned(), |terms| {
+            terms
+                .cloned()
+                .unwrap_or_else(Avl::new)
+                .upsert(term.clone(), |n| n.map_or(count, |n| n + count))
+        });
+        self.avl.upsert(term.clone(), |entries| {
+            let entries = entries.cloned().unwrap_or_else(IndexEntryList::new);
+            let entries = if membership_only {
+                entries.to_membership_only()
+            } else {
+                entries
+            };
+            entries.append_path(self.intern_pool.intern_by(key.as_ref(), || path.to_owned()))
+        });
+    }
+
+    /// Get the statistics of the given term, `None` if it's not found in any file.
+    ///
+    /// The counters are maintained by the index entries as they change, so this doesn't scan them.
+    pub fn term_stats(&self, term: &str) -> Option<TermStats> {
+        self.get(term)
+            .filter(|entries| entries.documents > 0)
+            .map(|entries| TermStats {
+                doc_count: entries.documents,
+                total_occurrences: entries.occurrences,
+            })
+    }
+
+    /// Get the term vector of the given path (if indexed).
+    pub fn term_vector(&self, path: &Path) -> Option<ValueRef<PathBuf, TermVector>> {
+        self.file_words
+            .snapshot()
+            .get(self.path_case.key(path).as_ref())
+    }
+
+    /// Find the files most similar to the given one, by cosine similarity of their term vectors.
+    ///
+    /// Only the files that share at least one term with the given one are considered. The result is
+    /// ordered by decreasing similarity and holds at most `limit` entries.
+    pub fn similar(&self, path: &Path, limit: usize) -> Vec<(PathBuf, f64)> {
+        let key = self.path_case.key(path);
+        let file_words = self.file_words.snapshot();
+        let terms = match file_words.get(key.as_ref()) {
+            Some(terms) => terms,
+            None => return Vec::new(),
+        };
+
+        let index = self.avl.snapshot();
+        let candidates = terms
+            .iter()
+            .filter_map(|(term, _)| index.get(term))
+            .flat_map(|entries| {
+                entries
+                    .iter()
+                    .map(|(path, _)| path.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<BTreeSet<_>>();
+
+        let mut similar = candidates
+            .into_iter()
+            .filter_map(|candidate| {
+                let candidate_key = self.path_case.key(&candidate);
+                if candidate_key == key {
+                    return None;
+                }
+
+                let candidate_terms = file_words.get(candidate_key.as_ref())?;
+                Some((candidate.to_path_buf(), cosine(&terms, &candidate_terms)))
+            })
+            .collect::<Vec<_>>();
+
+        similar.sort_by(|(a_path, a), (b_path, b)| {
+            b.partial_cmp(a)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_path.cmp(b_path))
+        });
+        similar.truncate(limit);
+        similar
+    }
+
+    /// Count the documents and the terms, and estimate the memory they use.
+    pub fn usage(&self) -> IndexUsage {
+        // Rough sizes of the tree nodes and their payloads, the trees share most of their nodes
+        // between the versions, so only the latest one is accounted for.
+        const NODE_BYTES: u64 = 48;
+        const TERM_BYTES: u64 = NODE_BYTES + 64;
+        const FILE_BYTES: u64 = NODE_BYTES + 256;
+
+        let mut usage = IndexUsage::default();
+
+        for (term, entries) in self.avl.snapshot().iter() {
+            let mut posted = false;
+
+            for (_, offsets) in entries.iter() {
+                posted = true;
+                // The posting itself and the term vector entry of the file.
+                usage.memory_bytes += 2 * NODE_BYTES + offsets.iter().count() as u64 * NODE_BYTES;
+            }
+
+            if posted {
+                usage.terms += 1;
+                usage.memory_bytes += TERM_BYTES + term.len() as u64;
+            }
+        }
+
+        for (path, _) in self.file_words.snapshot().iter() {
+            usage.documents += 1;
+            usage.memory_bytes += FILE_BYTES + path.as_os_str().len() as u64;
+        }
+        usage
+    }
+
+    /// Cross-check the postings of every term against the term vector of every file.
+    ///
+    /// Both are checked against the same snapshot of each tree, but the trees themselves are updated
+    /// one after the other, so the check is only exact when nothing is being indexed concurrently.
+    pub fn verify(&self) -> HealthReport {
+        let index = self.avl.snapshot();
+        let file_words = self.file_words.snapshot();
+        let mut report = HealthReport::default();
+
+        for (term, entries) in index.iter() {
+            let mut posted = false;
+
+            for (path, offsets) in entries.iter() {
+                posted = true;
+                report.postings += 1;
+
+                let postings = offsets.iter().count() as u64;
+                let term_vector = file_words
+                    .get(self.path_case.key(path).as_ref())
+                    .and_then(|terms| terms.get(term).as_deref().copied());
+
+                match term_vector {
+                    None => report
+                        .inconsistencies
+                        .push(Inconsistency::MissingFromTermVector {
+                            term: term.to_string(),
+                            path: path.to_path_buf(),
+                        }),
+                    // Files indexed without offsets have empty postings, see `insert_path_only`.
+                    Some(term_vector)
+                        if term_vector != postings && !entries.membership_only && postings != 0 =>
+                    {
+                        report.inconsistencies.push(Inconsistency::CountMismatch {
+                            term: term.to_string(),
+                            path: path.to_path_buf(),
+                            postings,
+                            term_vector,
+                        })
+                    }
+                    Some(_) => (),
+                }
+            }
+
+            if posted {
+                report.terms += 1;
+            }
+        }
+
+        for (key, terms) in file_words.iter() {
+            report.files += 1;
+
+            let interned_path = self.intern_pool.get(key.as_path());
+            for (term, _) in terms.iter() {
+                let posted = interned_path.as_ref().is_some_and(|interned_path| {
+                    index
+                        .get(term)
+                        .is_some_and(|entries| entries.entries.get(interned_path).is_some())
+                });
+
+                if !posted {
+                    report.inconsistencies.push(Inconsistency::MissingPosting {
+                        term: term.to_string(),
+                        path: interned_path
+                            .as_ref()
+                            .map_or_else(|| key.clone(), |path| path.to_path_buf()),
+                    });
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Insert an token-path association in the index, the token being at the given position of the
+    /// file, see [`Postings`].
+    pub fn insert(&self, path: &Path, token: Token, position: u64) {
+        self.add(path, token, Some(position));
+    }
+
+    /// Insert an token-path association in the index without the offset of the token, see
+    /// [`IndexEntryList::append_path`].
+    pub fn insert_path_only(&self, path: &Path, token: Token) {
+        self.add(path, token, None);
+    }
+
+    /// Downgrade the given number of terms with the most occurrences to keep only the paths they
+    /// occur in, returning the downgraded terms.
+    pub fn downgrade_hottest(&self, count: usize) -> Vec<CompactString> {
+        let mut terms = self
+            .avl
+            .snapshot()
+            .iter()
+            .filter(|(_, entries)| !entries.membership_only && entries.occurrences > 0)
+            .map(|(term, entries)| (entries.occurrences, term.clone()))
+            .collect::<Vec<_>>();
+        terms.sort_by(|a, b| b.cmp(a));
+        terms.truncate(count);
+
+        if !terms.is_empty() {
+            self.modified();
+        }
+        terms
+            .into_iter()
+            .map(|(_, term)| {
+                self.avl.update(&term, IndexEntryList::to_membership_only);
+                term
+            })
+            .collect()
     }
 
-    /// Insert an token-path association in the index.
-    pub fn insert(&self, path: &Path, token: Token) {
-        let Token { value, offset } = token;
+    fn add(&self, path: &Path, token: Token, position: Option<u64>) {
+        self.modified();
+        let Token { value, offset, .. } = token;
+        let key = self.path_case.key(path);
+        if let Some(term_filter) = &self.term_filter {
+            term_filter.insert(&value);
+        }
 
-        self.file_words.upsert(path.to_owned(), |set| {
-            set.as_deref()
+        self.file_words.upsert(key.clone().into_owned(), |terms| {
+            terms
                 .cloned()
-                .unwrap_or_else(AvlSet::new)
-                .insert(value.clone(), ())
+                .unwrap_or_else(Avl::new)
+                .upsert(value.clone(), |count| count.map_or(1, |count| count + 1))
         });
 
+        let term_len = value.len();
+        let mut postings_len = 0;
         self.avl.upsert(value, |entries| {
             let entries = entries.cloned().unwrap_or_else(IndexEntryList::new);
+            let path = self.intern_pool.intern_by(key.as_ref(), || path.to_owned());
+            let entries = match position {
+                Some(position) => entries.append(path, offset, position),
+                None => entries.append_path(path),
+            };
 
-            entries.append(self.intern_pool.intern(path), offset)
-        })
+            postings_len = entries.occurrences;
+
+            match self.posting_cap {
+                Some(cap) if entries.occurrences > cap => entries.to_membership_only(),
+                _ => entries,
+            }
+        });
+        if let Some(metrics) = &self.metrics {
+            metrics.on_insert(term_len, postings_len);
+        }
+    }
+}
+
+/// Check whether a term might have been inserted in a storage with the given term filter.
+fn may_contain(term_filter: &Option<Arc<BloomFilter>>, word: &str) -> bool {
+    term_filter
+        .as_ref()
+        .is_none_or(|term_filter| term_filter.may_contain(word))
+}
+
+/// Cosine similarity of two term vectors.
+fn cosine(a: &TermVector, b: &TermVector) -> f64 {
+    let norm = |v: &TermVector| {
+        v.iter()
+            .map(|(_, &n)| n as f64 * n as f64)
+            .sum::<f64>()
+            .sqrt()
+    };
+
+    // Both vectors are sorted by term, so the dot product is computed by merging them.
+    let mut dot = 0.0;
+    let (mut a_iter, mut b_iter) = (a.iter().peekable(), b.iter().peekable());
+    while let (Some((a_term, a_count)), Some((b_term, b_count))) = (a_iter.peek(), b_iter.peek()) {
+        match a_term.cmp(b_term) {
+            Ordering::Less => {
+                a_iter.next();
+            }
+            Ordering::Greater => {
+                b_iter.next();
+            }
+            Ordering::Equal => {
+                dot += **a_count as f64 * **b_count as f64;
+                a_iter.next();
+                b_iter.next();
+            }
+        }
+    }
+
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{cosine, AvlStorage, TermVector};
+    use crate::{
+        health::Inconsistency, rank::TermStats, storage_metrics::StorageMetrics, tokenise::Token,
+        PathCase,
+    };
+
+    fn paths(storage: &AvlStorage, word: &str) -> Vec<String> {
+        storage
+            .get(word)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|(path, _)| path.to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn case_insensitive_paths_share_a_key() {
+        let mut storage = AvlStorage::new();
+        storage.set_path_case(PathCase::Insensitive);
+
+        storage.insert(Path::new("/docs/Foo.txt"), Token::new("one"), 0);
+        storage.insert(Path::new("/docs/foo.txt"), Token::new("two"), 0);
+
+        assert_eq!(paths(&storage, "one"), ["/docs/Foo.txt"]);
+        assert_eq!(paths(&storage, "two"), ["/docs/Foo.txt"]);
+
+        storage.purge(Path::new("/DOCS/FOO.TXT"));
+
+        assert!(paths(&storage, "one").is_empty());
+        assert!(paths(&storage, "two").is_empty());
+    }
+
+    #[test]
+    fn case_sensitive_paths_are_distinct() {
+        let mut storage = AvlStorage::new();
+        storage.set_path_case(PathCase::Sensitive);
+
+        storage.insert(Path::new("/docs/Foo.txt"), Token::new("one"), 0);
+        storage.insert(Path::new("/docs/foo.txt"), Token::new("one"), 0);
+        storage.purge(Path::new("/docs/foo.txt"));
+
+        assert_eq!(paths(&storage, "one"), ["/docs/Foo.txt"]);
+    }
+
+    #[test]
+    fn similar_files_are_ranked_by_cosine() {
+        let storage = AvlStorage::new();
+        let insert = |path: &str, words: &[&str]| {
+            words
+                .iter()
+                .for_each(|&word| storage.insert(Path::new(path), Token::new(word), 0))
+        };
+
+        insert("/a", &["x", "y", "z"]);
+        insert("/b", &["x", "y", "z"]);
+        insert("/c", &["x", "w"]);
+        insert("/d", &["w"]);
+
+        let similar = storage
+            .similar(Path::new("/a"), 10)
+            .into_iter()
+            .map(|(path, score)| (path.to_string_lossy().into_owned(), (score * 100.0).round()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(similar, [("/b".to_owned(), 100.0), ("/c".to_owned(), 41.0)]);
+    }
+
+    #[test]
+    fn cosine_doesnt_overflow_on_large_counts() {
+        let vector = TermVector::new()
+            .insert("x".into(), u64::MAX / 2)
+            .insert("y".into(), 1 << 40);
+
+        assert!((cosine(&vector, &vector) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn purge_dir_removes_nested_paths_only() {
+        let storage = AvlStorage::new();
+
+        storage.insert(Path::new("/a/b/one.txt"), Token::new("word"), 0);
+        storage.insert(Path::new("/a/b/c/two.txt"), Token::new("word"), 0);
+        storage.insert(Path::new("/a/bc/three.txt"), Token::new("word"), 0);
+        storage.purge_dir(Path::new("/a/b"));
+
+        assert_eq!(paths(&storage, "word"), ["/a/bc/three.txt"]);
+    }
+
+    #[test]
+    fn verify_reports_drift_between_trees() {
+        let storage = AvlStorage::new();
+
+        storage.insert(Path::new("/a"), Token::new("x"), 0);
+        storage.insert(Path::new("/a"), Token::new("y"), 0);
+        storage.insert(Path::new("/b"), Token::new("x"), 0);
+        storage.purge(Path::new("/b"));
+
+        let report = storage.verify();
+
+        assert!(report.is_consistent());
+        assert_eq!((report.terms, report.postings, report.files), (2, 2, 1));
+
+        storage.file_words.update(Path::new("/a"), |terms| {
+            terms.remove("y").insert("z".into(), 1)
+        });
+
+        let path = Path::new("/a").to_path_buf();
+        assert_eq!(
+            storage.verify().inconsistencies,
+            [
+                Inconsistency::MissingFromTermVector {
+                    term: "y".to_owned(),
+                    path: path.clone()
+                },
+                Inconsistency::MissingPosting {
+                    term: "z".to_owned(),
+                    path
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn terms_are_paged_in_order() {
+        let storage = AvlStorage::new();
+
+        for &word in &["d", "b", "a", "c", "b"] {
+            storage.insert(Path::new("/a"), Token::new(word), 0);
+        }
+        storage.insert(Path::new("/b"), Token::new("b"), 0);
+        storage.insert(Path::new("/b"), Token::new("e"), 0);
+        storage.purge(Path::new("/b"));
+        storage.insert(Path::new("/c"), Token::new("b"), 0);
+
+        let page = |after, limit| {
+            storage
+                .terms(after, limit)
+                .into_iter()
+                .map(|(term, files)| (term.to_string(), files))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(page(None, 2), [("a".to_owned(), 1), ("b".to_owned(), 2)]);
+        assert_eq!(
+            page(Some("b"), 5),
+            [("c".to_owned(), 1), ("d".to_owned(), 1)]
+        );
+        assert_eq!(page(Some("bb"), 1), [("c".to_owned(), 1)]);
+        assert!(page(Some("d"), 5).is_empty());
+    }
+
+    #[test]
+    fn capped_terms_keep_membership_only() {
+        let mut storage = AvlStorage::new();
+        storage.set_posting_cap(Some(2));
+
+        let token = |word: &str, offset| Token::with_offset_at(word, offset);
+
+        storage.insert(Path::new("/a"), token("info", 0), 1);
+        storage.insert(Path::new("/a"), token("rare", 5), 2);
+        storage.insert(Path::new("/b"), token("info", 0), 1);
+        storage.insert(Path::new("/b"), token("info", 10), 2);
+
+        assert_eq!(storage.capped_terms(), ["info"]);
+        assert!(storage.has_capped_terms(Path::new("/b")));
+        assert_eq!(paths(&storage, "info"), ["/a", "/b"]);
+        assert!(storage.verify().is_consistent());
+
+        storage.remove(Path::new("/b"), &token("info", 0));
+
+        assert_eq!(paths(&storage, "info"), ["/a", "/b"]);
+
+        storage.remove(Path::new("/b"), &token("info", 10));
+
+        assert_eq!(paths(&storage, "info"), ["/a"]);
+        assert!(storage.verify().is_consistent());
+    }
+
+    #[test]
+    fn term_stats_follow_inserts_and_purges() {
+        let storage = AvlStorage::new();
+        let token = |word: &str, offset| Token::with_offset_at(word, offset);
+        let stats = |doc_count, total_occurrences| {
+            Some(TermStats {
+                doc_count,
+                total_occurrences,
+            })
+        };
+
+        storage.insert(Path::new("/a"), token("fox", 0), 1);
+        storage.insert(Path::new("/a"), token("fox", 4), 2);
+        storage.insert(Path::new("/b"), token("fox", 0), 1);
+        storage.insert(Path::new("/b"), token("dog", 4), 2);
+
+        assert_eq!(storage.term_stats("fox"), stats(2, 3));
+        assert_eq!(storage.term_stats("dog"), stats(1, 1));
+        assert_eq!(storage.term_stats("cat"), None);
+
+        storage.remove(Path::new("/a"), &token("fox", 4));
+        assert_eq!(storage.term_stats("fox"), stats(2, 2));
+
+        storage.purge(Path::new("/b"));
+        assert_eq!(storage.term_stats("fox"), stats(1, 1));
+        assert_eq!(storage.term_stats("dog"), None);
+    }
+
+    #[test]
+    fn storage_events_are_reported_to_the_metrics_sink() {
+        #[derive(Default)]
+        struct Events(Mutex<Vec<String>>);
+
+        impl StorageMetrics for Events {
+            fn on_insert(&self, term_len: usize, postings_len: u64) {
+                let event = format!("insert {} {}", term_len, postings_len);
+                self.0.lock().unwrap().push(event);
+            }
+
+            fn on_purge(&self, terms: usize) {
+                self.0.lock().unwrap().push(format!("purge {}", terms));
+            }
+
+            fn on_query(&self, term_len: usize, files: usize) {
+                let event = format!("query {} {}", term_len, files);
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        let events = Arc::new(Events::default());
+        let mut storage = AvlStorage::new();
+        storage.set_metrics(Some(Arc::clone(&events) as _));
+
+        storage.insert(Path::new("/a"), Token::with_offset_at("fox", 0), 1);
+        storage.insert(Path::new("/a"), Token::with_offset_at("fox", 4), 2);
+        storage.insert(Path::new("/a"), Token::with_offset_at("jumps", 8), 3);
+        storage.get("fox");
+        storage.purge(Path::new("/a"));
+        storage.purge(Path::new("/a"));
+        storage.get("fox");
+
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            [
+                "insert 3 1",
+                "insert 3 2",
+                "insert 5 1",
+                "query 3 1",
+                "purge 2",
+                "query 3 0"
+            ]
+        );
     }
 }
@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::hash;
+
+/// Bloom filter over a set of terms.
+///
+/// A term that was never inserted is reported as possibly present with about the configured false
+/// positive rate, while an inserted one is always reported as present. Terms can't be removed, so
+/// the rate grows as the terms that are no longer indexed accumulate.
+pub(crate) struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Create a filter sized for the given number of terms and false positive rate.
+    pub fn new(expected_terms: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let expected_terms = expected_terms.max(1) as f64;
+        let false_positive_rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let bits = (-expected_terms * false_positive_rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let words = bits.div_ceil(64).max(1);
+        let hashes = ((words * 64) as f64 / expected_terms * ln2).round() as u32;
+
+        Self {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            hashes: hashes.max(1),
+        }
+    }
+
+    pub fn insert(&self, term: &str) {
+        for bit in self.bits_of(term) {
+            self.bits[bit / 64].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// Check whether the term might have been inserted.
+    pub fn may_contain(&self, term: &str) -> bool {
+        self.bits_of(term)
+            .all(|bit| self.bits[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+
+    /// Positions of the bits of a term, derived from two halves of its hash.
+    fn bits_of(&self, term: &str) -> impl Iterator<Item = usize> {
+        let hash = hash::fnv1a(term.as_bytes());
+        let (a, b) = (hash, hash.rotate_left(32) | 1);
+        let len = (self.bits.len() * 64) as u64;
+
+        (0..u64::from(self.hashes)).map(move |i| (a.wrapping_add(i.wrapping_mul(b)) % len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BloomFilter;
+
+    #[test]
+    fn inserted_terms_are_found_and_others_mostly_rejected() {
+        let filter = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            filter.insert(&format!("term{}", i));
+        }
+
+        assert!((0..1000).all(|i| filter.may_contain(&format!("term{}", i))));
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.may_contain(&format!("other{}", i)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+}
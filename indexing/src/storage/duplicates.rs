@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    file_system::FileId,
+    storage::avl::{Avl, MvccAvl},
+    PathCase,
+};
+
+/// Registry of the indexed files that have identical contents.
+///
+/// The contents shared by several files are only indexed under one of them, the original. The
+/// other files, duplicates, refer to the original and share its index entries.
+///
+/// Like in [`super::AvlStorage`], files are identified by a path key built according to the
+/// configured [`PathCase`].
+pub(crate) struct Duplicates {
+    path_case: PathCase,
+    by_hash: MvccAvl<u64, PathBuf>,
+    by_file: MvccAvl<FileId, PathBuf>,
+    originals: MvccAvl<PathBuf, Original>,
+    duplicates: MvccAvl<PathBuf, PathBuf>,
+}
+
+/// Contents hash, identity and duplicates of an original file.
+#[derive(Clone)]
+struct Original {
+    hash: u64,
+    file: Option<FileId>,
+    // Maps the path keys of the duplicates to their paths as spelled when indexed.
+    duplicates: Avl<PathBuf, PathBuf>,
+}
+
+/// Immutable version of the originals of a [`Duplicates`] registry, see [`Duplicates::snapshot`].
+#[derive(Clone)]
+pub(crate) struct DuplicatesSnapshot {
+    path_case: PathCase,
+    originals: Avl<PathBuf, Original>,
+}
+
+impl DuplicatesSnapshot {
+    /// Paths of the duplicates of the given original file.
+    pub fn duplicates_of(&self, path: &Path) -> Vec<PathBuf> {
+        self.originals
+            .get(self.path_case.key(path).as_ref())
+            .map(|original| {
+                original
+                    .duplicates
+                    .iter()
+                    .map(|(_, path)| path.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Duplicates {
+    pub fn new(path_case: PathCase) -> Self {
+        Self {
+            path_case,
+            by_hash: MvccAvl::new(),
+            by_file: MvccAvl::new(),
+            originals: MvccAvl::new(),
+            duplicates: MvccAvl::new(),
+        }
+    }
+
+    /// Set the case sensitivity of path keys.
+    ///
+    /// Must be set before anything is inserted in the registry.
+    pub fn set_path_case(&mut self, path_case: PathCase) {
+        self.path_case = path_case;
+    }
+
+    /// Path key of the original file with contents of the given hash, if there is one.
+    pub fn original(&self, hash: u64) -> Option<PathBuf> {
+        self.by_hash.snapshot().get(&hash).map(|key| key.clone())
+    }
+
+    /// Path key of the original file with the given identity, i.e. a hard link to the file.
+    pub fn original_by_file(&self, file: FileId) -> Option<PathBuf> {
+        self.by_file.snapshot().get(&file).map(|key| key.clone())
+    }
+
+    /// Path key of the original file the given file is a duplicate of.
+    pub fn original_of(&self, path: &Path) -> Option<PathBuf> {
+        self.duplicates
+            .snapshot()
+            .get(self.path_case.key(path).as_ref())
+            .map(|key| key.clone())
+    }
+
+    /// Paths of the duplicates of the given original file.
+    pub fn duplicates_of(&self, path: &Path) -> Vec<PathBuf> {
+        self.snapshot().duplicates_of(path)
+    }
+
+    /// Take an immutable version of the originals and their duplicates.
+    pub fn snapshot(&self) -> DuplicatesSnapshot {
+        DuplicatesSnapshot {
+            path_case: self.path_case,
+            originals: self.originals.snapshot(),
+        }
+    }
+
+    /// Register an indexed file with contents of the given hash, and identity if it's known, as an
+    /// original.
+    pub fn insert_original(&self, path: &Path, hash: u64, file: Option<FileId>) {
+        let key = self.path_case.key(path).into_owned();
+
+        self.by_hash.insert(hash, key.clone());
+        if let Some(file) = file {
+            self.by_file.insert(file, key.clone());
+        }
+        self.originals.insert(
+            key,
+            Original {
+                hash,
+                file,
+                duplicates: Avl::new(),
+            },
+        );
+    }
+
+    /// Register a file as a duplicate of the original with the given path key.
+    pub fn insert_duplicate(&self, path: &Path, original: &Path) {
+        let key = self.path_case.key(path).into_owned();
+
+        self.originals.update(original, |original| Original {
+            hash: original.hash,
+            file: original.file,
+            duplicates: original.duplicates.insert(key.clone(), path.to_owned()),
+        });
+        self.duplicates.insert(key, original.to_owned());
+    }
+
+    /// Remove the given file from the registry.
+    ///
+    /// If the file is an original, its duplicates are removed as well and their paths are returned,
+    /// as they no longer have any index entries.
+    pub fn remove(&self, path: &Path) -> Vec<PathBuf> {
+        let key = self.path_case.key(path);
+
+        if let Some(original) = self.original_of(&key) {
+            self.duplicates.remove(key.as_ref());
+            self.originals.update(&original, |original| Original {
+                hash: original.hash,
+                file: original.file,
+                duplicates: original.duplicates.remove(key.as_ref()),
+            });
+            return Vec::new();
+        }
+
+        let original = match self.originals.snapshot().get(key.as_ref()) {
+            Some(original) => original.clone(),
+            None => return Vec::new(),
+        };
+
+        self.originals.remove(key.as_ref());
+        if self.original(original.hash).as_deref() == Some(key.as_ref()) {
+            self.by_hash.remove(&original.hash);
+        }
+        if let Some(file) = original.file {
+            if self.original_by_file(file).as_deref() == Some(key.as_ref()) {
+                self.by_file.remove(&file);
+            }
+        }
+
+        original
+            .duplicates
+            .iter()
+            .map(|(key, path)| {
+                self.duplicates.remove(key);
+                path.clone()
+            })
+            .collect()
+    }
+
+    /// Path keys of the registered files located under the given path, duplicates come first.
+    pub fn paths_under(&self, path: &Path) -> Vec<PathBuf> {
+        let prefix = self.path_case.key(path);
+        let duplicates = self.duplicates.snapshot();
+        let originals = self.originals.snapshot();
+
+        let duplicates = duplicates
+            .iter_from(prefix.as_ref())
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix));
+        let originals = originals
+            .iter_from(prefix.as_ref())
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix));
+
+        duplicates.chain(originals).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Duplicates;
+    use crate::{file_system::FileId, PathCase};
+
+    #[test]
+    fn removing_original_releases_duplicates() {
+        let duplicates = Duplicates::new(PathCase::Sensitive);
+        let file = FileId {
+            device: 1,
+            inode: 2,
+        };
+        duplicates.insert_original(Path::new("/a"), 1, Some(file));
+        duplicates.insert_duplicate(Path::new("/b"), Path::new("/a"));
+        duplicates.insert_duplicate(Path::new("/c"), Path::new("/a"));
+
+        assert_eq!(duplicates.original(1), Some(Path::new("/a").to_owned()));
+        assert_eq!(
+            duplicates.original_by_file(file),
+            Some(Path::new("/a").to_owned())
+        );
+        assert_eq!(
+            duplicates.original_of(Path::new("/b")),
+            Some(Path::new("/a").to_owned())
+        );
+
+        assert!(duplicates.remove(Path::new("/b")).is_empty());
+        assert_eq!(duplicates.duplicates_of(Path::new("/a")), [Path::new("/c")]);
+
+        assert_eq!(duplicates.remove(Path::new("/a")), [Path::new("/c")]);
+        assert_eq!(duplicates.original(1), None);
+        assert_eq!(duplicates.original_by_file(file), None);
+        assert_eq!(duplicates.original_of(Path::new("/c")), None);
+    }
+}
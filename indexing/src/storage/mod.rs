@@ -1,44 +1,319 @@
 //! This module defines building blocks for the index storage.
 mod avl;
 mod avl_storage;
+mod bloom;
+mod duplicates;
 
-use std::path::PathBuf;
+use std::{
+    cmp,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-pub(crate) use avl::{Avl, AvlSet, MvccAvl};
-pub(crate) use avl_storage::AvlStorage;
+pub(crate) use avl::{Avl, MvccAvl, ValueRef};
+pub(crate) use avl_storage::{AvlStorage, StorageSnapshot};
+pub(crate) use bloom::BloomFilter;
+pub(crate) use duplicates::{Duplicates, DuplicatesSnapshot};
 
-use crate::intern::InternRef;
+use crate::{hash, intern::InternRef};
+
+/// Offsets of the occurrences of a term in a file, mapped to their positions in the file: the
+/// ordinals of the kept tokens, including the gaps left by the dropped ones (see
+/// [`crate::tokenise::Token::position_increment`]).
+pub(crate) type Postings = Avl<u64, u64>;
 
 #[derive(Clone)]
 pub(crate) struct IndexEntryList {
-    pub entries: Avl<InternRef<PathBuf>, AvlSet<u64>>,
+    pub entries: Avl<InternRef<PathBuf>, Postings>,
+
+    /// Total number of offsets in the list.
+    pub occurrences: u64,
+
+    /// Number of paths in the list.
+    pub documents: usize,
+
+    /// Whether only the paths the term occurs in are kept, with empty sets of offsets, see
+    /// [`IndexEntryList::to_membership_only`].
+    pub membership_only: bool,
 }
 
 impl IndexEntryList {
     pub fn new() -> Self {
         Self {
             entries: Avl::new(),
+            occurrences: 0,
+            documents: 0,
+            membership_only: false,
         }
     }
 
-    pub fn append(&self, path: InternRef<PathBuf>, offset: u64) -> Self {
+    pub fn append(&self, path: InternRef<PathBuf>, offset: u64, position: u64) -> Self {
+        if self.membership_only {
+            return self.append_path(path);
+        }
+
+        let added = !self.contains(&path, offset);
+        let documents = self.documents + !self.has_path(&path) as usize;
         Self {
             entries: self.entries.upsert(path, |set| {
-                set.as_deref()
-                    .cloned()
-                    .unwrap_or_else(AvlSet::new)
-                    .insert(offset, ())
+                set.cloned()
+                    .unwrap_or_else(Avl::new)
+                    .insert(offset, position)
             }),
+            occurrences: self.occurrences + added as u64,
+            documents,
+            membership_only: false,
         }
     }
 
+    /// Add the path the term occurs in without the offset of the occurrence.
+    ///
+    /// Phrases can't be matched in the path afterwards, unless its other occurrences have offsets.
+    pub fn append_path(&self, path: InternRef<PathBuf>) -> Self {
+        Self {
+            documents: self.documents + !self.has_path(&path) as usize,
+            entries: self
+                .entries
+                .upsert(path, |set| set.cloned().unwrap_or_else(Avl::new)),
+            ..self.clone()
+        }
+    }
+
+    /// Replace the postings of the given path, dropping the path if they are empty.
+    pub fn set_postings(&self, path: InternRef<PathBuf>, offsets: Postings) -> Self {
+        if offsets.iter().next().is_none() {
+            self.remove(&path)
+        } else {
+            Self {
+                occurrences: self.occurrences - self.offsets_count(&path)
+                    + offsets.iter().count() as u64,
+                documents: self.documents + !self.has_path(&path) as usize,
+                entries: self.entries.insert(path, offsets),
+                membership_only: self.membership_only,
+            }
+        }
+    }
+
+    /// Drop the offsets of every path, keeping only the paths themselves.
+    ///
+    /// This is used for the terms that occur too often for their offsets to be worth keeping. Once
+    /// the list is membership only, the offsets of new occurrences are not kept either.
+    pub fn to_membership_only(&self) -> Self {
+        Self {
+            entries: self.entries.iter().fold(Avl::new(), |entries, (path, _)| {
+                entries.insert(path.clone(), Avl::new())
+            }),
+            occurrences: 0,
+            documents: self.documents,
+            membership_only: true,
+        }
+    }
+
+    /// Remove a single occurrence of the term, dropping the path once it has no occurrences left.
+    pub fn remove_offset(&self, path: &InternRef<PathBuf>, offset: u64) -> Self {
+        match self.entries.get(path) {
+            Some(offsets) => self.set_postings(path.clone(), offsets.remove(&offset)),
+            None => self.clone(),
+        }
+    }
+
+    /// Check whether the term occurs in the given path at the given offset.
+    pub fn contains(&self, path: &InternRef<PathBuf>, offset: u64) -> bool {
+        self.entries
+            .get(path)
+            .is_some_and(|offsets| offsets.get(&offset).is_some())
+    }
+
     pub fn remove(&self, path: &InternRef<PathBuf>) -> Self {
         Self {
             entries: self.entries.remove(path),
+            occurrences: self.occurrences - self.offsets_count(path),
+            documents: self.documents - self.has_path(path) as usize,
+            membership_only: self.membership_only,
         }
     }
 
-    pub fn iter(&self) -> avl::Iter<'_, InternRef<PathBuf>, AvlSet<u64>> {
+    pub fn iter(&self) -> avl::Iter<'_, InternRef<PathBuf>, Postings> {
         self.entries.iter()
     }
+
+    /// Iterate over the entries of the paths under the given directory, without visiting the
+    /// others.
+    ///
+    /// Paths are ordered component by component, so the ones under a directory follow each other.
+    pub fn iter_under<'a>(
+        &'a self,
+        dir: &'a Path,
+    ) -> impl Iterator<Item = (&'a InternRef<PathBuf>, &'a Postings)> + 'a {
+        self.entries
+            .iter_from(dir)
+            .take_while(move |(path, _)| path.starts_with(dir))
+    }
+
+    fn has_path(&self, path: &InternRef<PathBuf>) -> bool {
+        self.entries.get(path).is_some()
+    }
+
+    fn offsets_count(&self, path: &InternRef<PathBuf>) -> u64 {
+        self.entries
+            .get(path)
+            .map_or(0, |offsets| offsets.iter().count() as u64)
+    }
+}
+
+/// Number of bytes at the end of the indexed contents that are checked to detect appends.
+const TAIL_CHECK_LEN: u64 = 64;
+
+/// Per-file bookkeeping of the indexed contents.
+#[derive(Clone)]
+pub(crate) struct FileMeta {
+    /// Number of bytes indexed.
+    pub len: u64,
+
+    /// Offset of the last (possibly incomplete) line of the indexed contents.
+    pub last_line: u64,
+
+    /// Hash of the whole indexed contents.
+    pub content_hash: u64,
+
+    /// Hash of the indexed contents from [`FileMeta::check_start`] to the end.
+    pub tail_hash: u64,
+
+    /// Indexed contents lines by the line offset.
+    pub lines: Avl<u64, LineMeta>,
+
+    /// Position reached at the end of the indexed contents, see [`LineMeta::position`].
+    pub end_position: u64,
+
+    /// Modification time of the file when it was indexed, if known.
+    pub modified: Option<SystemTime>,
+
+    /// Time the file was last indexed at.
+    pub indexed_at: SystemTime,
+
+    /// Whether the file was indexed without the offsets of its terms, see
+    /// [`crate::budget::IndexBudget::with_memory_degradation`].
+    pub membership_only: bool,
+
+    /// Version of the analyser the file was indexed with, see [`crate::analyse::Analyser::with_version`].
+    pub analyser_version: u32,
+}
+
+impl FileMeta {
+    /// Build the metadata after indexing the given contents of a file, whose tokens reached the
+    /// given `positions`.
+    pub fn new(contents: &[u8], positions: &LinePositions) -> Self {
+        Self {
+            len: 0,
+            last_line: 0,
+            content_hash: hash::fnv1a(&[]),
+            tail_hash: hash::fnv1a(&[]),
+            lines: Avl::new(),
+            end_position: 0,
+            modified: None,
+            indexed_at: SystemTime::now(),
+            membership_only: false,
+            analyser_version: 0,
+        }
+        .appended(contents, 0, positions)
+    }
+
+    /// Set the modification time of the file when it was indexed.
+    pub fn with_modified(mut self, modified: Option<SystemTime>) -> Self {
+        self.modified = modified;
+        self
+    }
+
+    /// Set whether the file was indexed without the offsets of its terms.
+    pub fn with_membership_only(mut self, membership_only: bool) -> Self {
+        self.membership_only = membership_only;
+        self
+    }
+
+    /// Set the version of the analyser the file was indexed with.
+    pub fn with_analyser_version(mut self, analyser_version: u32) -> Self {
+        self.analyser_version = analyser_version;
+        self
+    }
+
+    /// Build the metadata after indexing an append to the file.
+    ///
+    /// `contents` are located at `base` offset of the file and extend to its end, `base` must not be
+    /// greater than [`FileMeta::check_start`]. `positions` are the ones reached by the tokens from
+    /// the last line of the file on.
+    pub fn appended(&self, contents: &[u8], base: u64, positions: &LinePositions) -> Self {
+        // The last line is hashed again, as the append might have continued it.
+        let lines = split_lines(&contents[(self.last_line - base) as usize..])
+            .enumerate()
+            .fold(
+                self.lines.remove(&self.last_line),
+                |lines, (i, (offset, line))| {
+                    let line = LineMeta {
+                        hash: hash::fnv1a(line),
+                        position: positions.lines.get(i).copied().unwrap_or(positions.end),
+                    };
+                    lines.insert(self.last_line + offset, line)
+                },
+            );
+        let last_line = contents
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(self.last_line, |newline| base + newline as u64 + 1);
+
+        let mut meta = Self {
+            len: base + contents.len() as u64,
+            last_line,
+            content_hash: hash::fnv1a_extend(
+                self.content_hash,
+                &contents[(self.len - base) as usize..],
+            ),
+            tail_hash: 0,
+            lines,
+            end_position: positions.end,
+            modified: self.modified,
+            indexed_at: SystemTime::now(),
+            membership_only: self.membership_only,
+            analyser_version: self.analyser_version,
+        };
+        meta.tail_hash = hash::fnv1a(&contents[(meta.check_start() - base) as usize..]);
+        meta
+    }
+
+    /// Offset from which the indexed contents are checked to be unchanged before indexing an append.
+    ///
+    /// This always covers the last line, as it is indexed again when the file is appended to.
+    pub fn check_start(&self) -> u64 {
+        cmp::min(self.last_line, self.len.saturating_sub(TAIL_CHECK_LEN))
+    }
+}
+
+/// Bookkeeping of an indexed line, see [`FileMeta::lines`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct LineMeta {
+    /// Hash of the line, including its terminator.
+    pub hash: u64,
+
+    /// Position reached before the line: the position of the last token kept before it, plus the
+    /// increments of the tokens dropped since. The tokens of the line are positioned from it, so that
+    /// the line can be indexed again without analysing the previous ones.
+    pub position: u64,
+}
+
+/// Positions reached before every line of some contents and at their end, see
+/// [`LineMeta::position`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LinePositions {
+    pub lines: Vec<u64>,
+    pub end: u64,
+}
+
+/// Split the contents into lines (including the line terminator) along with their offsets.
+pub(crate) fn split_lines(contents: &[u8]) -> impl Iterator<Item = (u64, &[u8])> {
+    contents
+        .split_inclusive(|&b| b == b'\n')
+        .scan(0, |offset, line| {
+            let line_offset = *offset;
+            *offset += line.len() as u64;
+            Some((line_offset, line))
+        })
 }
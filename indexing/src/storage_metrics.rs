@@ -0,0 +1,30 @@
+//! This module defines the hooks through which the index storage reports its activity, see
+//! [`crate::Indexer::with_storage_metrics`].
+//!
+//! A [`StorageMetrics`] sink is called by the storage on every token insertion, file purge and term
+//! lookup, so that embedders can forward storage-level telemetry (e.g. posting list growth or the
+//! share of lookups that find nothing) into their own monitoring systems. Every method has an
+//! empty default implementation, so a sink only implements the events it's interested in.
+
+/// Sink of the storage events, see the module documentation.
+///
+/// The methods are called on the indexing and querying threads while the storage is being modified
+/// or read, so they should return quickly, e.g. by bumping atomic counters.
+pub trait StorageMetrics: Send + Sync {
+    /// A token was inserted: the length of its term in bytes, and the number of occurrences of the
+    /// term across all the files after the insertion.
+    fn on_insert(&self, term_len: usize, postings_len: u64) {
+        let _ = (term_len, postings_len);
+    }
+
+    /// A file was purged, along with the number of the distinct terms it had.
+    fn on_purge(&self, terms: usize) {
+        let _ = terms;
+    }
+
+    /// A term was looked up: the length of the term in bytes, and the number of the files it was
+    /// found in, 0 if it wasn't found.
+    fn on_query(&self, term_len: usize, files: usize) {
+        let _ = (term_len, files);
+    }
+}
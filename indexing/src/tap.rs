@@ -0,0 +1,106 @@
+//! This module defines the debug tap of the token stream, see [`crate::Indexer::set_token_tap`].
+//!
+//! A [`TokenTap`] passes every token stored for the files matching a glob pattern to a
+//! [`TokenSink`], after normalisation. Taps can be set and removed while the indexer runs, so that
+//! the behaviour of an analyser can be looked into on live data without rebuilding the service.
+//! There are two predefined sinks:
+//!  * any `Fn(&Path, &Token, Target)` closure
+//!  * [`FileSink`] - writes a line per token to a file
+
+use std::{
+    fmt,
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use globset::{Glob, GlobMatcher};
+use tracing::warn;
+
+use crate::{dry_run::Target, tokenise::Token, Result};
+
+/// Destination of the tokens passed through a [`TokenTap`].
+///
+/// Sinks are called while the file is being indexed, so they should return quickly.
+pub trait TokenSink: Send + Sync {
+    /// Receive a normalised token of the file at `path`, along with the index it's stored in.
+    fn token(&self, path: &Path, token: &Token, target: Target);
+}
+
+impl<F> TokenSink for F
+where
+    F: Fn(&Path, &Token, Target) + Send + Sync,
+{
+    fn token(&self, path: &Path, token: &Token, target: Target) {
+        self(path, token, target)
+    }
+}
+
+/// Sink writing the tokens to a file, one tab separated line of path, offset, term and index per
+/// token.
+pub struct FileSink {
+    file: Mutex<LineWriter<File>>,
+}
+
+impl FileSink {
+    /// Create the file, truncating it if it exists.
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: Mutex::new(LineWriter::new(File::create(path)?)),
+        })
+    }
+}
+
+impl TokenSink for FileSink {
+    fn token(&self, path: &Path, token: &Token, target: Target) {
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(
+            file,
+            "{}\t{}\t{}\t{}",
+            path.display(),
+            token.offset,
+            token.value,
+            target
+        ) {
+            warn!(error = %e, "failed to write a tapped token");
+        }
+    }
+}
+
+/// Tap of the tokens stored for the files matching a glob pattern, see the module documentation.
+#[derive(Clone)]
+pub struct TokenTap {
+    pattern: GlobMatcher,
+    sink: Arc<dyn TokenSink>,
+}
+
+impl TokenTap {
+    /// Pass the tokens of the files whose paths match `pattern` (e.g. `**/*.md`) to `sink`.
+    pub fn new<S>(pattern: &str, sink: S) -> Result<Self>
+    where
+        S: 'static + TokenSink,
+    {
+        Ok(Self {
+            pattern: Glob::new(pattern)?.compile_matcher(),
+            sink: Arc::new(sink),
+        })
+    }
+
+    /// Check whether the tokens of the given file are tapped.
+    pub fn matches(&self, path: &Path) -> bool {
+        self.pattern.is_match(path)
+    }
+
+    pub(crate) fn token(&self, path: &Path, token: &Token, target: Target) {
+        self.sink.token(path, token, target);
+    }
+}
+
+impl fmt::Debug for TokenTap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenTap")
+            .field("pattern", &self.pattern.glob().glob())
+            .finish()
+    }
+}
@@ -0,0 +1,48 @@
+//! This module provides support for testing the integrations of a [`LiveIndexer`], it's only
+//! available with the `test-util` feature.
+//!
+//! Real file watcher events are debounced and delivered asynchronously, which makes the tests relying
+//! on them slow and flaky. Instead, synthetic events can be injected with [`inject_event`], and
+//! [`flush`] waits for the resulting indexing actions to be performed, so that the index can be
+//! checked right after.
+//!
+//! Combined with a [`crate::file_system::MemoryFileSystem`] (see [`crate::Indexer::with_file_system`])
+//! the tests don't have to touch the disk at all.
+
+use std::path::PathBuf;
+
+use notify::DebouncedEvent;
+
+use crate::LiveIndexer;
+
+/// Synthetic file watcher event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    Create(PathBuf),
+    Write(PathBuf),
+    /// A file or a directory was removed.
+    Remove(PathBuf),
+    /// A file or a directory was renamed, from the first path to the second one.
+    Rename(PathBuf, PathBuf),
+}
+
+/// Make the live indexer handle the event as if it was sent by its file watcher.
+///
+/// The event is recorded in the audit log, if it's enabled, and the resulting indexing actions are
+/// queued in order with the ones of real events and API calls.
+pub fn inject_event(indexer: &LiveIndexer, event: WatchEvent) {
+    indexer.inject(match event {
+        WatchEvent::Create(path) => DebouncedEvent::Create(path),
+        WatchEvent::Write(path) => DebouncedEvent::Write(path),
+        WatchEvent::Remove(path) => DebouncedEvent::Remove(path),
+        WatchEvent::Rename(from, to) => DebouncedEvent::Rename(from, to),
+    });
+}
+
+/// Wait until the live indexer performs all the indexing actions queued so far.
+///
+/// Files that were modified while being indexed are waited for until they settle, but the retries of
+/// the files that failed with a transient error are not, see [`crate::ErrorPolicy`].
+pub fn flush(indexer: &LiveIndexer) {
+    indexer.flush();
+}
@@ -10,33 +10,67 @@
 use std::{
     hash::Hash,
     io::{self, BufRead},
+    ops::Range,
     slice, str,
 };
 
 use regex::Regex;
 
-/// Token specifies a parsed value and its original offset in the file.
+use crate::CompactString;
+
+/// Token specifies a parsed value and the original slice of the file it was parsed from.
 ///
 /// The length of the value and its representation may differ from the original found in the file due
-/// to applied normalisers.
+/// to applied normalisers, so normalisers must keep `offset` and `len` intact for `offset..offset + len`
+/// to delimit the original surface form.
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Token {
     /// Token's string value.
-    pub value: String,
+    pub value: CompactString,
 
     /// Byte offset in the source text.
     pub offset: u64,
+
+    /// Byte length of the token in the source text.
+    pub len: u64,
+
+    /// Number of positions between the previous token and this one, 1 for adjacent tokens.
+    ///
+    /// Tokens dropped by the normalisers add their increments to the next kept token, unless disabled
+    /// with [`crate::analyse::Analyser::with_position_gaps`], so that phrases can tell there was a gap.
+    pub position_increment: u32,
 }
 
 impl Token {
     /// Create a new token with the given string value and an offset of zero.
-    pub fn new(value: String) -> Self {
-        Self { value, offset: 0 }
+    pub fn new<S>(value: S) -> Self
+    where
+        S: Into<CompactString>,
+    {
+        Self::with_offset_at(value, 0)
     }
 
     /// Create a new token with the given string value at the specified offset.
-    pub fn with_offset_at(value: String, offset: u64) -> Self {
-        Self { value, offset }
+    ///
+    /// The value is assumed to be the source text of the token.
+    pub fn with_offset_at<S>(value: S, offset: u64) -> Self
+    where
+        S: Into<CompactString>,
+    {
+        let value = value.into();
+        let len = value.len() as u64;
+
+        Self {
+            value,
+            offset,
+            len,
+            position_increment: 1,
+        }
+    }
+
+    /// Byte range of the token in the source text.
+    pub fn source_range(&self) -> Range<u64> {
+        self.offset..self.offset + self.len
     }
 }
 
@@ -90,6 +124,12 @@ impl SpaceTokeniser {
     }
 }
 
+impl Default for SpaceTokeniser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Tokeniser for SpaceTokeniser {
     fn read_token(&mut self, reader: &mut dyn BufRead) -> io::Result<Option<Token>> {
         // NOTE: generally one would implement incremental reading from the `reader`.
@@ -112,8 +152,10 @@ impl Tokeniser for SpaceTokeniser {
                     // We don't have to check for UTF-8 correctness as this is a view into a `String`
                     // that was already verified to be UTF-8 correct.
                     value: str::from_utf8_unchecked(slice::from_raw_parts(word_ptr, word_len))
-                        .to_owned(),
+                        .into(),
                     offset: word_ptr.offset_from(self.input.as_ptr()) as u64,
+                    len: word_len as u64,
+                    position_increment: 1,
                 }
             };
             self.given += 1;
@@ -166,8 +208,10 @@ impl Tokeniser for RegexTokeniser {
                     // We don't have to check for UTF-8 correctness as this is a view into a `String`
                     // that was already verified to be UTF-8 correct.
                     value: str::from_utf8_unchecked(slice::from_raw_parts(word_ptr, word_len))
-                        .to_owned(),
+                        .into(),
                     offset: word_ptr.offset_from(self.input.as_ptr()) as u64,
+                    len: word_len as u64,
+                    position_increment: 1,
                 }
             };
             self.given += 1;
@@ -181,7 +225,7 @@ mod tests {
     use super::*;
 
     fn token(value: &str, offset: u64) -> Token {
-        Token::with_offset_at(value.to_owned(), offset)
+        Token::with_offset_at(value, offset)
     }
 
     #[test]
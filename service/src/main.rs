@@ -1,25 +1,132 @@
-use std::time::Instant;
+mod stdio_json;
+
+use std::{
+    env,
+    fs::File,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use color_eyre::eyre;
 use dialoguer::Input;
 use tracing::{trace, warn};
 
-use indexing::{normalise, tokenise, Indexer, LiveIndexer};
+use indexing::{
+    budget::{IndexBudget, IndexHealth},
+    cancel::CancellationToken,
+    file_system::WalkOrder,
+    normalise::{self, NormaliserOverrides},
+    path_format::PathFormat,
+    query_pool::QueryPool,
+    rank::RecencyBoost,
+    rotation::SnapshotRotation,
+    snapshot::ConflictPolicy,
+    tap::{FileSink, TokenTap},
+    tokenise, ConfigUpdate, ErrorPolicy, Indexer, LiveIndexer, LiveIndexerOptions, QueryOptions,
+    WatchGroup, WatchOptions,
+};
+
+/// Time after which the queries scanning the whole term dictionary are given up on, so that they
+/// don't stall the prompt.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
 
 fn main() -> eyre::Result<()> {
     color_eyre::install()?;
-    tracing_subscriber::fmt::try_init().map_err(|e| eyre::eyre!(e))?;
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let stdio_json = args.iter().any(|arg| arg == "--stdio-json");
+    let stop_word_index = args.iter().any(|arg| arg == "--stop-word-index");
+    let exact_case_index = args.iter().any(|arg| arg == "--exact-case-index");
+    let mut path_format = match args.iter().position(|arg| arg == "--path-format") {
+        Some(i) => args
+            .get(i + 1)
+            .ok_or_else(|| eyre::eyre!("--path-format expects a format"))?
+            .parse()?,
+        None => PathFormat::default(),
+    };
+    let max_memory = match args.iter().position(|arg| arg == "--max-memory") {
+        Some(i) => Some(
+            args.get(i + 1)
+                .ok_or_else(|| eyre::eyre!("--max-memory expects a number of bytes"))?
+                .parse::<u64>()?,
+        ),
+        None => None,
+    };
+    let worker_niceness = match args.iter().position(|arg| arg == "--worker-niceness") {
+        Some(i) => Some(
+            args.get(i + 1)
+                .ok_or_else(|| eyre::eyre!("--worker-niceness expects a niceness"))?
+                .parse::<i32>()?,
+        ),
+        None => None,
+    };
+    if stdio_json {
+        // Stdout carries the responses, see `stdio_json` module.
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .try_init()
+            .map_err(|e| eyre::eyre!(e))?;
+    } else {
+        tracing_subscriber::fmt::try_init().map_err(|e| eyre::eyre!(e))?;
+    }
 
     let indexer =
         Indexer::new(|| Box::new(tokenise::RegexTokeniser::new(r"[^\w-]+").unwrap()) as _)
             .with_normaliser(normalise::Unicode::NFC)
             .with_normaliser(normalise::LowerCase)
             .with_normaliser(normalise::StopWords::new(&["a", "the", "and", "or", "not"]));
+    // Only needed for quoted queries to find the stop words.
+    let indexer = if stop_word_index {
+        indexer.with_stop_word_index()
+    } else {
+        indexer
+    };
+    // A second copy of the postings, only kept when case-sensitive `/exact` queries are wanted.
+    let indexer = if exact_case_index {
+        indexer.with_exact_case_index()
+    } else {
+        indexer
+    };
+    let indexer = indexer
+        .with_snippets(40)
+        .with_walk_order(WalkOrder::new().with_sorted_entries())
+        .with_line_diff_updates()
+        .with_posting_cap(1_000_000)
+        .with_term_filter(1_000_000, 0.01)
+        .with_slow_query_log(Duration::from_millis(10), 100)
+        .with_mutation_history(100)
+        .with_query_metrics()
+        .with_query_frequencies()
+        .with_query_concurrency(4)
+        .with_score_modifier(RecencyBoost::new(Duration::from_secs(24 * 3600)));
+    let indexer = match max_memory {
+        Some(bytes) => indexer.with_budget(
+            IndexBudget::new()
+                .with_max_memory(bytes)
+                .with_memory_degradation(),
+        ),
+        None => indexer,
+    };
 
-    let indexer = LiveIndexer::start(indexer)?;
+    let options = LiveIndexerOptions::new()
+        .with_audit_log(10_000)
+        .with_error_policy(ErrorPolicy::new().with_retries(5, Duration::from_millis(200)));
+    let options = match worker_niceness {
+        Some(niceness) => options.with_worker_niceness(niceness),
+        None => options,
+    };
+    let indexer = LiveIndexer::start_with(indexer, options)?;
+
+    if stdio_json {
+        return stdio_json::serve(&indexer, path_format);
+    }
+
+    let mut snapshot = indexer.indexer().snapshot();
+    let query_pool = QueryPool::new(indexer.indexer(), 4);
 
     loop {
         let input: String = Input::new().interact()?;
+        let formatter = indexer.path_formatter(path_format);
 
         if let Some(command) = input.strip_prefix("/") {
             let items = command.split_whitespace().collect::<Vec<_>>();
@@ -27,7 +134,361 @@ fn main() -> eyre::Result<()> {
             match items.as_slice() {
                 [] => (),
                 ["quit", ..] => return Ok(()),
-                ["watch", paths @ ..] => paths.iter().try_for_each(|path| indexer.watch(path))?,
+                ["format", format] => match format.parse() {
+                    Ok(format) => path_format = format,
+                    Err(e) => warn!(error = %e, "invalid path format"),
+                },
+                ["watch", paths @ ..] => paths.iter().try_for_each(|path| {
+                    indexer
+                        .watch_with(path, WatchOptions::new().with_progress_estimate())
+                        .map(drop)
+                })?,
+                ["group", name, paths @ ..] => {
+                    let group = paths
+                        .iter()
+                        .fold(WatchGroup::new(name, WatchOptions::new()), |group, path| {
+                            group.with_root(path)
+                        });
+                    match indexer.add_group(group) {
+                        Ok(()) => (),
+                        Err(e) => warn!(error = %e, "failed to add the watch group"),
+                    }
+                }
+                ["groups"] => indexer
+                    .groups()
+                    .iter()
+                    .for_each(|group| println!(" - {}", group)),
+                ["selftest"] => print!("{}", indexer.indexer().self_test()),
+                ["coverage"] => indexer
+                    .coverage()
+                    .iter()
+                    .for_each(|root| println!(" - {}", root)),
+                ["dropped"] => println!("{}", indexer.dropped_events()),
+                ["pause", name] => match indexer.pause_group(name) {
+                    Ok(()) => (),
+                    Err(e) => warn!(error = %e, "failed to pause the watch group"),
+                },
+                ["resume", name] => match indexer.resume_group(name) {
+                    Ok(()) => (),
+                    Err(e) => warn!(error = %e, "failed to resume the watch group"),
+                },
+                ["manifest", manifests @ ..] => {
+                    for manifest in manifests {
+                        match indexer.index_manifest(manifest) {
+                            Ok(()) => (),
+                            Err(e) => {
+                                warn!(error = %e, manifest = %manifest, "failed to index the manifest")
+                            }
+                        }
+                    }
+                }
+                ["similar", path] => match indexer.indexer().similar(Path::new(path), 10) {
+                    Ok(similar) => similar
+                        .iter()
+                        .for_each(|(path, score)| println!(" - {} ({:.3})", path, score)),
+                    Err(e) => warn!(error = %e, "failed to find similar files"),
+                },
+                ["exact", term] => indexer
+                    .indexer()
+                    .query_exact_case(term)
+                    .iter()
+                    .for_each(|path| println!(" - {}", formatter.format(path))),
+                ["count", term] => println!(" - {} files", indexer.indexer().count(term)),
+                ["symbol", name] => {
+                    indexer
+                        .indexer()
+                        .find_symbol(name)
+                        .into_iter()
+                        .for_each(|mut symbol| {
+                            symbol.path = Arc::new(formatter.format(&*symbol.path).into());
+                            println!(" - {} ({})", symbol, symbol.term)
+                        })
+                }
+                ["in", dir, term] => indexer
+                    .indexer()
+                    .query_in(term, Path::new(dir))
+                    .iter()
+                    .for_each(|path| println!(" - {}", formatter.format(path))),
+                ["prefix", prefix] => indexer
+                    .indexer()
+                    .query_prefix(prefix)
+                    .iter()
+                    .for_each(|path| println!(" - {}", formatter.format(path))),
+                ["wildcard", pattern] => match indexer.indexer().query_wildcard_cancellable(
+                    pattern,
+                    &CancellationToken::with_timeout(QUERY_TIMEOUT),
+                ) {
+                    Ok(paths) => paths
+                        .iter()
+                        .for_each(|path| println!(" - {}", formatter.format(path))),
+                    Err(e) => warn!(error = %e, "failed to query"),
+                },
+                ["fuzzy", term, distance] => match distance.parse() {
+                    Ok(distance) => match indexer.indexer().query_fuzzy_cancellable(
+                        term,
+                        distance,
+                        &CancellationToken::with_timeout(QUERY_TIMEOUT),
+                    ) {
+                        Ok(paths) => paths
+                            .iter()
+                            .for_each(|path| println!(" - {}", formatter.format(path))),
+                        Err(e) => warn!(error = %e, "failed to query"),
+                    },
+                    Err(e) => warn!(error = %e, "invalid edit distance"),
+                },
+                ["first", limit, term] => match limit.parse() {
+                    Ok(limit) => indexer
+                        .indexer()
+                        .query_iter(term)
+                        .take(limit)
+                        .for_each(|path| println!(" - {}", formatter.format(path))),
+                    Err(_) => warn!("invalid limit"),
+                },
+                ["page", term, offset, limit] => match (offset.parse(), limit.parse()) {
+                    (Ok(offset), Ok(limit)) => {
+                        let page = indexer.indexer().query_with_options(
+                            term,
+                            &QueryOptions::new().with_offset(offset).with_limit(limit),
+                        );
+                        page.paths
+                            .iter()
+                            .for_each(|path| println!(" - {}", formatter.format(path)));
+                        println!("{} of {} results", page.paths.len(), page.total);
+                    }
+                    _ => warn!("invalid offset or limit"),
+                },
+                ["skip", normalisers, term] => {
+                    let overrides = normalisers
+                        .split(',')
+                        .fold(NormaliserOverrides::new(), |overrides, name| {
+                            overrides.with_skipped(name)
+                        });
+                    indexer
+                        .indexer()
+                        .query_with_options(term, &QueryOptions::new().with_normalisers(overrides))
+                        .paths
+                        .iter()
+                        .for_each(|path| println!(" - {}", formatter.format(path)))
+                }
+                ["all", terms @ ..] => indexer
+                    .indexer()
+                    .query_all(terms)
+                    .iter()
+                    .for_each(|path| println!(" - {}", formatter.format(path))),
+                ["any", terms @ ..] => indexer
+                    .indexer()
+                    .query_any(terms)
+                    .iter()
+                    .for_each(|path| println!(" - {}", formatter.format(path))),
+                ["batch", terms @ ..] => terms
+                    .iter()
+                    .map(|term| (term, query_pool.query(term)))
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .for_each(|(term, handle)| match handle.wait() {
+                        Some(paths) => println!(" - {}: {} results", term, paths.len()),
+                        None => warn!(term, "query failed"),
+                    }),
+                ["near", ..] => match indexer.indexer().query_near(&items[1..].join(" ")) {
+                    Ok(paths) => paths
+                        .iter()
+                        .for_each(|path| println!(" - {}", formatter.format(path))),
+                    Err(e) => warn!(error = %e, "failed to run the proximity query"),
+                },
+                ["hits", term] => {
+                    indexer
+                        .indexer()
+                        .query_hits(term)
+                        .into_iter()
+                        .for_each(|mut hit| {
+                            hit.path = Arc::new(formatter.format(&*hit.path).into());
+                            println!(" - {}", hit)
+                        })
+                }
+                ["meta", path] => match indexer.indexer().metadata(Path::new(path)) {
+                    Ok(Some(meta)) => println!("{:#?}", meta),
+                    Ok(None) => println!("not indexed"),
+                    Err(e) => warn!(error = %e, "failed to get the metadata"),
+                },
+                ["dryrun", path] => match indexer.indexer().dry_run_file(Path::new(path)) {
+                    Ok(dry_run) => print!("{}", dry_run),
+                    Err(e) => warn!(error = %e, "failed to dry run the file"),
+                },
+                ["ranked", term] => indexer
+                    .indexer()
+                    .query_ranked(term)
+                    .iter()
+                    .for_each(|file| {
+                        println!(" - {} ({:.3})", formatter.format(&file.path), file.score)
+                    }),
+                ["explain", path, query @ ..] => {
+                    match indexer.indexer().explain(&query.join(" "), Path::new(path)) {
+                        Ok(Some(mut explanation)) => {
+                            explanation.path = formatter.format(&explanation.path);
+                            println!("{}", explanation)
+                        }
+                        Ok(None) => println!("not found"),
+                        Err(e) => warn!(error = %e, "failed to explain the score"),
+                    }
+                }
+                ["highlight", path, query @ ..] => {
+                    match indexer
+                        .indexer()
+                        .highlight(Path::new(path), &query.join(" "))
+                    {
+                        Ok(ranges) => ranges
+                            .iter()
+                            .for_each(|range| println!(" - {}..{}", range.start, range.end)),
+                        Err(e) => warn!(error = %e, "failed to highlight"),
+                    }
+                }
+                ["stats", ..] => {
+                    if let Some(metrics) = indexer.indexer().query_metrics() {
+                        print!("{}", metrics);
+                    }
+                    if let Some(terms) = indexer.indexer().capped_terms() {
+                        println!("capped terms: {}", terms.join(", "));
+                    }
+                }
+                ["terms", after @ ..] => indexer
+                    .indexer()
+                    .terms(after.first().copied(), 20)
+                    .iter()
+                    .for_each(|(term, files)| println!(" - {} ({} files)", term, files)),
+                #[cfg(feature = "lock-contention")]
+                ["contention", "reset"] => indexing::contention::reset(),
+                #[cfg(feature = "lock-contention")]
+                ["contention", ..] => print!("{}", indexing::contention::report()),
+                ["termstats", term] => match indexer.indexer().term_stats(term) {
+                    Some(stats) => println!(
+                        " - {} files, {} occurrences",
+                        stats.doc_count, stats.total_occurrences
+                    ),
+                    None => println!("not found"),
+                },
+                ["stopwords"] => println!(" - {}", indexer.indexer().stop_words().join(", ")),
+                ["stopwords", "apply", percent] => match percent.parse::<f64>() {
+                    Ok(percent) => indexer
+                        .indexer()
+                        .infer_stop_words(percent / 100.0)
+                        .iter()
+                        .for_each(|term| println!(" - {}", term)),
+                    Err(e) => warn!(error = %e, "invalid percentage"),
+                },
+                ["stopwords", "set", terms @ ..] => {
+                    indexer.update_config(ConfigUpdate::new().with_stop_words(terms).with_reindex())
+                }
+                ["stopwords", percent] => match percent.parse::<f64>() {
+                    Ok(percent) => indexer
+                        .indexer()
+                        .suggest_stop_words(percent / 100.0)
+                        .iter()
+                        .for_each(|(term, ratio)| println!(" - {} ({:.1}%)", term, ratio * 100.0)),
+                    Err(e) => warn!(error = %e, "invalid percentage"),
+                },
+                ["outdated"] => indexer
+                    .indexer()
+                    .outdated_files()
+                    .iter()
+                    .for_each(|path| println!(" - {}", formatter.format(path))),
+                ["outdated", "reindex"] => println!(
+                    "indexed {} files again",
+                    indexer.indexer().reindex_outdated()
+                ),
+                ["reindex", pattern] => match indexer.indexer().reindex_matching(pattern) {
+                    Ok(count) => println!("indexed {} files again", count),
+                    Err(e) => warn!(error = %e, "failed to reindex"),
+                },
+                ["export", path] => match File::create(path)
+                    .map_err(Into::into)
+                    .and_then(|file| indexer.indexer().export_snapshot(file))
+                {
+                    Ok(()) => println!("exported to {}", path),
+                    Err(e) => warn!(error = %e, "failed to export a snapshot"),
+                },
+                ["import", path, policy @ ..] => {
+                    let policy = match policy {
+                        ["existing"] => ConflictPolicy::KeepExisting,
+                        ["replace"] => ConflictPolicy::Replace,
+                        _ => ConflictPolicy::KeepNewest,
+                    };
+                    match File::open(path)
+                        .map_err(Into::into)
+                        .and_then(|file| indexer.indexer().import_snapshot(file, policy))
+                    {
+                        Ok(result) => print!("{}", result),
+                        Err(e) => warn!(error = %e, "failed to import a snapshot"),
+                    }
+                }
+                ["tap", "off"] => indexer.indexer().set_token_tap(None),
+                ["tap", pattern, file] => {
+                    match FileSink::create(Path::new(file))
+                        .and_then(|sink| TokenTap::new(pattern, sink))
+                    {
+                        Ok(tap) => indexer.indexer().set_token_tap(Some(tap)),
+                        Err(e) => warn!(error = %e, "failed to set the token tap"),
+                    }
+                }
+                ["rotate", dir] => match SnapshotRotation::new(dir, 5).rotate(&indexer.indexer()) {
+                    Ok(path) => println!("rotated to {}", path.display()),
+                    Err(e) => warn!(error = %e, "failed to rotate the snapshots"),
+                },
+                ["rollback", dir] => {
+                    let rotation = SnapshotRotation::new(dir, 5);
+                    match rotation.previous() {
+                        Ok(Some(previous)) => match rotation.restore(&indexer.indexer(), &previous)
+                        {
+                            Ok(result) => print!("{}", result),
+                            Err(e) => warn!(error = %e, "failed to restore a snapshot"),
+                        },
+                        Ok(None) => println!("no previous snapshot"),
+                        Err(e) => warn!(error = %e, "failed to find the previous snapshot"),
+                    }
+                }
+                ["diff", ..] => {
+                    let current = indexer.indexer().snapshot();
+                    print!("{}", snapshot.diff(&current));
+                    snapshot = current;
+                }
+                ["usage", ..] => {
+                    let usage = indexer.indexer().usage();
+                    println!(
+                        "{} documents, {} terms, ~{} bytes",
+                        usage.documents, usage.terms, usage.memory_bytes
+                    );
+                    if let IndexHealth::Degraded(alerts) = indexer.indexer().health() {
+                        alerts.iter().for_each(|alert| println!(" - {}", alert));
+                    }
+                }
+                ["histogram", ..] => print!("{}", indexer.indexer().frequency_histogram()),
+                ["verify", ..] => print!("{}", indexer.indexer().verify()),
+                ["slow", ..] => indexer
+                    .indexer()
+                    .slow_queries()
+                    .unwrap_or_default()
+                    .iter()
+                    .for_each(|query| println!(" - {}", query)),
+                ["popular", ..] => indexer
+                    .indexer()
+                    .query_frequencies()
+                    .unwrap_or_default()
+                    .iter()
+                    .take(10)
+                    .for_each(|frequency| println!(" - {}", frequency)),
+                ["recent"] => indexer
+                    .indexer()
+                    .recent_mutations()
+                    .unwrap_or_default()
+                    .iter()
+                    .for_each(|mutation| println!(" - {}", mutation)),
+                ["audit", paths @ ..] => indexer
+                    .audit_log()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|entry| {
+                        paths.is_empty() || paths.iter().any(|path| entry.concerns(Path::new(path)))
+                    })
+                    .for_each(|entry| println!(" - {}", entry)),
                 ["unwatch", paths @ ..] => {
                     match paths.iter().try_for_each(|path| indexer.unwatch(path)) {
                         Ok(_) => (),
@@ -41,12 +502,27 @@ fn main() -> eyre::Result<()> {
         }
 
         let start = Instant::now();
-        let items = indexer
-            .query(&input)
-            .into_iter()
-            .map(|path| format!(" - {}", path))
+        let response = indexer.indexer().query_with_stats(&input);
+        let items = response
+            .paths
+            .iter()
+            .map(|path| format!(" - {}", formatter.format(path)))
             .collect::<Vec<_>>();
-        println!(" :: {} matches:\n{}", items.len(), items.join("\n"));
+        println!(" :: {}:\n{}", response, items.join("\n"));
+
+        if items.is_empty() && indexer.indexer().is_filtered(&input) && stop_word_index {
+            println!(
+                " :: {:?} is filtered out by normalisers, quote it to search exactly",
+                input
+            );
+        } else if items.is_empty() && indexer.indexer().is_filtered(&input) {
+            println!(" :: {:?} is filtered out by normalisers", input);
+        } else if items.is_empty() {
+            let corrections = indexer.indexer().correct(&input);
+            if !corrections.is_empty() {
+                println!(" :: did you mean: {}", corrections.join(", "));
+            }
+        }
 
         trace!(term = ?input, duration = ?start.elapsed(), "query executed");
     }
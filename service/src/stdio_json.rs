@@ -0,0 +1,340 @@
+//! Machine-readable protocol of the service, enabled with `--stdio-json`.
+//!
+//! Requests are read from stdin, one JSON object per line, e.g.
+//! `{"id": 1, "method": "query", "params": {"term": "fox"}}`, and a response is written to stdout
+//! for each of them, again one per line: `{"id": 1, "result": [...]}`, or
+//! `{"id": 1, "error": {"message": "..."}}` if the request failed. This lets editors and other tools
+//! drive the service as a subprocess. The logs are written to stderr, so that they don't get mixed
+//! with the responses.
+//!
+//! Paths are formatted as given by the `--path-format` argument, or per request by the `format`
+//! parameter (`absolute`, `relative`, `tilde` or `uri`), see [`indexing::path_format`].
+//!
+//! Methods:
+//!  * `watch`, `unwatch` - `{"path": ...}`
+//!  * `query`, `ranked`, `hits` - `{"term": ...}`; `query` reports the files relative to their
+//!    watched paths with `"relative": true`, as `{"root": ..., "path": ...}` objects, and only the
+//!    first files by path with `"limit": n`
+//!  * `highlight` - `{"path": ..., "query": ...}`
+//!  * `coverage`
+//!  * `shutdown` - stops the service once answered
+
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use color_eyre::eyre;
+use serde_json::{json, Value};
+use tracing::trace;
+
+use indexing::{path_format::PathFormat, LiveIndexer};
+
+/// Answer the requests read from stdin until it's closed or a `shutdown` request is received.
+pub fn serve(indexer: &LiveIndexer, path_format: PathFormat) -> eyre::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    serve_io(indexer, path_format, stdin.lock(), stdout.lock())
+}
+
+/// Answer the requests read from `input`, writing the responses to `output`, until the input ends
+/// or a `shutdown` request is received.
+fn serve_io<R, W>(
+    indexer: &LiveIndexer,
+    path_format: PathFormat,
+    input: R,
+    mut output: W,
+) -> eyre::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (id, method, result) = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => {
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                let method = request["method"].as_str().unwrap_or_default().to_owned();
+                let result = handle(indexer, path_format, &method, &request["params"]);
+                (id, method, result)
+            }
+            Err(e) => (
+                Value::Null,
+                String::new(),
+                Err(format!("invalid request: {}", e)),
+            ),
+        };
+        trace!(%method, ok = result.is_ok(), "answered a request");
+
+        let response = match result {
+            Ok(result) => json!({ "id": id, "result": result }),
+            Err(message) => json!({ "id": id, "error": { "message": message } }),
+        };
+        serde_json::to_writer(&mut output, &response)?;
+        writeln!(output)?;
+        output.flush()?;
+
+        if method == "shutdown" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Perform a request, returning its result or the message of its error.
+fn handle(
+    indexer: &LiveIndexer,
+    path_format: PathFormat,
+    method: &str,
+    params: &Value,
+) -> Result<Value, String> {
+    let param = |name: &str| {
+        params
+            .get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("missing string parameter {:?}", name))
+    };
+    let path_format = match params.get("format").and_then(Value::as_str) {
+        Some(format) => format.parse::<PathFormat>().map_err(|e| e.to_string())?,
+        None => path_format,
+    };
+    let formatter = indexer.path_formatter(path_format);
+
+    match method {
+        "watch" => indexer
+            .watch(param("path")?)
+            .map(|_| Value::Null)
+            .map_err(|e| e.to_string()),
+        "unwatch" => indexer
+            .unwatch(param("path")?)
+            .map(|_| Value::Null)
+            .map_err(|e| e.to_string()),
+        "query" if params["relative"].as_bool() == Some(true) => Ok(indexer
+            .query_relative(param("term")?)
+            .into_iter()
+            .map(|path| {
+                json!({
+                    "root": path.root.map(|root| root.to_string_lossy().into_owned()),
+                    "path": path.path.to_string_lossy(),
+                })
+            })
+            .collect()),
+        "query" if params["limit"].is_u64() => Ok(indexer
+            .indexer()
+            .query_iter(param("term")?)
+            .take(params["limit"].as_u64().unwrap_or_default() as usize)
+            .map(|path| json!(formatter.format(path)))
+            .collect()),
+        "query" => {
+            let mut paths = indexer
+                .query(param("term")?)
+                .into_iter()
+                .map(|path| formatter.format(path))
+                .collect::<Vec<_>>();
+            paths.sort();
+            Ok(json!(paths))
+        }
+        "ranked" => Ok(indexer
+            .indexer()
+            .query_ranked(param("term")?)
+            .into_iter()
+            .map(|file| {
+                let aliases = file
+                    .aliases
+                    .iter()
+                    .map(|alias| formatter.format(alias))
+                    .collect::<Vec<_>>();
+                json!({ "path": formatter.format(&file.path), "score": file.score, "aliases": aliases })
+            })
+            .collect()),
+        "hits" => Ok(indexer
+            .indexer()
+            .query_hits(param("term")?)
+            .into_iter()
+            .map(|hit| {
+                let matches = hit
+                    .matches
+                    .iter()
+                    .map(|m| {
+                        json!({
+                            "term": m.term,
+                            "offset": m.offset,
+                            "line": m.position.map(|position| position.line),
+                            "column": m.position.map(|position| position.column),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({ "path": formatter.format(&*hit.path), "matches": matches })
+            })
+            .collect()),
+        "highlight" => indexer
+            .indexer()
+            .highlight(Path::new(param("path")?), param("query")?)
+            .map(|ranges| {
+                ranges
+                    .into_iter()
+                    .map(|range| json!([range.start, range.end]))
+                    .collect()
+            })
+            .map_err(|e| e.to_string()),
+        "coverage" => Ok(indexer
+            .coverage()
+            .into_iter()
+            .map(|root| {
+                json!({
+                    "root": root.root.to_string_lossy(),
+                    "documents": root.documents,
+                    "discovered": root.discovered,
+                    "indexed": root.indexed,
+                    "failed": root.failed,
+                    "progress": root.progress(),
+                })
+            })
+            .collect()),
+        "shutdown" => Ok(Value::Null),
+        _ => Err(format!("unknown method {:?}", method)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use serde_json::{json, Value};
+
+    use indexing::{
+        file_system::MemoryFileSystem, path_format::PathFormat, tokenise, Indexer, LiveIndexer,
+        LiveIndexerOptions,
+    };
+
+    use super::serve_io;
+
+    fn live_indexer() -> LiveIndexer {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        file_system.write(Path::new("/docs/fox.txt"), b"quick brown fox");
+        file_system.write(Path::new("/docs/dog.txt"), b"lazy dog");
+        file_system.write(Path::new("/other/fox.txt"), b"red fox");
+
+        LiveIndexer::start_with(
+            Indexer::new(|| Box::new(tokenise::SpaceTokeniser::new()) as _)
+                .with_file_system(file_system),
+            LiveIndexerOptions::new().with_synchronous_mode(),
+        )
+        .unwrap()
+    }
+
+    /// Responses to the given lines of requests.
+    fn answers(indexer: &LiveIndexer, requests: &[&str]) -> Vec<Value> {
+        let input = requests.join("\n");
+        let mut output = Vec::new();
+        serve_io(
+            indexer,
+            PathFormat::default(),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn malformed_requests_are_answered_with_errors() {
+        let indexer = live_indexer();
+        let responses = answers(
+            &indexer,
+            &[
+                "{not json",
+                "",
+                r#"{"method": "coverage"}"#,
+                r#"{"id": 1, "method": "frobnicate"}"#,
+                r#"{"id": 2, "method": "query", "params": {}}"#,
+                r#"{"id": 3, "method": "query", "params": {"term": "fox", "format": "dos"}}"#,
+            ],
+        );
+
+        assert_eq!(responses.len(), 5);
+        assert_eq!(responses[0]["id"], Value::Null);
+        assert!(responses[0]["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid request"));
+        assert_eq!(responses[1], json!({ "id": null, "result": [] }));
+        assert_eq!(
+            responses[2],
+            json!({ "id": 1, "error": { "message": "unknown method \"frobnicate\"" } })
+        );
+        assert_eq!(
+            responses[3],
+            json!({ "id": 2, "error": { "message": "missing string parameter \"term\"" } })
+        );
+        assert_eq!(responses[4]["id"], 3);
+        assert!(responses[4]["error"]["message"].is_string());
+    }
+
+    #[test]
+    fn shutdown_stops_answering() {
+        let indexer = live_indexer();
+        let responses = answers(
+            &indexer,
+            &[
+                r#"{"id": 1, "method": "shutdown"}"#,
+                r#"{"id": 2, "method": "coverage"}"#,
+            ],
+        );
+
+        assert_eq!(responses, [json!({ "id": 1, "result": null })]);
+    }
+
+    #[test]
+    fn every_method_is_answered() {
+        let indexer = live_indexer();
+        let responses = answers(
+            &indexer,
+            &[
+                r#"{"id": 1, "method": "watch", "params": {"path": "/docs"}}"#,
+                r#"{"id": 2, "method": "watch", "params": {"path": "/other"}}"#,
+                r#"{"id": 3, "method": "unwatch", "params": {"path": "/other"}}"#,
+                r#"{"id": 4, "method": "query", "params": {"term": "fox"}}"#,
+                r#"{"id": 5, "method": "query", "params": {"term": "fox", "relative": true}}"#,
+                r#"{"id": 6, "method": "query", "params": {"term": "fox", "limit": 0}}"#,
+                r#"{"id": 7, "method": "query", "params": {"term": "fox", "format": "uri"}}"#,
+                r#"{"id": 8, "method": "ranked", "params": {"term": "dog"}}"#,
+                r#"{"id": 9, "method": "hits", "params": {"term": "brown"}}"#,
+                r#"{"id": 10, "method": "highlight", "params": {"path": "/docs/fox.txt", "query": "fox"}}"#,
+                r#"{"id": 11, "method": "coverage"}"#,
+            ],
+        );
+        let results = responses
+            .iter()
+            .map(|response| response["result"].clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(results[0], Value::Null);
+        assert_eq!(results[1], Value::Null);
+        assert_eq!(results[2], Value::Null);
+        assert_eq!(results[3], json!(["/docs/fox.txt"]));
+        assert_eq!(results[4], json!([{ "root": "/docs", "path": "fox.txt" }]));
+        assert_eq!(results[5], json!([]));
+        assert_eq!(results[6], json!(["file:///docs/fox.txt"]));
+        assert_eq!(results[7][0]["path"], "/docs/dog.txt");
+        assert_eq!(results[7][0]["aliases"], json!([]));
+        assert_eq!(results[8][0]["path"], "/docs/fox.txt");
+        assert_eq!(
+            results[8][0]["matches"][0],
+            json!({ "term": "brown", "offset": 6, "line": 1, "column": 7 })
+        );
+        assert_eq!(results[9], json!([[12, 15]]));
+        assert_eq!(results[10][0]["root"], "/docs");
+        assert_eq!(results[10][0]["indexed"], 2);
+    }
+}